@@ -0,0 +1,120 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, LitInt, LitStr};
+
+/// The `#[problem(...)]` attribute of the enum or a variant.
+#[derive(Clone, Default)]
+struct ProblemAttr {
+    status: Option<u16>,
+    ty: Option<LitStr>,
+    detail: Option<LitStr>,
+    internal: Option<LitStr>,
+    confidential: bool,
+}
+
+impl ProblemAttr {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Option<Self>> {
+        let mut result = None;
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("problem")) {
+            let problem = result.get_or_insert_with(ProblemAttr::default);
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("status") {
+                    let lit: LitInt = meta.value()?.parse()?;
+                    let status = lit.base10_parse::<u16>()?;
+                    if !(100..1000).contains(&status) {
+                        return Err(syn::Error::new(lit.span(), "Invalid status code"));
+                    }
+                    problem.status = Some(status);
+                } else if meta.path.is_ident("type") {
+                    problem.ty = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("detail") {
+                    problem.detail = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("internal") {
+                    problem.internal = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("confidential") {
+                    problem.confidential = true;
+                } else {
+                    return Err(meta.error("Unknown problem attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(result)
+    }
+
+    fn into_problem(self, span: proc_macro2::Span) -> syn::Result<TokenStream2> {
+        if let Some(minimal) = self.internal {
+            return Ok(quote! {
+                shine_service::axum::Problem::internal_error(config, #minimal, &self)
+            });
+        }
+
+        let status = self.status.unwrap_or(500);
+        let ty = match self.ty {
+            Some(ty) => ty,
+            None if status >= 500 => LitStr::new("server-error", span),
+            None => return Err(syn::Error::new(span, "Missing problem type")),
+        };
+
+        let problem = quote! {
+            shine_service::axum::Problem::new(
+                shine_service::axum::StatusCode::from_u16(#status).expect("Invalid status code"),
+                #ty,
+            )
+        };
+        let problem = match (self.detail, self.confidential) {
+            (Some(detail), _) => quote! { #problem.with_detail(#detail) },
+            (None, true) => quote! {
+                if config.include_internal {
+                    #problem.with_detail(self.to_string())
+                } else {
+                    #problem
+                }
+            },
+            (None, false) => quote! { #problem.with_detail(self.to_string()) },
+        };
+        Ok(problem)
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new(
+            ident.span(),
+            "IntoProblem can be derived only for enums",
+        ));
+    };
+    let default = ProblemAttr::parse(&input.attrs)?;
+
+    let mut arms = Vec::new();
+    for variant in &data.variants {
+        let variant_ident = &variant.ident;
+        let attr = ProblemAttr::parse(&variant.attrs)?
+            .or_else(|| default.clone())
+            .ok_or_else(|| syn::Error::new(variant_ident.span(), "Missing #[problem(...)] attribute"))?;
+        let problem = attr.into_problem(variant_ident.span())?;
+        arms.push(quote! { Self::#variant_ident { .. } => #problem });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics shine_service::axum::IntoProblem for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn into_problem(self, config: &shine_service::axum::ProblemConfig) -> shine_service::axum::Problem {
+                match &self {
+                    #(#arms,)*
+                }
+            }
+        }
+    })
+}
+
+pub fn into_problem(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(expanded) => TokenStream::from(expanded),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
@@ -2,6 +2,9 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, DeriveInput};
 
+mod into_problem;
+mod sql_check;
+
 #[proc_macro_derive(RedisJsonValue)]
 pub fn redis_json_value(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -20,13 +23,23 @@ pub fn redis_json_value(input: TokenStream) -> TokenStream {
         impl redis::FromRedisValue for #struct_type {
           fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {
             match *v {
-              redis::Value::BulkString(ref bytes) => Ok(serde_json::from_slice(bytes).map_err(|err| {
-                (
-                  redis::ErrorKind::TypeError,
-                  "JSON deserialize failed",
-                  err.to_string(),
-                )
-              })?),
+              redis::Value::BulkString(ref bytes) => {
+                // the values may be compressed by a redis value migration
+                let bytes = shine_service::service::redis_json_payload(bytes).map_err(|err| {
+                  (
+                    redis::ErrorKind::TypeError,
+                    "JSON decompression failed",
+                    err.to_string(),
+                  )
+                })?;
+                Ok(serde_json::from_slice(&bytes).map_err(|err| {
+                  (
+                    redis::ErrorKind::TypeError,
+                    "JSON deserialize failed",
+                    err.to_string(),
+                  )
+                })?)
+              }
               _ => Err(
                 (
                   redis::ErrorKind::TypeError,
@@ -41,3 +54,24 @@ pub fn redis_json_value(input: TokenStream) -> TokenStream {
 
     TokenStream::from(expanded)
 }
+
+/// Validate a postgres SQL statement at compile time and expand to the string literal.
+/// The placeholders are always checked to be numbered continuously from `$1`, the syntax is
+/// checked only with the `sql_check` feature.
+#[proc_macro]
+pub fn checked_sql(input: TokenStream) -> TokenStream {
+    sql_check::checked_sql(input)
+}
+
+/// Implement `IntoProblem` for an error enum from the `#[problem(...)]` attributes of the variants.
+/// The attribute of the enum is used for the variants without an attribute.
+/// - `status = 404`: the status code, 500 by default
+/// - `type = "user_not_found"`: the problem type, `server-error` by default for the 5xx status codes
+/// - `detail = "..."`: a fixed detail, the `Display` of the error is used by default
+/// - `confidential`: include the detail only if the internal details are enabled by the `ProblemConfig`
+/// - `internal = "..."`: an internal server error with the given message and the `Debug` of the error
+///   as the confidential detail
+#[proc_macro_derive(IntoProblem, attributes(problem))]
+pub fn into_problem(input: TokenStream) -> TokenStream {
+    into_problem::into_problem(input)
+}
@@ -0,0 +1,147 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use std::{iter::Peekable, str::CharIndices};
+use syn::{parse_macro_input, LitStr};
+
+/// Skip the chars up to and including the terminator.
+fn skip_until(chars: &mut Peekable<CharIndices>, sql: &str, terminator: &str) {
+    while let Some((i, _)) = chars.next() {
+        if sql[i..].starts_with(terminator) {
+            for _ in 1..terminator.chars().count() {
+                chars.next();
+            }
+            return;
+        }
+    }
+}
+
+/// Check the placeholders are numbered continuously from `$1`. The string literals, quoted identifiers and
+/// comments are skipped.
+fn check_placeholders(sql: &str) -> Result<(), String> {
+    let mut ids = Vec::new();
+    let mut prev = None;
+    let mut chars = sql.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let rest = &sql[i..];
+        match c {
+            // a doubled quote is an escaped quote, it is skipped as an empty literal and a new literal
+            '\'' => skip_until(&mut chars, sql, "'"),
+            '"' => skip_until(&mut chars, sql, "\""),
+            '-' if rest.starts_with("--") => skip_until(&mut chars, sql, "\n"),
+            '/' if rest.starts_with("/*") => {
+                chars.next();
+                skip_until(&mut chars, sql, "*/");
+            }
+            // dollar quoted string: $$...$$ or $tag$...$tag$
+            '$' if !prev.is_some_and(|p: char| p.is_alphanumeric() || p == '_') => {
+                let tag_len = rest[1..]
+                    .find(|t: char| !(t.is_alphanumeric() || t == '_'))
+                    .filter(|len| rest[1 + len..].starts_with('$'));
+                match tag_len {
+                    Some(len) if !rest[1..].starts_with(|t: char| t.is_ascii_digit()) => {
+                        let tag = &rest[..len + 2];
+                        for _ in 1..tag.chars().count() {
+                            chars.next();
+                        }
+                        skip_until(&mut chars, sql, tag);
+                    }
+                    _ => {
+                        let mut digits = String::new();
+                        while let Some((_, d)) = chars.peek().filter(|(_, d)| d.is_ascii_digit()) {
+                            digits.push(*d);
+                            chars.next();
+                        }
+                        if !digits.is_empty() {
+                            match digits.parse::<usize>() {
+                                Ok(0) | Err(_) => return Err(format!("Invalid placeholder: ${digits}")),
+                                Ok(id) => ids.push(id),
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        prev = Some(c);
+    }
+
+    ids.sort_unstable();
+    ids.dedup();
+    for (expected, id) in (1..).zip(&ids) {
+        if *id != expected {
+            return Err(format!("Missing placeholder: ${expected}"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sql_check")]
+fn check_syntax(sql: &str) -> Result<(), String> {
+    use sqlparser::{dialect::PostgreSqlDialect, parser::Parser};
+
+    Parser::parse_sql(&PostgreSqlDialect {}, sql)
+        .map(|_| ())
+        .map_err(|err| format!("Invalid SQL: {err}"))
+}
+
+#[cfg(not(feature = "sql_check"))]
+fn check_syntax(_sql: &str) -> Result<(), String> {
+    Ok(())
+}
+
+pub fn checked_sql(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as LitStr);
+    let sql = input.value();
+
+    if let Err(err) = check_placeholders(&sql).and_then(|_| check_syntax(&sql)) {
+        return syn::Error::new(input.span(), err).to_compile_error().into();
+    }
+
+    TokenStream::from(quote! { #input })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn continuous_placeholders() {
+        assert!(check_placeholders("SELECT 1").is_ok());
+        assert!(check_placeholders("SELECT * FROM t WHERE a = $1 AND b = $2").is_ok());
+        assert!(check_placeholders("SELECT * FROM t WHERE a = $2 AND b = $1 OR c = $1").is_ok());
+        assert!(check_placeholders("SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10").is_ok());
+        assert!(check_placeholders("SELECT $1::text").is_ok());
+    }
+
+    #[test]
+    fn gaps() {
+        assert_eq!(
+            check_placeholders("SELECT $1, $3").unwrap_err(),
+            "Missing placeholder: $2"
+        );
+        assert_eq!(check_placeholders("SELECT $2").unwrap_err(), "Missing placeholder: $1");
+        // $10 is not $1 followed by a 0
+        assert_eq!(check_placeholders("SELECT $10").unwrap_err(), "Missing placeholder: $1");
+        assert_eq!(
+            check_placeholders("SELECT $1, $10").unwrap_err(),
+            "Missing placeholder: $2"
+        );
+        assert_eq!(check_placeholders("SELECT $0").unwrap_err(), "Invalid placeholder: $0");
+    }
+
+    #[test]
+    fn quoted_text() {
+        assert!(check_placeholders("SELECT '$2', $1").is_ok());
+        assert!(check_placeholders("SELECT 'it''s $2', $1").is_ok());
+        assert!(check_placeholders("SELECT \"col$2\", $1").is_ok());
+        assert!(check_placeholders("SELECT $1 -- $3\n").is_ok());
+        assert!(check_placeholders("SELECT /* $3 */ $1").is_ok());
+        assert!(check_placeholders("SELECT $$ $3 $$, $1").is_ok());
+        assert!(check_placeholders("SELECT $fn$ $3 $x$ $fn$, $1").is_ok());
+        assert!(check_placeholders("SELECT a$2 FROM t WHERE b = $1").is_ok());
+        assert_eq!(
+            check_placeholders("SELECT '$1', $2").unwrap_err(),
+            "Missing placeholder: $1"
+        );
+    }
+}
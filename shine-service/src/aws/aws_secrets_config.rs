@@ -0,0 +1,138 @@
+use async_trait::async_trait;
+use aws_config::BehaviorVersion;
+use config::{
+    AsyncSource as ConfigAsyncSource, ConfigError, Map as ConfigMap, Value as ConfigValue, ValueKind as ConfigValueKind,
+};
+use std::error::Error as StdError;
+use thiserror::Error as ThisError;
+
+#[derive(Debug, ThisError)]
+#[error("Aws sdk error: {0}")]
+pub struct AwsConfigError(#[source] Box<dyn StdError + Send + Sync>);
+
+impl AwsConfigError {
+    fn new<E: StdError + Send + Sync + 'static>(err: E) -> Self {
+        Self(Box::new(err))
+    }
+}
+
+impl From<AwsConfigError> for ConfigError {
+    fn from(err: AwsConfigError) -> Self {
+        log::error!("{:?}", err);
+        ConfigError::Foreign(Box::new(err))
+    }
+}
+
+/// Convert a string value into a config value, as conversion from string to a concrete type is not automatic.
+fn parse_value(origin: &str, value: String) -> ConfigValue {
+    let value = if let Ok(parsed) = value.parse::<i64>() {
+        ConfigValueKind::I64(parsed)
+    } else {
+        ConfigValueKind::String(value)
+    };
+    ConfigValue::new(Some(&origin.to_string()), value)
+}
+
+/// Config source reading the secrets of the AWS Secrets Manager with the given name prefix.
+#[derive(Clone, Debug)]
+pub struct AwsSecretsManagerConfigSource {
+    prefix: String,
+}
+
+impl AwsSecretsManagerConfigSource {
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl ConfigAsyncSource for AwsSecretsManagerConfigSource {
+    async fn collect(&self) -> Result<ConfigMap<String, ConfigValue>, ConfigError> {
+        let mut config = ConfigMap::new();
+
+        log::info!(
+            "Loading secrets from aws secrets manager with prefix {} ...",
+            self.prefix
+        );
+        let origin = format!("aws-sm://{}", self.prefix);
+        let sdk_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let client = aws_sdk_secretsmanager::Client::new(&sdk_config);
+
+        let mut stream = client.list_secrets().into_paginator().send();
+        while let Some(response) = stream.next().await {
+            let response = response.map_err(AwsConfigError::new)?;
+            for raw in response.secret_list() {
+                let key = raw.name().and_then(|name| name.strip_prefix(&self.prefix));
+                if let Some(key) = key {
+                    let path = key.trim_start_matches(['-', '/']).replace(['-', '/'], ".");
+                    log::info!("Reading secret {:?}", key);
+                    let secret = client
+                        .get_secret_value()
+                        .secret_id(raw.name().unwrap_or_default())
+                        .send()
+                        .await
+                        .map_err(AwsConfigError::new)?;
+                    if let Some(value) = secret.secret_string() {
+                        config.insert(path, parse_value(&origin, value.to_owned()));
+                    }
+                }
+            }
+        }
+
+        log::info!("aws secrets manager config keys: {:#?}", config.keys());
+        Ok(config)
+    }
+}
+
+/// Config source reading the parameters of the AWS SSM Parameter Store under the given path.
+#[derive(Clone, Debug)]
+pub struct AwsParameterStoreConfigSource {
+    path: String,
+}
+
+impl AwsParameterStoreConfigSource {
+    pub fn new(path: &str) -> Self {
+        let path = if path.starts_with('/') {
+            path.to_owned()
+        } else {
+            format!("/{path}")
+        };
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl ConfigAsyncSource for AwsParameterStoreConfigSource {
+    async fn collect(&self) -> Result<ConfigMap<String, ConfigValue>, ConfigError> {
+        let mut config = ConfigMap::new();
+
+        log::info!("Loading parameters from aws parameter store at {} ...", self.path);
+        let origin = format!("aws-ssm://{}", self.path);
+        let sdk_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let client = aws_sdk_ssm::Client::new(&sdk_config);
+
+        let mut stream = client
+            .get_parameters_by_path()
+            .path(&self.path)
+            .recursive(true)
+            .with_decryption(true)
+            .into_paginator()
+            .send();
+        while let Some(response) = stream.next().await {
+            let response = response.map_err(AwsConfigError::new)?;
+            for raw in response.parameters() {
+                let key = raw.name().and_then(|name| name.strip_prefix(&self.path));
+                if let (Some(key), Some(value)) = (key, raw.value()) {
+                    let path = key.trim_start_matches(['-', '/']).replace(['-', '/'], ".");
+                    log::info!("Reading parameter {:?}", key);
+                    config.insert(path, parse_value(&origin, value.to_owned()));
+                }
+            }
+        }
+
+        log::info!("aws parameter store config keys: {:#?}", config.keys());
+        Ok(config)
+    }
+}
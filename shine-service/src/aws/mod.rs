@@ -0,0 +1 @@
+pub mod aws_secrets_config;
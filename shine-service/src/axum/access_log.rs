@@ -0,0 +1,259 @@
+use crate::axum::telemetry::current_trace_id;
+use axum::{
+    body::{Body, HttpBody},
+    extract::MatchedPath,
+    http::{header, Extensions, Request},
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+/// Log target of the access log lines.
+pub const ACCESS_LOG_TARGET: &str = "access_log";
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccessLogFormat {
+    /// One json object per line.
+    #[default]
+    Json,
+    /// Common log format extended with the duration and the request id.
+    Common,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLogConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub format: AccessLogFormat,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            format: AccessLogFormat::default(),
+        }
+    }
+}
+
+impl AccessLogConfig {
+    pub fn into_layer(self) -> AccessLogLayer {
+        AccessLogLayer(Arc::new(self))
+    }
+}
+
+/// The user of the request, filled by the session extractors when the access log is enabled.
+#[derive(Clone, Default)]
+pub struct AccessLogUser(Arc<Mutex<Option<Uuid>>>);
+
+impl AccessLogUser {
+    /// Record the user of the request, it is a no-op without the access log layer.
+    pub fn record(extensions: &Extensions, user_id: Uuid) {
+        if let Some(user) = extensions.get::<AccessLogUser>() {
+            *user.0.lock().unwrap() = Some(user_id);
+        }
+    }
+
+    fn get(&self) -> Option<Uuid> {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessLogEntry {
+    timestamp: DateTime<Utc>,
+    method: String,
+    route: String,
+    path: String,
+    version: String,
+    status: u16,
+    duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+}
+
+impl AccessLogEntry {
+    fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Json => serde_json::to_string(self).unwrap(),
+            AccessLogFormat::Common => {
+                let dash = || "-".to_string();
+                format!(
+                    "- - {} [{}] \"{} {} {}\" {} {} {}ms {}",
+                    self.user_id.map(|id| id.to_string()).unwrap_or_else(dash),
+                    self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+                    self.method,
+                    self.path,
+                    self.version,
+                    self.status,
+                    self.bytes.map(|bytes| bytes.to_string()).unwrap_or_else(dash),
+                    self.duration_ms,
+                    self.request_id
+                        .clone()
+                        .or_else(|| self.trace_id.clone())
+                        .unwrap_or_else(dash),
+                )
+            }
+        }
+    }
+}
+
+/// Log a line for each request independent of the tracing exporters, for the environments collecting only
+/// the standard output.
+#[derive(Clone)]
+pub struct AccessLogLayer(Arc<AccessLogConfig>);
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogMiddleware {
+            inner,
+            config: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct AccessLogMiddleware<S> {
+    inner: S,
+    config: Arc<AccessLogConfig>,
+}
+
+impl<S> Service<Request<Body>> for AccessLogMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        if !self.config.enabled {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let format = self.config.format;
+        let start = Instant::now();
+        let timestamp = Utc::now();
+        let method = request.method().to_string();
+        let path = request.uri().path().to_string();
+        let version = format!("{:?}", request.version());
+        let route = match request.extensions().get::<MatchedPath>() {
+            Some(route) => route.as_str().to_string(),
+            None => path.clone(),
+        };
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .map(|id| id.to_string());
+        let trace_id = current_trace_id();
+        let user = AccessLogUser::default();
+        request.extensions_mut().insert(user.clone());
+
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await?;
+
+            let bytes = response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| len.parse().ok())
+                .or_else(|| response.body().size_hint().exact());
+            let entry = AccessLogEntry {
+                timestamp,
+                method,
+                route,
+                path,
+                version,
+                status: response.status().as_u16(),
+                duration_ms: start.elapsed().as_millis() as u64,
+                user_id: user.get(),
+                request_id,
+                trace_id,
+                bytes,
+            };
+            log::info!(target: ACCESS_LOG_TARGET, "{}", entry.format(format));
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn log_formats() {
+        let entry = AccessLogEntry {
+            timestamp: DateTime::parse_from_rfc3339("2024-03-01T10:20:30Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            method: "GET".into(),
+            route: "/api/users/:id".into(),
+            path: "/api/users/42".into(),
+            version: "HTTP/1.1".into(),
+            status: 200,
+            duration_ms: 12,
+            user_id: None,
+            request_id: Some("req-1".into()),
+            trace_id: None,
+            bytes: Some(128),
+        };
+
+        assert_eq!(
+            entry.format(AccessLogFormat::Common),
+            r#"- - - [01/Mar/2024:10:20:30 +0000] "GET /api/users/42 HTTP/1.1" 200 128 12ms req-1"#
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&entry.format(AccessLogFormat::Json)).unwrap();
+        assert_eq!(json["route"], "/api/users/:id");
+        assert_eq!(json["durationMs"], 12);
+        assert!(json.get("userId").is_none());
+    }
+
+    #[test]
+    fn record_user() {
+        let mut extensions = Extensions::new();
+        let user_id = Uuid::new_v4();
+        AccessLogUser::record(&extensions, user_id);
+
+        let user = AccessLogUser::default();
+        extensions.insert(user.clone());
+        AccessLogUser::record(&extensions, user_id);
+        assert_eq!(user.get(), Some(user_id));
+    }
+}
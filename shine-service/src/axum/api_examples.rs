@@ -0,0 +1,369 @@
+use crate::{
+    axum::openapi::to_swagger,
+    utils::{is_sensitive_config_key, REDACTED},
+};
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::MatchedPath,
+    http::{header, HeaderMap, Method, Request},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    task::{Context, Poll},
+};
+use thiserror::Error as ThisError;
+use tower::{Layer, Service};
+use utoipa::openapi::{example::ExampleBuilder, path::Operation, OpenApi, RefOr};
+
+/// Header marking a request of a test as a documentation example, the value is the name of the example.
+pub const API_EXAMPLE_HEADER: &str = "x-api-example";
+
+const MAX_EXAMPLE_SIZE: usize = 1024 * 1024;
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+#[derive(Debug, ThisError)]
+pub enum ApiExampleError {
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Json error")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A recorded request/response pair of an operation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiExample {
+    pub name: String,
+    pub method: String,
+    /// The path of the operation in the swagger format, ex: `/users/{id}`.
+    pub path: String,
+    pub request: Option<JsonValue>,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub response: Option<JsonValue>,
+}
+
+/// Replace the values of the sensitive fields (tokens, passwords, secrets, ...) recursively.
+fn sanitize(value: &mut JsonValue) {
+    match value {
+        JsonValue::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if is_sensitive_config_key(key) {
+                    *value = JsonValue::String(REDACTED.to_string());
+                } else {
+                    sanitize(value);
+                }
+            }
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(sanitize),
+        _ => {}
+    }
+}
+
+fn parse_json(headers: &HeaderMap, body: &Bytes) -> Option<JsonValue> {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.contains("json"));
+    if !is_json || body.is_empty() {
+        return None;
+    }
+    let mut value = serde_json::from_slice(body).ok()?;
+    sanitize(&mut value);
+    Some(value)
+}
+
+/// Record the requests of the (integration) tests marked with the `API_EXAMPLE_HEADER` as documentation
+/// examples. The examples are sanitized and stored as json files in the given directory to be
+/// injected into the OpenApi documentation by `ApiExamples`.
+/// The layer has to be added with `Router::route_layer` to find the matched route of the requests.
+#[derive(Clone)]
+pub struct ApiExampleRecorder {
+    directory: Arc<PathBuf>,
+}
+
+impl ApiExampleRecorder {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: Arc::new(directory.into()),
+        }
+    }
+
+    fn save(&self, example: &ApiExample) -> Result<(), ApiExampleError> {
+        fs::create_dir_all(self.directory.as_ref())?;
+        let file_name: String = format!("{}-{}-{}", example.method, example.path, example.name)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let content = serde_json::to_string_pretty(example)?;
+        fs::write(self.directory.join(format!("{file_name}.json")), content)?;
+        Ok(())
+    }
+}
+
+impl<S> Layer<S> for ApiExampleRecorder {
+    type Service = ApiExampleMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiExampleMiddleware {
+            inner,
+            recorder: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct ApiExampleMiddleware<S> {
+    inner: S,
+    recorder: ApiExampleRecorder,
+}
+
+impl<S> Service<Request<Body>> for ApiExampleMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        let Some(name) = request
+            .headers_mut()
+            .remove(API_EXAMPLE_HEADER)
+            .and_then(|name| name.to_str().map(String::from).ok())
+        else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        // the inner service has been polled, use it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let recorder = self.recorder.clone();
+        Box::pin(async move {
+            let path = request
+                .extensions()
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_string())
+                .unwrap_or_else(|| request.uri().path().to_string());
+            let method = request.method().clone();
+
+            let (parts, body) = request.into_parts();
+            let body = to_bytes(body, MAX_EXAMPLE_SIZE).await.unwrap_or_else(|err| {
+                log::warn!("Failed to read the request of example {name}: {err}");
+                Bytes::new()
+            });
+            let request_json = parse_json(&parts.headers, &body);
+            let response = inner.call(Request::from_parts(parts, Body::from(body))).await?;
+
+            let (parts, body) = response.into_parts();
+            let body = to_bytes(body, MAX_EXAMPLE_SIZE).await.unwrap_or_else(|err| {
+                log::warn!("Failed to read the response of example {name}: {err}");
+                Bytes::new()
+            });
+            let example = ApiExample {
+                name,
+                method: method_name(&method),
+                path: to_swagger(&path),
+                request: request_json,
+                status: parts.status.as_u16(),
+                content_type: parts
+                    .headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|content_type| content_type.to_str().ok())
+                    .map(String::from),
+                response: parse_json(&parts.headers, &body),
+            };
+            if let Err(err) = recorder.save(&example) {
+                log::warn!("Failed to save example {}: {err:?}", example.name);
+            }
+
+            Ok(Response::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+fn method_name(method: &Method) -> String {
+    method.as_str().to_ascii_lowercase()
+}
+
+fn operation_mut<'a>(doc: &'a mut OpenApi, path: &str, method: &str) -> Option<&'a mut Operation> {
+    let item = doc.paths.paths.get_mut(path)?;
+    match method {
+        "get" => item.get.as_mut(),
+        "post" => item.post.as_mut(),
+        "put" => item.put.as_mut(),
+        "delete" => item.delete.as_mut(),
+        "patch" => item.patch.as_mut(),
+        _ => None,
+    }
+}
+
+/// The recorded examples to be injected into the OpenApi documentation.
+#[derive(Clone, Debug, Default)]
+pub struct ApiExamples(pub Vec<ApiExample>);
+
+impl ApiExamples {
+    /// Load the examples recorded by `ApiExampleRecorder`, a missing directory has no examples.
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Self, ApiExampleError> {
+        let directory = directory.as_ref();
+        if !directory.exists() {
+            return Ok(Self::default());
+        }
+
+        let mut examples = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                examples.push(serde_json::from_slice(&fs::read(&path)?)?);
+            }
+        }
+        examples.sort_by(|a: &ApiExample, b: &ApiExample| {
+            (&a.path, &a.method, &a.name).cmp(&(&b.path, &b.method, &b.name))
+        });
+        Ok(Self(examples))
+    }
+
+    /// Add the examples to the request and response content of the matching operations.
+    pub fn apply(&self, doc: &mut OpenApi) {
+        for example in &self.0 {
+            let Some(operation) = operation_mut(doc, &example.path, &example.method) else {
+                log::warn!(
+                    "No operation for example {} of {} {}",
+                    example.name,
+                    example.method,
+                    example.path
+                );
+                continue;
+            };
+
+            if let (Some(value), Some(request_body)) = (&example.request, operation.request_body.as_mut()) {
+                if let Some(content) = request_body.content.get_mut(JSON_CONTENT_TYPE) {
+                    let request_example = ExampleBuilder::new().value(Some(value.clone())).build();
+                    content.examples.insert(example.name.clone(), RefOr::T(request_example));
+                }
+            }
+
+            let Some(value) = &example.response else {
+                continue;
+            };
+            match operation.responses.responses.get_mut(&example.status.to_string()) {
+                Some(RefOr::T(response)) => {
+                    let content_type = example.content_type.as_deref().unwrap_or(JSON_CONTENT_TYPE);
+                    let content = response
+                        .content
+                        .iter_mut()
+                        .find(|(name, _)| content_type.starts_with(name.as_str()))
+                        .map(|(_, content)| content);
+                    if let Some(content) = content {
+                        let response_example = ExampleBuilder::new().value(Some(value.clone())).build();
+                        content
+                            .examples
+                            .insert(example.name.clone(), RefOr::T(response_example));
+                    }
+                }
+                Some(RefOr::Ref(_)) => log::warn!(
+                    "Cannot add example {} to the shared response {} of {}",
+                    example.name,
+                    example.status,
+                    example.path
+                ),
+                None => log::warn!(
+                    "Response {} of example {} is not documented for {}",
+                    example.status,
+                    example.name,
+                    example.path
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::axum::{ApiEndpoint, ApiMethod, ApiRoute};
+    use axum::{http::StatusCode, Json, Router};
+    use serde_json::json;
+    use shine_test::test;
+    use tower::ServiceExt;
+    use utoipa::ToSchema;
+
+    #[derive(Serialize, Deserialize, ToSchema)]
+    struct Login {
+        name: String,
+        password: String,
+    }
+
+    #[derive(Serialize, Deserialize, ToSchema)]
+    struct LoginResponse {
+        name: String,
+        token: String,
+    }
+
+    async fn login(Json(login): Json<Login>) -> Json<LoginResponse> {
+        Json(LoginResponse {
+            name: login.name,
+            token: "secret-token".into(),
+        })
+    }
+
+    #[test]
+    async fn record_and_apply_examples() {
+        let directory = std::env::temp_dir().join(format!("api-examples-{}", uuid::Uuid::new_v4()));
+
+        let mut doc = OpenApi::default();
+        let app = Router::new()
+            .add_api(
+                ApiEndpoint::new(ApiMethod::Post, "/login", login)
+                    .with_json_request::<Login>()
+                    .with_json_response::<LoginResponse>(StatusCode::OK),
+                &mut doc,
+            )
+            .route_layer(ApiExampleRecorder::new(&directory));
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/login")
+            .header(API_EXAMPLE_HEADER, "login")
+            .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+            .body(Body::from(r#"{"name":"jane","password":"pwd"}"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let examples = ApiExamples::load(&directory).unwrap();
+        let _ = fs::remove_dir_all(&directory);
+        assert_eq!(examples.0.len(), 1);
+        let example = &examples.0[0];
+        assert_eq!(example.path, "/login");
+        assert_eq!(example.request, Some(json!({ "name": "jane", "password": REDACTED })));
+        assert_eq!(example.response, Some(json!({ "name": "jane", "token": REDACTED })));
+
+        examples.apply(&mut doc);
+        let operation = operation_mut(&mut doc, "/login", "post").unwrap();
+        let request_body = operation.request_body.as_ref().unwrap();
+        assert!(request_body.content[JSON_CONTENT_TYPE].examples.contains_key("login"));
+        let RefOr::T(response) = &operation.responses.responses["200"] else {
+            panic!("Unexpected shared response");
+        };
+        assert!(response.content[JSON_CONTENT_TYPE].examples.contains_key("login"));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{axum::Problem, utils::ByteSize};
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{DefaultBodyLimit, MatchedPath},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use http_body::Body as _;
+use http_body_util::Limited;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+fn default_max_size() -> ByteSize {
+    ByteSize::mb(2)
+}
+
+/// Request body limits. The routes are matched by the route pattern, ex: `/api/files/:id`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BodyLimitConfig {
+    /// Maximum size of the (compressed) request body, ex: `2MB`.
+    #[serde(default = "default_max_size")]
+    pub max_size: ByteSize,
+    /// Decompress the gzip and brotli encoded request bodies.
+    #[serde(default)]
+    pub decompress: bool,
+    /// Maximum size of the decompressed body, by default 10 times the `max_size`.
+    pub max_decompressed_size: Option<ByteSize>,
+    /// Per route override of the `max_size`.
+    #[serde(default)]
+    pub routes: HashMap<String, ByteSize>,
+}
+
+impl Default for BodyLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_max_size(),
+            decompress: false,
+            max_decompressed_size: None,
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl BodyLimitConfig {
+    pub fn into_layer(self) -> BodyLimitLayer {
+        BodyLimitLayer(Arc::new(self))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+enum BodyError {
+    TooLarge(usize),
+    UnsupportedEncoding(String),
+    Decompress(String),
+}
+
+impl IntoResponse for BodyError {
+    fn into_response(self) -> Response {
+        let problem = match self {
+            BodyError::TooLarge(limit) => Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large")
+                .with_detail(format!("Request body exceeds the limit of {limit} bytes")),
+            BodyError::UnsupportedEncoding(encoding) => {
+                Problem::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_encoding")
+                    .with_detail(format!("Unsupported content encoding: {encoding}"))
+            }
+            BodyError::Decompress(err) => {
+                Problem::bad_request("body_encoding_error").with_detail(format!("Failed to decompress body: {err}"))
+            }
+        };
+        problem.into_response()
+    }
+}
+
+impl BodyLimitConfig {
+    fn limit(&self, path: &str) -> usize {
+        self.routes.get(path).copied().unwrap_or(self.max_size).as_usize()
+    }
+
+    fn encoding(&self, headers: &HeaderMap) -> Result<Encoding, BodyError> {
+        let Some(encoding) = headers.get(header::CONTENT_ENCODING) else {
+            return Ok(Encoding::Identity);
+        };
+        let encoding = encoding.to_str().unwrap_or_default().trim().to_ascii_lowercase();
+        match encoding.as_str() {
+            "" | "identity" => Ok(Encoding::Identity),
+            "gzip" | "x-gzip" if self.decompress => Ok(Encoding::Gzip),
+            "br" if self.decompress => Ok(Encoding::Brotli),
+            _ if self.decompress => Err(BodyError::UnsupportedEncoding(encoding)),
+            // pass through, the handler is responsible for the encoding
+            _ => Ok(Encoding::Identity),
+        }
+    }
+
+    fn decompress(&self, encoding: Encoding, body: Bytes, limit: usize) -> Result<Bytes, BodyError> {
+        use std::io::Read;
+
+        let max = self
+            .max_decompressed_size
+            .map(|size| size.as_usize())
+            .unwrap_or(limit.saturating_mul(10));
+        let reader: Box<dyn Read + '_> = match encoding {
+            Encoding::Identity => return Ok(body),
+            Encoding::Gzip => Box::new(flate2::read::MultiGzDecoder::new(&body[..])),
+            Encoding::Brotli => Box::new(brotli::Decompressor::new(&body[..], 4096)),
+        };
+
+        // read one byte more than the limit to detect the decompression bombs
+        let mut decoded = Vec::new();
+        reader
+            .take(max as u64 + 1)
+            .read_to_end(&mut decoded)
+            .map_err(|err| BodyError::Decompress(err.to_string()))?;
+        if decoded.len() > max {
+            return Err(BodyError::TooLarge(max));
+        }
+        Ok(Bytes::from(decoded))
+    }
+}
+
+/// Limit the size of the request bodies and optionally decompress them. The requests with an oversized
+/// declared length are rejected with a `413 Payload Too Large` problem, the plain bodies are streamed through
+/// a size limit and only the compressed bodies are buffered for the decompression.
+/// The default body limit of the axum extractors is disabled, the limits of this layer apply instead.
+#[derive(Clone)]
+pub struct BodyLimitLayer(Arc<BodyLimitConfig>);
+
+impl<S> Layer<S> for BodyLimitLayer {
+    type Service = BodyLimitMiddleware<<DefaultBodyLimit as Layer<S>>::Service>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BodyLimitMiddleware {
+            inner: DefaultBodyLimit::disable().layer(inner),
+            config: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct BodyLimitMiddleware<S> {
+    inner: S,
+    config: Arc<BodyLimitConfig>,
+}
+
+impl<S> Service<Request<Body>> for BodyLimitMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let config = self.config.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let path = match request.extensions().get::<MatchedPath>() {
+                Some(path) => path.as_str().to_string(),
+                None => request.uri().path().to_string(),
+            };
+            let limit = config.limit(&path);
+
+            let encoding = match config.encoding(request.headers()) {
+                Ok(encoding) => encoding,
+                Err(err) => return Ok(err.into_response()),
+            };
+
+            let declared = request
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| len.parse::<usize>().ok());
+            if declared.is_some_and(|len| len > limit) {
+                return Ok(BodyError::TooLarge(limit).into_response());
+            }
+
+            let (mut parts, body) = request.into_parts();
+            if body.size_hint().lower() > limit as u64 {
+                return Ok(BodyError::TooLarge(limit).into_response());
+            }
+
+            // the plain bodies are streamed, the handler (extractor) fails once the limit is exceeded
+            if encoding == Encoding::Identity {
+                let body = Body::new(Limited::new(body, limit));
+                return inner.call(Request::from_parts(parts, body)).await;
+            }
+
+            let body = match to_bytes(body, limit).await {
+                Ok(body) => body,
+                Err(_) => return Ok(BodyError::TooLarge(limit).into_response()),
+            };
+            // decompression is cpu bound, keep it off the async workers
+            let decompress = {
+                let config = config.clone();
+                tokio::task::spawn_blocking(move || config.decompress(encoding, body, limit))
+            };
+            let body = match decompress.await {
+                Ok(Ok(body)) => body,
+                Ok(Err(err)) => return Ok(err.into_response()),
+                Err(err) => return Ok(BodyError::Decompress(err.to_string()).into_response()),
+            };
+
+            parts.headers.remove(header::CONTENT_ENCODING);
+            parts
+                .headers
+                .insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+
+            inner.call(Request::from_parts(parts, Body::from(body))).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{routing::post, Router};
+    use flate2::{write::GzEncoder, Compression};
+    use shine_test::test;
+    use std::io::Write;
+    use tower::ServiceExt;
+
+    fn gzip(data: &[u8]) -> Bytes {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(data).unwrap();
+        Bytes::from(encoder.finish().unwrap())
+    }
+
+    #[test]
+    fn decompress_within_limit() {
+        let config = BodyLimitConfig {
+            decompress: true,
+            ..Default::default()
+        };
+        let body = config.decompress(Encoding::Gzip, gzip(b"hello"), 1024).ok().unwrap();
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[test]
+    fn reject_decompression_bomb() {
+        let config = BodyLimitConfig {
+            decompress: true,
+            max_decompressed_size: Some(ByteSize::kb(1)),
+            ..Default::default()
+        };
+        let bomb = gzip(&vec![0_u8; 1024 * 1024]);
+        assert!(bomb.len() < 1024);
+        assert!(matches!(
+            config.decompress(Encoding::Gzip, bomb, 1024),
+            Err(BodyError::TooLarge(1024))
+        ));
+    }
+
+    #[test]
+    fn route_override() {
+        let config = BodyLimitConfig {
+            routes: HashMap::from([("/api/upload".to_string(), ByteSize::new(100))]),
+            ..Default::default()
+        };
+        assert_eq!(config.limit("/api/upload"), 100);
+        assert_eq!(config.limit("/api/other"), default_max_size().as_usize());
+    }
+
+    async fn upload(config: BodyLimitConfig, body: Bytes, encoding: Option<&str>) -> Response {
+        let app = Router::new()
+            .route("/api/upload", post(|body: Bytes| async move { body.len().to_string() }))
+            .layer(config.into_layer());
+        let mut request = Request::builder().method("POST").uri("/api/upload");
+        if let Some(encoding) = encoding {
+            request = request.header(header::CONTENT_ENCODING, encoding);
+        }
+        app.oneshot(request.body(Body::from(body)).unwrap()).await.unwrap()
+    }
+
+    #[test]
+    async fn route_limit_above_default_limit() {
+        let config = BodyLimitConfig {
+            decompress: true,
+            routes: HashMap::from([("/api/upload".to_string(), ByteSize::mb(5))]),
+            ..Default::default()
+        };
+        let body = Bytes::from(vec![1_u8; 3 * 1024 * 1024]);
+
+        let response = upload(config.clone(), body.clone(), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let len = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(len, body.len().to_string());
+
+        let response = upload(config.clone(), gzip(&body), Some("gzip")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let len = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(len, body.len().to_string());
+
+        let response = upload(config, Bytes::from(vec![1_u8; 6 * 1024 * 1024]), None).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    async fn streamed_body_limit() {
+        let app = Router::new()
+            .route("/api/upload", post(|body: Bytes| async move { body.len().to_string() }))
+            .layer(BodyLimitConfig::default().into_layer());
+        let call = |chunks: usize| {
+            let chunks = (0..chunks).map(|_| Ok::<_, std::io::Error>(Bytes::from(vec![1_u8; 1024 * 1024])));
+            let body = Body::from_stream(futures::stream::iter(chunks.collect::<Vec<_>>()));
+            let request = Request::builder().method("POST").uri("/api/upload").body(body).unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = call(2).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call(3).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
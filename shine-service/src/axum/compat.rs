@@ -0,0 +1,118 @@
+//! Compatibility facade for the services migrating from axum 0.6 / hyper 0.14.
+//!
+//! The crate is built on axum 0.7 and hyper 1.0: the routers have no body generic, the extractors take
+//! `Request<Body>` and the middlewares are `Service<Request<Body>>`. The services should import the http stack
+//! through this module, thus the `ApiEndpoint`s, the extractors (ex. `ValidatedJson`) and the layers of the crate
+//! are always used with the same axum, http and http-body versions as the crate itself.
+//!
+//! Migration path:
+//! - `Router<S, B>` becomes `Router<S>`, the `ApiEndpoint`s are registered on `Router<S>` with `ApiRoute`.
+//! - Custom extractors implement `FromRequest<S>` and take `Request`.
+//! - `hyper::Body`, `BoxBody` and `boxed` are replaced by `Body` and `Body::new`, the deprecated aliases below
+//!   keep the former names compiling during the migration.
+//! - `hyper::body::to_bytes` is replaced by `axum::body::to_bytes` with an explicit limit.
+//! - The requests with a foreign body (ex. `hyper::body::Incoming` or `Full<Bytes>` in the tests) are converted
+//!   by `into_request` or by the `BodyCompatLayer` in front of the layers of the crate.
+//! - `hyper::Server::bind(..).serve(..)` is replaced by `axum::serve(TcpListener::bind(..).await?, app)` with
+//!   `.with_graceful_shutdown(shutdown.shutdown_signal())`.
+
+use axum::{body::Bytes, BoxError};
+use tower::util::MapRequestLayer;
+
+pub use axum::{self, body::Body, extract::Request, response::Response, Router};
+pub use http_body;
+pub use tower;
+
+/// Body type of the axum 0.6 middlewares.
+#[deprecated(note = "Use Body instead")]
+pub type BoxBody = Body;
+
+/// Box a body, the axum 0.6 `axum::body::boxed`.
+#[deprecated(note = "Use Body::new instead")]
+pub fn boxed<B>(body: B) -> Body
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    Body::new(body)
+}
+
+/// Collect a body without limit, the hyper 0.14 `hyper::body::to_bytes`.
+#[deprecated(note = "Use axum::body::to_bytes with a limit instead")]
+pub async fn to_bytes(body: Body) -> Result<Bytes, axum::Error> {
+    axum::body::to_bytes(body, usize::MAX).await
+}
+
+/// Convert a request with any http-body 1.0 body into the request type of the extractors and the layers.
+pub fn into_request<B>(request: axum::http::Request<B>) -> Request
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    request.map(Body::new)
+}
+
+/// Layer converting the requests with a foreign body, ex. to serve a router by a plain hyper 1.0 connection.
+pub type BodyCompatLayer<B> = MapRequestLayer<fn(axum::http::Request<B>) -> Request>;
+
+pub fn body_compat_layer<B>() -> BodyCompatLayer<B>
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    MapRequestLayer::new(into_request::<B> as fn(axum::http::Request<B>) -> Request)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::axum::{BodyLimitConfig, ProblemConfig, ValidatedJson};
+    use axum::{http::StatusCode, routing::post};
+    use http_body_util::Full;
+    use serde::Deserialize;
+    use shine_test::test;
+    use tower::{Layer, ServiceExt};
+    use validator::Validate;
+
+    #[derive(Deserialize, Validate)]
+    struct Greeting {
+        #[validate(length(min = 3))]
+        name: String,
+    }
+
+    async fn greet(ValidatedJson(greeting): ValidatedJson<Greeting>) -> String {
+        format!("Hello {}", greeting.name)
+    }
+
+    async fn call(body: &'static str) -> (StatusCode, String) {
+        let app = Router::new()
+            .route("/", post(greet))
+            .layer(BodyLimitConfig::default().into_layer())
+            .layer(ProblemConfig::new(false).into_layer());
+        let service = body_compat_layer::<Full<Bytes>>().layer(app);
+
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("content-type", "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        let status = response.status();
+        #[allow(deprecated)]
+        let body = to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    async fn foreign_body() {
+        assert_eq!(
+            call(r#"{"name":"shine"}"#).await,
+            (StatusCode::OK, "Hello shine".to_string())
+        );
+
+        let (status, body) = call(r#"{"name":"x"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("validation_error"), "{body}");
+    }
+}
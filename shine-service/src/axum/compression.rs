@@ -0,0 +1,142 @@
+use axum::http::{header, Response};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tower_http::compression::{
+    predicate::{And, Predicate, SizeAbove},
+    CompressionLayer,
+};
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_min_size() -> u16 {
+    32
+}
+
+fn default_content_types() -> Vec<String> {
+    [
+        "text/html",
+        "text/plain",
+        "text/css",
+        "text/javascript",
+        "application/javascript",
+        "application/json",
+        "application/problem+json",
+        "application/xml",
+        "image/svg+xml",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+/// Response compression settings. To record the size of the transferred (compressed) body the
+/// compression layer should be added inside the `OtelLayer`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressionConfig {
+    #[serde(default = "default_true")]
+    pub gzip: bool,
+    #[serde(default = "default_true")]
+    pub br: bool,
+    #[serde(default = "default_true")]
+    pub zstd: bool,
+    /// Responses smaller than this size (in bytes) are not compressed.
+    #[serde(default = "default_min_size")]
+    pub min_size: u16,
+    /// Compressed content types without parameters, ex: `application/json`. A `type/*` pattern
+    /// allows all the subtypes.
+    #[serde(default = "default_content_types")]
+    pub content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            gzip: true,
+            br: true,
+            zstd: true,
+            min_size: default_min_size(),
+            content_types: default_content_types(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    pub fn into_layer(self) -> CompressionLayer<And<SizeAbove, ContentTypeAllowList>> {
+        let allow_list = ContentTypeAllowList::new(self.content_types);
+        CompressionLayer::new()
+            .gzip(self.gzip)
+            .br(self.br)
+            .zstd(self.zstd)
+            .no_deflate()
+            .compress_when(SizeAbove::new(self.min_size).and(allow_list))
+    }
+}
+
+/// Compression predicate allowing only the listed content types. Server-sent events are never compressed.
+#[derive(Clone, Debug)]
+pub struct ContentTypeAllowList(Arc<Vec<String>>);
+
+impl ContentTypeAllowList {
+    pub fn new<I, S>(content_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self(Arc::new(
+            content_types
+                .into_iter()
+                .map(|content_type| content_type.as_ref().trim().to_ascii_lowercase())
+                .collect(),
+        ))
+    }
+
+    pub fn is_allowed(&self, content_type: &str) -> bool {
+        let content_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        // server-sent events are flushed per event, compression would break the streaming
+        if content_type == "text/event-stream" {
+            return false;
+        }
+        self.0.iter().any(|allowed| match allowed.strip_suffix("/*") {
+            Some(main_type) => content_type.split_once('/').is_some_and(|(main, _)| main == main_type),
+            None => *allowed == content_type,
+        })
+    }
+}
+
+impl Predicate for ContentTypeAllowList {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: http_body::Body,
+    {
+        response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| self.is_allowed(content_type))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn content_type_allow_list() {
+        let allow_list = ContentTypeAllowList::new(["application/json", "text/*"]);
+        assert!(allow_list.is_allowed("application/json"));
+        assert!(allow_list.is_allowed("Application/JSON; charset=utf-8"));
+        assert!(allow_list.is_allowed("text/html"));
+        assert!(!allow_list.is_allowed("image/png"));
+        assert!(!allow_list.is_allowed("text"));
+        assert!(!allow_list.is_allowed("text/event-stream"));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::utils::DurationStr;
+use axum::http::{
+    header::{InvalidHeaderName, InvalidHeaderValue},
+    method::InvalidMethod,
+    HeaderName, HeaderValue, Method,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error as ThisError;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+#[derive(Debug, ThisError)]
+pub enum CorsConfigError {
+    #[error("Invalid origin: {0}")]
+    InvalidOrigin(String),
+    #[error("Credentials are not allowed with any origin")]
+    CredentialsWithAnyOrigin,
+    #[error(transparent)]
+    InvalidMethod(#[from] InvalidMethod),
+    #[error(transparent)]
+    InvalidHeaderName(#[from] InvalidHeaderName),
+    #[error(transparent)]
+    InvalidHeaderValue(#[from] InvalidHeaderValue),
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CorsConfig {
+    /// Allowed origins, ex: `https://example.com`. Subdomains can be allowed by a wildcard: `https://*.example.com`.
+    /// A single `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    /// Allowed methods, if empty GET, POST, PUT, DELETE, PATCH are allowed.
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    /// Allowed request headers.
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// Max age of the preflight response, ex: `"1h"`.
+    pub max_age: Option<DurationStr>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum OriginPattern {
+    Exact(String),
+    Wildcard { scheme: String, domain: String },
+}
+
+impl OriginPattern {
+    pub(crate) fn parse(origin: &str) -> Result<Self, CorsConfigError> {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        let (scheme, host) = origin
+            .split_once("://")
+            .ok_or_else(|| CorsConfigError::InvalidOrigin(origin.clone()))?;
+        if host.is_empty() || host.contains('/') {
+            return Err(CorsConfigError::InvalidOrigin(origin.clone()));
+        }
+
+        if let Some(domain) = host.strip_prefix("*.") {
+            Ok(Self::Wildcard {
+                scheme: format!("{scheme}://"),
+                domain: format!(".{domain}"),
+            })
+        } else if host.contains('*') {
+            Err(CorsConfigError::InvalidOrigin(origin.clone()))
+        } else {
+            Ok(Self::Exact(origin))
+        }
+    }
+
+    pub(crate) fn is_match(&self, origin: &str) -> bool {
+        match self {
+            Self::Exact(allowed) => allowed.eq_ignore_ascii_case(origin),
+            Self::Wildcard { scheme, domain } => {
+                let origin = origin.to_ascii_lowercase();
+                origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|host| host.strip_suffix(domain.as_str()))
+                    .is_some_and(|sub| {
+                        !sub.is_empty() && sub.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                    })
+            }
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Allow the https origins of the domain and all of its subdomains.
+    pub fn from_domain_name(domain: &str) -> Self {
+        Self {
+            allowed_origins: vec![format!("https://{domain}"), format!("https://*.{domain}")],
+            ..Default::default()
+        }
+    }
+
+    pub fn into_layer(self) -> Result<CorsLayer, CorsConfigError> {
+        let any_origin = self.allowed_origins.iter().any(|o| o == "*");
+        if any_origin && self.allow_credentials {
+            return Err(CorsConfigError::CredentialsWithAnyOrigin);
+        }
+
+        let allow_origin = if any_origin {
+            AllowOrigin::any()
+        } else {
+            let patterns = self
+                .allowed_origins
+                .iter()
+                .map(|o| OriginPattern::parse(o))
+                .collect::<Result<Vec<_>, _>>()?;
+            AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+                origin
+                    .to_str()
+                    .is_ok_and(|origin| patterns.iter().any(|p| p.is_match(origin)))
+            })
+        };
+
+        let methods = if self.allowed_methods.is_empty() {
+            vec![Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH]
+        } else {
+            self.allowed_methods
+                .iter()
+                .map(|m| Method::from_bytes(m.to_ascii_uppercase().as_bytes()))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        let headers = self
+            .allowed_headers
+            .iter()
+            .map(|h| HeaderName::from_bytes(h.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut layer = CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(self.allow_credentials);
+        if let Some(max_age) = self.max_age {
+            layer = layer.max_age(Duration::from(max_age));
+        }
+
+        Ok(layer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn origin_patterns() {
+        let exact = OriginPattern::parse("https://example.com").unwrap();
+        assert!(exact.is_match("https://example.com"));
+        assert!(!exact.is_match("http://example.com"));
+        assert!(!exact.is_match("https://cloud.example.com"));
+
+        let wildcard = OriginPattern::parse("https://*.example.com").unwrap();
+        assert!(wildcard.is_match("https://cloud.example.com"));
+        assert!(wildcard.is_match("https://a.b.example.com"));
+        assert!(!wildcard.is_match("https://example.com"));
+        assert!(!wildcard.is_match("https://evilexample.com"));
+        assert!(!wildcard.is_match("https://cloud.example.com.evil.com"));
+        assert!(!wildcard.is_match("http://cloud.example.com"));
+
+        assert!(OriginPattern::parse("example.com").is_err());
+        assert!(OriginPattern::parse("https://a*.example.com").is_err());
+    }
+
+    #[test]
+    fn credentials_with_any_origin() {
+        let config = CorsConfig {
+            allowed_origins: vec!["*".into()],
+            allow_credentials: true,
+            ..Default::default()
+        };
+        assert!(config.into_layer().is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::axum::telemetry::current_trace_id;
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, HeaderMap, HeaderValue, Request},
+    response::{Html, IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use serde_json::Value as JsonValue;
+use std::{
+    fmt::Write,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+const MAX_PROBLEM_SIZE: usize = 1024 * 1024;
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+const HIDDEN_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+
+pub(crate) fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+fn is_problem(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(PROBLEM_CONTENT_TYPE.as_bytes()))
+}
+
+/// Context of the failed request shown on the error page.
+struct RequestContext {
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+    trace_id: Option<String>,
+}
+
+impl RequestContext {
+    fn from_request(request: &Request<Body>) -> Self {
+        let headers = request
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = if HIDDEN_HEADERS.contains(&name.as_str()) {
+                    "***".to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+
+        Self {
+            method: request.method().to_string(),
+            uri: request.uri().to_string(),
+            headers,
+            trace_id: current_trace_id(),
+        }
+    }
+}
+
+/// Render the `Problem` responses as rich html pages for the browsers (requests accepting `text/html`)
+/// during the local development. The API clients still receive the json problem details.
+/// The layer should never be enabled in production as it exposes the request headers and the
+/// (internal) details of the problems.
+#[derive(Clone)]
+pub struct DevErrorPageLayer {
+    enabled: bool,
+    trace_url: Option<String>,
+}
+
+impl DevErrorPageLayer {
+    /// Create the layer, it is enabled only on the `dev` stage.
+    pub fn new(stage: &str) -> Self {
+        Self {
+            enabled: stage == "dev",
+            trace_url: None,
+        }
+    }
+
+    /// Link the trace of the request, the `{trace_id}` placeholder is replaced with the id of the trace.
+    /// ex: `http://localhost:16686/trace/{trace_id}` for a local Jaeger.
+    #[must_use]
+    pub fn with_trace_url(self, trace_url: &str) -> Self {
+        Self {
+            trace_url: Some(trace_url.to_string()),
+            ..self
+        }
+    }
+
+    fn render(&self, context: &RequestContext, problem: &JsonValue) -> String {
+        let status = problem["status"].as_u64().unwrap_or_default();
+        let ty = escape_html(problem["type"].as_str().unwrap_or_default());
+        let detail = problem["detail"].as_str().unwrap_or_default();
+
+        let mut page = String::new();
+        let _ = write!(
+            page,
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{status} {ty}</title>\
+             <style>body{{font-family:sans-serif;margin:2em}}pre{{background:#f4f4f4;padding:1em;overflow:auto}}\
+             td{{padding:0 1em 0 0;vertical-align:top}}</style></head><body>"
+        );
+        let _ = write!(page, "<h1>{status} {ty}</h1>");
+        let _ = write!(page, "<h2>Details</h2><pre>{}</pre>", escape_html(detail));
+
+        if !problem["extension"].is_null() {
+            let extension = serde_json::to_string_pretty(&problem["extension"]).unwrap_or_default();
+            let _ = write!(page, "<h2>Extension</h2><pre>{}</pre>", escape_html(&extension));
+        }
+
+        let _ = write!(
+            page,
+            "<h2>Request</h2><p><code>{} {}</code></p><table>",
+            escape_html(&context.method),
+            escape_html(&context.uri)
+        );
+        for (name, value) in &context.headers {
+            let _ = write!(
+                page,
+                "<tr><td><code>{}</code></td><td><code>{}</code></td></tr>",
+                escape_html(name),
+                escape_html(value)
+            );
+        }
+        page.push_str("</table>");
+
+        if let Some(trace_id) = &context.trace_id {
+            match &self.trace_url {
+                Some(url) => {
+                    let url = url.replace("{trace_id}", trace_id);
+                    let _ = write!(
+                        page,
+                        "<h2>Trace</h2><p><a href=\"{}\">{}</a></p>",
+                        escape_html(&url),
+                        escape_html(trace_id)
+                    );
+                }
+                None => {
+                    let _ = write!(page, "<h2>Trace</h2><p><code>{}</code></p>", escape_html(trace_id));
+                }
+            }
+        }
+
+        page.push_str("</body></html>");
+        page
+    }
+}
+
+impl<S> Layer<S> for DevErrorPageLayer {
+    type Service = DevErrorPageMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DevErrorPageMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct DevErrorPageMiddleware<S> {
+    inner: S,
+    layer: DevErrorPageLayer,
+}
+
+impl<S> Service<Request<Body>> for DevErrorPageMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if !self.layer.enabled || !accepts_html(request.headers()) {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let layer = self.layer.clone();
+        let context = RequestContext::from_request(&request);
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            if !is_problem(response.headers()) {
+                return Ok(response);
+            }
+
+            let (mut parts, body) = response.into_parts();
+            let problem = match to_bytes(body, MAX_PROBLEM_SIZE).await {
+                Ok(bytes) => serde_json::from_slice::<JsonValue>(&bytes).unwrap_or(JsonValue::Null),
+                Err(err) => {
+                    log::error!("Failed to read the problem response: {err}");
+                    JsonValue::Null
+                }
+            };
+
+            let page = Html(layer.render(&context, &problem)).into_response();
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            Ok(Response::from_parts(parts, page.into_body()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::axum::Problem;
+    use axum::{http::StatusCode, routing::get, Router};
+    use shine_test::test;
+    use tower::ServiceExt;
+
+    fn app(stage: &str) -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|| async { Problem::bad_request("invalid-input").with_detail("<script>") }),
+            )
+            .layer(DevErrorPageLayer::new(stage))
+    }
+
+    async fn call(stage: &str, accept: &str) -> Response {
+        let request = Request::builder()
+            .uri("/")
+            .header(header::ACCEPT, accept)
+            .header(header::COOKIE, "sid=secret")
+            .body(Body::empty())
+            .unwrap();
+        app(stage).oneshot(request).await.unwrap()
+    }
+
+    #[test]
+    async fn render_html_for_browsers() {
+        let response = call("dev", "text/html,application/xhtml+xml").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("invalid-input"));
+        assert!(body.contains("&lt;script&gt;"));
+        assert!(!body.contains("sid=secret"));
+
+        let response = call("dev", "application/json").await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_CONTENT_TYPE);
+
+        let response = call("prod", "text/html").await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_CONTENT_TYPE);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::{
+    axum::{IntoProblem, Problem, ProblemConfig},
+    utils::{Entropy, SystemEntropy},
+};
+use async_trait::async_trait;
+use axum::{extract::multipart::Field, http::StatusCode};
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
+use serde::Serialize;
+use std::{path::PathBuf, sync::Arc};
+use thiserror::Error as ThisError;
+use tokio::{fs, io::AsyncWriteExt};
+
+/// Number of the leading bytes required to detect the content type.
+const SNIFF_SIZE: usize = 16;
+const DEFAULT_MAX_SIZE: usize = 10 * 1024 * 1024;
+
+/// Magic bytes of the supported content types.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+];
+
+/// Content types stored in a container format, they are detected as the container.
+const CONTAINER_TYPES: &[(&str, &[&str])] = &[
+    (
+        "application/zip",
+        &[
+            "application/x-zip-compressed",
+            "application/java-archive",
+            "application/epub+zip",
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "application/vnd.oasis.opendocument.text",
+            "application/vnd.oasis.opendocument.spreadsheet",
+            "application/vnd.oasis.opendocument.presentation",
+        ],
+    ),
+    ("application/gzip", &["application/x-gzip"]),
+];
+
+/// Detect the content type from the magic bytes of the content.
+pub fn sniff_content_type(data: &[u8]) -> Option<&'static str> {
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+        .map(|(_, content_type)| *content_type)
+}
+
+/// The essence of a content type without the parameters, ex: `text/csv; charset=utf-8` -> `text/csv`.
+fn content_type_essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// The content type detected from the magic bytes of the declared type, the containers map to the
+/// container format.
+fn signature_family(content_type: &str) -> Option<&'static str> {
+    if content_type == "image/webp" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .map(|(_, detected)| *detected)
+        .find(|detected| *detected == content_type)
+        .or_else(|| {
+            CONTAINER_TYPES
+                .iter()
+                .find(|(_, members)| members.contains(&content_type))
+                .map(|(container, _)| *container)
+        })
+}
+
+#[derive(Debug, ThisError)]
+pub enum FileUploadError {
+    #[error("Missing file")]
+    MissingFile,
+    #[error("File exceeds the size limit of {0} bytes")]
+    TooLarge(usize),
+    #[error("Unsupported content type: {0}")]
+    UnsupportedContentType(String),
+    #[error("Content type {declared} does not match the content ({detected})")]
+    ContentTypeMismatch { declared: String, detected: String },
+    #[error("Multipart error: {0}")]
+    Multipart(String),
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Storage error: {0}")]
+    Storage(String),
+}
+
+impl FileUploadError {
+    fn reason(&self) -> &'static str {
+        match self {
+            FileUploadError::MissingFile | FileUploadError::Multipart(_) => "malformed",
+            FileUploadError::TooLarge(_) => "too_large",
+            FileUploadError::UnsupportedContentType(_) | FileUploadError::ContentTypeMismatch { .. } => "content_type",
+            FileUploadError::Io(_) | FileUploadError::Storage(_) => "storage",
+        }
+    }
+}
+
+impl IntoProblem for FileUploadError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            FileUploadError::MissingFile | FileUploadError::Multipart(_) => {
+                Problem::bad_request("body_format_error").with_detail(self.to_string())
+            }
+            FileUploadError::TooLarge(_) => {
+                Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large").with_detail(self.to_string())
+            }
+            FileUploadError::UnsupportedContentType(_) | FileUploadError::ContentTypeMismatch { .. } => {
+                Problem::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type").with_detail(self.to_string())
+            }
+            FileUploadError::Io(err) => Problem::internal_error(config, "Upload storage error", err),
+            FileUploadError::Storage(err) => Problem::internal_error(config, "Upload storage error", err),
+        }
+    }
+}
+
+/// A file being written into the storage.
+#[async_trait]
+pub trait UploadSink: Send {
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), FileUploadError>;
+
+    /// Complete the upload and return the location of the file.
+    async fn commit(self: Box<Self>) -> Result<String, FileUploadError>;
+
+    /// Drop the partially written file.
+    async fn abort(self: Box<Self>);
+}
+
+/// Backend storing the uploaded files.
+#[async_trait]
+pub trait UploadStorage: Send + Sync {
+    async fn create(&self, id: &str, content_type: &str) -> Result<Box<dyn UploadSink>, FileUploadError>;
+}
+
+/// Store the uploads in a local (temp) directory.
+pub struct LocalUploadStorage {
+    directory: PathBuf,
+}
+
+impl LocalUploadStorage {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// Store the uploads in a sub directory of the system temp directory.
+    pub fn temp(name: &str) -> Self {
+        Self::new(std::env::temp_dir().join(name))
+    }
+}
+
+struct LocalUploadSink {
+    file: fs::File,
+    part_path: PathBuf,
+    path: PathBuf,
+}
+
+#[async_trait]
+impl UploadSink for LocalUploadSink {
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), FileUploadError> {
+        Ok(self.file.write_all(chunk).await?)
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<String, FileUploadError> {
+        self.file.flush().await?;
+        fs::rename(&self.part_path, &self.path).await?;
+        Ok(self.path.to_string_lossy().into_owned())
+    }
+
+    async fn abort(self: Box<Self>) {
+        drop(self.file);
+        if let Err(err) = fs::remove_file(&self.part_path).await {
+            log::warn!("Failed to remove partial upload {}: {err}", self.part_path.display());
+        }
+    }
+}
+
+#[async_trait]
+impl UploadStorage for LocalUploadStorage {
+    async fn create(&self, id: &str, _content_type: &str) -> Result<Box<dyn UploadSink>, FileUploadError> {
+        fs::create_dir_all(&self.directory).await?;
+        let path = self.directory.join(id);
+        let part_path = self.directory.join(format!("{id}.part"));
+        let file = fs::File::create(&part_path).await?;
+        Ok(Box::new(LocalUploadSink { file, part_path, path }))
+    }
+}
+
+/// The result of a completed upload.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredFile {
+    pub id: String,
+    pub file_name: Option<String>,
+    pub content_type: String,
+    pub size: usize,
+    pub location: String,
+}
+
+struct UploadMetrics {
+    received_bytes: Counter<u64>,
+    file_size: Histogram<u64>,
+    rejected: Counter<u64>,
+}
+
+/// Stream the files of a multipart request into a storage with size and content type enforcement.
+/// The content type is detected from the magic bytes of the file, it has to match the declared type of the
+/// field (if the type has a known signature) and the resulting type has to be one of the allowed types.
+pub struct FileUploader {
+    storage: Arc<dyn UploadStorage>,
+    max_size: usize,
+    allowed_types: Vec<String>,
+    metrics: Option<UploadMetrics>,
+    entropy: Arc<dyn Entropy>,
+}
+
+impl FileUploader {
+    pub fn new(storage: Arc<dyn UploadStorage>) -> Self {
+        Self {
+            storage,
+            max_size: DEFAULT_MAX_SIZE,
+            allowed_types: Vec::new(),
+            metrics: None,
+            entropy: SystemEntropy::shared(),
+        }
+    }
+
+    /// Replace the source of the file ids, ex. with a `SeededEntropy` in the tests.
+    #[must_use]
+    pub fn with_entropy(self, entropy: Arc<dyn Entropy>) -> Self {
+        Self { entropy, ..self }
+    }
+
+    #[must_use]
+    pub fn with_max_size(self, max_size: usize) -> Self {
+        Self { max_size, ..self }
+    }
+
+    /// Limit the accepted content types (without parameters), ex: `["image/png", "image/jpeg"]`.
+    #[must_use]
+    pub fn with_allowed_types<I: IntoIterator<Item = S>, S: ToString>(self, allowed_types: I) -> Self {
+        Self {
+            allowed_types: allowed_types.into_iter().map(|t| t.to_string()).collect(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            metrics: Some(UploadMetrics {
+                received_bytes: meter.u64_counter("upload_received_bytes").init(),
+                file_size: meter.u64_histogram("upload_file_size").init(),
+                rejected: meter.u64_counter("upload_rejected").init(),
+            }),
+            ..self
+        }
+    }
+
+    /// Verify the declared type against the magic bytes and return the type of the file. A declared type with
+    /// a known signature (or a container format) has to match the detected signature, the types without a
+    /// signature (ex. `text/csv`) are accepted as declared unless the content has a known signature.
+    fn verify_content_type(&self, declared: Option<&str>, head: &[u8]) -> Result<String, FileUploadError> {
+        let declared = declared
+            .map(content_type_essence)
+            .filter(|declared| !declared.is_empty() && declared != "application/octet-stream");
+        let detected = sniff_content_type(head);
+
+        let content_type = match (declared, detected) {
+            (Some(declared), detected) => {
+                let expected = signature_family(&declared);
+                if expected != detected {
+                    return Err(FileUploadError::ContentTypeMismatch {
+                        detected: detected.unwrap_or("application/octet-stream").to_string(),
+                        declared,
+                    });
+                }
+                declared
+            }
+            (None, Some(detected)) => detected.to_string(),
+            (None, None) => "application/octet-stream".to_string(),
+        };
+
+        if !self.allowed_types.is_empty() && !self.allowed_types.iter().any(|allowed| *allowed == content_type) {
+            return Err(FileUploadError::UnsupportedContentType(content_type));
+        }
+        Ok(content_type)
+    }
+
+    async fn next_chunk(&self, field: &mut Field<'_>, size: &mut usize) -> Result<Option<Vec<u8>>, FileUploadError> {
+        let chunk = field
+            .chunk()
+            .await
+            .map_err(|err| FileUploadError::Multipart(err.body_text()))?;
+        if let Some(chunk) = &chunk {
+            *size += chunk.len();
+            if let Some(metrics) = &self.metrics {
+                metrics.received_bytes.add(chunk.len() as u64, &[]);
+            }
+            if *size > self.max_size {
+                return Err(FileUploadError::TooLarge(self.max_size));
+            }
+        }
+        Ok(chunk.map(|chunk| chunk.to_vec()))
+    }
+
+    async fn store(&self, mut field: Field<'_>) -> Result<StoredFile, FileUploadError> {
+        let file_name = field.file_name().map(String::from);
+        let declared = field.content_type().map(String::from);
+
+        // buffer the head of the file to detect the content type before storing anything
+        let mut size = 0;
+        let mut head = Vec::new();
+        while head.len() < SNIFF_SIZE {
+            match self.next_chunk(&mut field, &mut size).await? {
+                Some(chunk) => head.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        let content_type = self.verify_content_type(declared.as_deref(), &head)?;
+
+        let id = self
+            .entropy
+            .uuid_v7()
+            .map_err(|err| FileUploadError::Storage(err.to_string()))?
+            .to_string();
+        let mut sink = self.storage.create(&id, &content_type).await?;
+        let result = async {
+            sink.write(&head).await?;
+            while let Some(chunk) = self.next_chunk(&mut field, &mut size).await? {
+                sink.write(&chunk).await?;
+            }
+            Ok::<_, FileUploadError>(())
+        }
+        .await;
+        if let Err(err) = result {
+            sink.abort().await;
+            return Err(err);
+        }
+
+        let location = sink.commit().await?;
+        Ok(StoredFile {
+            id,
+            file_name,
+            content_type,
+            size,
+            location,
+        })
+    }
+
+    /// Stream a multipart file field into the storage.
+    pub async fn upload(&self, field: Field<'_>) -> Result<StoredFile, FileUploadError> {
+        let result = self.store(field).await;
+        if let Some(metrics) = &self.metrics {
+            match &result {
+                Ok(file) => metrics.file_size.record(file.size as u64, &[]),
+                Err(err) => metrics.rejected.add(1, &[KeyValue::new("reason", err.reason())]),
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn detect_content_type() {
+        assert_eq!(sniff_content_type(b"\x89PNG\r\n\x1a\n0000"), Some("image/png"));
+        assert_eq!(sniff_content_type(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_content_type(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(sniff_content_type(b"plain text"), None);
+
+        let uploader = FileUploader::new(Arc::new(LocalUploadStorage::temp("upload-test")))
+            .with_allowed_types(["image/png", "image/jpeg"]);
+        assert_eq!(
+            uploader
+                .verify_content_type(Some("image/png"), b"\x89PNG\r\n\x1a\n")
+                .unwrap(),
+            "image/png"
+        );
+        assert!(matches!(
+            uploader.verify_content_type(Some("image/png"), b"\xff\xd8\xff\xe0"),
+            Err(FileUploadError::ContentTypeMismatch { .. })
+        ));
+        assert!(matches!(
+            uploader.verify_content_type(None, b"%PDF-1.7"),
+            Err(FileUploadError::UnsupportedContentType(_))
+        ));
+        assert!(matches!(
+            uploader.verify_content_type(Some("image/png"), b"plain text"),
+            Err(FileUploadError::ContentTypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn declared_content_type() {
+        let uploader = FileUploader::new(Arc::new(LocalUploadStorage::temp("upload-test")));
+        let xlsx = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+        // the types without signature are accepted as declared, the parameters are ignored
+        assert_eq!(
+            uploader
+                .verify_content_type(Some("text/csv; charset=utf-8"), b"id,name\n1,a")
+                .unwrap(),
+            "text/csv"
+        );
+        assert!(matches!(
+            uploader.verify_content_type(Some("text/csv"), b"\x89PNG\r\n\x1a\n"),
+            Err(FileUploadError::ContentTypeMismatch { .. })
+        ));
+        assert_eq!(
+            uploader
+                .verify_content_type(Some("Image/PNG; name=a.png"), b"\x89PNG\r\n\x1a\n")
+                .unwrap(),
+            "image/png"
+        );
+
+        // the documents of a container format are detected as the container
+        assert_eq!(
+            uploader.verify_content_type(Some(xlsx), b"PK\x03\x04\x14\0").unwrap(),
+            xlsx
+        );
+        assert!(matches!(
+            uploader.verify_content_type(Some(xlsx), b"%PDF-1.7"),
+            Err(FileUploadError::ContentTypeMismatch { .. })
+        ));
+
+        assert_eq!(
+            uploader.verify_content_type(None, b"binary").unwrap(),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use crate::{
+    axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig},
+    service::{Flash, FlashMessage, UncheckedCurrentUser, UserSessionCacheReader},
+    utils::{Entropy, SystemEntropy},
+};
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderValue, Request, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Extension, RequestPartsExt,
+};
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use futures::future::BoxFuture;
+use minijinja::{context, Environment};
+use serde::Serialize;
+use std::{
+    path::Path,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use thiserror::Error as ThisError;
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+/// The default policy, the `{nonce}` is replaced by the nonce of the request.
+const DEFAULT_CSP_POLICY: &str = "script-src 'nonce-{nonce}' 'strict-dynamic'; object-src 'none'; base-uri 'none'";
+const CSP_NONCE_SIZE: usize = 16;
+
+#[derive(Debug, ThisError)]
+pub enum HtmlTemplateError {
+    #[error("Template error")]
+    TemplateError(#[from] minijinja::Error),
+}
+
+impl IntoProblem for HtmlTemplateError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            HtmlTemplateError::TemplateError(err) => Problem::internal_error(config, "Template error", err),
+        }
+    }
+}
+
+/// Nonce of the Content-Security-Policy generated for the current request.
+/// The `CspNonceLayer` inserts it into the request extensions.
+#[derive(Clone, Debug)]
+pub struct CspNonce(pub String);
+
+/// Generate a nonce for each request, insert it into the request extensions as `CspNonce` (available as the
+/// `layout.cspNonce` in the templates) and set the Content-Security-Policy header of the response with it,
+/// unless the handler has set the header.
+#[derive(Clone)]
+pub struct CspNonceLayer {
+    policy: Arc<String>,
+    entropy: Arc<dyn Entropy>,
+}
+
+impl Default for CspNonceLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CspNonceLayer {
+    pub fn new() -> Self {
+        Self {
+            policy: Arc::new(DEFAULT_CSP_POLICY.to_string()),
+            entropy: SystemEntropy::shared(),
+        }
+    }
+
+    /// Replace the policy, the `{nonce}` placeholders are replaced by the nonce of the request.
+    #[must_use]
+    pub fn with_policy(self, policy: &str) -> Self {
+        Self {
+            policy: Arc::new(policy.to_string()),
+            ..self
+        }
+    }
+
+    /// Replace the source of the nonces, ex. with a `SeededEntropy` in the tests.
+    #[must_use]
+    pub fn with_entropy(self, entropy: Arc<dyn Entropy>) -> Self {
+        Self { entropy, ..self }
+    }
+}
+
+impl<S> Layer<S> for CspNonceLayer {
+    type Service = CspNonceMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CspNonceMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct CspNonceMiddleware<S> {
+    inner: S,
+    layer: CspNonceLayer,
+}
+
+impl<S> Service<Request<Body>> for CspNonceMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        let policy = self.layer.policy.clone();
+        let mut raw = [0_u8; CSP_NONCE_SIZE];
+        let nonce = match self.layer.entropy.fill(&mut raw) {
+            Ok(()) => B64.encode(raw),
+            Err(err) => {
+                log::error!("Failed to generate the CSP nonce: {err}");
+                return Box::pin(async { Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()) });
+            }
+        };
+        request.extensions_mut().insert(CspNonce(nonce.clone()));
+
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let mut response = inner.call(request).await?;
+            if !response.headers().contains_key(header::CONTENT_SECURITY_POLICY) {
+                if let Ok(policy) = HeaderValue::from_str(&policy.replace("{nonce}", &nonce)) {
+                    response.headers_mut().insert(header::CONTENT_SECURITY_POLICY, policy);
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// Builder of the `TemplateEngine`, the templates are registered before the engine is shared.
+pub struct TemplateEngineBuilder {
+    env: Environment<'static>,
+}
+
+impl Default for TemplateEngineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemplateEngineBuilder {
+    pub fn new() -> Self {
+        Self {
+            env: Environment::new(),
+        }
+    }
+
+    /// Load the templates from the given folder on demand. Template names are the relative path of the files.
+    pub fn from_folder<P: AsRef<Path>>(folder: P) -> Self {
+        let mut env = Environment::new();
+        env.set_loader(minijinja::path_loader(folder));
+        Self { env }
+    }
+
+    pub fn with_template<N: ToString, S: ToString>(mut self, name: N, source: S) -> Result<Self, HtmlTemplateError> {
+        self.env.add_template_owned(name.to_string(), source.to_string())?;
+        Ok(self)
+    }
+
+    pub fn build(self) -> TemplateEngine {
+        TemplateEngine {
+            env: Arc::new(self.env),
+        }
+    }
+}
+
+/// Shared collection of the html templates.
+#[derive(Clone)]
+pub struct TemplateEngine {
+    env: Arc<Environment<'static>>,
+}
+
+impl TemplateEngine {
+    pub fn builder() -> TemplateEngineBuilder {
+        TemplateEngineBuilder::new()
+    }
+
+    /// Load all the templates from the given folder. Template names are the relative path of the files.
+    pub fn from_folder<P: AsRef<Path>>(folder: P) -> Self {
+        TemplateEngineBuilder::from_folder(folder).build()
+    }
+
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+
+    pub fn render<T: Serialize>(
+        &self,
+        name: &str,
+        layout: &LayoutContext,
+        page: &T,
+    ) -> Result<String, HtmlTemplateError> {
+        let template = self.env.get_template(name)?;
+        Ok(template.render(context! { layout => layout, page => page })?)
+    }
+}
+
+impl Default for TemplateEngine {
+    fn default() -> Self {
+        TemplateEngineBuilder::new().build()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutUser {
+    pub user_id: Uuid,
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+/// Data shared by all the pages and available as `layout` in the templates.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutContext {
+    pub user: Option<LayoutUser>,
+    pub csp_nonce: Option<String>,
+    pub flash: Vec<FlashMessage>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for LayoutContext
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<HtmlTemplateError>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = if parts.extensions.get::<Arc<UserSessionCacheReader>>().is_some() {
+            parts
+                .extract::<UncheckedCurrentUser>()
+                .await
+                .ok()
+                .map(|user| LayoutUser {
+                    user_id: user.user_id,
+                    name: user.name.clone(),
+                    roles: user.roles.clone(),
+                })
+        } else {
+            None
+        };
+
+        let csp_nonce = parts.extensions.get::<CspNonce>().map(|nonce| nonce.0.clone());
+
+        Ok(LayoutContext {
+            user,
+            csp_nonce,
+            flash: Vec::new(),
+        })
+    }
+}
+
+/// Extractor to create template responses with the layout context of the current request.
+/// The flash messages of the session are consumed and moved into the layout.
+pub struct HtmlRenderer {
+    engine: TemplateEngine,
+    problem_config: ProblemConfig,
+    flash: Option<Flash>,
+    pub layout: LayoutContext,
+}
+
+impl HtmlRenderer {
+    /// The flash of the session to add the messages shown on the next page, None without the user session layer.
+    pub fn flash(&self) -> Option<&Flash> {
+        self.flash.as_ref()
+    }
+
+    pub fn template<T: Serialize>(self, name: &'static str, page: T) -> HtmlTemplate<T> {
+        HtmlTemplate {
+            renderer: self,
+            status: StatusCode::OK,
+            name,
+            page,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for HtmlRenderer
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<HtmlTemplateError>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(engine) = parts
+            .extract::<Extension<TemplateEngine>>()
+            .await
+            .expect("Missing TemplateEngine extension");
+        let mut layout = LayoutContext::from_request_parts(parts, state).await?;
+        let flash = if parts.extensions.get::<Arc<UserSessionCacheReader>>().is_some() {
+            match Flash::from_request_parts(parts, state).await {
+                Ok(mut flash) => {
+                    layout.flash = flash.take_messages();
+                    Some(flash)
+                }
+                Err(err) => {
+                    log::warn!("Failed to read the flash messages: {:?}", err.problem);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Self {
+            engine,
+            problem_config,
+            flash,
+            layout,
+        })
+    }
+}
+
+/// Html response rendered from a template with the shared layout context.
+pub struct HtmlTemplate<T: Serialize> {
+    renderer: HtmlRenderer,
+    status: StatusCode,
+    name: &'static str,
+    page: T,
+}
+
+impl<T: Serialize> HtmlTemplate<T> {
+    #[must_use]
+    pub fn with_status(self, status: StatusCode) -> Self {
+        Self { status, ..self }
+    }
+}
+
+impl<T: Serialize> IntoResponse for HtmlTemplate<T> {
+    fn into_response(self) -> Response {
+        let HtmlTemplate {
+            renderer,
+            status,
+            name,
+            page,
+        } = self;
+        match renderer.engine.render(name, &renderer.layout, &page) {
+            Ok(html) => (status, Html(html)).into_response(),
+            Err(err) => renderer.problem_config.configure(err).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::SeededEntropy;
+    use axum::{routing::get, Router};
+    use shine_test::test;
+    use tower::ServiceExt;
+
+    #[test]
+    async fn csp_nonce() {
+        let engine = TemplateEngine::builder()
+            .with_template("page.html", r#"<script nonce="{{ layout.cspNonce }}"></script>"#)
+            .unwrap()
+            .build();
+        let app = Router::new()
+            .route(
+                "/",
+                get(
+                    |layout: LayoutContext, Extension(engine): Extension<TemplateEngine>| async move {
+                        Html(engine.render("page.html", &layout, &()).unwrap())
+                    },
+                ),
+            )
+            .layer(engine.into_layer())
+            .layer(CspNonceLayer::new().with_entropy(SeededEntropy::shared(1)));
+
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let policy = response.headers()[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let nonce = body
+            .strip_prefix(r#"<script nonce=""#)
+            .and_then(|rest| rest.strip_suffix(r#""></script>"#))
+            .unwrap();
+        assert_eq!(nonce.len(), 24);
+        assert!(policy.contains(&format!("'nonce-{nonce}'")));
+    }
+}
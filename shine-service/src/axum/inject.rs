@@ -0,0 +1,214 @@
+use crate::axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, Extension, RequestPartsExt};
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    ops::Deref,
+    sync::Arc,
+};
+use thiserror::Error as ThisError;
+
+#[derive(Debug, ThisError)]
+pub enum InjectError {
+    #[error("Service {0} is not registered")]
+    NotRegistered(&'static str),
+    #[error("Failed to create service {0}")]
+    Factory(&'static str, Problem),
+}
+
+impl IntoProblem for InjectError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            InjectError::NotRegistered(_) => Problem::internal_error(config, "Service is not registered", self),
+            InjectError::Factory(_, problem) => problem,
+        }
+    }
+}
+
+type AnyService = Arc<dyn Any + Send + Sync>;
+type Factory = Arc<dyn Fn(&mut Parts, &ServiceRegistry) -> Result<AnyService, Problem> + Send + Sync>;
+
+/// The services resolved for a request, stored in the request extensions.
+struct Resolved<T>(Arc<T>);
+
+impl<T> Clone for Resolved<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+/// Registry of the services available for the `Inject<T>` extractor, a single layer instead of an `Extension`
+/// layer for each component. The request scoped services are created on the first use and cached in the
+/// request extensions, a factory can resolve the services it depends on from the registry.
+///
+/// ```ignore
+/// let services = ServiceRegistry::new()
+///     .with_singleton(IdEncoder::new(..))
+///     .with_factory(|parts, services| {
+///         let encoder = services.resolve::<IdEncoder>(parts).expect("IdEncoder is registered");
+///         Ok(TenantContext::from_headers(&parts.headers, encoder))
+///     });
+/// let router = router.layer(services.into_layer());
+///
+/// async fn handler(Inject(tenant): Inject<TenantContext>) {}
+/// ```
+#[derive(Clone, Default)]
+pub struct ServiceRegistry {
+    factories: HashMap<TypeId, Factory>,
+}
+
+impl ServiceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a service shared by all the requests.
+    #[must_use]
+    pub fn with_singleton<T>(self, service: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.with_shared(Arc::new(service))
+    }
+
+    /// Register a service shared by all the requests that is already wrapped into an `Arc`.
+    #[must_use]
+    pub fn with_shared<T>(mut self, service: Arc<T>) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        let service: AnyService = service;
+        self.factories
+            .insert(TypeId::of::<T>(), Arc::new(move |_, _| Ok(service.clone())));
+        self
+    }
+
+    /// Register a request scoped service, the factory is called at most once for each request.
+    #[must_use]
+    pub fn with_factory<T, F>(mut self, factory: F) -> Self
+    where
+        T: Send + Sync + 'static,
+        F: Fn(&mut Parts, &ServiceRegistry) -> Result<T, Problem> + Send + Sync + 'static,
+    {
+        self.factories.insert(
+            TypeId::of::<T>(),
+            Arc::new(move |parts, registry| Ok(Arc::new(factory(parts, registry)?) as AnyService)),
+        );
+        self
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.factories.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Get the service of the request, creating it on the first use.
+    pub fn resolve<T>(&self, parts: &mut Parts) -> Result<Arc<T>, InjectError>
+    where
+        T: Send + Sync + 'static,
+    {
+        if let Some(Resolved(service)) = parts.extensions.get::<Resolved<T>>() {
+            return Ok(service.clone());
+        }
+
+        let factory = self
+            .factories
+            .get(&TypeId::of::<T>())
+            .ok_or(InjectError::NotRegistered(type_name::<T>()))?;
+        let service = factory(parts, self)
+            .map_err(|problem| InjectError::Factory(type_name::<T>(), problem))?
+            .downcast::<T>()
+            .expect("Service registered with a mismatching type");
+        parts.extensions.insert(Resolved(service.clone()));
+        Ok(service)
+    }
+
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+}
+
+/// Extractor of a service registered in the `ServiceRegistry`.
+pub struct Inject<T>(pub Arc<T>);
+
+impl<T> Deref for Inject<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for Inject<T>
+where
+    S: Send + Sync,
+    T: Send + Sync + 'static,
+{
+    type Rejection = ConfiguredProblem<InjectError>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(registry) = parts
+            .extract::<Extension<Arc<ServiceRegistry>>>()
+            .await
+            .expect("Missing ServiceRegistry extension");
+
+        registry
+            .resolve::<T>(parts)
+            .map(Inject)
+            .map_err(|err| problem_config.configure(err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::{Request, StatusCode};
+    use shine_test::test;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Greeting(String);
+    struct Tenant(String);
+
+    #[test]
+    fn resolve_services() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let registry = {
+            let created = created.clone();
+            ServiceRegistry::new()
+                .with_singleton(Greeting("hello".into()))
+                .with_factory(move |parts, registry| {
+                    created.fetch_add(1, Ordering::Relaxed);
+                    let greeting = registry.resolve::<Greeting>(parts).map_err(|_| Problem::forbidden())?;
+                    let tenant = parts
+                        .headers
+                        .get("x-tenant")
+                        .and_then(|tenant| tenant.to_str().ok())
+                        .ok_or_else(|| Problem::bad_request("missing-tenant"))?;
+                    Ok(Tenant(format!("{} {tenant}", greeting.0)))
+                })
+        };
+
+        let (mut parts, _) = Request::builder()
+            .header("x-tenant", "acme")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(registry.resolve::<Tenant>(&mut parts).unwrap().0, "hello acme");
+        assert_eq!(registry.resolve::<Tenant>(&mut parts).unwrap().0, "hello acme");
+        assert_eq!(created.load(Ordering::Relaxed), 1);
+
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+        match registry.resolve::<Tenant>(&mut parts) {
+            Err(InjectError::Factory(_, problem)) => assert_eq!(problem.status(), StatusCode::BAD_REQUEST),
+            _ => panic!("Expected a factory error"),
+        }
+        assert!(matches!(
+            registry.resolve::<String>(&mut parts),
+            Err(InjectError::NotRegistered(_))
+        ));
+    }
+}
@@ -0,0 +1,388 @@
+use crate::{
+    axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig},
+    utils::DurationStr,
+};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+    Extension, RequestPartsExt,
+};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+use tokio::sync::{Mutex, RwLock};
+
+#[derive(Debug, ThisError)]
+pub enum JwtError {
+    #[error("Missing bearer token")]
+    MissingToken,
+    #[error("Invalid token")]
+    InvalidToken(#[from] jsonwebtoken::errors::Error),
+    #[error("Token has no key id")]
+    MissingKeyId,
+    #[error("Unknown signing key: {0}")]
+    UnknownKey(String),
+    #[error("Algorithm {0:?} is not allowed for the signing key")]
+    AlgorithmMismatch(Algorithm),
+    #[error("Failed to fetch JWKS")]
+    JwksFetchError(#[from] reqwest::Error),
+    #[error("JWKS is unavailable, the last fetch failed: {0}")]
+    JwksUnavailable(String),
+}
+
+impl IntoProblem for JwtError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            JwtError::JwksFetchError(err) => Problem::internal_error(config, "Failed to fetch JWKS", err),
+            JwtError::JwksUnavailable(err) => Problem::internal_error(config, "JWKS is unavailable", err),
+            _ => Problem::unauthorized()
+                .with_detail(self.to_string())
+                .with_extension(config, format!("{:#?}", self)),
+        }
+    }
+}
+
+fn default_clock_skew() -> DurationStr {
+    DurationStr::from_secs(60)
+}
+
+fn default_refresh_interval() -> DurationStr {
+    DurationStr::from_secs(3600)
+}
+
+fn default_fetch_timeout() -> DurationStr {
+    DurationStr::from_secs(10)
+}
+
+fn default_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::RS256]
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JwtConfig {
+    pub issuer: String,
+    pub jwks_url: String,
+    pub audience: Vec<String>,
+    /// Allowed clock skew for the time based claims.
+    #[serde(default = "default_clock_skew")]
+    pub clock_skew: DurationStr,
+    /// Refresh period of the cached keys.
+    #[serde(default = "default_refresh_interval")]
+    pub refresh_interval: DurationStr,
+    /// Timeout of fetching the keys.
+    #[serde(default = "default_fetch_timeout")]
+    pub fetch_timeout: DurationStr,
+    /// Allowed algorithms of the keys without an `alg` parameter, by default `RS256`. The tokens are accepted
+    /// only with the algorithm of the key.
+    #[serde(default = "default_algorithms")]
+    pub algorithms: Vec<Algorithm>,
+}
+
+/// A signing key with the algorithms it can be used with.
+#[derive(Clone)]
+struct JwksKey {
+    key: DecodingKey,
+    algorithms: Vec<Algorithm>,
+}
+
+struct JwksCache {
+    keys: HashMap<String, JwksKey>,
+    /// Time of the last successful fetch.
+    updated: Option<Instant>,
+    /// Time of the last fetch attempt.
+    attempted: Option<Instant>,
+    /// Error of the last fetch attempt, if it has failed.
+    failure: Option<String>,
+}
+
+/// Validate bearer tokens with the signing keys of the issuer. The keys are cached
+/// and refreshed periodically or when a token with an unknown key id is received. If a refresh fails,
+/// the cached keys are used until the next attempt. Only a single fetch is in flight at a time and
+/// without any cached key a failed fetch is not retried for the `FAILURE_BACKOFF`.
+pub struct JwtValidator {
+    config: JwtConfig,
+    client: reqwest::Client,
+    cache: RwLock<JwksCache>,
+    refresh_lock: Mutex<()>,
+}
+
+impl JwtValidator {
+    /// Minimum time between two key refreshes triggered by unknown key ids.
+    const MIN_REFRESH: Duration = Duration::from_secs(60);
+    /// Time the failure of a fetch is reported without a new attempt, when there are no cached keys.
+    const FAILURE_BACKOFF: Duration = Duration::from_secs(5);
+
+    pub fn new(config: JwtConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(config.fetch_timeout.as_duration())
+            .build()
+            .expect("Failed to create JWKS client");
+        Self {
+            config,
+            client,
+            cache: RwLock::new(JwksCache {
+                keys: HashMap::new(),
+                updated: None,
+                attempted: None,
+                failure: None,
+            }),
+            refresh_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    fn needs_refresh(&self, cache: &JwksCache, force: bool) -> Result<bool, JwtError> {
+        let recently_attempted =
+            |period: Duration| cache.attempted.is_some_and(|attempted| attempted.elapsed() < period);
+
+        // with cached keys the attempts are rate limited, also when the fetch fails
+        if !cache.keys.is_empty() && recently_attempted(Self::MIN_REFRESH) {
+            return Ok(false);
+        }
+        // without keys the failure is cached for a short time not to flood the issuer
+        if let Some(failure) = cache.failure.as_ref().filter(|_| cache.keys.is_empty()) {
+            if recently_attempted(Self::FAILURE_BACKOFF) {
+                return Err(JwtError::JwksUnavailable(failure.clone()));
+            }
+        }
+        let max_age = if force {
+            Self::MIN_REFRESH
+        } else {
+            self.config.refresh_interval.as_duration()
+        };
+        Ok(!cache.updated.is_some_and(|updated| updated.elapsed() < max_age))
+    }
+
+    /// Map the JWKS into the signing keys, the algorithm of a key is taken from the JWK or from the configuration.
+    fn parse_keys(&self, jwks: &JwkSet) -> HashMap<String, JwksKey> {
+        jwks.keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                match self.parse_key(jwk) {
+                    Ok(key) => Some((kid, key)),
+                    Err(err) => {
+                        log::warn!("Skipping unsupported JWK {kid}: {err}");
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn parse_key(&self, jwk: &Jwk) -> Result<JwksKey, String> {
+        let algorithms = match &jwk.common.key_algorithm {
+            Some(algorithm) => vec![Algorithm::from_str(&algorithm.to_string()).map_err(|err| err.to_string())?],
+            None => self.config.algorithms.clone(),
+        };
+        if algorithms.is_empty() {
+            return Err("no algorithm for the key".to_string());
+        }
+        let key = DecodingKey::from_jwk(jwk).map_err(|err| err.to_string())?;
+        Ok(JwksKey { key, algorithms })
+    }
+
+    async fn refresh(&self, force: bool) -> Result<(), JwtError> {
+        if !self.needs_refresh(&*self.cache.read().await, force)? {
+            return Ok(());
+        }
+
+        // single flight, the keys are still readable while fetching
+        let _guard = self.refresh_lock.lock().await;
+        // another request may have refreshed the keys (or failed) while waiting for the lock
+        if !self.needs_refresh(&*self.cache.read().await, force)? {
+            return Ok(());
+        }
+
+        log::info!("Fetching JWKS from {}", self.config.jwks_url);
+        let result = self.fetch().await;
+
+        let mut cache = self.cache.write().await;
+        cache.attempted = Some(Instant::now());
+        match result {
+            Ok(jwks) => {
+                cache.keys = self.parse_keys(&jwks);
+                cache.updated = Some(Instant::now());
+                cache.failure = None;
+                Ok(())
+            }
+            Err(err) => {
+                cache.failure = Some(err.to_string());
+                if cache.keys.is_empty() {
+                    Err(err)
+                } else {
+                    log::warn!("Failed to refresh JWKS, using the cached keys: {err}");
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    async fn fetch(&self) -> Result<JwkSet, JwtError> {
+        Ok(self
+            .client
+            .get(&self.config.jwks_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn find_key(&self, kid: &str) -> Option<JwksKey> {
+        let cache = self.cache.read().await;
+        cache.keys.get(kid).cloned()
+    }
+
+    /// Validate the token and return the claims.
+    pub async fn validate<C: DeserializeOwned>(&self, token: &str) -> Result<C, JwtError> {
+        let header = decode_header(token)?;
+        let kid = header.kid.ok_or(JwtError::MissingKeyId)?;
+
+        self.refresh(false).await?;
+        let key = match self.find_key(&kid).await {
+            Some(key) => key,
+            None => {
+                self.refresh(true).await?;
+                self.find_key(&kid).await.ok_or(JwtError::UnknownKey(kid))?
+            }
+        };
+        // the algorithm of the header is not trusted, it has to be one of the key
+        if !key.algorithms.contains(&header.alg) {
+            return Err(JwtError::AlgorithmMismatch(header.alg));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&self.config.audience);
+        validation.leeway = self.config.clock_skew.as_duration().as_secs();
+
+        let data = decode::<C>(token, &key.key, &validation)?;
+        Ok(data.claims)
+    }
+}
+
+/// Extractor for the validated claims of the `Authorization: Bearer` token.
+pub struct JwtBearer<C>(pub C);
+
+#[async_trait]
+impl<S, C> FromRequestParts<S> for JwtBearer<C>
+where
+    S: Send + Sync,
+    C: DeserializeOwned + Send,
+{
+    type Rejection = ConfiguredProblem<JwtError>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(validator) = parts
+            .extract::<Extension<Arc<JwtValidator>>>()
+            .await
+            .expect("Missing JwtValidator extension");
+
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| problem_config.configure(JwtError::MissingToken))?;
+
+        let claims = validator
+            .validate::<C>(token)
+            .await
+            .map_err(|err| problem_config.configure(err))?;
+        Ok(Self(claims))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    fn validator(algorithms: Vec<Algorithm>) -> JwtValidator {
+        JwtValidator::new(JwtConfig {
+            issuer: "https://issuer.example.com".into(),
+            jwks_url: "https://issuer.example.com/jwks".into(),
+            audience: vec!["api".into()],
+            clock_skew: default_clock_skew(),
+            refresh_interval: default_refresh_interval(),
+            fetch_timeout: default_fetch_timeout(),
+            algorithms,
+        })
+    }
+
+    #[test]
+    fn key_algorithms() {
+        let jwks: JwkSet = serde_json::from_value(serde_json::json!({
+            "keys": [
+                { "kty": "oct", "kid": "with-alg", "alg": "HS384", "k": "c2VjcmV0" },
+                { "kty": "oct", "kid": "without-alg", "k": "c2VjcmV0" }
+            ]
+        }))
+        .unwrap();
+
+        let keys = validator(vec![]).parse_keys(&jwks);
+        assert_eq!(keys["with-alg"].algorithms, vec![Algorithm::HS384]);
+        assert!(!keys.contains_key("without-alg"));
+
+        let keys = validator(vec![Algorithm::HS256]).parse_keys(&jwks);
+        assert_eq!(keys["with-alg"].algorithms, vec![Algorithm::HS384]);
+        assert_eq!(keys["without-alg"].algorithms, vec![Algorithm::HS256]);
+
+        let config: JwtConfig = serde_json::from_value(serde_json::json!({
+            "issuer": "https://issuer.example.com",
+            "jwksUrl": "https://issuer.example.com/jwks",
+            "audience": ["api"]
+        }))
+        .unwrap();
+        assert_eq!(config.algorithms, vec![Algorithm::RS256]);
+    }
+
+    #[test]
+    fn failure_backoff() {
+        let validator = validator(default_algorithms());
+        let mut cache = JwksCache {
+            keys: HashMap::new(),
+            updated: None,
+            attempted: None,
+            failure: None,
+        };
+        assert!(validator.needs_refresh(&cache, false).unwrap());
+
+        // without keys the failure is reported until the backoff expires
+        cache.attempted = Some(Instant::now());
+        cache.failure = Some("connection refused".into());
+        assert!(matches!(
+            validator.needs_refresh(&cache, true),
+            Err(JwtError::JwksUnavailable(_))
+        ));
+        cache.attempted = Instant::now().checked_sub(JwtValidator::FAILURE_BACKOFF * 2);
+        assert!(validator.needs_refresh(&cache, true).unwrap());
+
+        // with keys the cached keys are used
+        let jwk: Jwk =
+            serde_json::from_value(serde_json::json!({ "kty": "oct", "kid": "k", "k": "c2VjcmV0" })).unwrap();
+        cache.keys.insert("k".into(), validator.parse_key(&jwk).unwrap());
+        cache.attempted = Some(Instant::now());
+        assert!(!validator.needs_refresh(&cache, true).unwrap());
+    }
+}
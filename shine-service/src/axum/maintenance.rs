@@ -0,0 +1,160 @@
+use crate::{axum::Problem, utils::DurationStr};
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::{
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower::{Layer, Service};
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
+
+/// The active maintenance window.
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceState {
+    pub message: String,
+    pub since: DateTime<Utc>,
+    pub retry_after: DurationStr,
+}
+
+/// Reject the requests with 503 while the service is in maintenance, except for the operational endpoints
+/// (`/admin`, `/health` and `/metrics` by default). The state is local to the replica.
+#[derive(Clone)]
+pub struct MaintenanceMode {
+    state: Arc<RwLock<Option<MaintenanceState>>>,
+    allowed_prefixes: Arc<Vec<String>>,
+}
+
+impl Default for MaintenanceMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MaintenanceMode {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(RwLock::new(None)),
+            allowed_prefixes: Arc::new(vec!["/admin".into(), "/health".into(), "/metrics".into()]),
+        }
+    }
+
+    /// Path prefixes served during the maintenance.
+    #[must_use]
+    pub fn with_allowed_prefixes<I: IntoIterator<Item = P>, P: ToString>(self, prefixes: I) -> Self {
+        Self {
+            allowed_prefixes: Arc::new(prefixes.into_iter().map(|prefix| prefix.to_string()).collect()),
+            ..self
+        }
+    }
+
+    pub fn state(&self) -> Option<MaintenanceState> {
+        self.state.read().unwrap().clone()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state.read().unwrap().is_some()
+    }
+
+    pub fn enable(&self, message: &str, retry_after: Duration) {
+        log::warn!("Maintenance mode enabled: {message}");
+        *self.state.write().unwrap() = Some(MaintenanceState {
+            message: message.to_string(),
+            since: Utc::now(),
+            retry_after: retry_after.into(),
+        });
+    }
+
+    pub fn disable(&self) {
+        log::warn!("Maintenance mode disabled");
+        *self.state.write().unwrap() = None;
+    }
+
+    fn is_allowed(&self, path: &str) -> bool {
+        self.allowed_prefixes.iter().any(|prefix| {
+            path.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+impl<S> Layer<S> for MaintenanceMode {
+    type Service = MaintenanceMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MaintenanceMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct MaintenanceMiddleware<S> {
+    inner: S,
+    layer: MaintenanceMode,
+}
+
+impl<S> Service<Request<Body>> for MaintenanceMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let state = match self.layer.state() {
+            Some(state) if !self.layer.is_allowed(request.uri().path()) => state,
+            _ => return Box::pin(self.inner.call(request)),
+        };
+
+        Box::pin(async move {
+            let mut response = Problem::new(StatusCode::SERVICE_UNAVAILABLE, "maintenance")
+                .with_detail(state.message)
+                .into_response();
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(state.retry_after.as_duration().as_secs()),
+            );
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn allowed_paths() {
+        let mode = MaintenanceMode::new();
+        assert!(mode.is_allowed("/admin"));
+        assert!(mode.is_allowed("/admin/maintenance"));
+        assert!(mode.is_allowed("/health/ready"));
+        assert!(!mode.is_allowed("/administrator"));
+        assert!(!mode.is_allowed("/api/users"));
+
+        assert!(!mode.is_active());
+        mode.enable("Database upgrade", Duration::from_secs(60));
+        assert_eq!(mode.state().unwrap().retry_after, DurationStr::from_secs(60));
+        mode.disable();
+        assert!(mode.state().is_none());
+    }
+}
@@ -3,14 +3,79 @@ pub use self::powered_by::*;
 pub mod site_info;
 pub use self::site_info::*;
 
+pub mod compat;
+
 mod page;
 pub use self::page::*;
+#[cfg(feature = "html_template")]
+mod html_template;
+#[cfg(feature = "html_template")]
+pub use self::html_template::*;
 mod problem_detail;
 pub use self::problem_detail::*;
+mod problem_fallback;
+pub use self::problem_fallback::*;
+mod service_error;
+pub use self::service_error::*;
+mod multi_status;
+pub use self::multi_status::*;
+mod dev_error_page;
+pub use self::dev_error_page::*;
 mod validated;
 pub use self::validated::*;
+mod cors;
+pub use self::cors::*;
+mod body_limit;
+pub use self::body_limit::*;
+mod file_upload;
+pub use self::file_upload::*;
+mod compression;
+pub use self::compression::*;
+mod single_flight;
+pub use self::single_flight::*;
+#[cfg(feature = "jwt")]
+mod jwt_bearer;
+#[cfg(feature = "jwt")]
+pub use self::jwt_bearer::*;
+mod safe_redirect;
+pub use self::safe_redirect::*;
+mod shutdown;
+pub use self::shutdown::*;
+mod access_log;
+pub use self::access_log::*;
+mod maintenance;
+pub use self::maintenance::*;
+mod required_layers;
+pub use self::required_layers::*;
+mod inject;
+pub use self::inject::*;
+#[cfg(feature = "redis")]
+mod websocket;
+#[cfg(feature = "redis")]
+pub use self::websocket::*;
 
+#[cfg(feature = "openapi")]
 mod openapi;
+#[cfg(feature = "openapi")]
 pub use self::openapi::*;
+#[cfg(feature = "openapi")]
+mod openapi_audience;
+#[cfg(feature = "openapi")]
+pub use self::openapi_audience::*;
+#[cfg(feature = "openapi")]
+mod openapi_validation;
+#[cfg(feature = "openapi")]
+pub use self::openapi_validation::*;
+#[cfg(feature = "openapi")]
+mod permission_matrix;
+#[cfg(feature = "openapi")]
+pub use self::permission_matrix::*;
+#[cfg(feature = "openapi")]
+mod api_examples;
+#[cfg(feature = "openapi")]
+pub use self::api_examples::*;
 
 pub mod telemetry;
+
+/// Re-exported for the code generated by the `IntoProblem` derive macro, thus the users need no direct axum dependency.
+pub use axum::http::StatusCode;
@@ -0,0 +1,133 @@
+use crate::{axum::Problem, utils::serde_status_code};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
+
+/// Result of a single item of a batch request.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct MultiStatusItem<T> {
+    #[serde(serialize_with = "serde_status_code::serialize")]
+    #[cfg_attr(feature = "openapi", schema(value_type = u16))]
+    pub status: StatusCode,
+    /// The result of the item, present only on success.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<T>,
+    /// The problem details of the item, present only on failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub problem: Option<Problem>,
+}
+
+impl<T> MultiStatusItem<T> {
+    pub fn success(status: StatusCode, value: T) -> Self {
+        Self {
+            status,
+            value: Some(value),
+            problem: None,
+        }
+    }
+
+    pub fn failure(problem: Problem) -> Self {
+        Self {
+            status: problem.status(),
+            value: None,
+            problem: Some(problem),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+/// Response of the batch endpoints with a result for each item in the order of the request.
+/// If any of the items failed, the response is sent with the `207 Multi-Status` status code, otherwise
+/// with `200 OK`.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct MultiStatus<T> {
+    pub items: Vec<MultiStatusItem<T>>,
+}
+
+impl<T> MultiStatus<T> {
+    /// Create the response from the item results, the successful items get the given status code.
+    pub fn from_results_with_status(results: Vec<Result<T, Problem>>, success_status: StatusCode) -> Self {
+        let items = results
+            .into_iter()
+            .map(|result| match result {
+                Ok(value) => MultiStatusItem::success(success_status, value),
+                Err(problem) => MultiStatusItem::failure(problem),
+            })
+            .collect();
+        Self { items }
+    }
+
+    pub fn from_results(results: Vec<Result<T, Problem>>) -> Self {
+        Self::from_results_with_status(results, StatusCode::OK)
+    }
+
+    pub fn has_failure(&self) -> bool {
+        self.items.iter().any(|item| !item.is_success())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        if self.has_failure() {
+            StatusCode::MULTI_STATUS
+        } else {
+            StatusCode::OK
+        }
+    }
+}
+
+impl<T> From<Vec<Result<T, Problem>>> for MultiStatus<T> {
+    fn from(results: Vec<Result<T, Problem>>) -> Self {
+        Self::from_results(results)
+    }
+}
+
+impl<T> FromIterator<Result<T, Problem>> for MultiStatus<T> {
+    fn from_iter<I: IntoIterator<Item = Result<T, Problem>>>(iter: I) -> Self {
+        Self::from_results(iter.into_iter().collect())
+    }
+}
+
+impl<T> IntoResponse for MultiStatus<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        (self.status(), Json(self)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn build_multi_status() {
+        let response: MultiStatus<u32> = vec![Ok(1), Err(Problem::not_found()), Ok(3)].into();
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        assert_eq!(response.items[1].status, StatusCode::NOT_FOUND);
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["items"][0]["status"], 200);
+        assert_eq!(json["items"][0]["value"], 1);
+        assert!(json["items"][0].get("problem").is_none());
+        assert_eq!(json["items"][1]["status"], 404);
+        assert!(json["items"][1].get("value").is_none());
+
+        let response: MultiStatus<u32> = MultiStatus::from_results_with_status(vec![Ok(1)], StatusCode::CREATED);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.items[0].status, StatusCode::CREATED);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::axum::{
+    ApiAudience, ApiRoutes, EndpointPermissions, LayerRequirement, RequiredLayers, AUDIENCE_EXTENSION,
+    PERMISSIONS_EXTENSION,
+};
 use axum::{
     handler::Handler,
     http::StatusCode,
@@ -10,10 +14,11 @@ use std::ops::{Deref, DerefMut};
 use url::Url;
 use utoipa::{
     openapi::{
+        header::{Header, HeaderBuilder},
         path::{OperationBuilder, Parameter, ParameterIn, PathItemBuilder},
         request_body::RequestBodyBuilder,
-        ComponentsBuilder, Content, ContentBuilder, HttpMethod, OpenApi, OpenApiBuilder, PathsBuilder, Ref, Response,
-        ResponseBuilder,
+        ComponentsBuilder, Content, ContentBuilder, HttpMethod, OpenApi, OpenApiBuilder, PathsBuilder, Ref, RefOr,
+        Response, ResponseBuilder,
     },
     IntoParams, PartialSchema, ToResponse, ToSchema,
 };
@@ -93,7 +98,7 @@ impl ApiPath for String {
     }
 }
 
-fn to_swagger(path: &str) -> String {
+pub(crate) fn to_swagger(path: &str) -> String {
     let re = Regex::new(r":(\w+)").unwrap();
     re.replace_all(path, "{${1}}").to_string()
 }
@@ -103,6 +108,10 @@ pub struct ApiEndpoint<S = ()> {
     path: String,
     pub operation: OperationBuilder,
     pub components: ComponentsBuilder,
+    response_headers: Vec<(Option<StatusCode>, String, Header)>,
+    permissions: EndpointPermissions,
+    audience: Option<ApiAudience>,
+    requirements: Vec<LayerRequirement>,
     router: MethodRouter<S>,
 }
 
@@ -115,7 +124,7 @@ where
         P: ApiPath,
         H: Handler<T, S>,
         T: 'static,
-        S: Clone + Send + Sync + 'static,        
+        S: Clone + Send + Sync + 'static,
     {
         let path = path.path();
 
@@ -131,6 +140,10 @@ where
             path,
             operation: OperationBuilder::new(),
             components: ComponentsBuilder::new(),
+            response_headers: Vec::new(),
+            permissions: EndpointPermissions::default(),
+            audience: None,
+            requirements: Vec::new(),
             router,
         }
     }
@@ -248,10 +261,142 @@ where
         self
     }
 
-    fn register(self, router: Router<S>, doc: Option<&mut OpenApi>) -> Router<S> {
+    fn header<T: PartialSchema, D: ToString>(description: D) -> Header {
+        HeaderBuilder::new()
+            .schema(T::schema())
+            .description(Some(description.to_string()))
+            .build()
+    }
+
+    /// Document a header of the successful (2xx) responses, ex: `ETag`, `Location`.
+    #[must_use]
+    pub fn with_response_header<T, D>(mut self, name: &str, description: D) -> Self
+    where
+        T: PartialSchema,
+        D: ToString,
+    {
+        let header = Self::header::<T, D>(description);
+        self.response_headers.push((None, name.to_string(), header));
+        self
+    }
+
+    /// Document a header of the response with the given status code, ex: `Retry-After` for 503.
+    /// The status response have to be an inline response (ex. `with_status_response`), the headers
+    /// cannot be added to the shared (referenced) responses.
+    #[must_use]
+    pub fn with_status_response_header<T, D>(mut self, code: StatusCode, name: &str, description: D) -> Self
+    where
+        T: PartialSchema,
+        D: ToString,
+    {
+        let header = Self::header::<T, D>(description);
+        self.response_headers.push((Some(code), name.to_string(), header));
+        self
+    }
+
+    /// Declare the endpoint to be accessible without authentication in the permission matrix.
+    #[must_use]
+    pub fn with_public_access(mut self) -> Self {
+        self.permissions.public = true;
+        self
+    }
+
+    /// Declare the roles required by the endpoint in the permission matrix.
+    #[must_use]
+    pub fn with_required_roles<I: IntoIterator<Item = R>, R: ToString>(mut self, roles: I) -> Self {
+        self.permissions
+            .roles
+            .extend(roles.into_iter().map(|role| role.to_string()));
+        self
+    }
+
+    /// Declare the token scopes required by the endpoint in the permission matrix.
+    #[must_use]
+    pub fn with_required_scopes<I: IntoIterator<Item = R>, R: ToString>(mut self, scopes: I) -> Self {
+        self.permissions
+            .scopes
+            .extend(scopes.into_iter().map(|scope| scope.to_string()));
+        self
+    }
+
+    /// Declare a (custom) policy enforced by the endpoint in the permission matrix.
+    #[must_use]
+    pub fn with_policy<P: ToString>(mut self, policy: P) -> Self {
+        self.permissions.policies.push(policy.to_string());
+        self
+    }
+
+    /// Declare the consumers of the endpoint, see `OpenApiAudiences`.
+    #[must_use]
+    pub fn with_audience(mut self, audience: ApiAudience) -> Self {
+        self.audience = Some(audience);
+        self
+    }
+
+    /// Declare an extension required by the extractors of the handler, ex. `requires::<ProblemConfig>()`.
+    /// The requirements are registered by `ApiRoute::add_required_api` and checked against the provided layers
+    /// when the router is finalized, see `RequiredLayers`.
+    #[must_use]
+    pub fn requires<T: 'static>(mut self) -> Self {
+        self.requirements.push(LayerRequirement::of::<T>());
+        self
+    }
+
+    fn register(
+        self,
+        router: Router<S>,
+        doc: Option<&mut OpenApi>,
+        layers: Option<&RequiredLayers>,
+        routes: Option<&ApiRoutes>,
+    ) -> Router<S> {
+        if let Some(routes) = routes {
+            routes.add(self.method, &self.path);
+        }
+
+        if let Some(layers) = layers {
+            let method = format!("{:?}", self.method).to_uppercase();
+            let endpoint = format!("{method} {}", self.path);
+            for requirement in &self.requirements {
+                layers.require_requirement(&endpoint, *requirement);
+            }
+        }
+
         if let Some(doc) = doc {
             let components = self.components.build();
-            let operation = self.operation.build();
+            let mut operation = self.operation.build();
+            if !self.permissions.is_empty() {
+                let permissions = serde_json::to_value(&self.permissions).unwrap();
+                operation
+                    .extensions
+                    .get_or_insert_with(Default::default)
+                    .insert(PERMISSIONS_EXTENSION.to_string(), permissions);
+            }
+            if let Some(audience) = self.audience {
+                operation
+                    .extensions
+                    .get_or_insert_with(Default::default)
+                    .insert(AUDIENCE_EXTENSION.to_string(), serde_json::to_value(audience).unwrap());
+            }
+            for (code, name, header) in self.response_headers {
+                for (status, response) in operation.responses.responses.iter_mut() {
+                    let is_match = match code {
+                        Some(code) => status == code.as_str(),
+                        None => status.starts_with('2'),
+                    };
+                    match response {
+                        RefOr::T(response) if is_match => {
+                            response.headers.insert(name.clone(), header.clone());
+                        }
+                        RefOr::Ref(_) if is_match => {
+                            log::warn!(
+                                "Cannot add header {name} to the shared response {status} of {}",
+                                self.path
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+            }
             let method = self.method.into();
 
             let components_doc = OpenApiBuilder::new().components(Some(components)).build();
@@ -277,6 +422,12 @@ where
 {
     fn add_opt_api(self, endpoint: ApiEndpoint<S>, doc: Option<&mut OpenApi>) -> Self;
 
+    /// Add the endpoint and register its requirements in the registry of the router, see `RequiredLayers`.
+    fn add_required_api(self, endpoint: ApiEndpoint<S>, doc: Option<&mut OpenApi>, layers: &RequiredLayers) -> Self;
+
+    /// Add the endpoint and collect its route for the undocumented route detection, see `OpenApiValidator`.
+    fn add_tracked_api(self, endpoint: ApiEndpoint<S>, doc: Option<&mut OpenApi>, routes: &ApiRoutes) -> Self;
+
     fn add_api(self, endpoint: ApiEndpoint<S>, doc: &mut OpenApi) -> Self
     where
         Self: Sized,
@@ -290,6 +441,62 @@ where
     S: Clone + Send + Sync + 'static,
 {
     fn add_opt_api(self, endpoint: ApiEndpoint<S>, doc: Option<&mut OpenApi>) -> Self {
-        endpoint.register(self, doc)
+        endpoint.register(self, doc, None, None)
+    }
+
+    fn add_required_api(self, endpoint: ApiEndpoint<S>, doc: Option<&mut OpenApi>, layers: &RequiredLayers) -> Self {
+        endpoint.register(self, doc, Some(layers), None)
+    }
+
+    fn add_tracked_api(self, endpoint: ApiEndpoint<S>, doc: Option<&mut OpenApi>, routes: &ApiRoutes) -> Self {
+        endpoint.register(self, doc, None, Some(routes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, http::header, response::IntoResponse};
+    use shine_test::test;
+    use tower::ServiceExt;
+
+    async fn create_item() -> impl IntoResponse {
+        (
+            StatusCode::CREATED,
+            [(header::LOCATION, "/items/1"), (header::ETAG, "\"1\"")],
+        )
+    }
+
+    #[test]
+    async fn documented_response_headers() {
+        let mut doc = OpenApiBuilder::new().build();
+        let router: Router = Router::new().add_api(
+            ApiEndpoint::new(ApiMethod::Post, "/items".to_string(), create_item)
+                .with_status_response(StatusCode::CREATED, "Item created")
+                .with_status_response(StatusCode::CONFLICT, "Item exists")
+                .with_response_header::<String, _>("Location", "Url of the item")
+                .with_response_header::<String, _>("ETag", "Version of the item"),
+            &mut doc,
+        );
+
+        let operation = doc.paths.paths["/items"].post.as_ref().unwrap();
+        let documented = |status: &str| match &operation.responses.responses[status] {
+            RefOr::T(response) => response.headers.keys().cloned().collect::<Vec<_>>(),
+            RefOr::Ref(_) => unreachable!(),
+        };
+        assert!(documented("409").is_empty());
+
+        // contract check: the documented headers of the status are emitted
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri("/items")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let headers = documented(response.status().as_str());
+        assert_eq!(headers.len(), 2);
+        for name in headers {
+            assert!(response.headers().contains_key(name.as_str()), "Missing header {name}");
+        }
     }
 }
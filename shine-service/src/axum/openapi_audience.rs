@@ -0,0 +1,253 @@
+use crate::axum::PERMISSIONS_EXTENSION;
+use axum::{routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, sync::Arc};
+use utoipa::openapi::{
+    path::{Operation, PathItem},
+    security::{SecurityRequirement, SecurityScheme},
+    OpenApi,
+};
+
+/// Name of the OpenApi operation extension holding the audience of an endpoint.
+pub const AUDIENCE_EXTENSION: &str = "x-audience";
+
+/// The consumers of an endpoint, a separate OpenApi document is generated for each audience.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ApiAudience {
+    Public,
+    Internal,
+    Admin,
+}
+
+impl ApiAudience {
+    pub const ALL: [ApiAudience; 3] = [ApiAudience::Public, ApiAudience::Internal, ApiAudience::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiAudience::Public => "public",
+            ApiAudience::Internal => "internal",
+            ApiAudience::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for ApiAudience {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+struct AudienceDocument {
+    path: String,
+    security: Option<(String, SecurityScheme)>,
+}
+
+/// Split the OpenApi document of the service into a document per audience. The audience of an endpoint is
+/// the one declared with `ApiEndpoint::with_audience`, then the audience mapped to one of its tags and the
+/// public audience otherwise. The shared components are kept in all the documents. The audience and the
+/// permissions of the endpoints are published only in the internal document, the roles and the policies are
+/// not exposed to the other consumers.
+///
+/// ```ignore
+/// let audiences = OpenApiAudiences::new()
+///     .with_tag("admin", ApiAudience::Admin)
+///     .with_security_scheme(ApiAudience::Internal, "serviceToken", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+/// let router = router.merge(audiences.into_router(&doc));
+/// ```
+pub struct OpenApiAudiences {
+    tags: HashMap<String, ApiAudience>,
+    documents: HashMap<ApiAudience, AudienceDocument>,
+}
+
+impl Default for OpenApiAudiences {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenApiAudiences {
+    pub fn new() -> Self {
+        let documents = ApiAudience::ALL
+            .into_iter()
+            .map(|audience| {
+                let document = AudienceDocument {
+                    path: format!("/doc/{audience}/openapi.json"),
+                    security: None,
+                };
+                (audience, document)
+            })
+            .collect();
+        Self {
+            tags: HashMap::new(),
+            documents,
+        }
+    }
+
+    /// Assign the endpoints of a tag without an explicit audience to an audience.
+    #[must_use]
+    pub fn with_tag<T: ToString>(mut self, tag: T, audience: ApiAudience) -> Self {
+        self.tags.insert(tag.to_string(), audience);
+        self
+    }
+
+    /// Path of the document of an audience, by default `/doc/{audience}/openapi.json`.
+    #[must_use]
+    pub fn with_path<P: ToString>(mut self, audience: ApiAudience, path: P) -> Self {
+        if let Some(document) = self.documents.get_mut(&audience) {
+            document.path = path.to_string();
+        }
+        self
+    }
+
+    /// Security scheme required by all the operations of an audience.
+    #[must_use]
+    pub fn with_security_scheme<N: ToString>(mut self, audience: ApiAudience, name: N, scheme: SecurityScheme) -> Self {
+        if let Some(document) = self.documents.get_mut(&audience) {
+            document.security = Some((name.to_string(), scheme));
+        }
+        self
+    }
+
+    fn audience_of(&self, operation: &Operation) -> ApiAudience {
+        let declared = operation
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions.get(AUDIENCE_EXTENSION))
+            .and_then(|value| serde_json::from_value::<ApiAudience>(value.clone()).ok());
+        let tagged = || {
+            operation
+                .tags
+                .iter()
+                .flatten()
+                .find_map(|tag| self.tags.get(tag).copied())
+        };
+        declared.or_else(tagged).unwrap_or(ApiAudience::Public)
+    }
+
+    fn retain_operations(&self, item: &mut PathItem, audience: ApiAudience) -> bool {
+        let operations = [
+            &mut item.get,
+            &mut item.put,
+            &mut item.post,
+            &mut item.delete,
+            &mut item.options,
+            &mut item.head,
+            &mut item.patch,
+            &mut item.trace,
+        ];
+        let mut any = false;
+        for operation in operations {
+            if operation.as_ref().is_some_and(|op| self.audience_of(op) != audience) {
+                *operation = None;
+            }
+            if let Some(operation) = operation {
+                if audience != ApiAudience::Internal {
+                    Self::strip_extensions(operation);
+                }
+                any = true;
+            }
+        }
+        any
+    }
+
+    fn strip_extensions(operation: &mut Operation) {
+        if let Some(extensions) = &mut operation.extensions {
+            extensions.remove(AUDIENCE_EXTENSION);
+            extensions.remove(PERMISSIONS_EXTENSION);
+        }
+    }
+
+    /// Create the document of an audience from the document of the service.
+    pub fn document(&self, doc: &OpenApi, audience: ApiAudience) -> OpenApi {
+        let mut doc = doc.clone();
+        doc.paths.paths.retain(|_, item| self.retain_operations(item, audience));
+
+        if let Some((name, scheme)) = self.documents.get(&audience).and_then(|d| d.security.clone()) {
+            doc.components
+                .get_or_insert_with(Default::default)
+                .add_security_scheme(name.clone(), scheme);
+            doc.security = Some(vec![SecurityRequirement::new::<_, _, String>(name, [])]);
+        }
+        doc
+    }
+
+    /// Create the routes serving the document of each audience.
+    pub fn into_router<S>(self, doc: &OpenApi) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let mut router = Router::new();
+        for audience in ApiAudience::ALL {
+            let document = Arc::new(self.document(doc, audience));
+            let path = &self.documents[&audience].path;
+            router = router.route(
+                path,
+                get(move || {
+                    let document = document.clone();
+                    async move { Json(document.as_ref().clone()) }
+                }),
+            );
+        }
+        router
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::axum::{ApiEndpoint, ApiMethod, ApiRoute};
+    use shine_test::test;
+    use utoipa::openapi::{
+        security::{ApiKey, ApiKeyValue},
+        OpenApiBuilder,
+    };
+
+    #[test]
+    fn split_by_audience() {
+        let mut doc = OpenApiBuilder::new().build();
+        let _router: Router = Router::new()
+            .add_api(
+                ApiEndpoint::new(ApiMethod::Get, "/users/:id".to_string(), || async {})
+                    .with_audience(ApiAudience::Public)
+                    .with_required_roles(["user"]),
+                &mut doc,
+            )
+            .add_api(
+                ApiEndpoint::new(ApiMethod::Delete, "/users/:id".to_string(), || async {})
+                    .with_audience(ApiAudience::Internal)
+                    .with_required_roles(["admin"]),
+                &mut doc,
+            )
+            .add_api(
+                ApiEndpoint::new(ApiMethod::Get, "/admin/status".to_string(), || async {}).with_tag("admin"),
+                &mut doc,
+            );
+
+        let audiences = OpenApiAudiences::new()
+            .with_tag("admin", ApiAudience::Admin)
+            .with_security_scheme(
+                ApiAudience::Admin,
+                "adminKey",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-admin-api-key"))),
+            );
+
+        let public = audiences.document(&doc, ApiAudience::Public);
+        assert_eq!(public.paths.paths.len(), 1);
+        let users = &public.paths.paths["/users/{id}"];
+        assert!(users.get.is_some() && users.delete.is_none());
+        assert!(users.get.as_ref().unwrap().extensions.as_ref().unwrap().is_empty());
+        assert!(public.security.is_none());
+
+        let internal = audiences.document(&doc, ApiAudience::Internal);
+        let users = &internal.paths.paths["/users/{id}"];
+        assert!(users.get.is_none() && users.delete.is_some());
+        let extensions = users.delete.as_ref().unwrap().extensions.as_ref().unwrap();
+        assert!(extensions.contains_key(AUDIENCE_EXTENSION) && extensions.contains_key(PERMISSIONS_EXTENSION));
+
+        let admin = audiences.document(&doc, ApiAudience::Admin);
+        assert_eq!(admin.paths.paths.keys().collect::<Vec<_>>(), vec!["/admin/status"]);
+        assert!(admin.security.is_some());
+        assert!(admin.components.unwrap().security_schemes.contains_key("adminKey"));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::axum::{openapi::to_swagger, ApiMethod};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+use thiserror::Error as ThisError;
+use utoipa::openapi::{
+    path::{Operation, ParameterIn, PathItem},
+    OpenApi,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ValidationSeverity {
+    Ignore,
+    Warn,
+    #[default]
+    Error,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiValidationConfig {
+    #[serde(default)]
+    pub duplicate_operation_id: ValidationSeverity,
+    #[serde(default)]
+    pub path_parameter_mismatch: ValidationSeverity,
+    #[serde(default)]
+    pub conflicting_path: ValidationSeverity,
+    #[serde(default)]
+    pub undocumented_route: ValidationSeverity,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OpenApiIssue {
+    DuplicateOperationId {
+        operation_id: String,
+        paths: Vec<String>,
+    },
+    PathParameterMismatch {
+        path: String,
+        in_path: Vec<String>,
+        documented: Vec<String>,
+    },
+    ConflictingPath {
+        paths: Vec<String>,
+    },
+    UndocumentedRoute {
+        method: ApiMethod,
+        path: String,
+    },
+}
+
+#[derive(Debug, ThisError)]
+#[error("OpenApi validation failed with {} issue(s): {:?}", .0.len(), .0)]
+pub struct OpenApiValidationError(pub Vec<OpenApiIssue>);
+
+fn operations(item: &PathItem) -> impl Iterator<Item = (ApiMethod, &Operation)> {
+    [
+        (ApiMethod::Get, item.get.as_ref()),
+        (ApiMethod::Post, item.post.as_ref()),
+        (ApiMethod::Put, item.put.as_ref()),
+        (ApiMethod::Delete, item.delete.as_ref()),
+    ]
+    .into_iter()
+    .filter_map(|(method, operation)| operation.map(|operation| (method, operation)))
+}
+
+/// Routes served by a router. The endpoints added with `ApiRoute::add_tracked_api` are collected
+/// regardless of the document they are added to, thus the endpoints added without a document or to an other
+/// document are detected as undocumented. The clones share the routes.
+#[derive(Clone, Default)]
+pub struct ApiRoutes {
+    routes: Arc<Mutex<Vec<(ApiMethod, String)>>>,
+}
+
+impl ApiRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a route served by the router, ex: `/users/:id`.
+    pub fn add(&self, method: ApiMethod, path: &str) -> &Self {
+        self.routes.lock().unwrap().push((method, to_swagger(path)));
+        self
+    }
+
+    pub fn routes(&self) -> Vec<(ApiMethod, String)> {
+        self.routes.lock().unwrap().clone()
+    }
+}
+
+/// Validate the assembled OpenApi document at startup to catch the broken docs before shipping them.
+/// The routes are collected by the `ApiRoutes` of the router, as axum routers cannot be inspected the routes
+/// registered without an `ApiEndpoint` should be listed with `with_route`.
+pub struct OpenApiValidator {
+    config: OpenApiValidationConfig,
+    routes: ApiRoutes,
+}
+
+impl OpenApiValidator {
+    pub fn new(config: OpenApiValidationConfig) -> Self {
+        Self {
+            config,
+            routes: ApiRoutes::new(),
+        }
+    }
+
+    /// Check the routes collected while the router was built.
+    #[must_use]
+    pub fn with_routes(self, routes: ApiRoutes) -> Self {
+        Self { routes, ..self }
+    }
+
+    /// Register a route served by the router without an `ApiEndpoint`, ex: `/users/:id`.
+    #[must_use]
+    pub fn with_route(self, method: ApiMethod, path: &str) -> Self {
+        self.routes.add(method, path);
+        self
+    }
+
+    fn severity(&self, issue: &OpenApiIssue) -> ValidationSeverity {
+        match issue {
+            OpenApiIssue::DuplicateOperationId { .. } => self.config.duplicate_operation_id,
+            OpenApiIssue::PathParameterMismatch { .. } => self.config.path_parameter_mismatch,
+            OpenApiIssue::ConflictingPath { .. } => self.config.conflicting_path,
+            OpenApiIssue::UndocumentedRoute { .. } => self.config.undocumented_route,
+        }
+    }
+
+    /// Collect all the issues regardless of the severity.
+    pub fn issues(&self, doc: &OpenApi) -> Vec<OpenApiIssue> {
+        let mut issues = Vec::new();
+        let param_re = Regex::new(r"\{(\w+)\}").unwrap();
+
+        let mut operation_ids: HashMap<&str, Vec<String>> = HashMap::new();
+        let mut shapes: HashMap<String, Vec<String>> = HashMap::new();
+
+        for (path, item) in &doc.paths.paths {
+            let in_path: BTreeSet<String> = param_re.captures_iter(path).map(|c| c[1].to_string()).collect();
+            shapes
+                .entry(param_re.replace_all(path, "{}").to_string())
+                .or_default()
+                .push(path.clone());
+
+            let common = item.parameters.iter().flatten();
+            for (method, operation) in operations(item) {
+                if let Some(id) = operation.operation_id.as_deref() {
+                    operation_ids.entry(id).or_default().push(format!("{method:?} {path}"));
+                }
+
+                let documented: BTreeSet<String> = common
+                    .clone()
+                    .chain(operation.parameters.iter().flatten())
+                    .filter(|param| matches!(param.parameter_in, ParameterIn::Path))
+                    .map(|param| param.name.clone())
+                    .collect();
+                if documented != in_path {
+                    issues.push(OpenApiIssue::PathParameterMismatch {
+                        path: format!("{method:?} {path}"),
+                        in_path: in_path.iter().cloned().collect(),
+                        documented: documented.into_iter().collect(),
+                    });
+                }
+            }
+        }
+
+        let mut duplicates: Vec<_> = operation_ids
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(id, paths)| OpenApiIssue::DuplicateOperationId {
+                operation_id: id.to_string(),
+                paths,
+            })
+            .collect();
+        duplicates.sort_by_key(|issue| format!("{issue:?}"));
+        issues.extend(duplicates);
+
+        let mut conflicts: Vec<_> = shapes
+            .into_values()
+            .filter(|paths| paths.len() > 1)
+            .map(|paths| OpenApiIssue::ConflictingPath { paths })
+            .collect();
+        conflicts.sort_by_key(|issue| format!("{issue:?}"));
+        issues.extend(conflicts);
+
+        for (method, path) in self.routes.routes() {
+            let documented = doc
+                .paths
+                .paths
+                .get(&path)
+                .is_some_and(|item| operations(item).any(|(m, _)| m == method));
+            if !documented {
+                issues.push(OpenApiIssue::UndocumentedRoute { method, path });
+            }
+        }
+
+        issues
+    }
+
+    /// Validate the document, log the warnings and fail if any issue with an error severity is found.
+    pub fn validate(&self, doc: &OpenApi) -> Result<(), OpenApiValidationError> {
+        let mut errors = Vec::new();
+        for issue in self.issues(doc) {
+            match self.severity(&issue) {
+                ValidationSeverity::Ignore => {}
+                ValidationSeverity::Warn => log::warn!("OpenApi issue: {issue:?}"),
+                ValidationSeverity::Error => {
+                    log::error!("OpenApi issue: {issue:?}");
+                    errors.push(issue);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(OpenApiValidationError(errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::axum::{ApiEndpoint, ApiRoute};
+    use axum::Router;
+    use shine_test::test;
+    use utoipa::{
+        openapi::{
+            path::{OperationBuilder, ParameterBuilder, PathItemBuilder},
+            HttpMethod, OpenApiBuilder, PathsBuilder,
+        },
+        IntoParams,
+    };
+
+    #[derive(Deserialize, IntoParams)]
+    #[allow(dead_code)]
+    struct UserPath {
+        id: String,
+    }
+
+    fn operation(id: &str, params: &[&str]) -> Operation {
+        let mut builder = OperationBuilder::new().operation_id(Some(id));
+        for param in params {
+            builder = builder.parameter(ParameterBuilder::new().name(*param).parameter_in(ParameterIn::Path));
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn detect_issues() {
+        let paths = PathsBuilder::new()
+            .path(
+                "/users/{id}",
+                PathItemBuilder::new()
+                    .operation(HttpMethod::Get, operation("getUser", &["id"]))
+                    .build(),
+            )
+            .path(
+                "/users/{name}",
+                PathItemBuilder::new()
+                    .operation(HttpMethod::Delete, operation("getUser", &["id"]))
+                    .build(),
+            )
+            .build();
+        let doc = OpenApiBuilder::new().paths(paths).build();
+
+        let validator = OpenApiValidator::new(OpenApiValidationConfig::default())
+            .with_route(ApiMethod::Get, "/users/:id")
+            .with_route(ApiMethod::Post, "/users/:id");
+        let issues = validator.issues(&doc);
+
+        assert!(issues.contains(&OpenApiIssue::PathParameterMismatch {
+            path: "Delete /users/{name}".into(),
+            in_path: vec!["name".into()],
+            documented: vec!["id".into()],
+        }));
+        assert!(issues
+            .iter()
+            .any(|issue| matches!(issue, OpenApiIssue::DuplicateOperationId { operation_id, .. } if operation_id == "getUser")));
+        assert!(issues
+            .iter()
+            .any(|issue| matches!(issue, OpenApiIssue::ConflictingPath { .. })));
+        assert!(issues.contains(&OpenApiIssue::UndocumentedRoute {
+            method: ApiMethod::Post,
+            path: "/users/{id}".into(),
+        }));
+        assert_eq!(issues.len(), 4);
+        assert!(validator.validate(&doc).is_err());
+    }
+
+    #[test]
+    fn collect_routes() {
+        let routes = ApiRoutes::new();
+        let mut doc = OpenApiBuilder::new().build();
+        let _router: Router = Router::new()
+            .add_tracked_api(
+                ApiEndpoint::new(ApiMethod::Get, "/users/:id".to_string(), || async {})
+                    .with_operation_id("getUser")
+                    .with_path_parameter::<UserPath>(),
+                Some(&mut doc),
+                &routes,
+            )
+            .add_tracked_api(
+                ApiEndpoint::new(ApiMethod::Delete, "/users/:id".to_string(), || async {}),
+                None,
+                &routes,
+            );
+
+        let validator = OpenApiValidator::new(OpenApiValidationConfig::default()).with_routes(routes);
+        assert_eq!(
+            validator.issues(&doc),
+            vec![OpenApiIssue::UndocumentedRoute {
+                method: ApiMethod::Delete,
+                path: "/users/{id}".into(),
+            }]
+        );
+    }
+}
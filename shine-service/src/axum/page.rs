@@ -1,7 +1,19 @@
+use crate::{
+    axum::{ConfiguredProblem, InputError, ProblemConfig, ValidationErrorEx},
+    utils::{IdEncoder, IdEncoderError},
+};
 use axum::{
-    http::StatusCode,
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
     response::{Html, IntoResponse, Response},
+    Extension, Json, RequestPartsExt,
 };
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+#[cfg(feature = "openapi")]
+use utoipa::{IntoParams, ToSchema};
+use validator::ValidationError;
 
 pub struct Page {
     status: StatusCode,
@@ -29,3 +41,245 @@ impl IntoResponse for Page {
         (self.status, self.html).into_response()
     }
 }
+
+/// Pagination settings shared by the list endpoints. The cursors are the obfuscated sequence
+/// numbers of the items, thus the clients cannot tamper with them.
+#[derive(Clone)]
+pub struct PageConfig {
+    pub default_limit: usize,
+    pub max_limit: usize,
+    encoder: Arc<dyn IdEncoder>,
+}
+
+impl PageConfig {
+    pub fn new<E: IdEncoder>(encoder: E) -> Self {
+        Self {
+            default_limit: 20,
+            max_limit: 100,
+            encoder: Arc::new(encoder),
+        }
+    }
+
+    #[must_use]
+    pub fn with_limits(self, default_limit: usize, max_limit: usize) -> Self {
+        Self {
+            default_limit: default_limit.clamp(1, max_limit.max(1)),
+            max_limit: max_limit.max(1),
+            ..self
+        }
+    }
+
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+}
+
+/// The raw pagination query parameters.
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(IntoParams))]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct PageQuery {
+    /// Maximum number of the returned items, it is capped by the server.
+    pub limit: Option<usize>,
+    /// Opaque cursor returned as the `next` or `prev` of a page.
+    pub cursor: Option<String>,
+}
+
+/// Prefix of the cursors pointing backward, it is not part of the alphabet of the id encoders.
+const BACKWARD_CURSOR_PREFIX: char = '~';
+
+/// The decoded cursor of a page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageCursor {
+    /// The items after the id in ascending order, query them as `id > cursor ORDER BY id ASC`.
+    After(u64),
+    /// The items before the id, query them as `id < cursor ORDER BY id DESC`.
+    Before(u64),
+}
+
+/// Extractor of the pagination parameters with the limit capped and the cursor decoded.
+pub struct PageParams {
+    pub limit: usize,
+    pub cursor: Option<PageCursor>,
+    encoder: Arc<dyn IdEncoder>,
+}
+
+impl PageParams {
+    pub fn encode_cursor(&self, cursor: PageCursor) -> Result<String, IdEncoderError> {
+        match cursor {
+            PageCursor::After(id) => self.encoder.obfuscate(id),
+            PageCursor::Before(id) => Ok(format!("{BACKWARD_CURSOR_PREFIX}{}", self.encoder.obfuscate(id)?)),
+        }
+    }
+
+    fn decode_cursor(encoder: &dyn IdEncoder, cursor: &str) -> Result<PageCursor, IdEncoderError> {
+        match cursor.strip_prefix(BACKWARD_CURSOR_PREFIX) {
+            Some(cursor) => Ok(PageCursor::Before(encoder.deobfuscate(cursor)?)),
+            None => Ok(PageCursor::After(encoder.deobfuscate(cursor)?)),
+        }
+    }
+
+    /// Create the response from the items fetched with a limit of `limit + 1` in the order given by the
+    /// cursor (descending for the `Before` cursors). The presence of the extra item indicates a further page
+    /// in the direction of the query, the items of the response are always in ascending order.
+    pub fn into_response<T, F>(self, mut items: Vec<T>, cursor_of: F) -> Result<PagedResponse<T>, IdEncoderError>
+    where
+        F: Fn(&T) -> u64,
+    {
+        let has_more = items.len() > self.limit;
+        items.truncate(self.limit);
+        if matches!(self.cursor, Some(PageCursor::Before(_))) {
+            items.reverse();
+        }
+
+        let first = items.first().map(&cursor_of);
+        let last = items.last().map(&cursor_of);
+        let (next, prev) = match self.cursor {
+            None => (last.filter(|_| has_more).map(PageCursor::After), None),
+            // on an empty page the adjacent page is bounded by the cursor (the item of the cursor is included)
+            Some(PageCursor::After(id)) => (
+                last.filter(|_| has_more).map(PageCursor::After),
+                Some(PageCursor::Before(first.unwrap_or(id.saturating_add(1)))),
+            ),
+            Some(PageCursor::Before(id)) => (
+                Some(PageCursor::After(last.unwrap_or(id.saturating_sub(1)))),
+                first.filter(|_| has_more).map(PageCursor::Before),
+            ),
+        };
+
+        Ok(PagedResponse {
+            items,
+            next: next.map(|cursor| self.encode_cursor(cursor)).transpose()?,
+            prev: prev.map(|cursor| self.encode_cursor(cursor)).transpose()?,
+        })
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for PageParams
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<InputError>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(config) = parts
+            .extract::<Extension<PageConfig>>()
+            .await
+            .expect("Missing PageConfig extension");
+
+        let Query(query) = Query::<PageQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| problem_config.configure(InputError::QueryFormat(err)))?;
+
+        let limit = match query.limit {
+            Some(0) => {
+                let err = ValidationError::new("range").with_message("Limit must be positive");
+                return Err(problem_config.configure(err.into_constraint_error("limit")));
+            }
+            Some(limit) => limit.min(config.max_limit),
+            None => config.default_limit,
+        };
+        let cursor = query
+            .cursor
+            .map(|cursor| Self::decode_cursor(config.encoder.as_ref(), &cursor))
+            .transpose()
+            .map_err(|_| {
+                let err = ValidationError::new("invalid_cursor").with_message("Invalid cursor");
+                problem_config.configure(err.into_constraint_error("cursor"))
+            })?;
+
+        Ok(Self {
+            limit,
+            cursor,
+            encoder: config.encoder,
+        })
+    }
+}
+
+/// Standard envelope of the paginated list responses.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PagedResponse<T> {
+    pub items: Vec<T>,
+    /// Cursor of the next page, missing on the last page.
+    pub next: Option<String>,
+    /// Cursor of the previous page, missing on the first page.
+    pub prev: Option<String>,
+}
+
+impl<T> IntoResponse for PagedResponse<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    struct PlainEncoder;
+
+    impl IdEncoder for PlainEncoder {
+        fn obfuscate(&self, id: u64) -> Result<String, IdEncoderError> {
+            Ok(id.to_string())
+        }
+
+        fn deobfuscate(&self, id: &str) -> Result<u64, IdEncoderError> {
+            id.parse()
+                .map_err(|_| IdEncoderError::InvalidObfuscatedId(id.to_string()))
+        }
+    }
+
+    /// Query the ids 1..=10 as a store would do for the cursor.
+    fn page(cursor: Option<&str>) -> PagedResponse<u64> {
+        let encoder: Arc<dyn IdEncoder> = Arc::new(PlainEncoder);
+        let cursor = cursor.map(|cursor| PageParams::decode_cursor(encoder.as_ref(), cursor).unwrap());
+        let params = PageParams {
+            limit: 3,
+            cursor,
+            encoder,
+        };
+        let items: Vec<u64> = match cursor {
+            None => (1..=10).take(4).collect(),
+            Some(PageCursor::After(id)) => (id + 1..=10).take(4).collect(),
+            Some(PageCursor::Before(id)) => (1..id).rev().take(4).collect(),
+        };
+        params.into_response(items, |id| *id).unwrap()
+    }
+
+    #[test]
+    fn forward_and_backward_cursors() {
+        let first = page(None);
+        assert_eq!(first.items, [1, 2, 3]);
+        assert_eq!(first.prev, None);
+        assert_eq!(first.next.as_deref(), Some("3"));
+
+        let second = page(first.next.as_deref());
+        assert_eq!(second.items, [4, 5, 6]);
+        assert_eq!(second.prev.as_deref(), Some("~4"));
+
+        let back = page(second.prev.as_deref());
+        assert_eq!(back.items, [1, 2, 3]);
+        assert_eq!(back.prev, None);
+        assert_eq!(back.next.as_deref(), Some("3"));
+
+        let last = page(Some("9"));
+        assert_eq!(last.items, [10]);
+        assert_eq!(last.next, None);
+        let before_last = page(last.prev.as_deref());
+        assert_eq!(before_last.items, [7, 8, 9]);
+        assert_eq!(before_last.prev.as_deref(), Some("~7"));
+        assert_eq!(before_last.next.as_deref(), Some("9"));
+    }
+}
@@ -0,0 +1,204 @@
+#[cfg(feature = "redis")]
+use crate::service::AdminCaller;
+#[cfg(feature = "redis")]
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+#[cfg(feature = "redis")]
+use std::sync::Arc;
+use utoipa::openapi::{path::Operation, OpenApi};
+
+/// Name of the OpenApi operation extension holding the permissions of an endpoint.
+pub const PERMISSIONS_EXTENSION: &str = "x-permissions";
+
+/// Access requirements of an endpoint as declared on the `ApiEndpoint`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointPermissions {
+    /// The endpoint is accessible without authentication.
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub policies: Vec<String>,
+}
+
+impl EndpointPermissions {
+    pub fn is_empty(&self) -> bool {
+        !self.public && self.roles.is_empty() && self.scopes.is_empty() && self.policies.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PermissionMatrixEntry {
+    pub method: String,
+    pub path: String,
+    pub operation_id: Option<String>,
+    /// The endpoint has no declared permissions, it should be reviewed.
+    pub undeclared: bool,
+    pub permissions: EndpointPermissions,
+}
+
+/// The effective policy matrix of the endpoints collected from the OpenApi document.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PermissionMatrix {
+    pub endpoints: Vec<PermissionMatrixEntry>,
+}
+
+impl PermissionMatrix {
+    pub fn from_openapi(doc: &OpenApi) -> Self {
+        fn entry(method: &str, path: &str, operation: &Operation) -> PermissionMatrixEntry {
+            let permissions = operation
+                .extensions
+                .as_ref()
+                .and_then(|extensions| extensions.get(PERMISSIONS_EXTENSION))
+                .and_then(|value| serde_json::from_value::<EndpointPermissions>(value.clone()).ok())
+                .unwrap_or_default();
+            PermissionMatrixEntry {
+                method: method.to_string(),
+                path: path.to_string(),
+                operation_id: operation.operation_id.clone(),
+                undeclared: permissions.is_empty(),
+                permissions,
+            }
+        }
+
+        let mut endpoints = Vec::new();
+        for (path, item) in &doc.paths.paths {
+            let operations = [
+                ("GET", &item.get),
+                ("POST", &item.post),
+                ("PUT", &item.put),
+                ("PATCH", &item.patch),
+                ("DELETE", &item.delete),
+            ];
+            endpoints.extend(
+                operations.into_iter().filter_map(|(method, operation)| {
+                    operation.as_ref().map(|operation| entry(method, path, operation))
+                }),
+            );
+        }
+
+        Self { endpoints }
+    }
+
+    /// Render the matrix as a markdown table for the security reviews.
+    pub fn to_table(&self) -> String {
+        fn list(items: &[String]) -> String {
+            if items.is_empty() {
+                "-".to_string()
+            } else {
+                items.join(", ")
+            }
+        }
+
+        let mut table = String::new();
+        let _ = writeln!(table, "| Method | Path | Access | Roles | Scopes | Policies |");
+        let _ = writeln!(table, "|---|---|---|---|---|---|");
+        for entry in &self.endpoints {
+            let access = if entry.undeclared {
+                "UNDECLARED"
+            } else if entry.permissions.public {
+                "public"
+            } else {
+                "restricted"
+            };
+            let _ = writeln!(
+                table,
+                "| {} | {} | {} | {} | {} | {} |",
+                entry.method,
+                entry.path,
+                access,
+                list(&entry.permissions.roles),
+                list(&entry.permissions.scopes),
+                list(&entry.permissions.policies)
+            );
+        }
+        table
+    }
+
+    /// Create the admin route exporting the matrix, it is authorized by the `AdminCaller`, see
+    /// `AdminRouter::with_routes`.
+    ///  - GET /admin/permissions?format=json|table
+    #[cfg(feature = "redis")]
+    pub fn into_router<S>(self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        #[derive(Deserialize)]
+        struct FormatQuery {
+            format: Option<String>,
+        }
+
+        let matrix = Arc::new(self);
+
+        let route = get(move |_: AdminCaller, Query(query): Query<FormatQuery>| async move {
+            let response: Response = match query.format.as_deref() {
+                Some("table") => matrix.to_table().into_response(),
+                _ => Json(matrix.as_ref()).into_response(),
+            };
+            response
+        });
+
+        Router::new().route("/admin/permissions", route)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::axum::{ApiEndpoint, ApiMethod, ApiRoute};
+    use axum::Router;
+    use shine_test::test;
+    use utoipa::openapi::OpenApiBuilder;
+
+    #[test]
+    fn collect_declared_permissions() {
+        let mut doc = OpenApiBuilder::new().build();
+        let _router: Router = Router::new()
+            .add_api(
+                ApiEndpoint::new(ApiMethod::Get, "/users/:id".to_string(), || async {})
+                    .with_operation_id("getUser")
+                    .with_required_roles(["admin"])
+                    .with_policy("owner"),
+                &mut doc,
+            )
+            .add_api(
+                ApiEndpoint::new(ApiMethod::Get, "/info".to_string(), || async {}).with_public_access(),
+                &mut doc,
+            )
+            .add_api(
+                ApiEndpoint::new(ApiMethod::Delete, "/users/:id".to_string(), || async {}),
+                &mut doc,
+            );
+
+        let matrix = PermissionMatrix::from_openapi(&doc);
+        assert_eq!(matrix.endpoints.len(), 3);
+
+        let get_user = matrix
+            .endpoints
+            .iter()
+            .find(|e| e.method == "GET" && e.path == "/users/{id}")
+            .unwrap();
+        assert_eq!(get_user.permissions.roles, vec!["admin".to_string()]);
+        assert_eq!(get_user.permissions.policies, vec!["owner".to_string()]);
+        assert!(!get_user.undeclared);
+
+        let delete_user = matrix.endpoints.iter().find(|e| e.method == "DELETE").unwrap();
+        assert!(delete_user.undeclared);
+
+        let table = matrix.to_table();
+        assert!(table.contains("| GET | /info | public |"));
+        assert!(table.contains("UNDECLARED"));
+    }
+}
@@ -1,4 +1,7 @@
-use crate::utils::serde_status_code;
+use crate::{
+    axum::telemetry::{current_trace_id, record_problem_type},
+    utils::serde_status_code,
+};
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
@@ -7,6 +10,7 @@ use axum::{
 use serde::Serialize;
 use serde_json::Value as JsonValue;
 use std::fmt;
+use tracing::Span;
 use url::Url;
 
 #[derive(Clone)]
@@ -45,6 +49,9 @@ pub struct Problem {
     detail: String,
     #[serde(rename = "extension")]
     extension: JsonValue,
+    /// Id of the trace of the request, it is set for the server errors to help the correlation with the logs.
+    #[serde(rename = "traceId", skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
 }
 
 impl Problem {
@@ -55,6 +62,7 @@ impl Problem {
             instance: None,
             detail: String::new(),
             extension: JsonValue::Null,
+            trace_id: None,
         }
     }
 
@@ -74,6 +82,10 @@ impl Problem {
         Self::new(StatusCode::FORBIDDEN, "forbidden")
     }
 
+    pub fn conflict(ty: &'static str) -> Self {
+        Self::new(StatusCode::CONFLICT, ty)
+    }
+
     pub fn internal_error<M, F>(config: &ProblemConfig, minimal: M, full: F) -> Self
     where
         M: fmt::Display,
@@ -87,6 +99,10 @@ impl Problem {
         }
     }
 
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
     pub fn with_detail<S: ToString>(self, detail: S) -> Self {
         Self {
             detail: detail.to_string(),
@@ -119,7 +135,12 @@ impl Problem {
 }
 
 impl IntoResponse for Problem {
-    fn into_response(self) -> Response {
+    fn into_response(mut self) -> Response {
+        if self.status.is_server_error() {
+            record_problem_type(&Span::current(), self.ty);
+            self.trace_id = current_trace_id();
+        }
+
         let mut response = (self.status, Json(self)).into_response();
         response
             .headers_mut()
@@ -128,6 +149,8 @@ impl IntoResponse for Problem {
     }
 }
 
+pub use shine_macros::IntoProblem;
+
 pub trait IntoProblem {
     fn into_problem(self, config: &ProblemConfig) -> Problem;
 }
@@ -150,3 +173,53 @@ impl<P: IntoProblem> IntoResponse for ConfiguredProblem<P> {
         problem.into_problem(&config).into_response()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[derive(Debug, IntoProblem)]
+    enum TestError {
+        #[problem(status = 404, type = "user_not_found")]
+        UserNotFound,
+        #[problem(status = 400, type = "invalid_user", confidential)]
+        InvalidUser(String),
+        #[problem(internal = "Database error")]
+        Database(String),
+    }
+
+    impl fmt::Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                TestError::UserNotFound => write!(f, "User not found"),
+                TestError::InvalidUser(reason) => write!(f, "Invalid user: {reason}"),
+                TestError::Database(err) => write!(f, "Database error: {err}"),
+            }
+        }
+    }
+
+    #[test]
+    fn derive_into_problem() {
+        let public = ProblemConfig::new(false);
+        let internal = ProblemConfig::new(true);
+
+        let problem = TestError::UserNotFound.into_problem(&public);
+        assert_eq!(problem.status, StatusCode::NOT_FOUND);
+        assert_eq!(problem.ty, "user_not_found");
+        assert_eq!(problem.detail, "User not found");
+
+        let problem = TestError::InvalidUser("banned".into()).into_problem(&public);
+        assert_eq!(problem.status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem.detail, "");
+        let problem = TestError::InvalidUser("banned".into()).into_problem(&internal);
+        assert_eq!(problem.detail, "Invalid user: banned");
+
+        let problem = TestError::Database("timeout".into()).into_problem(&public);
+        assert_eq!(problem.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(problem.ty, "server-error");
+        assert_eq!(problem.detail, "Database error");
+        let problem = TestError::Database("timeout".into()).into_problem(&internal);
+        assert!(problem.detail.contains("timeout"));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::axum::Problem;
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use futures::future::BoxFuture;
+use opentelemetry::{
+    metrics::{Counter, Meter},
+    KeyValue,
+};
+use serde_json::json;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// Replace the plain text 404 and 405 responses of axum with problem responses and count the requests without
+/// a matching route to spot the client bugs.
+///
+/// ```ignore
+/// let router = ProblemFallback::new().with_meter(meter).install(router);
+/// ```
+#[derive(Clone, Default)]
+pub struct ProblemFallback {
+    unmatched_count: Option<Counter<u64>>,
+}
+
+impl ProblemFallback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            unmatched_count: Some(meter.u64_counter("http_unmatched_route_count").init()),
+        }
+    }
+
+    fn record(&self, method: &Method, status: StatusCode) {
+        if let Some(counter) = &self.unmatched_count {
+            counter.add(
+                1,
+                &[
+                    KeyValue::new("http.request.method", method.to_string()),
+                    KeyValue::new("http.response.status_code", status.as_u16() as i64),
+                ],
+            );
+        }
+    }
+
+    /// Response for the requests without a matching route.
+    pub fn not_found(&self, method: &Method, uri: &Uri) -> Response {
+        log::debug!("No route for {method} {}", uri.path());
+        self.record(method, StatusCode::NOT_FOUND);
+        Problem::not_found()
+            .with_detail(format!("No route for {method} {}", uri.path()))
+            .into_response()
+    }
+
+    fn method_not_allowed(&self, method: &Method, uri: &Uri, response: Response) -> Response {
+        self.record(method, StatusCode::METHOD_NOT_ALLOWED);
+        let allow = response.headers().get(header::ALLOW).cloned();
+        let allowed_methods = allow
+            .as_ref()
+            .and_then(|allow| allow.to_str().ok())
+            .map(|allow| allow.split(',').map(|m| m.trim()).filter(|m| !m.is_empty()).collect())
+            .unwrap_or_else(Vec::new);
+
+        let mut response = Problem::new(StatusCode::METHOD_NOT_ALLOWED, "method-not-allowed")
+            .with_detail(format!("Method {method} is not allowed for {}", uri.path()))
+            .with_public_extension(json!({ "allowedMethods": allowed_methods }))
+            .into_response();
+        if let Some(allow) = allow {
+            response.headers_mut().insert(header::ALLOW, allow);
+        }
+        response
+    }
+
+    /// Set the fallback of the router and add the method not allowed layer, the existing fallback of the
+    /// router is replaced.
+    pub fn install<S>(self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let fallback = self.clone();
+        router
+            .fallback(move |method: Method, uri: Uri| {
+                let fallback = fallback.clone();
+                async move { fallback.not_found(&method, &uri) }
+            })
+            .layer(self.into_layer())
+    }
+
+    pub fn into_layer(self) -> MethodNotAllowedLayer {
+        MethodNotAllowedLayer(self)
+    }
+}
+
+/// Convert the empty 405 responses of the method routers into problem responses keeping the `Allow` header.
+#[derive(Clone)]
+pub struct MethodNotAllowedLayer(ProblemFallback);
+
+impl<S> Layer<S> for MethodNotAllowedLayer {
+    type Service = MethodNotAllowedMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MethodNotAllowedMiddleware {
+            inner,
+            fallback: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct MethodNotAllowedMiddleware<S> {
+    inner: S,
+    fallback: ProblemFallback,
+}
+
+impl<S> Service<Request<Body>> for MethodNotAllowedMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let method = request.method().clone();
+        let uri = request.uri().clone();
+        let fallback = self.fallback.clone();
+
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await?;
+            // the responses of the handlers have a content type, only the default responses of axum are replaced
+            if response.status() == StatusCode::METHOD_NOT_ALLOWED
+                && !response.headers().contains_key(header::CONTENT_TYPE)
+            {
+                Ok(fallback.method_not_allowed(&method, &uri, response))
+            } else {
+                Ok(response)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::to_bytes, routing::get};
+    use shine_test::test;
+    use tower::ServiceExt;
+
+    async fn call(method: Method, uri: &str) -> Response {
+        let app: Router = ProblemFallback::new().install(Router::new().route("/users", get(|| async { "users" })));
+        let request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap()
+    }
+
+    async fn problem_body(response: Response) -> serde_json::Value {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    async fn problem_responses() {
+        let response = call(Method::GET, "/users").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call(Method::GET, "/unknown").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/problem+json");
+        assert_eq!(problem_body(response).await["type"], "not-found");
+
+        let response = call(Method::DELETE, "/users").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/problem+json");
+        assert!(response.headers().contains_key(header::ALLOW));
+        let body = problem_body(response).await;
+        assert_eq!(body["type"], "method-not-allowed");
+        assert!(body["extension"]["allowedMethods"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("GET")));
+    }
+}
@@ -0,0 +1,168 @@
+use axum::{Extension, Router};
+use std::{
+    any::{type_name, TypeId},
+    collections::{BTreeMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error as ThisError;
+
+/// An extension type required by an extractor, ex. `ProblemConfig` or `Arc<UserSessionCacheReader>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LayerRequirement {
+    type_id: TypeId,
+    name: &'static str,
+}
+
+impl LayerRequirement {
+    pub fn of<T: 'static>() -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            name: type_name::<T>(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// The extensions required by the endpoints but not provided by any layer, grouped by the extension type.
+#[derive(Debug, ThisError)]
+pub struct MissingLayersError {
+    pub missing: BTreeMap<&'static str, Vec<String>>,
+}
+
+impl fmt::Display for MissingLayersError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Missing layers for the extensions:")?;
+        for (name, endpoints) in &self.missing {
+            writeln!(f, "  {name} required by {}", endpoints.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    provided: HashSet<TypeId>,
+    required: Vec<(String, LayerRequirement)>,
+}
+
+/// Registry of the extensions required by the extractors and the extensions provided by the layers of a
+/// router. The extractors panic at request time when a layer is missing, checking the registry when the router
+/// is finalized turns it into a startup error. The clones share the registry.
+///
+/// The endpoints declare their requirements with `ApiEndpoint::requires::<T>()` and they are registered when
+/// the endpoint is added with `ApiRoute::add_required_api`. The layers added by the `ShineService` are
+/// registered as provided, the extension layers of the service (ex. the `into_layer` helpers) are added and
+/// registered with `RequiredLayers::layer`.
+#[derive(Clone, Default)]
+pub struct RequiredLayers {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl RequiredLayers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an extension layer to the router and register the extension as provided.
+    pub fn layer<S, T>(&self, router: Router<S>, extension: Extension<T>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+        T: Clone + Send + Sync + 'static,
+    {
+        self.provide::<T>();
+        router.layer(extension)
+    }
+
+    /// Register an extension added by a layer.
+    pub fn provide<T: 'static>(&self) -> &Self {
+        self.provide_requirement(LayerRequirement::of::<T>())
+    }
+
+    pub fn provide_requirement(&self, requirement: LayerRequirement) -> &Self {
+        self.registry.lock().unwrap().provided.insert(requirement.type_id);
+        self
+    }
+
+    /// Register an extension required by an endpoint, ex: `GET /api/users`.
+    pub fn require<T: 'static>(&self, endpoint: &str) -> &Self {
+        self.require_requirement(endpoint, LayerRequirement::of::<T>())
+    }
+
+    pub fn require_requirement(&self, endpoint: &str, requirement: LayerRequirement) -> &Self {
+        self.registry
+            .lock()
+            .unwrap()
+            .required
+            .push((endpoint.to_string(), requirement));
+        self
+    }
+
+    /// Check that all the required extensions are provided.
+    pub fn check(&self) -> Result<(), MissingLayersError> {
+        let registry = self.registry.lock().unwrap();
+        let mut missing = BTreeMap::<_, Vec<_>>::new();
+        for (endpoint, requirement) in &registry.required {
+            if !registry.provided.contains(&requirement.type_id) {
+                missing.entry(requirement.name).or_default().push(endpoint.clone());
+            }
+        }
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MissingLayersError { missing })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[derive(Clone)]
+    struct ProvidedExtension;
+    #[derive(Clone)]
+    struct MissingExtension;
+
+    #[test]
+    fn missing_layers() {
+        let layers = RequiredLayers::new();
+        layers
+            .provide::<ProvidedExtension>()
+            .require::<ProvidedExtension>("GET /a")
+            .require::<MissingExtension>("GET /a")
+            .require::<MissingExtension>("PUT /b");
+
+        let err = layers.check().unwrap_err();
+        assert_eq!(err.missing.len(), 1);
+        assert_eq!(
+            err.missing.get(type_name::<MissingExtension>()),
+            Some(&vec!["GET /a".to_string(), "PUT /b".to_string()])
+        );
+        assert!(err.to_string().contains("MissingExtension required by GET /a, PUT /b"));
+
+        layers.provide::<MissingExtension>();
+        assert!(layers.check().is_ok());
+    }
+
+    #[test]
+    fn layers_of_the_router() {
+        let layers = RequiredLayers::new();
+        layers.require::<ProvidedExtension>("GET /a");
+        let _router: Router = layers.layer(Router::new(), Extension(ProvidedExtension));
+        assert!(layers.check().is_ok());
+
+        let other = RequiredLayers::new();
+        other.require::<ProvidedExtension>("GET /b");
+        assert!(other.check().is_err());
+
+        let shared = layers.clone();
+        shared.require::<MissingExtension>("GET /c");
+        assert!(layers.check().is_err());
+    }
+}
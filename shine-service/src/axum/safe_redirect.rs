@@ -0,0 +1,138 @@
+use axum::response::Redirect;
+use thiserror::Error as ThisError;
+use url::Url;
+
+/// Base used to resolve the relative targets, a relative target is accepted only if it stays on this origin.
+const RELATIVE_BASE: &str = "https://redirect.invalid/";
+
+#[derive(Debug, ThisError)]
+pub enum SafeRedirectError {
+    #[error("Invalid redirect url")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("Unsupported redirect scheme: {0}")]
+    UnsupportedScheme(String),
+    #[error("Redirect target is not allowed: {0}")]
+    NotAllowed(String),
+}
+
+/// Validate the return urls of redirect flows to prevent open redirects.
+#[derive(Clone, Debug)]
+pub struct SafeRedirect {
+    exact_hosts: Vec<String>,
+    wildcard_domains: Vec<String>,
+}
+
+impl SafeRedirect {
+    /// Create a validator from a list of allowed hosts. An entry starting with `*.` matches any subdomain.
+    pub fn new<I, S>(allowed_hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut exact_hosts = Vec::new();
+        let mut wildcard_domains = Vec::new();
+        for host in allowed_hosts {
+            let host = host.as_ref().trim_end_matches('.').to_ascii_lowercase();
+            if let Some(domain) = host.strip_prefix("*.") {
+                wildcard_domains.push(domain.to_string());
+            } else {
+                exact_hosts.push(host);
+            }
+        }
+
+        Self {
+            exact_hosts,
+            wildcard_domains,
+        }
+    }
+
+    /// Allow the domain and all of its subdomains.
+    pub fn from_domain_name(domain: &str) -> Self {
+        Self::new([domain.to_string(), format!("*.{domain}")])
+    }
+
+    fn is_host_allowed(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.exact_hosts.iter().any(|allowed| *allowed == host)
+            || self.wildcard_domains.iter().any(|domain| {
+                host.strip_suffix(domain.as_str())
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+            })
+    }
+
+    /// Validate the target and return the location to redirect to.
+    /// Absolute paths of the current site (`/path`) are accepted, protocol relative urls (`//host`) are rejected.
+    /// The browsers drop the tabs and newlines and treat the backslash as a slash, thus the targets containing
+    /// any of the control, whitespace or backslash characters are rejected.
+    pub fn validate(&self, target: &str) -> Result<String, SafeRedirectError> {
+        if target
+            .chars()
+            .any(|c| c.is_ascii_control() || c.is_whitespace() || c == '\\')
+        {
+            return Err(SafeRedirectError::NotAllowed(target.to_string()));
+        }
+
+        if target.starts_with('/') {
+            let base = Url::parse(RELATIVE_BASE).expect("Invalid relative base");
+            let url = base.join(target)?;
+            if target.starts_with("//") || url.origin() != base.origin() {
+                return Err(SafeRedirectError::NotAllowed(target.to_string()));
+            }
+            return Ok(target.to_string());
+        }
+
+        let url = Url::parse(target)?;
+        if url.scheme() != "https" && url.scheme() != "http" {
+            return Err(SafeRedirectError::UnsupportedScheme(url.scheme().to_string()));
+        }
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(SafeRedirectError::NotAllowed(target.to_string()));
+        }
+        match url.host_str() {
+            Some(host) if self.is_host_allowed(host) => Ok(url.to_string()),
+            _ => Err(SafeRedirectError::NotAllowed(target.to_string())),
+        }
+    }
+
+    /// Create a redirect response to the target if it is valid, to the fallback otherwise.
+    pub fn redirect_or(&self, target: Option<&str>, fallback: &str) -> Redirect {
+        match target.map(|target| self.validate(target)) {
+            Some(Ok(location)) => Redirect::to(&location),
+            Some(Err(err)) => {
+                log::info!("Rejected redirect target: {err}");
+                Redirect::to(fallback)
+            }
+            None => Redirect::to(fallback),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn validate_redirect() {
+        let redirect = SafeRedirect::from_domain_name("example.com");
+
+        assert_eq!(redirect.validate("/home?a=1").unwrap(), "/home?a=1");
+        assert!(redirect.validate("https://example.com/a").is_ok());
+        assert!(redirect.validate("https://cloud.example.com/a").is_ok());
+        assert!(redirect.validate("https://evilexample.com/a").is_err());
+        assert!(redirect.validate("https://example.com.evil.com/a").is_err());
+        assert!(redirect.validate("https://user@evil.com@example.com").is_err());
+        assert!(redirect.validate("//evil.com").is_err());
+        assert!(redirect.validate("/\\evil.com").is_err());
+        assert!(redirect.validate("/\t/evil.com").is_err());
+        assert!(redirect.validate("/\n/evil.com").is_err());
+        assert!(redirect.validate("/\u{0b}/evil.com").is_err());
+        assert!(redirect.validate("/ /evil.com").is_err());
+        assert!(redirect.validate("/a\\b").is_err());
+        assert!(redirect.validate("https://example.com\\@evil.com/").is_err());
+        assert!(redirect.validate("https://example.com/\tpath").is_err());
+        assert_eq!(redirect.validate("/a/../b").unwrap(), "/a/../b");
+        assert!(redirect.validate("javascript:alert(1)").is_err());
+        assert!(redirect.validate("evil.com").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::axum::{Problem, ProblemConfig};
+use axum::http::StatusCode;
+use bb8::RunError;
+use opentelemetry::KeyValue;
+use serde::Serialize;
+use std::error::Error as StdError;
+
+/// The party responsible for an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCategory {
+    /// The request is invalid, it fails the same way until the caller changes it.
+    User,
+    /// A bug or misconfiguration of the service.
+    Internal,
+    /// A backing service (database, cache, remote api) failed or is unavailable.
+    Dependency,
+}
+
+impl ErrorCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::User => "user",
+            ErrorCategory::Internal => "internal",
+            ErrorCategory::Dependency => "dependency",
+        }
+    }
+}
+
+/// Common classification of the errors, the middlewares, the retry policies and the problem conversions can
+/// handle the errors uniformly without matching on the concrete types.
+pub trait ServiceError: StdError {
+    fn category(&self) -> ErrorCategory;
+
+    /// If the same operation could succeed when it is repeated.
+    fn is_retryable(&self) -> bool {
+        false
+    }
+
+    /// Map the error into a problem. By default the user errors are reported as bad request with the message of
+    /// the error, the dependency errors as 503 and the internal errors as 500 with the details hidden unless
+    /// it is enabled by the config.
+    fn to_problem(&self, config: &ProblemConfig) -> Problem {
+        match self.category() {
+            ErrorCategory::User => Problem::bad_request("invalid-request").with_detail(self.to_string()),
+            ErrorCategory::Dependency => Problem::new(StatusCode::SERVICE_UNAVAILABLE, "dependency-unavailable")
+                .with_detail(self.to_string())
+                .with_extension(config, format!("{:#?}", self)),
+            ErrorCategory::Internal => Problem::internal_error(config, self.to_string(), self),
+        }
+    }
+
+    /// Attributes of the error for the metrics and the traces.
+    fn telemetry_attributes(&self) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new("error.category", self.category().as_str()),
+            KeyValue::new("error.retryable", self.is_retryable()),
+        ]
+    }
+}
+
+/// Pool errors, the timeout of the pool is a retryable dependency error.
+impl<E> ServiceError for RunError<E>
+where
+    E: ServiceError + 'static,
+{
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Dependency
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            RunError::User(err) => err.is_retryable(),
+            RunError::TimedOut => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+    use thiserror::Error as ThisError;
+
+    #[derive(Debug, ThisError)]
+    enum TestError {
+        #[error("Invalid input")]
+        Input,
+        #[error("Backend is down")]
+        Backend,
+    }
+
+    impl ServiceError for TestError {
+        fn category(&self) -> ErrorCategory {
+            match self {
+                TestError::Input => ErrorCategory::User,
+                TestError::Backend => ErrorCategory::Dependency,
+            }
+        }
+
+        fn is_retryable(&self) -> bool {
+            matches!(self, TestError::Backend)
+        }
+    }
+
+    #[test]
+    fn default_mapping() {
+        let config = ProblemConfig::new(false);
+
+        let problem = TestError::Input.to_problem(&config);
+        assert_eq!(problem.status(), StatusCode::BAD_REQUEST);
+
+        let problem = TestError::Backend.to_problem(&config);
+        assert_eq!(problem.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let attributes = TestError::Backend.telemetry_attributes();
+        assert!(attributes.contains(&KeyValue::new("error.category", "dependency")));
+        assert!(attributes.contains(&KeyValue::new("error.retryable", true)));
+
+        let err: RunError<TestError> = RunError::User(TestError::Input);
+        assert_eq!(err.category(), ErrorCategory::Dependency);
+        assert!(!err.is_retryable());
+        assert!(RunError::<TestError>::TimedOut.is_retryable());
+    }
+}
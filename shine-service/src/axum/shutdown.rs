@@ -0,0 +1,206 @@
+use crate::axum::Problem;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, RequestPartsExt,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{watch, Notify};
+
+struct Inner {
+    draining: watch::Sender<bool>,
+    active: AtomicUsize,
+    drained: Notify,
+}
+
+/// Coordinate the shutdown of the long lived (websocket, SSE) connections. When the shutdown starts,
+/// the new upgrades are rejected, the active connections are notified to send a goaway message
+/// and the controller waits for them to close up to the drain period.
+#[derive(Clone)]
+pub struct ShutdownController {
+    drain_period: Duration,
+    retry_after: Duration,
+    inner: Arc<Inner>,
+}
+
+impl ShutdownController {
+    pub fn new(drain_period: Duration) -> Self {
+        let (draining, _) = watch::channel(false);
+        Self {
+            drain_period,
+            retry_after: Duration::from_secs(5),
+            inner: Arc::new(Inner {
+                draining,
+                active: AtomicUsize::new(0),
+                drained: Notify::new(),
+            }),
+        }
+    }
+
+    /// Hint for the clients when they should reconnect.
+    #[must_use]
+    pub fn with_retry_after(self, retry_after: Duration) -> Self {
+        Self { retry_after, ..self }
+    }
+
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.inner.draining.borrow()
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.inner.active.load(Ordering::Acquire)
+    }
+
+    /// Register a new long lived connection, fails if the shutdown has already started.
+    pub fn connect(&self) -> Result<ConnectionGuard, ShutdownRejection> {
+        self.inner.active.fetch_add(1, Ordering::AcqRel);
+        if self.is_draining() {
+            self.release();
+            return Err(ShutdownRejection {
+                retry_after: self.retry_after,
+            });
+        }
+
+        Ok(ConnectionGuard {
+            controller: self.clone(),
+            draining: self.inner.draining.subscribe(),
+        })
+    }
+
+    fn release(&self) {
+        if self.inner.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inner.drained.notify_waiters();
+        }
+    }
+
+    /// Start draining and wait until all the connections are closed or the drain period is elapsed.
+    pub async fn drain(&self) {
+        let drained = self.inner.drained.notified();
+        tokio::pin!(drained);
+        drained.as_mut().enable();
+
+        self.inner.draining.send_replace(true);
+        let active = self.active_connections();
+        if active == 0 {
+            return;
+        }
+
+        log::info!("Draining {active} connection(s) for at most {:?}", self.drain_period);
+        if tokio::time::timeout(self.drain_period, drained).await.is_err() {
+            log::warn!(
+                "Drain period elapsed, closing {} connection(s)",
+                self.active_connections()
+            );
+        }
+    }
+
+    /// Wait for a termination signal and drain the connections. It can be used with the
+    /// `with_graceful_shutdown` of the server.
+    pub async fn shutdown_signal(self) {
+        let ctrl_c = async {
+            if let Err(err) = tokio::signal::ctrl_c().await {
+                log::error!("Failed to listen for ctrl-c: {err}");
+                std::future::pending::<()>().await;
+            }
+        };
+
+        #[cfg(unix)]
+        let terminate = async {
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(mut signal) => {
+                    signal.recv().await;
+                }
+                Err(err) => {
+                    log::error!("Failed to listen for terminate signal: {err}");
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => {},
+            _ = terminate => {},
+        }
+
+        log::info!("Shutdown signal received");
+        self.drain().await;
+    }
+}
+
+/// Handle of an active long lived connection. The handler should select on `draining` and
+/// send a goaway message (including the `retry_after` hint) to the client when it completes.
+pub struct ConnectionGuard {
+    controller: ShutdownController,
+    draining: watch::Receiver<bool>,
+}
+
+impl ConnectionGuard {
+    /// Completes when the shutdown has started.
+    pub async fn draining(&mut self) {
+        if self.draining.wait_for(|draining| *draining).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    pub fn retry_after(&self) -> Duration {
+        self.controller.retry_after
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.controller.release();
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ConnectionGuard
+where
+    S: Send + Sync,
+{
+    type Rejection = ShutdownRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(controller) = parts
+            .extract::<Extension<ShutdownController>>()
+            .await
+            .expect("Missing ShutdownController extension");
+        controller.connect()
+    }
+}
+
+/// The service is shutting down and no new connection is accepted.
+#[derive(Debug)]
+pub struct ShutdownRejection {
+    pub retry_after: Duration,
+}
+
+impl IntoResponse for ShutdownRejection {
+    fn into_response(self) -> Response {
+        let mut response = Problem::new(StatusCode::SERVICE_UNAVAILABLE, "shutting-down")
+            .with_detail("Service is shutting down")
+            .into_response();
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(self.retry_after.as_secs()));
+        response
+    }
+}
@@ -0,0 +1,325 @@
+use axum::{
+    body::{to_bytes, Body, Bytes, HttpBody},
+    extract::MatchedPath,
+    http::{header, HeaderMap, HeaderName, Method, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use opentelemetry::{
+    metrics::{Counter, Meter},
+    KeyValue,
+};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+const DEFAULT_MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Clone)]
+struct SharedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl SharedResponse {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, self.body).into_response();
+        *response.headers_mut() = self.headers;
+        response
+    }
+}
+
+/// Outcome of an execution, the responses that cannot be shared are kept for the leader request and the
+/// coalesced requests are executed on their own.
+#[derive(Clone)]
+enum Outcome {
+    Shared(SharedResponse),
+    Private(Arc<Mutex<Option<Response>>>),
+}
+
+type InFlight = Shared<BoxFuture<'static, Outcome>>;
+
+/// Coalesce the identical concurrent GET requests: the requests with the same key await a single
+/// execution and share its response. The key is built from the uri and the `vary_headers`, by default
+/// the `authorization` and `cookie` headers are included to never share a response between users.
+/// The responses setting a cookie, without a known length or larger than the `max_body_size` are not
+/// shared, the coalesced requests are executed on their own instead.
+/// It should be applied only on the expensive, idempotent endpoints.
+#[derive(Clone)]
+pub struct SingleFlightLayer {
+    vary_headers: Vec<HeaderName>,
+    max_body_size: usize,
+    coalesced: Option<Counter<u64>>,
+    in_flight: Arc<Mutex<HashMap<String, InFlight>>>,
+}
+
+impl Default for SingleFlightLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SingleFlightLayer {
+    pub fn new() -> Self {
+        Self {
+            vary_headers: vec![
+                HeaderName::from_static("authorization"),
+                HeaderName::from_static("cookie"),
+            ],
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            coalesced: None,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Set the headers the key is built from in addition to the uri.
+    #[must_use]
+    pub fn with_vary_headers(self, headers: Vec<HeaderName>) -> Self {
+        Self {
+            vary_headers: headers,
+            ..self
+        }
+    }
+
+    /// Maximum size of a shared response body, the larger responses are not shared.
+    #[must_use]
+    pub fn with_max_body_size(self, max_body_size: usize) -> Self {
+        Self { max_body_size, ..self }
+    }
+
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            coalesced: Some(meter.u64_counter("request_coalesced").init()),
+            ..self
+        }
+    }
+
+    fn key(&self, request: &Request<Body>) -> String {
+        let mut key = request.uri().to_string();
+        for name in &self.vary_headers {
+            for value in request.headers().get_all(name) {
+                key.push('\n');
+                key.push_str(name.as_str());
+                key.push(':');
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+        key
+    }
+}
+
+impl<S> Layer<S> for SingleFlightLayer {
+    type Service = SingleFlightMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SingleFlightMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct SingleFlightMiddleware<S> {
+    inner: S,
+    layer: SingleFlightLayer,
+}
+
+impl<S> Service<Request<Body>> for SingleFlightMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        if request.method() != Method::GET {
+            return Box::pin(inner.call(request));
+        }
+
+        let layer = self.layer.clone();
+        let key = layer.key(&request);
+        // the matched route keeps the cardinality of the metrics low
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string())
+            .unwrap_or_default();
+
+        let in_flight = {
+            let mut in_flight = layer.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(shared) => shared.clone(),
+                None => {
+                    let execution = Self::execute(inner, request, &layer, key.clone());
+                    in_flight.insert(key, execution.clone());
+                    return Box::pin(async move {
+                        match execution.await {
+                            Outcome::Shared(response) => Ok(response.into_response()),
+                            Outcome::Private(response) => Ok(response
+                                .lock()
+                                .unwrap()
+                                .take()
+                                .expect("The private response is taken only by the leader")),
+                        }
+                    });
+                }
+            }
+        };
+
+        if let Some(coalesced) = &layer.coalesced {
+            coalesced.add(1, &[KeyValue::new("route", route)]);
+        }
+
+        Box::pin(async move {
+            match in_flight.await {
+                Outcome::Shared(response) => Ok(response.into_response()),
+                // the response of the leader was not shared, execute the request on its own
+                Outcome::Private(_) => inner.call(request).await,
+            }
+        })
+    }
+}
+
+impl<S> SingleFlightMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    fn is_shareable(response: &Response, max_body_size: usize) -> bool {
+        if response.headers().contains_key(header::SET_COOKIE) {
+            return false;
+        }
+        response
+            .body()
+            .size_hint()
+            .upper()
+            .is_some_and(|size| size <= max_body_size as u64)
+    }
+
+    fn execute(mut inner: S, request: Request<Body>, layer: &SingleFlightLayer, key: String) -> InFlight {
+        let max_body_size = layer.max_body_size;
+        let registry = layer.in_flight.clone();
+        async move {
+            let response = match inner.call(request).await {
+                Ok(response) => response,
+                Err(err) => match err {},
+            };
+
+            let outcome = if Self::is_shareable(&response, max_body_size) {
+                let (parts, body) = response.into_parts();
+                match to_bytes(body, max_body_size).await {
+                    Ok(body) => Outcome::Shared(SharedResponse {
+                        status: parts.status,
+                        headers: parts.headers,
+                        body,
+                    }),
+                    Err(err) => {
+                        log::error!("Failed to buffer the coalesced response: {err}");
+                        Outcome::Shared(SharedResponse {
+                            status: StatusCode::INTERNAL_SERVER_ERROR,
+                            headers: HeaderMap::new(),
+                            body: Bytes::new(),
+                        })
+                    }
+                }
+            } else {
+                Outcome::Private(Arc::new(Mutex::new(Some(response))))
+            };
+
+            // the entry is removed by the execution itself as the leader request may be dropped
+            registry.lock().unwrap().remove(&key);
+            outcome
+        }
+        .boxed()
+        .shared()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{routing::get, Router};
+    use shine_test::test;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tower::ServiceExt;
+
+    async fn call_concurrently(router: Router) -> Vec<Response> {
+        let requests = (0..2).map(|_| {
+            let request = Request::builder().uri("/data").body(Body::empty()).unwrap();
+            router.clone().oneshot(request)
+        });
+        futures::future::join_all(requests)
+            .await
+            .into_iter()
+            .map(|response| response.unwrap())
+            .collect()
+    }
+
+    fn router(set_cookie: bool) -> (Router, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let router = Router::new()
+            .route(
+                "/data",
+                get(move || {
+                    let calls = counter.clone();
+                    async move {
+                        let call = calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        let mut response = format!("call-{call}").into_response();
+                        if set_cookie {
+                            let cookie = format!("sid=session-{call}").parse().unwrap();
+                            response.headers_mut().insert(header::SET_COOKIE, cookie);
+                        }
+                        response
+                    }
+                }),
+            )
+            .layer(SingleFlightLayer::new());
+        (router, calls)
+    }
+
+    #[test]
+    async fn coalesce_requests() {
+        let (router, calls) = router(false);
+        let responses = call_concurrently(router).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        for response in responses {
+            let body = to_bytes(response.into_body(), 1024).await.unwrap();
+            assert_eq!(&body[..], b"call-0");
+        }
+    }
+
+    #[test]
+    async fn cookies_are_not_shared() {
+        let (router, calls) = router(true);
+        let responses = call_concurrently(router).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let mut cookies = responses
+            .iter()
+            .map(|response| response.headers()[header::SET_COOKIE].to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        cookies.sort();
+        assert_eq!(cookies, ["sid=session-0", "sid=session-1"]);
+    }
+}
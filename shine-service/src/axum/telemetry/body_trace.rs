@@ -0,0 +1,365 @@
+use crate::utils::{is_sensitive_config_key, redact_json, ByteSize, REDACTED};
+use axum::{
+    body::{to_bytes, Body, Bytes, HttpBody},
+    extract::MatchedPath,
+    http::{header, HeaderMap, Request},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+/// Log target of the captured bodies.
+pub const BODY_TRACE_TARGET: &str = "body_trace";
+
+fn default_max_size() -> ByteSize {
+    ByteSize::kb(4)
+}
+
+fn default_content_types() -> Vec<String> {
+    vec![
+        "application/json".into(),
+        "application/problem+json".into(),
+        "application/x-www-form-urlencoded".into(),
+    ]
+}
+
+/// Where the captured bodies are recorded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BodyTraceOutput {
+    /// Event of the current (http request) span, exported with the traces.
+    #[default]
+    Span,
+    /// Debug log with the `body_trace` target.
+    Log,
+}
+
+/// Capture of the request and response bodies for debugging. It is meant for the staging environments, the
+/// bodies are buffered in memory and they may contain personal data even after the masking.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BodyTraceConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub output: BodyTraceOutput,
+    /// Bodies larger than this (or with unknown size) are not captured, ex: `4KB`.
+    #[serde(default = "default_max_size")]
+    pub max_size: ByteSize,
+    /// Captured content types, the parameters (charset) are ignored. Only the json and the url encoded form
+    /// bodies can be masked, the bodies of the other types (and the malformed ones) are replaced by `***`.
+    #[serde(default = "default_content_types")]
+    pub content_types: Vec<String>,
+    /// Route patterns to capture, ex: `/api/users/:id`. All the routes are captured if it is empty.
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// Masked json paths in addition to the sensitive keys, ex: `$.user.email` or `$.items[*].iban`.
+    #[serde(default)]
+    pub masks: Vec<String>,
+}
+
+impl Default for BodyTraceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            output: BodyTraceOutput::default(),
+            max_size: default_max_size(),
+            content_types: default_content_types(),
+            routes: Vec::new(),
+            masks: Vec::new(),
+        }
+    }
+}
+
+impl BodyTraceConfig {
+    pub fn into_layer(self) -> BodyTraceLayer {
+        let masks = self.masks.iter().map(|mask| parse_mask(mask)).collect();
+        BodyTraceLayer(Arc::new(BodyTrace { config: self, masks }))
+    }
+
+    fn is_traced_route(&self, route: &str) -> bool {
+        self.routes.is_empty() || self.routes.iter().any(|r| r == route)
+    }
+
+    fn is_traced_content(&self, headers: &HeaderMap) -> bool {
+        let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|ct| ct.to_str().ok()) else {
+            return false;
+        };
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        self.content_types.iter().any(|ct| ct.eq_ignore_ascii_case(mime))
+    }
+
+    fn is_traced_size<B: HttpBody>(&self, body: &B) -> bool {
+        body.size_hint()
+            .upper()
+            .is_some_and(|size| size <= self.max_size.as_usize() as u64)
+    }
+}
+
+/// Split a json path into segments, `*` matches any key or array item.
+fn parse_mask(mask: &str) -> Vec<String> {
+    let mask = mask.strip_prefix('$').unwrap_or(mask);
+    mask.replace("[*]", ".*")
+        .replace('[', ".")
+        .replace(']', "")
+        .split('.')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.to_string())
+        .collect()
+}
+
+fn mask_json(value: &mut JsonValue, path: &[String]) {
+    let Some((head, tail)) = path.split_first() else {
+        *value = JsonValue::String(REDACTED.to_string());
+        return;
+    };
+
+    match value {
+        JsonValue::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                if head == "*" || head == key {
+                    mask_json(item, tail);
+                }
+            }
+        }
+        JsonValue::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                if head == "*" || head.parse::<usize>().is_ok_and(|i| i == index) {
+                    mask_json(item, tail);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+struct BodyTrace {
+    config: BodyTraceConfig,
+    masks: Vec<Vec<String>>,
+}
+
+impl BodyTrace {
+    /// Render the body with the sensitive values masked, the bodies that cannot be parsed (thus masked) are
+    /// omitted.
+    fn render(&self, headers: &HeaderMap, body: &Bytes) -> String {
+        let mime = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .and_then(|ct| ct.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        let rendered = if mime.ends_with("json") {
+            self.render_json(body)
+        } else if mime == "application/x-www-form-urlencoded" {
+            self.render_form(body)
+        } else {
+            None
+        };
+        rendered.unwrap_or_else(|| REDACTED.to_string())
+    }
+
+    fn render_json(&self, body: &Bytes) -> Option<String> {
+        let json = serde_json::from_slice::<JsonValue>(body).ok()?;
+        let mut json = redact_json(&json);
+        for mask in &self.masks {
+            mask_json(&mut json, mask);
+        }
+        Some(json.to_string())
+    }
+
+    /// Mask the form fields by the sensitive keys and the single segment masks, ex: `$.email`.
+    fn render_form(&self, body: &Bytes) -> Option<String> {
+        let fields = serde_urlencoded::from_bytes::<Vec<(String, String)>>(body).ok()?;
+        let fields = fields
+            .into_iter()
+            .map(|(key, value)| {
+                let masked = is_sensitive_config_key(&key)
+                    || self
+                        .masks
+                        .iter()
+                        .any(|mask| matches!(mask.as_slice(), [field] if field == "*" || *field == key));
+                if masked {
+                    (key, REDACTED.to_string())
+                } else {
+                    (key, value)
+                }
+            })
+            .collect::<Vec<_>>();
+        serde_urlencoded::to_string(fields).ok()
+    }
+
+    fn record(&self, direction: &str, route: &str, body: &str) {
+        match self.config.output {
+            BodyTraceOutput::Span => tracing::info!(target: BODY_TRACE_TARGET, direction, route, body, "http.body"),
+            BodyTraceOutput::Log => log::debug!(target: BODY_TRACE_TARGET, "{direction} body of {route}: {body}"),
+        }
+    }
+}
+
+/// Record the request and response bodies of the traced routes. Apply it on the whole router with the route
+/// filter of the config or with `route_layer` on the individual routes.
+#[derive(Clone)]
+pub struct BodyTraceLayer(Arc<BodyTrace>);
+
+impl<S> Layer<S> for BodyTraceLayer {
+    type Service = BodyTraceMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BodyTraceMiddleware {
+            inner,
+            trace: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct BodyTraceMiddleware<S> {
+    inner: S,
+    trace: Arc<BodyTrace>,
+}
+
+impl<S> Service<Request<Body>> for BodyTraceMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let config = &self.trace.config;
+        let route = match request.extensions().get::<MatchedPath>() {
+            Some(path) => path.as_str().to_string(),
+            None => request.uri().path().to_string(),
+        };
+        if !config.enabled || !config.is_traced_route(&route) {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let trace = self.trace.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let config = &trace.config;
+            let max_size = config.max_size.as_usize();
+
+            let request = if config.is_traced_content(request.headers()) && config.is_traced_size(request.body()) {
+                let (parts, body) = request.into_parts();
+                // the size hint is an upper bound, the limit is not hit
+                let body = to_bytes(body, max_size).await.unwrap_or_default();
+                trace.record("request", &route, &trace.render(&parts.headers, &body));
+                Request::from_parts(parts, Body::from(body))
+            } else {
+                request
+            };
+
+            let response = inner.call(request).await?;
+
+            let response = if config.is_traced_content(response.headers()) && config.is_traced_size(response.body()) {
+                let (parts, body) = response.into_parts();
+                let body = to_bytes(body, max_size).await.unwrap_or_default();
+                trace.record("response", &route, &trace.render(&parts.headers, &body));
+                Response::from_parts(parts, Body::from(body))
+            } else {
+                response
+            };
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+    use shine_test::test;
+
+    #[test]
+    fn json_masks() {
+        let config = BodyTraceConfig {
+            masks: vec!["$.user.email".into(), "$.items[*].iban".into(), "tags[1]".into()],
+            ..Default::default()
+        };
+        let trace = config.into_layer().0;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json; charset=utf-8"),
+        );
+        let body = Bytes::from(
+            serde_json::json!({
+                "user": {"name": "a", "email": "a@b.c", "password": "p"},
+                "items": [{"iban": "x", "amount": 1}, {"iban": "y"}],
+                "tags": ["t0", "t1"]
+            })
+            .to_string(),
+        );
+        let rendered: JsonValue = serde_json::from_str(&trace.render(&headers, &body)).unwrap();
+        assert_eq!(
+            rendered,
+            serde_json::json!({
+                "user": {"name": "a", "email": "***", "password": "***"},
+                "items": [{"iban": "***", "amount": 1}, {"iban": "***"}],
+                "tags": ["t0", "***"]
+            })
+        );
+        assert!(trace.config.is_traced_content(&headers));
+        assert!(!trace.config.is_traced_content(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn form_and_unparsed_bodies() {
+        let config = BodyTraceConfig {
+            masks: vec!["$.email".into()],
+            ..Default::default()
+        };
+        let trace = config.into_layer().0;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        let body = Bytes::from_static(b"user=a&password=secret&email=a%40b.c");
+        assert_eq!(trace.render(&headers, &body), "user=a&password=***&email=***");
+
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        assert_eq!(
+            trace.render(&headers, &Bytes::from_static(b"{\"password\": \"p\"")),
+            REDACTED
+        );
+
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        assert_eq!(trace.render(&headers, &Bytes::from_static(b"password=p")), REDACTED);
+    }
+
+    #[test]
+    fn traced_routes_and_sizes() {
+        let config = BodyTraceConfig {
+            routes: vec!["/api/users/:id".into()],
+            max_size: ByteSize::new(8),
+            ..Default::default()
+        };
+        assert!(config.is_traced_route("/api/users/:id"));
+        assert!(!config.is_traced_route("/api/users"));
+        assert!(config.is_traced_size(&Body::from("short")));
+        assert!(!config.is_traced_size(&Body::from("too long body")));
+    }
+}
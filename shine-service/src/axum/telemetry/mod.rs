@@ -1,6 +1,22 @@
 mod otel_http;
+#[cfg(feature = "grpc")]
+pub(crate) use self::otel_http::extract_context;
+pub use self::otel_http::update_span_from_service_error;
+#[cfg(any(feature = "grpc", feature = "http_client"))]
+pub(crate) use self::otel_http::TRACING_TARGET;
+pub(crate) use self::otel_http::{current_trace_id, record_problem_type};
 
 mod otel_layer;
 pub use self::otel_layer::*;
+mod tenant_metrics;
+pub use self::tenant_metrics::*;
 mod telemetry_service;
 pub use self::telemetry_service::*;
+mod telemetry_compat;
+pub use self::telemetry_compat::*;
+mod trace_link;
+pub use self::trace_link::*;
+mod resilient_exporter;
+pub use self::resilient_exporter::*;
+mod body_trace;
+pub use self::body_trace::*;
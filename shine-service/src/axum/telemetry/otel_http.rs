@@ -1,10 +1,12 @@
+use crate::axum::ServiceError;
 use axum::{
     extract::MatchedPath,
     http::{header, HeaderMap, Method, Request, Response, Uri, Version},
 };
-use opentelemetry::{propagation::Extractor, Context};
+use opentelemetry::{propagation::Extractor, trace::TraceContextExt, Context};
 use std::{borrow::Cow, error::Error as StdError};
 use tracing::{field::Empty, trace_span, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 pub const TRACING_TARGET: &str = "otel::tracing";
 
@@ -106,6 +108,9 @@ pub fn make_span_from_request<B>(req: &Request<B>) -> Span {
         trace_id = Empty, // set on response
         //request_id = Empty, // set
         exception.message = Empty, // set on response
+        problem.type = Empty, // set on server error problems
+        error.category = Empty, // set on service errors
+        error.retryable = Empty, // set on service errors
         "span.type" = "web", // non-official open-telemetry key, only supported by Datadog
     )
 }
@@ -119,6 +124,17 @@ pub fn update_span_from_response<B>(span: &Span, response: &Response<B>) {
     }
 }
 
+/// Return the id of the trace of the current span, if there is an active trace.
+pub fn current_trace_id() -> Option<String> {
+    let span_context = Span::current().context().span().span_context().clone();
+    span_context.is_valid().then(|| span_context.trace_id().to_string())
+}
+
+/// Record the type of a problem response on the span.
+pub fn record_problem_type(span: &Span, ty: &str) {
+    span.record("problem.type", ty);
+}
+
 pub fn update_span_from_error<E>(span: &Span, error: &E)
 where
     E: StdError,
@@ -128,6 +144,15 @@ where
     error.source().map(|s| span.record("exception.message", s.to_string()));
 }
 
+pub fn update_span_from_service_error<E>(span: &Span, error: &E)
+where
+    E: ServiceError,
+{
+    update_span_from_error(span, error);
+    span.record("error.category", error.category().as_str());
+    span.record("error.retryable", error.is_retryable());
+}
+
 pub fn update_span_from_response_or_error<B, E>(span: &Span, response: &Result<Response<B>, E>)
 where
     E: StdError,
@@ -3,12 +3,14 @@ use axum::{
     extract::MatchedPath,
     http::{Method, Request, Response},
 };
+use bytes::Buf;
 use futures::ready;
+use http_body::{Body, Frame, SizeHint};
 use opentelemetry::{
     metrics::{Counter, Histogram, Meter},
     KeyValue,
 };
-use pin_project::pin_project;
+use pin_project::{pin_project, pinned_drop};
 use std::{
     error::Error as StdError,
     future::Future,
@@ -57,6 +59,7 @@ impl<S> Layer<S> for OtelLayer {
             request_counter: meter.u64_counter("request_count").init(),
             request_duration: meter.f64_histogram("request_duration").init(),
             error_counter: meter.u64_counter("error_count").init(),
+            response_body_size: meter.u64_histogram("http.response.body.size").init(),
         });
 
         OtelService {
@@ -79,6 +82,7 @@ struct OtelMeters {
     request_counter: Counter<u64>,
     request_duration: Histogram<f64>,
     error_counter: Counter<u64>,
+    response_body_size: Histogram<u64>,
 }
 
 #[derive(Clone)]
@@ -93,7 +97,7 @@ where
     S: Service<Request<ReqBody>, Response = Response<ResBody>>,
     S::Error: StdError + 'static,
 {
-    type Response = S::Response;
+    type Response = Response<MeteredBody<ResBody>>;
     type Error = S::Error;
     type Future = ResponseFuture<S::Future>;
 
@@ -149,19 +153,18 @@ where
     Fut: Future<Output = Result<Response<B>, E>>,
     E: std::error::Error + 'static,
 {
-    type Output = Result<Response<B>, E>;
+    type Output = Result<Response<MeteredBody<B>>, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
         let _guard = this.span.enter();
         let result = ready!(this.inner.poll(cx));
+        let ep_attribute = [
+            KeyValue::new("method", this.context.method.to_string()),
+            KeyValue::new("route", this.context.route.clone()),
+        ];
 
         if let Some(meters) = this.meters.as_ref() {
-            let ep_attribute = [
-                KeyValue::new("method", this.context.method.to_string()),
-                KeyValue::new("route", this.context.route.clone()),
-            ];
-
             if result.is_err() {
                 meters.error_counter.add(1, &ep_attribute);
             }
@@ -172,6 +175,75 @@ where
         }
 
         otel_http::update_span_from_response_or_error(this.span, &result);
-        Poll::Ready(result)
+        let recorder = this
+            .meters
+            .as_ref()
+            .map(|meters| (meters.response_body_size.clone(), ep_attribute));
+        Poll::Ready(result.map(|response| {
+            response.map(|inner| MeteredBody {
+                inner,
+                size: 0,
+                recorder,
+            })
+        }))
+    }
+}
+
+/// Response body recording the number of the transferred bytes when the body is completed. As the server
+/// stops polling a body once it reports the end of the stream (or never polls an empty body), the size is
+/// recorded after the last frame or, the latest, when the body is dropped.
+#[pin_project(PinnedDrop)]
+pub struct MeteredBody<B> {
+    #[pin]
+    inner: B,
+    size: u64,
+    recorder: Option<(Histogram<u64>, [KeyValue; 2])>,
+}
+
+fn record_body_size(size: u64, recorder: &mut Option<(Histogram<u64>, [KeyValue; 2])>) {
+    if let Some((histogram, attributes)) = recorder.take() {
+        histogram.record(size, &attributes);
+    }
+}
+
+#[pinned_drop]
+impl<B> PinnedDrop for MeteredBody<B> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        record_body_size(*this.size, this.recorder);
+    }
+}
+
+impl<B> Body for MeteredBody<B>
+where
+    B: Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.as_mut().poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    *this.size += data.remaining() as u64;
+                }
+                if this.inner.is_end_stream() {
+                    record_body_size(*this.size, this.recorder);
+                }
+            }
+            Some(Err(_)) => {}
+            None => record_body_size(*this.size, this.recorder),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
     }
 }
@@ -0,0 +1,261 @@
+use crate::utils::DurationStr;
+use futures::future::BoxFuture;
+use opentelemetry::{
+    metrics::{Counter, Gauge, Meter},
+    trace::TraceError,
+};
+use opentelemetry_sdk::{
+    export::trace::{ExportResult, SpanData, SpanExporter},
+    Resource,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Behavior of the span export when the collector is not available.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExporterOutageConfig {
+    /// Number of the consecutive failed exports after which the exporter is suspended.
+    pub failure_threshold: u32,
+    /// Time to wait before a suspended exporter tries to reconnect, ex: `"30s"`.
+    pub retry_interval: DurationStr,
+}
+
+impl Default for ExporterOutageConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            retry_interval: DurationStr::from_secs(30),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ExporterMeters {
+    exported: Counter<u64>,
+    dropped: Counter<u64>,
+    failures: Counter<u64>,
+    suspended: Gauge<u64>,
+}
+
+/// Snapshot of the health of the span exporter.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExporterHealthStatus {
+    pub exported_spans: u64,
+    pub dropped_spans: u64,
+    pub failed_exports: u64,
+    pub suspended: bool,
+}
+
+/// Shared health state of a span exporter.
+#[derive(Default)]
+pub struct ExporterHealth {
+    exported: AtomicU64,
+    dropped: AtomicU64,
+    failures: AtomicU64,
+    consecutive_failures: AtomicU32,
+    suspended_until: Mutex<Option<Instant>>,
+    meters: Option<ExporterMeters>,
+}
+
+impl ExporterHealth {
+    pub fn new(meter: Option<&Meter>) -> Self {
+        Self {
+            meters: meter.map(|meter| ExporterMeters {
+                exported: meter.u64_counter("telemetry_exported_spans").init(),
+                dropped: meter.u64_counter("telemetry_dropped_spans").init(),
+                failures: meter.u64_counter("telemetry_export_failures").init(),
+                suspended: meter.u64_gauge("telemetry_exporter_suspended").init(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    pub fn status(&self) -> ExporterHealthStatus {
+        ExporterHealthStatus {
+            exported_spans: self.exported.load(Ordering::Relaxed),
+            dropped_spans: self.dropped.load(Ordering::Relaxed),
+            failed_exports: self.failures.load(Ordering::Relaxed),
+            suspended: self.suspended_until.lock().unwrap().is_some(),
+        }
+    }
+
+    fn record_dropped(&self, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+        if let Some(meters) = &self.meters {
+            meters.dropped.add(count, &[]);
+        }
+    }
+
+    fn record_success(&self, count: u64) {
+        self.exported.fetch_add(count, Ordering::Relaxed);
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        if self.suspended_until.lock().unwrap().take().is_some() {
+            log::warn!("Span exporter recovered, resuming the export");
+        }
+        if let Some(meters) = &self.meters {
+            meters.exported.add(count, &[]);
+            meters.suspended.record(0, &[]);
+        }
+    }
+
+    fn record_failure(&self, count: u64, config: &ExporterOutageConfig, err: &TraceError) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        self.record_dropped(count);
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(meters) = &self.meters {
+            meters.failures.add(1, &[]);
+        }
+
+        if failures >= config.failure_threshold {
+            let mut suspended_until = self.suspended_until.lock().unwrap();
+            if suspended_until.is_none() {
+                log::warn!(
+                    "Span export failed {failures} times ({err}), suspending the export for {}",
+                    config.retry_interval
+                );
+            }
+            *suspended_until = Some(Instant::now() + config.retry_interval.as_duration());
+            if let Some(meters) = &self.meters {
+                meters.suspended.record(1, &[]);
+            }
+        }
+    }
+
+    /// Check if the export is suspended. When the retry interval has elapsed, a single export is let
+    /// through to probe the collector.
+    fn is_suspended(&self, retry_interval: Duration) -> bool {
+        let mut suspended_until = self.suspended_until.lock().unwrap();
+        match *suspended_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // keep suspended while the probe is in flight
+                *suspended_until = Some(Instant::now() + retry_interval);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Wrap a span exporter to keep the service healthy during a collector outage: after consecutive
+/// failures the spans are dropped (and counted) without calling the exporter, and the exporter is
+/// probed periodically to resume the export.
+pub struct ResilientSpanExporter<E: SpanExporter> {
+    inner: E,
+    config: ExporterOutageConfig,
+    health: Arc<ExporterHealth>,
+}
+
+impl<E: SpanExporter> ResilientSpanExporter<E> {
+    pub fn new(inner: E, config: ExporterOutageConfig, health: Arc<ExporterHealth>) -> Self {
+        Self { inner, config, health }
+    }
+}
+
+impl<E: SpanExporter> fmt::Debug for ResilientSpanExporter<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResilientSpanExporter")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for ResilientSpanExporter<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let count = batch.len() as u64;
+        let retry_interval = self.config.retry_interval.as_duration();
+        if self.health.is_suspended(retry_interval) {
+            self.health.record_dropped(count);
+            return Box::pin(async { Ok(()) });
+        }
+
+        let export = self.inner.export(batch);
+        let health = self.health.clone();
+        let config = self.config.clone();
+        Box::pin(async move {
+            match export.await {
+                Ok(()) => health.record_success(count),
+                Err(err) => health.record_failure(count, &config, &err),
+            }
+            // the failure is handled here, reporting it would only flood the global error handler
+            Ok(())
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.force_flush()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+    use std::sync::atomic::AtomicBool;
+
+    #[derive(Debug, Default)]
+    struct FlakyExporter {
+        down: Arc<AtomicBool>,
+        calls: Arc<AtomicU32>,
+    }
+
+    impl SpanExporter for FlakyExporter {
+        fn export(&mut self, _batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            let down = self.down.load(Ordering::Relaxed);
+            Box::pin(async move {
+                if down {
+                    Err(TraceError::Other("collector is down".into()))
+                } else {
+                    Ok(())
+                }
+            })
+        }
+    }
+
+    #[test]
+    async fn suspend_and_recover() {
+        let flaky = FlakyExporter::default();
+        let (down, calls) = (flaky.down.clone(), flaky.calls.clone());
+        let health = Arc::new(ExporterHealth::new(None));
+        let config = ExporterOutageConfig {
+            failure_threshold: 2,
+            retry_interval: DurationStr::from_secs(0),
+        };
+        let mut exporter = ResilientSpanExporter::new(flaky, config, health.clone());
+
+        exporter.export(Vec::new()).await.unwrap();
+        down.store(true, Ordering::Relaxed);
+        exporter.export(Vec::new()).await.unwrap();
+        assert!(!health.status().suspended);
+        exporter.export(Vec::new()).await.unwrap();
+        assert!(health.status().suspended);
+        assert_eq!(health.status().failed_exports, 2);
+
+        // the probe fails, then the collector recovers
+        exporter.export(Vec::new()).await.unwrap();
+        assert!(health.status().suspended);
+        down.store(false, Ordering::Relaxed);
+        exporter.export(Vec::new()).await.unwrap();
+        assert!(!health.status().suspended);
+        assert_eq!(calls.load(Ordering::Relaxed), 5);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::axum::telemetry::{TelemetryBuildError, TelemetryConfig, TelemetryService, Tracing};
+use serde::{Deserialize, Serialize};
+
+/// Configuration shape of the former tracing manager, it has no metrics support.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TracingConfig {
+    #[serde(default)]
+    pub allow_reconfigure: bool,
+    #[serde(default)]
+    pub enable_console_log: bool,
+    #[serde(default = "default_tracing")]
+    pub tracing: Tracing,
+    pub default_level: Option<String>,
+}
+
+fn default_tracing() -> Tracing {
+    Tracing::None
+}
+
+impl From<TracingConfig> for TelemetryConfig {
+    fn from(config: TracingConfig) -> Self {
+        TelemetryConfig {
+            allow_reconfigure: config.allow_reconfigure,
+            enable_console_log: config.enable_console_log,
+            metrics: false,
+            tracing: config.tracing,
+            default_level: config.default_level,
+            tenant_metrics: None,
+        }
+    }
+}
+
+/// Configuration shape of the former telemetry manager where the tracing options were nested.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Telemetry {
+    #[serde(default)]
+    pub metrics: bool,
+    pub tracing: TracingConfig,
+}
+
+impl From<Telemetry> for TelemetryConfig {
+    fn from(config: Telemetry) -> Self {
+        TelemetryConfig {
+            metrics: config.metrics,
+            ..config.tracing.into()
+        }
+    }
+}
+
+/// Accept any of the supported telemetry configuration shapes to allow an incremental migration. The former
+/// shapes reject the unknown fields, thus a mistyped configuration fails instead of falling back to the defaults
+/// of the former tracing configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AnyTelemetryConfig {
+    Current(TelemetryConfig),
+    Telemetry(Telemetry),
+    Tracing(TracingConfig),
+}
+
+impl From<AnyTelemetryConfig> for TelemetryConfig {
+    fn from(config: AnyTelemetryConfig) -> Self {
+        match config {
+            AnyTelemetryConfig::Current(config) => config,
+            AnyTelemetryConfig::Telemetry(config) => {
+                log::warn!("Deprecated telemetry configuration, migrate to TelemetryConfig");
+                config.into()
+            }
+            AnyTelemetryConfig::Tracing(config) => {
+                log::warn!("Deprecated tracing configuration, migrate to TelemetryConfig");
+                config.into()
+            }
+        }
+    }
+}
+
+impl TelemetryService {
+    /// Create a Service from the former tracing manager configuration.
+    pub async fn from_tracing_config(
+        service_name: &'static str,
+        config: TracingConfig,
+    ) -> Result<Self, TelemetryBuildError> {
+        Self::new(service_name, &config.into()).await
+    }
+
+    /// Create a Service from any of the supported configuration shapes.
+    pub async fn from_any_config(
+        service_name: &'static str,
+        config: AnyTelemetryConfig,
+    ) -> Result<Self, TelemetryBuildError> {
+        Self::new(service_name, &config.into()).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn parse_legacy_shapes() {
+        let current = r#"{"allowReconfigure":true,"enableConsoleLog":false,"metrics":true,"tracing":{"type":"none"}}"#;
+        let config: TelemetryConfig = serde_json::from_str::<AnyTelemetryConfig>(current).unwrap().into();
+        assert!(config.metrics && config.allow_reconfigure);
+
+        let telemetry = r#"{"metrics":true,"tracing":{"enableConsoleLog":true,"tracing":{"type":"stdOut"}}}"#;
+        let config: TelemetryConfig = serde_json::from_str::<AnyTelemetryConfig>(telemetry).unwrap().into();
+        assert!(config.metrics && config.enable_console_log);
+        assert!(matches!(config.tracing, Tracing::StdOut));
+
+        let tracing = r#"{"allowReconfigure":true,"defaultLevel":"info"}"#;
+        let config: TelemetryConfig = serde_json::from_str::<AnyTelemetryConfig>(tracing).unwrap().into();
+        assert!(!config.metrics && config.allow_reconfigure);
+        assert_eq!(config.default_level.as_deref(), Some("info"));
+    }
+
+    #[test]
+    fn reject_mistyped_config() {
+        let typo = r#"{"allowReconfigure":true,"enableConsoleLog":false,"metrcs":true,"tracing":{"type":"none"}}"#;
+        assert!(serde_json::from_str::<AnyTelemetryConfig>(typo).is_err());
+
+        let typo = r#"{"metrics":true,"tracing":{"enableConsoleLg":true}}"#;
+        assert!(serde_json::from_str::<AnyTelemetryConfig>(typo).is_err());
+    }
+}
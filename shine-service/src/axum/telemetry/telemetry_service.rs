@@ -1,11 +1,24 @@
-use crate::axum::telemetry::OtelLayer;
+#[cfg(feature = "ot_otlp")]
+use crate::axum::telemetry::{ExporterOutageConfig, ResilientSpanExporter};
+use crate::{
+    axum::telemetry::{ExporterHealth, OtelLayer, TenantMetricsConfig, TenantMetricsError, TenantRegistries},
+    utils::Sensitive,
+};
+#[cfg(feature = "ot_otlp")]
+use crate::{
+    service::cacerts::{get_root_certs, to_pem},
+    utils::DurationStr,
+};
 use opentelemetry::{
     global,
     metrics::{Meter, MeterProvider, MetricsError},
     trace::{TraceError, Tracer, TracerProvider as _},
     KeyValue,
 };
-use opentelemetry_otlp::WithExportConfig;
+#[cfg(feature = "ot_otlp")]
+use opentelemetry_otlp::{Compression, SpanExporterBuilder, TonicExporterBuilder, WithExportConfig};
+#[cfg(feature = "ot_otlp")]
+use opentelemetry_sdk::trace::{BatchConfigBuilder, BatchSpanProcessor};
 use opentelemetry_sdk::{
     metrics::SdkMeterProvider,
     runtime::Tokio,
@@ -15,8 +28,15 @@ use opentelemetry_sdk::{
 use opentelemetry_semantic_conventions as otconv;
 use prometheus::{Encoder, Registry as PromRegistry, TextEncoder};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "ot_otlp")]
+use std::collections::HashMap;
 use std::{error::Error as StdError, sync::Arc};
 use thiserror::Error as ThisError;
+#[cfg(feature = "ot_otlp")]
+use tonic::{
+    metadata::{MetadataKey, MetadataMap, MetadataValue},
+    transport::{Certificate, ClientTlsConfig},
+};
 use tracing::{level_filters::LevelFilter, subscriber::SetGlobalDefaultError, Dispatch, Subscriber};
 use tracing_opentelemetry::{OpenTelemetryLayer, PreSampledTracer};
 use tracing_subscriber::{
@@ -40,6 +60,42 @@ pub enum TelemetryBuildError {
     TraceError(#[from] TraceError),
     #[error(transparent)]
     MetricsError(#[from] MetricsError),
+    #[cfg(feature = "ot_otlp")]
+    #[error("Invalid OTLP exporter configuration: {0}")]
+    OtlpConfigError(String),
+}
+
+/// TLS settings of the OTLP exporter. The root certificates of the crate's cert store (see `cacerts`) are
+/// always trusted.
+#[cfg(feature = "ot_otlp")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OtlpTlsConfig {
+    /// Override the domain name used to verify the certificate of the collector.
+    pub domain_name: Option<String>,
+    /// PEM file of the additional CAs added to the cert store.
+    pub ca_file: Option<String>,
+}
+
+#[cfg(feature = "ot_otlp")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OtlpConfig {
+    pub endpoint: String,
+    /// Additional metadata sent with each export request, ex: api-key of the collector.
+    #[serde(default)]
+    pub headers: HashMap<String, Sensitive<String>>,
+    pub tls: Option<OtlpTlsConfig>,
+    /// Compress the exported data with gzip.
+    #[serde(default)]
+    pub gzip: bool,
+    /// Export timeout, ex: `"10s"`.
+    pub timeout: Option<DurationStr>,
+    /// Maximum number of spans buffered for export, spans are dropped when the queue is full.
+    pub max_queue_size: Option<usize>,
+    /// Suspend the export while the collector is not available.
+    #[serde(default)]
+    pub outage: ExporterOutageConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -54,7 +110,7 @@ pub enum Tracing {
 
     /// Enable Jaeger tracing (https://www.jaegertracing.io)
     #[cfg(feature = "ot_otlp")]
-    OpenTelemetryProtocol { endpoint: String },
+    OpenTelemetryProtocol(OtlpConfig),
 
     /// Enable Zipkin tracing (https://zipkin.io/)
     #[cfg(feature = "ot_zipkin")]
@@ -62,17 +118,20 @@ pub enum Tracing {
 
     /// Enable AppInsight tracing
     #[cfg(feature = "ot_app_insight")]
-    AppInsight { instrumentation_key: String },
+    AppInsight { instrumentation_key: Sensitive<String> },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TelemetryConfig {
-    allow_reconfigure: bool,
-    enable_console_log: bool,
-    metrics: bool,
-    tracing: Tracing,
-    default_level: Option<String>,
+    pub(crate) allow_reconfigure: bool,
+    pub(crate) enable_console_log: bool,
+    pub(crate) metrics: bool,
+    pub(crate) tracing: Tracing,
+    pub(crate) default_level: Option<String>,
+    /// Enable separately scraped per-tenant metrics
+    #[serde(default)]
+    pub(crate) tenant_metrics: Option<TenantMetricsConfig>,
 }
 
 trait DynHandle: Send + Sync {
@@ -116,6 +175,8 @@ pub struct Metrics {
 pub struct TelemetryService {
     reconfigure: Option<Arc<dyn DynHandle>>,
     metrics: Option<Metrics>,
+    tenant_metrics: Option<Arc<TenantRegistries>>,
+    exporter_health: Option<Arc<ExporterHealth>>,
 }
 
 impl TelemetryService {
@@ -124,6 +185,11 @@ impl TelemetryService {
         let mut service = TelemetryService {
             reconfigure: None,
             metrics: None,
+            tenant_metrics: config
+                .tenant_metrics
+                .clone()
+                .map(|config| Arc::new(TenantRegistries::new(config))),
+            exporter_health: None,
         };
         service.install_telemetry(service_name, config)?;
         Ok(service)
@@ -193,6 +259,48 @@ impl TelemetryService {
             .with_tracer(tracer)
     }
 
+    #[cfg(feature = "ot_otlp")]
+    fn otlp_exporter(config: &OtlpConfig) -> Result<TonicExporterBuilder, TelemetryBuildError> {
+        let mut exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(&config.endpoint);
+
+        if !config.headers.is_empty() {
+            let mut metadata = MetadataMap::new();
+            for (name, value) in &config.headers {
+                let key = MetadataKey::from_bytes(name.to_ascii_lowercase().as_bytes())
+                    .map_err(|err| TelemetryBuildError::OtlpConfigError(format!("Invalid header {name}: {err}")))?;
+                let value = MetadataValue::try_from(value.expose().as_str())
+                    .map_err(|err| TelemetryBuildError::OtlpConfigError(format!("Invalid header {name}: {err}")))?;
+                metadata.insert(key, value);
+            }
+            exporter = exporter.with_metadata(metadata);
+        }
+
+        if let Some(tls) = &config.tls {
+            // tonic accepts only PEM certificates, thus the cert store is passed in PEM
+            let ca_files: Vec<&str> = tls.ca_file.iter().map(String::as_str).collect();
+            let certs =
+                get_root_certs(&ca_files).map_err(|err| TelemetryBuildError::OtlpConfigError(err.to_string()))?;
+            let mut tls_config = certs.iter().fold(ClientTlsConfig::new(), |tls_config, cert| {
+                tls_config.ca_certificate(Certificate::from_pem(to_pem(cert)))
+            });
+            if let Some(domain_name) = &tls.domain_name {
+                tls_config = tls_config.domain_name(domain_name);
+            }
+            exporter = exporter.with_tls_config(tls_config);
+        }
+
+        if config.gzip {
+            exporter = exporter.with_compression(Compression::Gzip);
+        }
+        if let Some(timeout) = config.timeout {
+            exporter = exporter.with_timeout(timeout.into());
+        }
+
+        Ok(exporter)
+    }
+
     fn install_telemetry(
         &mut self,
         service_name: &'static str,
@@ -245,15 +353,32 @@ impl TelemetryService {
                 self.install_tracing_layer(config, Self::ot_layer(tracer))?;
             }
             #[cfg(feature = "ot_otlp")]
-            Tracing::OpenTelemetryProtocol { endpoint } => {
+            Tracing::OpenTelemetryProtocol(otlp) => {
                 log::info!("Registering OpenTelemetryProtocol tracing...");
-                let exporter = opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint);
-                let tracer = opentelemetry_otlp::new_pipeline()
-                    .tracing()
-                    .with_exporter(exporter)
-                    .with_trace_config(OtConfig::default().with_resource(resource))
-                    .install_batch(Tokio)?
-                    .tracer("otlp");
+                let exporter = SpanExporterBuilder::from(Self::otlp_exporter(otlp)?).build_span_exporter()?;
+                let meter = self.metrics.as_ref().map(|m| m.provider.meter("telemetry"));
+                let health = Arc::new(ExporterHealth::new(meter.as_ref()));
+                let exporter = ResilientSpanExporter::new(exporter, otlp.outage.clone(), health.clone());
+                self.exporter_health = Some(health);
+
+                // the queue is bounded, the spans are dropped when it is full
+                let mut batch_config = BatchConfigBuilder::default();
+                if let Some(max_queue_size) = otlp.max_queue_size {
+                    batch_config = batch_config.with_max_queue_size(max_queue_size);
+                }
+                let processor = BatchSpanProcessor::builder(exporter, Tokio)
+                    .with_batch_config(batch_config.build())
+                    .build();
+                let provider = TracerProvider::builder()
+                    .with_span_processor(processor)
+                    .with_config(OtConfig::default().with_resource(resource))
+                    .build();
+                let tracer = provider
+                    .tracer_builder("otlp")
+                    .with_version(env!("CARGO_PKG_VERSION"))
+                    .with_schema_url(otconv::SCHEMA_URL)
+                    .build();
+                let _ = global::set_tracer_provider(provider);
                 self.install_tracing_layer(config, Self::ot_layer(tracer))?;
             }
             #[cfg(feature = "ot_zipkin")]
@@ -268,7 +393,7 @@ impl TelemetryService {
             #[cfg(feature = "ot_app_insight")]
             Tracing::AppInsight { instrumentation_key } => {
                 log::info!("Registering AppInsight tracing...");
-                let key = instrumentation_key.clone();
+                let key = instrumentation_key.expose().clone();
                 let tracer = opentelemetry_application_insights::new_pipeline_from_connection_string(key)
                     .map_err(TelemetryBuildError::AppInsightConfigError)?
                     .with_trace_config(OtConfig::default().with_resource(resource))
@@ -301,6 +426,11 @@ impl TelemetryService {
         }
     }
 
+    /// Health of the span exporter, if the tracing is exported to a collector.
+    pub fn exporter_health(&self) -> Option<&Arc<ExporterHealth>> {
+        self.exporter_health.as_ref()
+    }
+
     pub fn create_meter(&self, metrics_scope: &'static str) -> Option<Meter> {
         self.metrics.as_ref().map(|m| m.provider.meter(metrics_scope))
     }
@@ -321,6 +451,19 @@ impl TelemetryService {
         }
     }
 
+    /// Get the prometheus registry of a tenant to register the tenant specific metrics.
+    pub fn tenant_registry(&self, tenant: &str) -> Option<Result<PromRegistry, TenantMetricsError>> {
+        self.tenant_metrics.as_ref().map(|t| t.registry(tenant))
+    }
+
+    pub fn has_tenant_metrics(&self) -> bool {
+        self.tenant_metrics.is_some()
+    }
+
+    pub fn tenant_metrics(&self, tenant: &str) -> Option<String> {
+        self.tenant_metrics.as_ref().and_then(|t| t.metrics(tenant))
+    }
+
     pub fn create_layer(&self) -> OtelLayer {
         //todo: read route filtering from config
         let mut layer = OtelLayer::default();
@@ -0,0 +1,145 @@
+use prometheus::{proto::MetricFamily, Encoder, Registry as PromRegistry, TextEncoder};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::RwLock};
+use thiserror::Error as ThisError;
+
+const MAX_TENANT_ID_LENGTH: usize = 64;
+
+#[derive(Debug, ThisError)]
+pub enum TenantMetricsError {
+    #[error("Invalid tenant id: {0}")]
+    InvalidTenant(String),
+    #[error("Tenant limit ({0}) reached")]
+    TenantLimitReached(usize),
+    #[error(transparent)]
+    PrometheusError(#[from] prometheus::Error),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantMetricsConfig {
+    /// Maximum number of tenant registries, registration of new tenants fails above this limit.
+    pub max_tenants: usize,
+    /// Maximum number of the label sets (series) in a tenant registry, the rest is dropped from the scrape.
+    pub max_series_per_tenant: usize,
+}
+
+/// Partitioned prometheus registries with a registry for each tenant.
+pub struct TenantRegistries {
+    config: TenantMetricsConfig,
+    registries: RwLock<HashMap<String, PromRegistry>>,
+}
+
+impl TenantRegistries {
+    pub fn new(config: TenantMetricsConfig) -> Self {
+        Self {
+            config,
+            registries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn check_tenant(tenant: &str) -> Result<(), TenantMetricsError> {
+        if tenant.is_empty()
+            || tenant.len() > MAX_TENANT_ID_LENGTH
+            || !tenant
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            Err(TenantMetricsError::InvalidTenant(tenant.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get or create the registry of a tenant.
+    pub fn registry(&self, tenant: &str) -> Result<PromRegistry, TenantMetricsError> {
+        Self::check_tenant(tenant)?;
+
+        if let Some(registry) = self.registries.read().unwrap().get(tenant) {
+            return Ok(registry.clone());
+        }
+
+        let mut registries = self.registries.write().unwrap();
+        if let Some(registry) = registries.get(tenant) {
+            return Ok(registry.clone());
+        }
+        if registries.len() >= self.config.max_tenants {
+            return Err(TenantMetricsError::TenantLimitReached(self.config.max_tenants));
+        }
+
+        let mut labels = HashMap::new();
+        labels.insert("tenant".to_string(), tenant.to_string());
+        let registry = PromRegistry::new_custom(None, Some(labels))?;
+        registries.insert(tenant.to_string(), registry.clone());
+        Ok(registry)
+    }
+
+    /// Keep the first `max_series` label sets, the families without any remaining series are dropped.
+    fn limit_series(metric_families: &mut Vec<MetricFamily>, max_series: usize) {
+        let mut remaining = max_series;
+        for family in metric_families.iter_mut() {
+            let metrics = family.mut_metric();
+            metrics.truncate(remaining);
+            remaining -= metrics.len();
+        }
+        metric_families.retain(|family| !family.get_metric().is_empty());
+    }
+
+    /// Encode the metrics of a tenant in the prometheus text format.
+    pub fn metrics(&self, tenant: &str) -> Option<String> {
+        let registry = self.registries.read().unwrap().get(tenant).cloned()?;
+
+        let mut metric_families = registry.gather();
+        let series = metric_families
+            .iter()
+            .map(|family| family.get_metric().len())
+            .sum::<usize>();
+        if series > self.config.max_series_per_tenant {
+            log::warn!(
+                "Tenant {tenant} has {series} series, limited to {}",
+                self.config.max_series_per_tenant
+            );
+            Self::limit_series(&mut metric_families, self.config.max_series_per_tenant);
+        }
+
+        let mut buffer = vec![];
+        let encoder = TextEncoder::new();
+        encoder.encode(&metric_families, &mut buffer).unwrap();
+        Some(String::from_utf8(buffer).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use prometheus::{IntCounter, IntCounterVec, Opts};
+    use shine_test::test;
+
+    #[test]
+    fn series_limit() {
+        let registries = TenantRegistries::new(TenantMetricsConfig {
+            max_tenants: 1,
+            max_series_per_tenant: 3,
+        });
+        let registry = registries.registry("tenant-a").unwrap();
+
+        let requests = IntCounterVec::new(Opts::new("requests", "requests"), &["route"]).unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        for route in ["a", "b", "c", "d"] {
+            requests.with_label_values(&[route]).inc();
+        }
+        let sessions = IntCounter::new("sessions", "sessions").unwrap();
+        registry.register(Box::new(sessions.clone())).unwrap();
+        sessions.inc();
+
+        let metrics = registries.metrics("tenant-a").unwrap();
+        let series = metrics.lines().filter(|line| !line.starts_with('#')).count();
+        assert_eq!(series, 3);
+        assert!(!metrics.contains("sessions"));
+
+        assert!(matches!(
+            registries.registry("tenant-b"),
+            Err(TenantMetricsError::TenantLimitReached(1))
+        ));
+    }
+}
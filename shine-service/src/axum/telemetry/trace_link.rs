@@ -0,0 +1,107 @@
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    trace::{SpanContext, TraceContextExt},
+};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "redis")]
+use shine_macros::RedisJsonValue;
+use std::collections::BTreeMap;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// W3C trace context (`traceparent`, `tracestate`) of the span that produced a message, carried along
+/// with the message to link the processing spans to the originating trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "redis", derive(RedisJsonValue))]
+#[serde(transparent)]
+pub struct TraceContext(BTreeMap<String, String>);
+
+impl Injector for TraceContext {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_string(), value);
+    }
+}
+
+impl Extractor for TraceContext {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+impl TraceContext {
+    /// Capture the context of the current span, if it is part of a trace.
+    pub fn current() -> Option<Self> {
+        Self::from_span(&Span::current())
+    }
+
+    pub fn from_span(span: &Span) -> Option<Self> {
+        let context = span.context();
+        if !context.span().span_context().is_valid() {
+            return None;
+        }
+        let mut carrier = Self::default();
+        TraceContextPropagator::new().inject_context(&context, &mut carrier);
+        (!carrier.0.is_empty()).then_some(carrier)
+    }
+
+    pub fn traceparent(&self) -> Option<&str> {
+        self.get("traceparent")
+    }
+
+    /// The propagation headers, ex. to inject them into an outgoing request.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn span_context(&self) -> Option<SpanContext> {
+        let context = TraceContextPropagator::new().extract(self);
+        let span_context = context.span().span_context().clone();
+        span_context.is_valid().then_some(span_context)
+    }
+
+    /// Link the span to the originating trace. Links are preferred over parenting for the async
+    /// processing, as a batch may have many origins and the processing may happen much later.
+    pub fn link_to(&self, span: &Span) {
+        if let Some(span_context) = self.span_context() {
+            span.add_link(span_context);
+        }
+    }
+}
+
+/// Link the span to the traces of all the (traced) items of a batch.
+pub fn link_trace_contexts<'a, I>(span: &Span, contexts: I)
+where
+    I: IntoIterator<Item = &'a TraceContext>,
+{
+    for context in contexts {
+        context.link_to(span);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn parse_traceparent() {
+        let mut context = TraceContext::default();
+        context.set(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string(),
+        );
+        let span_context = context.span_context().unwrap();
+        assert_eq!(span_context.trace_id().to_string(), "0af7651916cd43dd8448eb211c80319c");
+        assert!(span_context.is_remote());
+
+        let json = serde_json::to_string(&context).unwrap();
+        assert_eq!(serde_json::from_str::<TraceContext>(&json).unwrap(), context);
+
+        assert!(TraceContext::default().span_context().is_none());
+    }
+}
@@ -1,17 +1,19 @@
 use crate::{
     axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig},
-    utils::serde_string,
+    utils::{serde_string, ByteSize},
 };
 use axum::{
     async_trait,
     extract::{
-        rejection::{JsonRejection, PathRejection, QueryRejection},
-        FromRequest, FromRequestParts, Path, Query, Request,
+        multipart::{Field, MultipartRejection},
+        rejection::{FormRejection, JsonRejection, PathRejection, QueryRejection},
+        FromRequest, FromRequestParts, Multipart, Path, Query, Request,
     },
-    http::request::Parts,
-    Extension, Json, RequestExt, RequestPartsExt,
+    http::{request::Parts, StatusCode},
+    Extension, Form, Json, RequestExt, RequestPartsExt,
 };
-use serde::{de::DeserializeOwned, Serialize};
+use bytes::{Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::borrow::Cow;
 use thiserror::Error as ThisError;
 use validator::{Validate, ValidationError, ValidationErrors};
@@ -76,6 +78,16 @@ pub enum InputError {
     #[error("Body could not be parsed for input")]
     #[serde(with = "serde_string")]
     JsonFormat(JsonRejection),
+    #[error("Form could not be parsed for input")]
+    #[serde(with = "serde_string")]
+    FormFormat(FormRejection),
+    #[error("Multipart body could not be parsed for input")]
+    #[serde(with = "serde_string")]
+    MultipartFormat(MultipartRejection),
+    #[error("Multipart field could not be parsed for input: {0}")]
+    MultipartField(String),
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
     #[error("Input constraint violated")]
     Constraint(ValidationErrors),
 }
@@ -92,6 +104,18 @@ impl IntoProblem for InputError {
                 Problem::bad_request("body_format_error").with_detail(err.body_text())
             }
             InputError::JsonFormat(err) => Problem::internal_error(config, "Json error", err),
+            InputError::FormFormat(err) if err.status().is_client_error() => {
+                Problem::bad_request("body_format_error").with_detail(err.body_text())
+            }
+            InputError::FormFormat(err) => Problem::internal_error(config, "Form error", err),
+            InputError::MultipartFormat(err) if err.status().is_client_error() => {
+                Problem::bad_request("body_format_error").with_detail(err.body_text())
+            }
+            InputError::MultipartFormat(err) => Problem::internal_error(config, "Multipart error", err),
+            InputError::MultipartField(err) => Problem::bad_request("body_format_error").with_detail(err),
+            InputError::PayloadTooLarge(err) => {
+                Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large").with_detail(err)
+            }
             InputError::Constraint(detail) => Problem::bad_request("validation_error").with_public_extension(detail),
         }
     }
@@ -179,3 +203,247 @@ where
         Ok(Self(data))
     }
 }
+
+pub struct ValidatedForm<T>(pub T)
+where
+    T: Validate + 'static;
+
+#[async_trait]
+impl<S, T> FromRequest<S> for ValidatedForm<T>
+where
+    S: Send + Sync,
+    T: Validate + 'static,
+    Form<T>: FromRequest<(), Rejection = FormRejection>,
+{
+    type Rejection = ConfiguredProblem<InputError>;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = req
+            .extract_parts::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+
+        let Form(data) = req
+            .extract::<Form<T>, _>()
+            .await
+            .map_err(|err| problem_config.configure(InputError::FormFormat(err)))?;
+        data.validate()
+            .map_err(|err| problem_config.configure(InputError::Constraint(err)))?;
+        Ok(Self(data))
+    }
+}
+
+fn default_max_field_size() -> ByteSize {
+    ByteSize::mb(1)
+}
+
+fn default_max_total_size() -> ByteSize {
+    ByteSize::mb(10)
+}
+
+/// Size limits of the multipart requests, the default limits are used if the extension is missing.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultipartLimits {
+    #[serde(default = "default_max_field_size")]
+    pub max_field_size: ByteSize,
+    #[serde(default = "default_max_total_size")]
+    pub max_total_size: ByteSize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_field_size: default_max_field_size(),
+            max_total_size: default_max_total_size(),
+        }
+    }
+}
+
+impl MultipartLimits {
+    pub fn into_layer(self) -> Extension<Self> {
+        Extension(self)
+    }
+}
+
+/// A file uploaded in a multipart request.
+#[derive(Clone, Debug)]
+pub struct MultipartFile {
+    pub name: String,
+    pub file_name: String,
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+/// Multipart request with the text fields mapped into a struct (as in a urlencoded form) and
+/// the files (the fields with a file name) collected separately.
+pub struct ValidatedMultipart<T>
+where
+    T: Validate + 'static,
+{
+    pub data: T,
+    pub files: Vec<MultipartFile>,
+}
+
+async fn read_field(
+    field: &mut Field<'_>,
+    name: &str,
+    limits: &MultipartLimits,
+    total_size: &mut usize,
+) -> Result<Bytes, InputError> {
+    let mut data = BytesMut::new();
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|err| InputError::MultipartField(err.body_text()))?
+    {
+        *total_size += chunk.len();
+        if data.len() + chunk.len() > limits.max_field_size.as_usize() {
+            return Err(InputError::PayloadTooLarge(format!(
+                "Field {name} exceeds {}",
+                limits.max_field_size
+            )));
+        }
+        if *total_size > limits.max_total_size.as_usize() {
+            return Err(InputError::PayloadTooLarge(format!(
+                "Request exceeds {}",
+                limits.max_total_size
+            )));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data.freeze())
+}
+
+async fn read_multipart<T>(
+    mut multipart: Multipart,
+    limits: &MultipartLimits,
+) -> Result<(T, Vec<MultipartFile>), InputError>
+where
+    T: DeserializeOwned,
+{
+    let mut fields = url::form_urlencoded::Serializer::new(String::new());
+    let mut files = Vec::new();
+    let mut total_size = 0;
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| InputError::MultipartField(err.body_text()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        let file_name = field.file_name().map(String::from);
+        let content_type = field.content_type().map(String::from);
+        let data = read_field(&mut field, &name, limits, &mut total_size).await?;
+
+        match file_name {
+            Some(file_name) => files.push(MultipartFile {
+                name,
+                file_name,
+                content_type,
+                data,
+            }),
+            None => {
+                let value = std::str::from_utf8(&data)
+                    .map_err(|_| InputError::MultipartField(format!("Field {name} is not a valid utf-8 text")))?;
+                fields.append_pair(&name, value);
+            }
+        }
+    }
+
+    let data =
+        serde_urlencoded::from_str(&fields.finish()).map_err(|err| InputError::MultipartField(format!("{err}")))?;
+    Ok((data, files))
+}
+
+#[async_trait]
+impl<S, T> FromRequest<S> for ValidatedMultipart<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate + Send + 'static,
+{
+    type Rejection = ConfiguredProblem<InputError>;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = req
+            .extract_parts::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let limits = req
+            .extract_parts::<Extension<MultipartLimits>>()
+            .await
+            .map(|Extension(limits)| limits)
+            .unwrap_or_default();
+
+        let multipart = req
+            .extract::<Multipart, _>()
+            .await
+            .map_err(|err| problem_config.configure(InputError::MultipartFormat(err)))?;
+        let (data, files) = read_multipart::<T>(multipart, &limits)
+            .await
+            .map_err(|err| problem_config.configure(err))?;
+        data.validate()
+            .map_err(|err| problem_config.configure(InputError::Constraint(err)))?;
+        Ok(Self { data, files })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, http::header, response::IntoResponse, routing::post, Router};
+    use shine_test::test;
+    use tower::ServiceExt;
+
+    #[derive(Deserialize, Validate)]
+    struct Upload {
+        #[validate(length(min = 3))]
+        title: String,
+        count: u32,
+    }
+
+    async fn upload(ValidatedMultipart { data, files }: ValidatedMultipart<Upload>) -> impl IntoResponse {
+        format!("{}:{}:{}", data.title, data.count, files.len())
+    }
+
+    async fn call(title: &str, content: &str) -> (StatusCode, String) {
+        let body = format!(
+            "--X\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\n{title}\r\n\
+             --X\r\nContent-Disposition: form-data; name=\"count\"\r\n\r\n2\r\n\
+             --X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n{content}\r\n\
+             --X--\r\n"
+        );
+        let request = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(Body::from(body))
+            .unwrap();
+        let app = Router::new()
+            .route("/", post(upload))
+            .layer(
+                MultipartLimits {
+                    max_field_size: ByteSize::new(16),
+                    max_total_size: ByteSize::new(64),
+                }
+                .into_layer(),
+            )
+            .layer(ProblemConfig::new(false).into_layer());
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    async fn validated_multipart() {
+        assert_eq!(call("hello", "data").await, (StatusCode::OK, "hello:2:1".to_string()));
+
+        let (status, body) = call("hi", "data").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("validation_error"), "{body}");
+
+        let (status, _) = call("hello", "a file content larger than the limit").await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::{
+    axum::{ConfiguredProblem, CorsConfigError, IntoProblem, OriginPattern, Problem, ProblemConfig},
+    service::{CheckedCurrentUser, CurrentUser, UserSessionError},
+};
+use axum::{
+    async_trait,
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        FromRequestParts, MatchedPath,
+    },
+    http::{header, request::Parts},
+    response::Response,
+    Extension, RequestPartsExt,
+};
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter, UpDownCounter},
+    KeyValue,
+};
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+use tokio::time::{interval_at, Interval, MissedTickBehavior};
+
+#[derive(Debug, ThisError)]
+pub enum WebSocketError {
+    #[error("Session error")]
+    UserSessionError(#[from] UserSessionError),
+    #[error("Origin is not allowed: {0}")]
+    OriginNotAllowed(String),
+    #[error("Invalid websocket upgrade: {0}")]
+    InvalidUpgrade(String),
+}
+
+impl IntoProblem for WebSocketError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            WebSocketError::UserSessionError(err) => err.into_problem(config),
+            WebSocketError::OriginNotAllowed(_) => Problem::forbidden()
+                .with_detail(self.to_string())
+                .with_extension(config, format!("{:#?}", self)),
+            WebSocketError::InvalidUpgrade(_) => Problem::bad_request("websocket_upgrade_error")
+                .with_detail(self.to_string())
+                .with_extension(config, format!("{:#?}", self)),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct WebSocketMeters {
+    active: UpDownCounter<i64>,
+    connections: Counter<u64>,
+    messages: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+/// Policy of the authenticated websocket endpoints. If the extension is missing, the default policy
+/// is used, but it accepts the handshake from the same origin only.
+#[derive(Clone)]
+pub struct WebSocketPolicy {
+    allowed_origins: Vec<OriginPattern>,
+    ping_interval: Duration,
+    idle_timeout: Duration,
+    max_message_size: usize,
+    meters: Option<WebSocketMeters>,
+}
+
+impl Default for WebSocketPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            ping_interval: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(90),
+            max_message_size: 64 * 1024,
+            meters: None,
+        }
+    }
+}
+
+impl WebSocketPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow the handshake from the given origins in addition to the same origin. As the browsers send the
+    /// session cookie from any site, the origin is the only protection against the cross-site hijacking.
+    pub fn with_allowed_origins<S: AsRef<str>>(self, origins: &[S]) -> Result<Self, CorsConfigError> {
+        let allowed_origins = origins
+            .iter()
+            .map(|origin| OriginPattern::parse(origin.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            allowed_origins,
+            ..self
+        })
+    }
+
+    /// The server pings the client periodically, and closes the connection if nothing (not even a pong)
+    /// was received for the `idle_timeout`.
+    #[must_use]
+    pub fn with_keep_alive(self, ping_interval: Duration, idle_timeout: Duration) -> Self {
+        Self {
+            ping_interval,
+            idle_timeout: idle_timeout.max(ping_interval),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_max_message_size(self, max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            meters: Some(WebSocketMeters {
+                active: meter.i64_up_down_counter("websocket_active_connections").init(),
+                connections: meter.u64_counter("websocket_connections").init(),
+                messages: meter.u64_counter("websocket_messages").init(),
+                duration: meter.f64_histogram("websocket_connection_duration").init(),
+            }),
+            ..self
+        }
+    }
+
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    fn check_origin(&self, parts: &Parts) -> Result<(), WebSocketError> {
+        // non-browser clients don't send origin, and they don't send the cookies of the user either
+        let Some(origin) = parts.headers.get(header::ORIGIN) else {
+            return Ok(());
+        };
+        let origin = origin
+            .to_str()
+            .map_err(|_| WebSocketError::OriginNotAllowed("<invalid>".into()))?;
+
+        let host = parts.headers.get(header::HOST).and_then(|host| host.to_str().ok());
+        let same_origin = host.is_some_and(|host| {
+            origin
+                .split_once("://")
+                .is_some_and(|(_, origin_host)| origin_host.eq_ignore_ascii_case(host))
+        });
+        if same_origin || self.allowed_origins.iter().any(|pattern| pattern.is_match(origin)) {
+            Ok(())
+        } else {
+            Err(WebSocketError::OriginNotAllowed(origin.to_string()))
+        }
+    }
+}
+
+/// Extractor of the websocket endpoints. It validates the session cookie and the origin during the
+/// handshake and passes the user to the socket handler.
+pub struct UserWebSocketUpgrade {
+    user: CurrentUser,
+    upgrade: WebSocketUpgrade,
+    policy: Arc<WebSocketPolicy>,
+    route: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for UserWebSocketUpgrade
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<WebSocketError>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let policy = parts
+            .extract::<Extension<Arc<WebSocketPolicy>>>()
+            .await
+            .map(|Extension(policy)| policy)
+            .unwrap_or_default();
+
+        policy
+            .check_origin(parts)
+            .map_err(|err| problem_config.configure(err))?;
+        let upgrade = WebSocketUpgrade::from_request_parts(parts, state)
+            .await
+            .map_err(|err| problem_config.configure(WebSocketError::InvalidUpgrade(err.body_text())))?;
+        let user = parts
+            .extract::<CheckedCurrentUser>()
+            .await
+            .map_err(|err| problem_config.configure(WebSocketError::from(err.problem)))?
+            .into_user();
+
+        Ok(Self {
+            user,
+            upgrade: upgrade.max_message_size(policy.max_message_size),
+            policy,
+            // the matched route keeps the cardinality of the metrics low
+            route: parts
+                .extensions
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_string())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl UserWebSocketUpgrade {
+    pub fn user(&self) -> &CurrentUser {
+        &self.user
+    }
+
+    /// Complete the upgrade and run the handler on the socket.
+    pub fn on_upgrade<F, Fut>(self, handler: F) -> Response
+    where
+        F: FnOnce(UserWebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let Self {
+            user,
+            upgrade,
+            policy,
+            route,
+        } = self;
+
+        upgrade.on_upgrade(move |socket| async move {
+            let attributes = [KeyValue::new("route", route)];
+            if let Some(meters) = &policy.meters {
+                meters.connections.add(1, &attributes);
+                meters.active.add(1, &attributes);
+            }
+            let start = Instant::now();
+
+            let mut ping = interval_at(tokio::time::Instant::now() + policy.ping_interval, policy.ping_interval);
+            ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            handler(UserWebSocket {
+                socket,
+                user,
+                policy: policy.clone(),
+                attributes: attributes.clone(),
+                ping,
+                last_activity: Instant::now(),
+                closed: false,
+            })
+            .await;
+
+            if let Some(meters) = &policy.meters {
+                meters.active.add(-1, &attributes);
+                meters.duration.record(start.elapsed().as_secs_f64(), &attributes);
+            }
+        })
+    }
+}
+
+/// An upgraded websocket of an authenticated user. The pings and the idle timeout are handled
+/// while the handler is waiting for the next message.
+pub struct UserWebSocket {
+    socket: WebSocket,
+    user: CurrentUser,
+    policy: Arc<WebSocketPolicy>,
+    attributes: [KeyValue; 1],
+    ping: Interval,
+    last_activity: Instant,
+    closed: bool,
+}
+
+impl UserWebSocket {
+    pub fn user(&self) -> &CurrentUser {
+        &self.user
+    }
+
+    fn count_message(&self, direction: &'static str) {
+        if let Some(meters) = &self.policy.meters {
+            let attributes = [self.attributes[0].clone(), KeyValue::new("direction", direction)];
+            meters.messages.add(1, &attributes);
+        }
+    }
+
+    /// Receive the next text or binary message. Returns `None` if the connection was closed by the
+    /// client, timed out or failed.
+    pub async fn recv(&mut self) -> Option<Message> {
+        while !self.closed {
+            tokio::select! {
+                message = self.socket.recv() => {
+                    self.last_activity = Instant::now();
+                    match message {
+                        Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                        Some(Ok(Message::Close(_))) | None => self.closed = true,
+                        Some(Ok(message)) => {
+                            self.count_message("received");
+                            return Some(message);
+                        }
+                        Err(err) => {
+                            log::debug!("Websocket of {} failed: {err}", self.user.user_id);
+                            self.closed = true;
+                        }
+                    }
+                }
+                _ = self.ping.tick() => {
+                    if self.last_activity.elapsed() > self.policy.idle_timeout {
+                        log::debug!("Websocket of {} is idle, closing", self.user.user_id);
+                        self.close(close_code::AWAY, "Idle timeout").await;
+                    } else if self.socket.send(Message::Ping(Vec::new())).await.is_err() {
+                        self.closed = true;
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<(), axum::Error> {
+        self.socket.send(message).await?;
+        self.count_message("sent");
+        Ok(())
+    }
+
+    /// Close the connection with the given close code, ex. `close_code::POLICY` when the session has ended.
+    pub async fn close(&mut self, code: u16, reason: &'static str) {
+        if !self.closed {
+            self.closed = true;
+            let frame = CloseFrame {
+                code,
+                reason: reason.into(),
+            };
+            let _ = self.socket.send(Message::Close(Some(frame))).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::Request;
+    use shine_test::test;
+
+    fn parts(origin: Option<&str>) -> Parts {
+        let mut request = Request::builder().uri("/ws").header(header::HOST, "api.example.com");
+        if let Some(origin) = origin {
+            request = request.header(header::ORIGIN, origin);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn check_origin() {
+        let policy = WebSocketPolicy::new()
+            .with_allowed_origins(&["https://*.example.com"])
+            .unwrap();
+        assert!(policy.check_origin(&parts(None)).is_ok());
+        assert!(policy.check_origin(&parts(Some("https://api.example.com"))).is_ok());
+        assert!(policy.check_origin(&parts(Some("https://www.example.com"))).is_ok());
+        assert!(matches!(
+            policy.check_origin(&parts(Some("https://evil.com"))),
+            Err(WebSocketError::OriginNotAllowed(_))
+        ));
+
+        let policy = WebSocketPolicy::default();
+        assert!(policy.check_origin(&parts(Some("https://www.example.com"))).is_err());
+    }
+}
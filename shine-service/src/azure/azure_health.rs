@@ -0,0 +1,185 @@
+use crate::service::{CachedStatus, DependencyHealth, DependencyStatus, StatusSource};
+use async_trait::async_trait;
+use azure_core::{
+    auth::TokenCredential,
+    headers::{HeaderName, AUTHORIZATION},
+    HttpClient, Method, Request, Url,
+};
+use azure_security_keyvault::SecretClient;
+use futures::StreamExt;
+use std::{sync::Arc, time::Instant};
+
+const KEYVAULT_SCOPE: &str = "https://vault.azure.net/.default";
+const STORAGE_SCOPE: &str = "https://storage.azure.com/.default";
+const STORAGE_API_VERSION: &str = "2021-08-06";
+
+fn unhealthy(status: DependencyStatus, error: String) -> DependencyStatus {
+    DependencyStatus {
+        health: DependencyHealth::Unhealthy,
+        ..status.with_detail("error", error)
+    }
+}
+
+/// Probe a keyvault: acquire a token and list the first page of the secrets. It verifies both the identity
+/// of the service and the access policy of the vault.
+pub struct KeyVaultProbe {
+    name: String,
+    credential: Arc<dyn TokenCredential>,
+    client: SecretClient,
+}
+
+impl KeyVaultProbe {
+    pub fn new(name: &str, keyvault_url: &str, credential: Arc<dyn TokenCredential>) -> azure_core::Result<Self> {
+        let client = SecretClient::new(keyvault_url, credential.clone())?;
+        Ok(Self {
+            name: name.to_string(),
+            credential,
+            client,
+        })
+    }
+
+    /// Wrap the probe into a cache, the vault is throttled and a probe on each status request is expensive.
+    pub fn cached(self) -> CachedStatus<Self> {
+        CachedStatus::new(self)
+    }
+}
+
+#[async_trait]
+impl StatusSource for KeyVaultProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn status(&self) -> DependencyStatus {
+        let status = DependencyStatus::new(&self.name, "keyvault", DependencyHealth::Healthy);
+
+        let start = Instant::now();
+        if let Err(err) = self.credential.get_token(&[KEYVAULT_SCOPE]).await {
+            return unhealthy(status, format!("Token acquisition failed: {err}"));
+        }
+        let status = status.with_detail("tokenLatencyMs", start.elapsed().as_millis() as u64);
+
+        let start = Instant::now();
+        let probe = self.client.list_secrets().into_stream().next().await;
+        let status = status.with_detail("probeLatencyMs", start.elapsed().as_millis() as u64);
+        match probe {
+            Some(Err(err)) => unhealthy(status, format!("List secrets failed: {err}")),
+            Some(Ok(_)) | None => status,
+        }
+    }
+}
+
+/// Probe a storage account by reading the properties of the blob service with the identity of the service.
+pub struct BlobStorageProbe {
+    name: String,
+    url: Url,
+    credential: Arc<dyn TokenCredential>,
+    http_client: Arc<dyn HttpClient>,
+}
+
+impl BlobStorageProbe {
+    pub fn new(name: &str, account: &str, credential: Arc<dyn TokenCredential>) -> azure_core::Result<Self> {
+        let url = Url::parse(&format!(
+            "https://{account}.blob.core.windows.net/?restype=service&comp=properties"
+        ))?;
+        Ok(Self {
+            name: name.to_string(),
+            url,
+            credential,
+            http_client: azure_core::new_http_client(),
+        })
+    }
+
+    pub fn cached(self) -> CachedStatus<Self> {
+        CachedStatus::new(self)
+    }
+}
+
+#[async_trait]
+impl StatusSource for BlobStorageProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn status(&self) -> DependencyStatus {
+        let status = DependencyStatus::new(&self.name, "blobStorage", DependencyHealth::Healthy);
+
+        let start = Instant::now();
+        let token = match self.credential.get_token(&[STORAGE_SCOPE]).await {
+            Ok(token) => token,
+            Err(err) => return unhealthy(status, format!("Token acquisition failed: {err}")),
+        };
+        let status = status.with_detail("tokenLatencyMs", start.elapsed().as_millis() as u64);
+
+        let mut request = Request::new(self.url.clone(), Method::Get);
+        request.insert_header(AUTHORIZATION, format!("Bearer {}", token.token.secret()));
+        request.insert_header(HeaderName::from_static("x-ms-version"), STORAGE_API_VERSION);
+
+        let start = Instant::now();
+        let probe = self.http_client.execute_request(&request).await;
+        let status = status.with_detail("probeLatencyMs", start.elapsed().as_millis() as u64);
+        match probe {
+            Ok(response) => {
+                let code: u16 = response.status().into();
+                let status = status.with_detail("statusCode", code);
+                if (200..300).contains(&code) {
+                    status
+                } else {
+                    unhealthy(status, format!("Unexpected status code: {code}"))
+                }
+            }
+            Err(err) => unhealthy(status, format!("Request failed: {err}")),
+        }
+    }
+}
+
+/// Probe the connectivity of a service bus namespace. The shared access policies of the services usually
+/// lack the manage right and receiving from a queue is not side effect free, thus only the reachability of
+/// the endpoint is checked, any http response (including 401) means the namespace is available.
+pub struct ServiceBusProbe {
+    name: String,
+    url: Url,
+    http_client: Arc<dyn HttpClient>,
+}
+
+impl ServiceBusProbe {
+    pub fn new(name: &str, namespace: &str) -> azure_core::Result<Self> {
+        let url = Url::parse(&format!("https://{namespace}.servicebus.windows.net/"))?;
+        Ok(Self {
+            name: name.to_string(),
+            url,
+            http_client: azure_core::new_http_client(),
+        })
+    }
+
+    pub fn cached(self) -> CachedStatus<Self> {
+        CachedStatus::new(self)
+    }
+}
+
+#[async_trait]
+impl StatusSource for ServiceBusProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn status(&self) -> DependencyStatus {
+        let status = DependencyStatus::new(&self.name, "serviceBus", DependencyHealth::Healthy);
+
+        let request = Request::new(self.url.clone(), Method::Get);
+        let start = Instant::now();
+        let probe = self.http_client.execute_request(&request).await;
+        let status = status.with_detail("probeLatencyMs", start.elapsed().as_millis() as u64);
+        match probe {
+            Ok(response) => {
+                let code: u16 = response.status().into();
+                if code >= 500 {
+                    unhealthy(status.with_detail("statusCode", code), format!("Server error: {code}"))
+                } else {
+                    status.with_detail("statusCode", code)
+                }
+            }
+            Err(err) => unhealthy(status, format!("Connection failed: {err}")),
+        }
+    }
+}
@@ -1,16 +1,33 @@
+use crate::{azure::azure_secret_cache::SecretCache, service::ConfigSourceReport, utils::DurationStr};
 use async_trait::async_trait;
-use azure_core::auth::TokenCredential;
+use azure_core::{auth::TokenCredential, error::ErrorKind as AzureErrorKind};
 use azure_security_keyvault::SecretClient;
 use config::{
     AsyncSource as ConfigAsyncSource, ConfigError, Map as ConfigMap, Value as ConfigValue, ValueKind as ConfigValueKind,
 };
-use futures::StreamExt;
-use std::sync::Arc;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use thiserror::Error as ThisError;
 
+const DEFAULT_CONCURRENCY: usize = 8;
+const DEFAULT_MAX_RETRIES: usize = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(200);
+
 #[derive(Debug, ThisError)]
-#[error("Azure core error: {0}")]
-pub struct AzureKeyvaultConfigError(#[source] azure_core::Error);
+pub enum AzureKeyvaultConfigError {
+    #[error("Azure core error: {0}")]
+    Azure(#[source] azure_core::Error),
+    #[error("Keyvault request failed after {1} retries")]
+    RetriesExhausted(#[source] azure_core::Error, usize),
+    #[error("Failed to read secret {0}")]
+    Secret(String, #[source] Box<AzureKeyvaultConfigError>),
+}
 
 impl From<AzureKeyvaultConfigError> for ConfigError {
     fn from(err: AzureKeyvaultConfigError) -> Self {
@@ -19,10 +36,85 @@ impl From<AzureKeyvaultConfigError> for ConfigError {
     }
 }
 
+/// Throttling (429) and server errors are worth to retry.
+fn is_transient(err: &azure_core::Error) -> bool {
+    match err.kind() {
+        AzureErrorKind::HttpResponse { status, .. } => {
+            let status: u16 = (*status).into();
+            status == 429 || status >= 500
+        }
+        AzureErrorKind::Io => true,
+        _ => false,
+    }
+}
+
+/// The keyvault could not be reached (connection, dns, tls, timeout), the content of the response errors
+/// (ex. authorization) should not be masked by the offline cache.
+fn is_unreachable(err: &AzureKeyvaultConfigError) -> bool {
+    match err {
+        AzureKeyvaultConfigError::Azure(err) | AzureKeyvaultConfigError::RetriesExhausted(err, _) => {
+            matches!(err.kind(), AzureErrorKind::Io)
+        }
+        AzureKeyvaultConfigError::Secret(_, err) => is_unreachable(err),
+    }
+}
+
+/// Handling of the failures of the individual secrets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SecretErrorPolicy {
+    /// Abort the config load on the first error.
+    Fail,
+    /// Log a warning and continue without the secret.
+    Skip,
+    /// Retry the throttled (429) and server (5xx) errors with an exponential backoff, then fail.
+    #[default]
+    Retry,
+}
+
+/// Loading options of the keyvault config sources, the missing values keep the defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AzureKeyvaultSourceConfig {
+    #[serde(default)]
+    pub error_policy: SecretErrorPolicy,
+    /// Error handling of the individual secrets by the name of the secret in the keyvault.
+    #[serde(default)]
+    pub secret_policies: HashMap<String, SecretErrorPolicy>,
+    /// Maximum number of the secrets fetched in parallel.
+    pub concurrency: Option<usize>,
+    pub max_retries: Option<usize>,
+    /// The delay of the first retry, ex. `"200ms"`, it is doubled after each attempt.
+    pub backoff: Option<DurationStr>,
+}
+
+enum SecretResult {
+    Loaded(String, String),
+    Disabled(String),
+    Skipped(String, String),
+}
+
 #[derive(Clone, Debug)]
 pub struct AzureKeyvaultConfigSource {
     keyvault_url: String,
     client: SecretClient,
+    error_policy: SecretErrorPolicy,
+    secret_policies: HashMap<String, SecretErrorPolicy>,
+    concurrency: usize,
+    max_retries: usize,
+    backoff: Duration,
+    report: Arc<Mutex<Option<ConfigSourceReport>>>,
+    offline_cache: Option<Arc<SecretCache>>,
+}
+
+/// Try to parse value, as conversion from string to a concrete type is not automatic.
+fn secret_value(origin: &str, value: String) -> ConfigValue {
+    let value = if let Ok(parsed) = value.parse::<i64>() {
+        ConfigValueKind::I64(parsed)
+    } else {
+        ConfigValueKind::String(value)
+    };
+    ConfigValue::new(Some(&origin.to_string()), value)
 }
 
 impl AzureKeyvaultConfigSource {
@@ -30,52 +122,299 @@ impl AzureKeyvaultConfigSource {
         azure_credentials: Arc<dyn TokenCredential>,
         keyvault_url: &str,
     ) -> Result<AzureKeyvaultConfigSource, ConfigError> {
-        let client = SecretClient::new(keyvault_url, azure_credentials).map_err(AzureKeyvaultConfigError)?;
+        let client = SecretClient::new(keyvault_url, azure_credentials).map_err(AzureKeyvaultConfigError::Azure)?;
         Ok(Self {
             keyvault_url: keyvault_url.to_owned(),
             client,
+            error_policy: SecretErrorPolicy::default(),
+            secret_policies: HashMap::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+            max_retries: DEFAULT_MAX_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+            report: Arc::new(Mutex::new(None)),
+            offline_cache: None,
         })
     }
-}
 
-#[async_trait]
-impl ConfigAsyncSource for AzureKeyvaultConfigSource {
-    async fn collect(&self) -> Result<ConfigMap<String, ConfigValue>, ConfigError> {
+    /// Apply the loading options of the configuration.
+    #[must_use]
+    pub fn with_config(self, config: &AzureKeyvaultSourceConfig) -> Self {
+        let mut source = self.with_error_policy(config.error_policy);
+        for (secret, policy) in &config.secret_policies {
+            source = source.with_secret_policy(secret, *policy);
+        }
+        if let Some(concurrency) = config.concurrency {
+            source = source.with_concurrency(concurrency);
+        }
+        let max_retries = config.max_retries.unwrap_or(source.max_retries);
+        let backoff = config.backoff.map(Duration::from).unwrap_or(source.backoff);
+        source.with_retry(max_retries, backoff)
+    }
+
+    /// The default handling of the failed secrets.
+    #[must_use]
+    pub fn with_error_policy(self, error_policy: SecretErrorPolicy) -> Self {
+        Self { error_policy, ..self }
+    }
+
+    /// Override the error handling of a secret (by the name of the secret in the keyvault).
+    #[must_use]
+    pub fn with_secret_policy(mut self, secret: &str, policy: SecretErrorPolicy) -> Self {
+        self.secret_policies.insert(secret.to_string(), policy);
+        self
+    }
+
+    /// Maximum number of the secrets fetched in parallel.
+    #[must_use]
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            ..self
+        }
+    }
+
+    /// Retry the transient errors `max_retries` times, the delay is doubled after each attempt.
+    #[must_use]
+    pub fn with_retry(self, max_retries: usize, backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            backoff,
+            ..self
+        }
+    }
+
+    /// Snapshot the loaded secrets into an encrypted local cache and load from it when the keyvault
+    /// is unreachable. For the local development only.
+    #[must_use]
+    pub fn with_offline_cache(self, cache: SecretCache) -> Self {
+        Self {
+            offline_cache: Some(Arc::new(cache)),
+            ..self
+        }
+    }
+
+    /// The summary of the last load, None if the source has not been loaded yet. The clones of the source share
+    /// the report, `CoreConfig::trace_config` records it in the `ConfigTrace`.
+    pub fn report(&self) -> Option<ConfigSourceReport> {
+        self.report.lock().unwrap().clone()
+    }
+
+    /// Call the request and retry the transient failures, return the result and the number of the retries.
+    async fn with_retries<T, F, Fut>(
+        &self,
+        max_retries: usize,
+        request: F,
+    ) -> (Result<T, AzureKeyvaultConfigError>, usize)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, azure_core::Error>>,
+    {
+        let mut retries = 0;
+        let mut delay = self.backoff;
+        loop {
+            match request().await {
+                Ok(value) => return (Ok(value), retries),
+                Err(err) if is_transient(&err) && retries < max_retries => {
+                    log::warn!("Transient keyvault error, retrying in {delay:?}: {err}");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    retries += 1;
+                }
+                Err(err) if is_transient(&err) && retries > 0 => {
+                    return (Err(AzureKeyvaultConfigError::RetriesExhausted(err, retries)), retries)
+                }
+                Err(err) => return (Err(AzureKeyvaultConfigError::Azure(err)), retries),
+            }
+        }
+    }
+
+    async fn list_secret_names(&self) -> Result<(Vec<String>, usize), AzureKeyvaultConfigError> {
+        let mut names = Vec::new();
+        let mut retries = 0;
+        let mut pages = self.client.list_secrets().into_stream();
+        loop {
+            // the pageable stream cannot be restarted at a page, thus the listing is retried only as a whole
+            match pages.next().await {
+                Some(Ok(response)) => names.extend(
+                    response
+                        .value
+                        .iter()
+                        .filter_map(|raw| raw.id.split('/').last().map(String::from)),
+                ),
+                Some(Err(err)) if is_transient(&err) && retries < self.max_retries => {
+                    log::warn!("Transient keyvault error while listing the secrets, restarting: {err}");
+                    tokio::time::sleep(self.backoff * 2_u32.pow(retries as u32)).await;
+                    retries += 1;
+                    names.clear();
+                    pages = self.client.list_secrets().into_stream();
+                }
+                Some(Err(err)) => return Err(AzureKeyvaultConfigError::Azure(err)),
+                None => return Ok((names, retries)),
+            }
+        }
+    }
+
+    async fn fetch_secret(&self, name: String) -> (Result<SecretResult, AzureKeyvaultConfigError>, usize) {
+        let policy = self.secret_policies.get(&name).copied().unwrap_or(self.error_policy);
+        let max_retries = if policy == SecretErrorPolicy::Retry {
+            self.max_retries
+        } else {
+            0
+        };
+
+        log::info!("Reading secret {:?}", name);
+        let (result, retries) = self
+            .with_retries(max_retries, || self.client.get(&name).into_future())
+            .await;
+        let result = match result {
+            Ok(secret) if secret.attributes.enabled => Ok(SecretResult::Loaded(name, secret.value)),
+            Ok(_) => Ok(SecretResult::Disabled(name)),
+            Err(err) if policy == SecretErrorPolicy::Skip => {
+                log::warn!("Skipping secret {name:?}: {err}");
+                Ok(SecretResult::Skipped(name, err.to_string()))
+            }
+            Err(err) => Err(AzureKeyvaultConfigError::Secret(name, Box::new(err))),
+        };
+        (result, retries)
+    }
+
+    fn load_offline_cache(&self, cache: &SecretCache) -> Result<ConfigMap<String, ConfigValue>, ConfigError> {
+        let values = cache.load().map_err(|err| ConfigError::Foreign(Box::new(err)))?;
+        let origin = format!("{} (offline cache)", self.keyvault_url);
+        let report = ConfigSourceReport {
+            source: origin.clone(),
+            loaded: values.keys().cloned().collect(),
+            ..Default::default()
+        };
+        *self.report.lock().unwrap() = Some(report);
+
+        Ok(values
+            .into_iter()
+            .map(|(key, value)| (key, secret_value(&origin, value)))
+            .collect())
+    }
+
+    async fn load(&self) -> Result<ConfigMap<String, ConfigValue>, AzureKeyvaultConfigError> {
         let mut config = ConfigMap::new();
 
         log::info!("Loading secrets from {} ...", self.keyvault_url);
         let origin = self.keyvault_url.to_string();
-        let mut stream = self.client.list_secrets().into_stream();
-        while let Some(response) = stream.next().await {
-            let response = response.map_err(AzureKeyvaultConfigError)?;
-            for raw in &response.value {
-                let key = raw.id.split('/').last();
-                if let Some(key) = key {
-                    let path = key.replace('-', ".");
-                    log::info!("Reading secret {:?}", key);
-                    let secret = self
-                        .client
-                        .get(key)
-                        .into_future()
-                        .await
-                        .map_err(AzureKeyvaultConfigError)?;
-                    if secret.attributes.enabled {
-                        let value = secret.value;
-
-                        // try to parse value, as conversion from string to a concrete type is not automatic.
-                        let value = if let Ok(parsed) = value.parse::<i64>() {
-                            ConfigValueKind::I64(parsed)
-                        } else {
-                            ConfigValueKind::String(value)
-                        };
-
-                        config.insert(path, ConfigValue::new(Some(&origin), value));
-                    }
+        let (names, retries) = self.list_secret_names().await?;
+        let mut report = ConfigSourceReport {
+            source: origin.clone(),
+            retries,
+            ..Default::default()
+        };
+
+        let mut results = stream::iter(names)
+            .map(|name| self.fetch_secret(name))
+            .buffer_unordered(self.concurrency);
+        while let Some((result, retries)) = results.next().await {
+            report.retries += retries;
+            match result? {
+                SecretResult::Loaded(name, value) => {
+                    let path = name.replace('-', ".");
+                    report.loaded.push(path.clone());
+                    config.insert(path, secret_value(&origin, value));
                 }
+                SecretResult::Disabled(name) => report.disabled.push(name),
+                SecretResult::Skipped(name, reason) => report.skipped.push((name, reason)),
             }
         }
 
-        log::info!("keyvault config: {:#?}", config);
+        // all the keyvault values are secrets, thus keys are logged only
+        report.loaded.sort();
+        log::info!("keyvault config keys: {:#?}", config.keys());
+        log::info!(
+            "Keyvault {} loaded: {}, disabled: {}, skipped: {}, retries: {}",
+            self.keyvault_url,
+            report.loaded.len(),
+            report.disabled.len(),
+            report.skipped.len(),
+            report.retries
+        );
+        *self.report.lock().unwrap() = Some(report);
         Ok(config)
     }
 }
+
+#[async_trait]
+impl ConfigAsyncSource for AzureKeyvaultConfigSource {
+    async fn collect(&self) -> Result<ConfigMap<String, ConfigValue>, ConfigError> {
+        let result = self.load().await;
+        let Some(cache) = &self.offline_cache else {
+            return Ok(result?);
+        };
+
+        match result {
+            Ok(config) => {
+                let values: BTreeMap<String, String> = config
+                    .iter()
+                    .filter_map(|(key, value)| Some((key.clone(), value.clone().into_string().ok()?)))
+                    .collect();
+                if let Err(err) = cache.save(&values) {
+                    log::warn!("Failed to update the secret cache {}: {err:?}", cache.path().display());
+                }
+                Ok(config)
+            }
+            Err(err) if is_unreachable(&err) => {
+                log::warn!(
+                    "Keyvault {} is unreachable ({err}), loading the secrets from the offline cache {}",
+                    self.keyvault_url,
+                    cache.path().display()
+                );
+                self.load_offline_cache(cache).map_err(|_| err.into())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use azure_core::StatusCode;
+    use shine_test::test;
+
+    #[test]
+    fn offline_fallback_errors() {
+        let io = || azure_core::Error::message(AzureErrorKind::Io, "connection refused");
+        assert!(is_unreachable(&AzureKeyvaultConfigError::Azure(io())));
+        assert!(is_unreachable(&AzureKeyvaultConfigError::RetriesExhausted(io(), 3)));
+        assert!(is_unreachable(&AzureKeyvaultConfigError::Secret(
+            "db-password".into(),
+            Box::new(AzureKeyvaultConfigError::Azure(io()))
+        )));
+
+        let forbidden = azure_core::Error::message(
+            AzureErrorKind::HttpResponse {
+                status: StatusCode::Forbidden,
+                error_code: None,
+            },
+            "forbidden",
+        );
+        assert!(!is_unreachable(&AzureKeyvaultConfigError::Azure(forbidden)));
+    }
+
+    #[test]
+    fn source_config() {
+        let config: AzureKeyvaultSourceConfig = serde_json::from_value(serde_json::json!({
+            "errorPolicy": "skip",
+            "secretPolicies": { "db-password": "fail" },
+            "maxRetries": 5,
+            "backoff": "1s"
+        }))
+        .unwrap();
+        assert_eq!(config.error_policy, SecretErrorPolicy::Skip);
+        assert_eq!(
+            config.secret_policies.get("db-password"),
+            Some(&SecretErrorPolicy::Fail)
+        );
+        assert_eq!(config.concurrency, None);
+        assert_eq!(config.max_retries, Some(5));
+        assert_eq!(config.backoff.map(Duration::from), Some(Duration::from_secs(1)));
+
+        let config: AzureKeyvaultSourceConfig = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(config, AzureKeyvaultSourceConfig::default());
+    }
+}
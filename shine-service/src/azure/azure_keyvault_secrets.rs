@@ -0,0 +1,139 @@
+use crate::utils::Sensitive;
+use azure_core::auth::TokenCredential;
+use azure_security_keyvault::SecretClient;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+use tokio::{sync::broadcast, task::JoinHandle};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+const CHANGE_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, ThisError)]
+pub enum AzureKeyvaultSecretError {
+    #[error("Azure core error: {0}")]
+    Azure(#[from] azure_core::Error),
+    #[error("Secret {0} is disabled")]
+    Disabled(String),
+}
+
+/// Notification of a secret rotation, the new value can be queried from the provider.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecretChange {
+    pub name: String,
+    /// The version (id) of the new secret.
+    pub version: String,
+}
+
+struct CachedSecret {
+    value: Sensitive<String>,
+    version: String,
+    fetched_at: Instant,
+}
+
+/// Runtime access to the keyvault secrets. The secrets are fetched on demand and cached for the `ttl`.
+/// When a refresh finds a new version of a secret, a change is notified to the subscribers,
+/// thus the services can rotate the db passwords, api keys, etc. without a restart.
+pub struct AzureKeyvaultSecretProvider {
+    keyvault_url: String,
+    client: SecretClient,
+    ttl: Duration,
+    cache: RwLock<HashMap<String, CachedSecret>>,
+    changes: broadcast::Sender<SecretChange>,
+}
+
+impl AzureKeyvaultSecretProvider {
+    pub fn new(
+        azure_credentials: Arc<dyn TokenCredential>,
+        keyvault_url: &str,
+    ) -> Result<Self, AzureKeyvaultSecretError> {
+        let client = SecretClient::new(keyvault_url, azure_credentials)?;
+        let (changes, _) = broadcast::channel(CHANGE_CHANNEL_CAPACITY);
+        Ok(Self {
+            keyvault_url: keyvault_url.to_owned(),
+            client,
+            ttl: DEFAULT_TTL,
+            cache: RwLock::new(HashMap::new()),
+            changes,
+        })
+    }
+
+    #[must_use]
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// Subscribe to the changes of the (cached) secrets.
+    pub fn subscribe(&self) -> broadcast::Receiver<SecretChange> {
+        self.changes.subscribe()
+    }
+
+    fn cached(&self, name: &str) -> Option<Sensitive<String>> {
+        let cache = self.cache.read().unwrap();
+        cache
+            .get(name)
+            .filter(|secret| secret.fetched_at.elapsed() < self.ttl)
+            .map(|secret| secret.value.clone())
+    }
+
+    /// Get a secret from the cache or fetch it from the keyvault if it is missing or expired.
+    pub async fn get(&self, name: &str) -> Result<Sensitive<String>, AzureKeyvaultSecretError> {
+        match self.cached(name) {
+            Some(value) => Ok(value),
+            None => self.refresh(name).await,
+        }
+    }
+
+    /// Fetch a secret from the keyvault ignoring the cache.
+    pub async fn refresh(&self, name: &str) -> Result<Sensitive<String>, AzureKeyvaultSecretError> {
+        log::debug!("Fetching secret {name:?} from {}", self.keyvault_url);
+        let secret = self.client.get(name).await?;
+        if !secret.attributes.enabled {
+            return Err(AzureKeyvaultSecretError::Disabled(name.to_string()));
+        }
+
+        let value = Sensitive::new(secret.value);
+        let previous = self.cache.write().unwrap().insert(
+            name.to_string(),
+            CachedSecret {
+                value: value.clone(),
+                version: secret.id.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+        if previous.is_some_and(|previous| previous.version != secret.id) {
+            log::info!("Secret {name:?} of {} has changed", self.keyvault_url);
+            // it is not an error if there are no subscribers
+            let _ = self.changes.send(SecretChange {
+                name: name.to_string(),
+                version: secret.id,
+            });
+        }
+        Ok(value)
+    }
+
+    /// Refresh all the cached secrets, the failed secrets keep their cached values.
+    pub async fn refresh_all(&self) {
+        let names: Vec<String> = self.cache.read().unwrap().keys().cloned().collect();
+        for name in names {
+            if let Err(err) = self.refresh(&name).await {
+                log::warn!("Failed to refresh secret {name:?} of {}: {err}", self.keyvault_url);
+            }
+        }
+    }
+
+    /// Refresh the cached secrets periodically to detect the rotations.
+    pub fn start_refresh(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                self.refresh_all().await;
+            }
+        })
+    }
+}
@@ -0,0 +1,178 @@
+use crate::utils::Sensitive;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+use thiserror::Error as ThisError;
+
+/// Environment variable with the developer passphrase of the secret cache, the cache is disabled without it.
+pub const SECRET_CACHE_PASSPHRASE: &str = "SHINE_SECRET_CACHE_PASSPHRASE";
+/// The only stage where the secret cache is enabled.
+const DEV_STAGE: &str = "dev";
+/// Directory of the secret cache files.
+pub const DEFAULT_SECRET_CACHE_DIR: &str = "temp/secret_cache";
+
+const VERSION: u32 = 1;
+const PBKDF2_ITERATIONS: u32 = if cfg!(test) { 1000 } else { 600_000 };
+const SALT_LEN: usize = 16;
+const AAD: &[u8] = b"shine-secret-cache";
+
+#[derive(Debug, ThisError)]
+pub enum SecretCacheError {
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Json error")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid cache encoding")]
+    Encoding,
+    #[error("Unsupported cache version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("Failed to encrypt the cache")]
+    Encrypt,
+    #[error("Failed to decrypt the cache, check the passphrase")]
+    Decrypt,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Envelope {
+    version: u32,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+/// An AES-256-GCM encrypted snapshot of the secrets on the local disk. The key is derived from a developer
+/// passphrase with PBKDF2, thus the file can be kept around to boot the services without access to the vault.
+/// It is meant for the local development only.
+#[derive(Clone, Debug)]
+pub struct SecretCache {
+    path: PathBuf,
+    passphrase: Sensitive<String>,
+    random: SystemRandom,
+}
+
+impl SecretCache {
+    pub fn new<P: Into<PathBuf>>(path: P, passphrase: Sensitive<String>) -> Self {
+        Self {
+            path: path.into(),
+            passphrase,
+            random: SystemRandom::new(),
+        }
+    }
+
+    /// Create a cache for the source in the default directory if the passphrase is set in the environment.
+    /// The cache is a developer tool, it is available only in the `dev` stage.
+    pub fn from_env(stage: &str, source: &str) -> Option<Self> {
+        let passphrase = env::var(SECRET_CACHE_PASSPHRASE).ok().filter(|p| !p.is_empty())?;
+        if stage != DEV_STAGE {
+            log::warn!("{SECRET_CACHE_PASSPHRASE} is ignored in the {stage} stage, the secret cache is dev only");
+            return None;
+        }
+        let file_name: String = source
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = Path::new(DEFAULT_SECRET_CACHE_DIR).join(format!("{file_name}.json"));
+        Some(Self::new(path, Sensitive::new(passphrase)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn key(&self, salt: &[u8], iterations: u32) -> Result<LessSafeKey, SecretCacheError> {
+        let iterations = NonZeroU32::new(iterations).ok_or(SecretCacheError::Encoding)?;
+        let mut key = [0; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            salt,
+            self.passphrase.expose().as_bytes(),
+            &mut key,
+        );
+        let key = UnboundKey::new(&aead::AES_256_GCM, &key).map_err(|_| SecretCacheError::Encrypt)?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    /// Encrypt and store the values, the previous snapshot is replaced.
+    pub fn save(&self, values: &BTreeMap<String, String>) -> Result<(), SecretCacheError> {
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; aead::NONCE_LEN];
+        self.random.fill(&mut salt).map_err(|_| SecretCacheError::Encrypt)?;
+        self.random.fill(&mut nonce).map_err(|_| SecretCacheError::Encrypt)?;
+
+        let mut data = serde_json::to_vec(values)?;
+        self.key(&salt, PBKDF2_ITERATIONS)?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(AAD), &mut data)
+            .map_err(|_| SecretCacheError::Encrypt)?;
+
+        let envelope = Envelope {
+            version: VERSION,
+            iterations: PBKDF2_ITERATIONS,
+            salt: B64.encode(salt),
+            nonce: B64.encode(nonce),
+            data: B64.encode(data),
+        };
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_vec_pretty(&envelope)?)?;
+        Ok(())
+    }
+
+    /// Load and decrypt the last snapshot.
+    pub fn load(&self) -> Result<BTreeMap<String, String>, SecretCacheError> {
+        let envelope: Envelope = serde_json::from_slice(&fs::read(&self.path)?)?;
+        if envelope.version != VERSION {
+            return Err(SecretCacheError::UnsupportedVersion(envelope.version));
+        }
+
+        let salt = B64.decode(&envelope.salt).map_err(|_| SecretCacheError::Encoding)?;
+        let nonce = B64.decode(&envelope.nonce).map_err(|_| SecretCacheError::Encoding)?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce).map_err(|_| SecretCacheError::Encoding)?;
+        let mut data = B64.decode(&envelope.data).map_err(|_| SecretCacheError::Encoding)?;
+
+        let plain = self
+            .key(&salt, envelope.iterations)?
+            .open_in_place(nonce, Aad::from(AAD), &mut data)
+            .map_err(|_| SecretCacheError::Decrypt)?;
+        Ok(serde_json::from_slice(plain)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("secret-cache-{}.json", uuid::Uuid::new_v4()));
+        let values = BTreeMap::from([
+            ("db.password".to_string(), "secret".to_string()),
+            ("port".to_string(), "8080".to_string()),
+        ]);
+
+        let cache = SecretCache::new(&path, Sensitive::new("passphrase".to_string()));
+        cache.save(&values).unwrap();
+        let raw = fs::read_to_string(&path).unwrap();
+        assert!(!raw.contains("secret\""));
+        assert_eq!(cache.load().unwrap(), values);
+
+        let wrong = SecretCache::new(&path, Sensitive::new("wrong".to_string()));
+        let result = wrong.load();
+        let _ = fs::remove_file(&path);
+        assert!(matches!(result, Err(SecretCacheError::Decrypt)));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::axum::{FileUploadError, UploadSink, UploadStorage};
+use async_trait::async_trait;
+use azure_core::auth::TokenCredential;
+use azure_storage::StorageCredentials;
+use azure_storage_blobs::prelude::{BlobBlockType, BlobClient, BlockId, BlockList, ClientBuilder, ContainerClient};
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// Size of the staged blocks, the chunks of the upload are buffered up to this size.
+const DEFAULT_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Store the uploads in an Azure Blob container. The file is staged in blocks and the blob is created
+/// only when the upload is committed, the blocks of an aborted upload are garbage collected by the storage.
+pub struct AzureBlobUploadStorage {
+    container: ContainerClient,
+    block_size: usize,
+}
+
+impl AzureBlobUploadStorage {
+    pub fn new(azure_credentials: Arc<dyn TokenCredential>, account: &str, container: &str) -> Self {
+        let credentials = StorageCredentials::token_credential(azure_credentials);
+        Self {
+            container: ClientBuilder::new(account, credentials).container_client(container),
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+
+    #[must_use]
+    pub fn with_block_size(self, block_size: usize) -> Self {
+        Self {
+            block_size: block_size.max(1),
+            ..self
+        }
+    }
+}
+
+struct AzureBlobUploadSink {
+    blob: BlobClient,
+    content_type: String,
+    block_size: usize,
+    buffer: Vec<u8>,
+    blocks: Vec<BlockId>,
+}
+
+impl AzureBlobUploadSink {
+    async fn put_block(&mut self) -> Result<(), FileUploadError> {
+        // the ids of the blocks of a blob must have the same length
+        let block_id = BlockId::new(format!("{:010}", self.blocks.len()));
+        let data = Bytes::from(std::mem::take(&mut self.buffer));
+        self.blob
+            .put_block(block_id.clone(), data)
+            .await
+            .map_err(|err| FileUploadError::Storage(err.to_string()))?;
+        self.blocks.push(block_id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UploadSink for AzureBlobUploadSink {
+    async fn write(&mut self, mut chunk: &[u8]) -> Result<(), FileUploadError> {
+        while !chunk.is_empty() {
+            let len = chunk.len().min(self.block_size - self.buffer.len());
+            self.buffer.extend_from_slice(&chunk[..len]);
+            chunk = &chunk[len..];
+            if self.buffer.len() >= self.block_size {
+                self.put_block().await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<String, FileUploadError> {
+        if !self.buffer.is_empty() || self.blocks.is_empty() {
+            self.put_block().await?;
+        }
+
+        let block_list = BlockList {
+            blocks: self.blocks.drain(..).map(BlobBlockType::new_uncommitted).collect(),
+        };
+        self.blob
+            .put_block_list(block_list)
+            .content_type(self.content_type.clone())
+            .await
+            .map_err(|err| FileUploadError::Storage(err.to_string()))?;
+
+        let url = self
+            .blob
+            .url()
+            .map_err(|err| FileUploadError::Storage(err.to_string()))?;
+        Ok(url.to_string())
+    }
+
+    async fn abort(self: Box<Self>) {
+        log::info!(
+            "Upload of {} aborted, {} staged blocks are left to expire",
+            self.blob.blob_name(),
+            self.blocks.len()
+        );
+    }
+}
+
+#[async_trait]
+impl UploadStorage for AzureBlobUploadStorage {
+    async fn create(&self, id: &str, content_type: &str) -> Result<Box<dyn UploadSink>, FileUploadError> {
+        Ok(Box::new(AzureBlobUploadSink {
+            blob: self.container.blob_client(id),
+            content_type: content_type.to_string(),
+            block_size: self.block_size,
+            buffer: Vec::new(),
+            blocks: Vec::new(),
+        }))
+    }
+}
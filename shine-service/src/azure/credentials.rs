@@ -0,0 +1,234 @@
+use async_trait::async_trait;
+use azure_core::{
+    auth::{AccessToken, TokenCredential},
+    error::ErrorKind as AzureErrorKind,
+};
+use azure_identity::{
+    AzureCliCredential, EnvironmentCredential, TokenCredentialOptions, VirtualMachineManagedIdentityCredential,
+    WorkloadIdentityCredential,
+};
+use std::{
+    collections::HashMap,
+    env,
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+};
+use time::{Duration, OffsetDateTime};
+
+/// Environment variable with the path of the federated (workload identity) token file. It is set by the
+/// workload identity webhook in kubernetes, but any projected token (ex. a SPIFFE JWT-SVID) can be used.
+pub const AZURE_FEDERATED_TOKEN_FILE: &str = "AZURE_FEDERATED_TOKEN_FILE";
+/// Environment variable to override the order of the credential sources, ex: `managed,cli`.
+pub const AZURE_CREDENTIAL_SOURCES: &str = "AZURE_CREDENTIAL_SOURCES";
+
+/// The tokens are refreshed this long before they expire.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::minutes(5);
+
+/// The supported sources of the azure credentials.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CredentialSource {
+    /// Workload identity federation, requires a federated token file, no client secret is required.
+    WorkloadIdentity,
+    /// Client secret or certificate from the environment, requires `AZURE_TENANT_ID`.
+    Environment,
+    /// Managed identity of the host through the instance metadata service (IMDS).
+    ManagedIdentity,
+    /// Azure cli for the local development.
+    AzureCli,
+}
+
+impl CredentialSource {
+    pub const DEFAULT_ORDER: [CredentialSource; 4] = [
+        CredentialSource::WorkloadIdentity,
+        CredentialSource::Environment,
+        CredentialSource::ManagedIdentity,
+        CredentialSource::AzureCli,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            CredentialSource::WorkloadIdentity => "workload",
+            CredentialSource::Environment => "environment",
+            CredentialSource::ManagedIdentity => "managed",
+            CredentialSource::AzureCli => "cli",
+        }
+    }
+
+    /// Create the credential, None if the source is not available in the current environment.
+    fn create(&self) -> Option<Arc<dyn TokenCredential>> {
+        let options = TokenCredentialOptions::default();
+        match self {
+            CredentialSource::WorkloadIdentity if env::var(AZURE_FEDERATED_TOKEN_FILE).is_ok() => {
+                match WorkloadIdentityCredential::create(options) {
+                    Ok(credential) => Some(Arc::new(credential)),
+                    Err(err) => {
+                        log::warn!("Workload identity is not available: {err}");
+                        None
+                    }
+                }
+            }
+            CredentialSource::Environment if env::var("AZURE_TENANT_ID").is_ok() => {
+                match EnvironmentCredential::create(options) {
+                    Ok(credential) => Some(Arc::new(credential)),
+                    Err(err) => {
+                        log::warn!("Environment credential is not available: {err}");
+                        None
+                    }
+                }
+            }
+            CredentialSource::ManagedIdentity => Some(Arc::new(VirtualMachineManagedIdentityCredential::new(options))),
+            CredentialSource::AzureCli => Some(Arc::new(AzureCliCredential::new())),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for CredentialSource {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        CredentialSource::DEFAULT_ORDER
+            .into_iter()
+            .find(|source| source.name() == value.trim())
+            .ok_or_else(|| format!("Unknown credential source: {value}"))
+    }
+}
+
+/// Parse a comma separated list of the credential sources.
+pub fn parse_credential_sources(value: &str) -> Result<Vec<CredentialSource>, String> {
+    value
+        .split(',')
+        .filter(|source| !source.trim().is_empty())
+        .map(CredentialSource::from_str)
+        .collect()
+}
+
+/// Try the credential sources in order until one succeeds. The successful source is remembered and it is used
+/// for the subsequent requests, the tokens are cached per scope until they are about to expire.
+pub struct CredentialChain {
+    sources: Vec<(CredentialSource, Arc<dyn TokenCredential>)>,
+    selected: Mutex<Option<usize>>,
+    tokens: Mutex<HashMap<String, AccessToken>>,
+}
+
+impl CredentialChain {
+    pub fn new(order: &[CredentialSource]) -> Result<Self, azure_core::Error> {
+        let sources: Vec<_> = order
+            .iter()
+            .filter_map(|source| source.create().map(|credential| (*source, credential)))
+            .collect();
+        if sources.is_empty() {
+            return Err(azure_core::Error::message(
+                AzureErrorKind::Credential,
+                "No azure credential source is available",
+            ));
+        }
+        log::info!(
+            "Azure credential chain: {:?}",
+            sources.iter().map(|(source, _)| source.name()).collect::<Vec<_>>()
+        );
+
+        Ok(Self {
+            sources,
+            selected: Mutex::new(None),
+            tokens: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn cached_token(&self, key: &str) -> Option<AccessToken> {
+        let tokens = self.tokens.lock().unwrap();
+        tokens
+            .get(key)
+            .filter(|token| token.expires_on - TOKEN_REFRESH_MARGIN > OffsetDateTime::now_utc())
+            .cloned()
+    }
+
+    async fn request_token(&self, scopes: &[&str]) -> azure_core::Result<AccessToken> {
+        let selected = *self.selected.lock().unwrap();
+        if let Some(index) = selected {
+            let (source, credential) = &self.sources[index];
+            match credential.get_token(scopes).await {
+                Ok(token) => return Ok(token),
+                Err(err) => log::warn!("Azure credential {} failed, trying the chain: {err}", source.name()),
+            }
+        }
+
+        let mut errors = Vec::new();
+        for (index, (source, credential)) in self.sources.iter().enumerate() {
+            match credential.get_token(scopes).await {
+                Ok(token) => {
+                    log::info!("Using azure credential {}", source.name());
+                    *self.selected.lock().unwrap() = Some(index);
+                    return Ok(token);
+                }
+                Err(err) => errors.push(format!("{}: {err}", source.name())),
+            }
+        }
+
+        Err(azure_core::Error::message(
+            AzureErrorKind::Credential,
+            format!("All the azure credential sources failed: {}", errors.join("; ")),
+        ))
+    }
+}
+
+#[async_trait]
+impl TokenCredential for CredentialChain {
+    async fn get_token(&self, scopes: &[&str]) -> azure_core::Result<AccessToken> {
+        let key = scopes.join(" ");
+        if let Some(token) = self.cached_token(&key) {
+            return Ok(token);
+        }
+
+        let token = self.request_token(scopes).await?;
+        self.tokens.lock().unwrap().insert(key, token.clone());
+        Ok(token)
+    }
+
+    async fn clear_cache(&self) -> azure_core::Result<()> {
+        self.tokens.lock().unwrap().clear();
+        *self.selected.lock().unwrap() = None;
+        for (_, credential) in &self.sources {
+            credential.clear_cache().await?;
+        }
+        Ok(())
+    }
+}
+
+/// The shared credential to access the azure resources. The sources are tried in the order given by the
+/// `AZURE_CREDENTIAL_SOURCES` environment variable or in the default order:
+///  - workload identity, when a federated token file is provided
+///  - environment (client secret or certificate), when `AZURE_TENANT_ID` is set
+///  - managed identity
+///  - azure cli for the local development
+pub fn default_chain() -> Result<Arc<dyn TokenCredential>, azure_core::Error> {
+    static DEFAULT_CHAIN: OnceLock<Arc<CredentialChain>> = OnceLock::new();
+
+    if let Some(chain) = DEFAULT_CHAIN.get() {
+        return Ok(chain.clone());
+    }
+
+    let order = match env::var(AZURE_CREDENTIAL_SOURCES) {
+        Ok(value) => parse_credential_sources(&value)
+            .map_err(|err| azure_core::Error::message(AzureErrorKind::Credential, err))?,
+        Err(_) => CredentialSource::DEFAULT_ORDER.to_vec(),
+    };
+    let chain = Arc::new(CredentialChain::new(&order)?);
+    Ok(DEFAULT_CHAIN.get_or_init(|| chain).clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn parse_sources() {
+        assert_eq!(
+            parse_credential_sources("managed, cli").unwrap(),
+            vec![CredentialSource::ManagedIdentity, CredentialSource::AzureCli]
+        );
+        assert_eq!(parse_credential_sources("").unwrap(), vec![]);
+        assert!(parse_credential_sources("managed,imds").is_err());
+    }
+}
@@ -1 +1,9 @@
+pub mod azure_health;
 pub mod azure_keyvault_config;
+pub mod azure_keyvault_secrets;
+pub mod azure_secret_cache;
+#[cfg(feature = "azure_blob")]
+pub mod blob_upload;
+pub mod credentials;
+#[cfg(feature = "azure_servicebus")]
+pub mod servicebus;
@@ -0,0 +1,330 @@
+use crate::{
+    azure::azure_health::ServiceBusProbe,
+    service::{EgressConfig, EgressError, EgressGuard},
+    utils::Sensitive,
+};
+use azure_core::{error::ErrorKind as AzureErrorKind, HttpClient, StatusCode};
+use azure_messaging_servicebus::service_bus::{PeekLockResponse, QueueClient};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{error::Error as StdError, future::Future, marker::PhantomData, sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{field::Empty, info_span, Instrument};
+
+#[derive(Debug, ThisError)]
+pub enum ServiceBusError {
+    #[error("Azure core error: {0}")]
+    Azure(#[from] azure_core::Error),
+    #[error("Failed to serialize the message")]
+    Serialize(#[source] serde_json::Error),
+    #[error(transparent)]
+    Egress(#[from] EgressError),
+    #[error("Failed to create http client")]
+    ClientBuild(#[source] reqwest::Error),
+}
+
+/// Connection to a service bus namespace with a shared access policy.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceBusConfig {
+    pub namespace: String,
+    pub policy_name: String,
+    pub policy_key: Sensitive<String>,
+    /// Restrict the destination of the calls, the namespace is connected only through the addresses passing
+    /// the checks.
+    #[serde(default)]
+    pub egress: Option<EgressConfig>,
+}
+
+impl ServiceBusConfig {
+    fn http_client(&self) -> Result<Arc<dyn HttpClient>, ServiceBusError> {
+        let Some(egress) = &self.egress else {
+            return Ok(azure_core::new_http_client());
+        };
+
+        let guard = EgressGuard::new(egress);
+        let url = url::Url::parse(&format!("https://{}.servicebus.windows.net/", self.namespace))
+            .map_err(|_| EgressError::HostNotAllowed(self.namespace.clone()))?;
+        guard.check_url(&url)?;
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(guard.resolver()))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(ServiceBusError::ClientBuild)?;
+        Ok(Arc::new(client))
+    }
+
+    pub fn queue_client(&self, queue: &str) -> Result<QueueClient, ServiceBusError> {
+        Ok(QueueClient::new(
+            self.http_client()?,
+            &self.namespace,
+            queue,
+            &self.policy_name,
+            self.policy_key.expose(),
+        )?)
+    }
+
+    /// Connectivity probe of the namespace for the status dashboard and the readiness check.
+    pub fn status_probe(&self, name: &str) -> Result<ServiceBusProbe, ServiceBusError> {
+        Ok(ServiceBusProbe::new(name, &self.namespace)?)
+    }
+}
+
+/// A message received from a queue.
+#[derive(Clone, Debug)]
+pub struct QueueMessage<T> {
+    pub message_id: String,
+    /// Number of times the message was delivered (1 for the first delivery, 0 if unknown).
+    pub delivery_count: usize,
+    pub payload: T,
+}
+
+/// Send typed messages into a queue, the payload is serialized as json.
+pub struct ServiceBusProducer<T> {
+    queue: String,
+    client: QueueClient,
+    _phantom: PhantomData<fn(&T)>,
+}
+
+impl<T> ServiceBusProducer<T>
+where
+    T: Serialize + Send + Sync,
+{
+    pub fn new(config: &ServiceBusConfig, queue: &str) -> Result<Self, ServiceBusError> {
+        Ok(Self {
+            queue: queue.to_string(),
+            client: config.queue_client(queue)?,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub async fn send(&self, payload: &T) -> Result<(), ServiceBusError> {
+        let body = serde_json::to_string(payload).map_err(ServiceBusError::Serialize)?;
+        let span = info_span!(
+            "servicebus.send",
+            otel.kind = "producer",
+            messaging.system = "servicebus",
+            messaging.destination.name = self.queue,
+            otel.status_code = Empty,
+        );
+        let result = self.client.send_message(&body, None).instrument(span.clone()).await;
+        if result.is_err() {
+            span.record("otel.status_code", "ERROR");
+        }
+        Ok(result?)
+    }
+}
+
+/// The result of the processing of a message.
+enum Settlement {
+    Complete,
+    Abandon,
+    DeadLetter(String),
+}
+
+/// Process the messages of a queue in peek-lock mode. A message is completed (deleted) when the handler
+/// succeeds and it is abandoned (unlocked) on failure to be delivered again. The malformed messages and the
+/// messages exceeding the `max_delivery_count` are forwarded into the dead-letter queue (if set) and completed,
+/// without a dead-letter queue they are left for the broker to dead-letter them based on the queue settings.
+/// The lock is not renewed, the handler has to complete within the lock duration of the queue.
+/// Unlike the `RedisStreamDeadLetters` and `OutboxDeadLetters`, no admin API is provided for the dead-letter
+/// queue: the REST API of the service bus can read the messages only by locking them, thus a listing would
+/// interfere with the processing. Use the Azure tooling to inspect and resubmit them.
+pub struct ServiceBusConsumer<T> {
+    queue: String,
+    client: QueueClient,
+    dead_letter: Option<QueueClient>,
+    concurrency: usize,
+    max_delivery_count: Option<usize>,
+    wait: Duration,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> ServiceBusConsumer<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    pub fn new(config: &ServiceBusConfig, queue: &str) -> Result<Self, ServiceBusError> {
+        Ok(Self {
+            queue: queue.to_string(),
+            client: config.queue_client(queue)?,
+            dead_letter: None,
+            concurrency: 1,
+            max_delivery_count: None,
+            wait: Duration::from_secs(30),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Forward the messages that cannot be processed into the given queue.
+    #[must_use]
+    pub fn with_dead_letter_queue(self, dead_letter: QueueClient) -> Self {
+        Self {
+            dead_letter: Some(dead_letter),
+            ..self
+        }
+    }
+
+    /// Maximum number of the messages processed in parallel.
+    #[must_use]
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            ..self
+        }
+    }
+
+    /// Dead-letter the messages after the given number of failed deliveries.
+    #[must_use]
+    pub fn with_max_delivery_count(self, max_delivery_count: usize) -> Self {
+        Self {
+            max_delivery_count: Some(max_delivery_count.max(1)),
+            ..self
+        }
+    }
+
+    /// Maximum time to wait for a new message in a single receive.
+    #[must_use]
+    pub fn with_wait(self, wait: Duration) -> Self {
+        Self { wait, ..self }
+    }
+
+    /// Receive and lock the next message, None if no message arrived within the wait period.
+    async fn receive(&self) -> Result<Option<PeekLockResponse>, ServiceBusError> {
+        match self.client.peek_lock_message2(Some(self.wait)).await {
+            Ok(response) if *response.status() == StatusCode::NoContent => Ok(None),
+            Ok(response) => Ok(Some(response)),
+            Err(err) if matches!(err.kind(), AzureErrorKind::HttpResponse { status, .. } if *status == StatusCode::NoContent) => {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn settle(&self, response: &PeekLockResponse, settlement: Settlement) -> Result<(), ServiceBusError> {
+        match settlement {
+            Settlement::Complete => {
+                response.delete_message().await?;
+            }
+            Settlement::Abandon => response.unlock_message().await?,
+            Settlement::DeadLetter(reason) => match &self.dead_letter {
+                Some(dead_letter) => {
+                    log::warn!("Dead-lettering message of queue {}: {reason}", self.queue);
+                    dead_letter.send_message(&response.body(), None).await?;
+                    response.delete_message().await?;
+                }
+                None => {
+                    log::warn!(
+                        "Abandoning message of queue {} without a dead-letter queue: {reason}",
+                        self.queue
+                    );
+                    response.unlock_message().await?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    async fn process<F, Fut, E>(&self, response: PeekLockResponse, handler: &F) -> Result<(), ServiceBusError>
+    where
+        F: Fn(QueueMessage<T>) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: StdError,
+    {
+        let properties = response.broker_properties().ok();
+        let message_id = properties
+            .as_ref()
+            .map(|properties| properties.message_id.clone())
+            .unwrap_or_default();
+        let delivery_count = properties
+            .as_ref()
+            .and_then(|properties| usize::try_from(properties.delivery_count).ok())
+            .unwrap_or(0);
+
+        let span = info_span!(
+            "servicebus.process",
+            otel.kind = "consumer",
+            messaging.system = "servicebus",
+            messaging.destination.name = self.queue,
+            messaging.message.id = message_id,
+            messaging.servicebus.delivery_count = delivery_count,
+            otel.status_code = Empty,
+        );
+
+        let settlement = async {
+            let payload = match serde_json::from_str::<T>(&response.body()) {
+                Ok(payload) => payload,
+                Err(err) => return Settlement::DeadLetter(format!("malformed message {message_id}: {err}")),
+            };
+            let message = QueueMessage {
+                message_id: message_id.clone(),
+                delivery_count,
+                payload,
+            };
+            match handler(message).await {
+                Ok(()) => Settlement::Complete,
+                Err(err) if self.max_delivery_count.is_some_and(|max| delivery_count >= max) => {
+                    Settlement::DeadLetter(format!("message {message_id} failed {delivery_count} times: {err}"))
+                }
+                Err(err) => {
+                    log::warn!("Failed to handle message {message_id} of queue {}: {err}", self.queue);
+                    Settlement::Abandon
+                }
+            }
+        }
+        .instrument(span.clone())
+        .await;
+
+        if !matches!(settlement, Settlement::Complete) {
+            span.record("otel.status_code", "ERROR");
+        }
+        self.settle(&response, settlement).instrument(span).await
+    }
+
+    /// Start processing the messages.
+    pub fn start<F, Fut, E>(self, handler: F) -> Vec<JoinHandle<()>>
+    where
+        F: Fn(QueueMessage<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send,
+        E: StdError + Send,
+    {
+        let (_, shutdown) = watch::channel(false);
+        self.start_with_shutdown(handler, shutdown)
+    }
+
+    /// Start processing the messages with `concurrency` workers until a shutdown is signaled. The messages
+    /// in progress are completed before the workers finish.
+    pub fn start_with_shutdown<F, Fut, E>(self, handler: F, shutdown: watch::Receiver<bool>) -> Vec<JoinHandle<()>>
+    where
+        F: Fn(QueueMessage<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send,
+        E: StdError + Send,
+    {
+        let consumer = Arc::new(self);
+        let handler = Arc::new(handler);
+        (0..consumer.concurrency)
+            .map(|_| {
+                let consumer = consumer.clone();
+                let handler = handler.clone();
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move {
+                    while !*shutdown.borrow() {
+                        let response = match consumer.receive().await {
+                            Ok(Some(response)) => response,
+                            Ok(None) => continue,
+                            Err(err) => {
+                                log::error!("Failed to receive from queue {}: {err:?}", consumer.queue);
+                                tokio::time::sleep(consumer.wait).await;
+                                continue;
+                            }
+                        };
+
+                        if let Err(err) = consumer.process(response, handler.as_ref()).await {
+                            log::error!("Failed to settle message of queue {}: {err:?}", consumer.queue);
+                        }
+                    }
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,79 @@
+/// Assert at compile time that the items are available when all the listed features are enabled, the check
+/// is skipped otherwise. The features are evaluated for the crate invoking the macro, thus it is used by the
+/// tests of this crate to keep the feature gates of the module tree consistent. Build the tests with the
+/// feature combinations in the CI (ex. with `cargo hack`) to check them.
+///
+/// ```ignore
+/// assert_compiles!("redis", "openapi" => crate::service::AdminRouter);
+/// ```
+#[macro_export]
+macro_rules! assert_compiles {
+    ($($feature:literal),+ => $($($item:ident)::+),+ $(,)?) => {
+        #[cfg(all($(feature = $feature),+))]
+        const _: () = {
+            $(
+                #[allow(unused_imports)]
+                use $($item)::+;
+            )+
+        };
+    };
+}
+
+pub use crate::assert_compiles;
+
+/// The features the crate was built with, ex. to report it on the diagnostic endpoints.
+pub fn enabled() -> Vec<&'static str> {
+    [
+        ("telemetry", cfg!(feature = "telemetry")),
+        ("ot_otlp", cfg!(feature = "ot_otlp")),
+        ("ot_zipkin", cfg!(feature = "ot_zipkin")),
+        ("ot_app_insight", cfg!(feature = "ot_app_insight")),
+        ("postgres", cfg!(feature = "postgres")),
+        ("sql_check", cfg!(feature = "sql_check")),
+        ("sqlx", cfg!(feature = "sqlx")),
+        ("redis", cfg!(feature = "redis")),
+        ("azure", cfg!(feature = "azure")),
+        ("azure_servicebus", cfg!(feature = "azure_servicebus")),
+        ("aws_config", cfg!(feature = "aws_config")),
+        ("openapi", cfg!(feature = "openapi")),
+        ("html_template", cfg!(feature = "html_template")),
+        ("jwt", cfg!(feature = "jwt")),
+        ("email_smtp", cfg!(feature = "email_smtp")),
+        ("email_acs", cfg!(feature = "email_acs")),
+        ("grpc", cfg!(feature = "grpc")),
+        ("http_client", cfg!(feature = "http_client")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    assert_compiles!("telemetry" => crate::axum::telemetry::TelemetryService);
+    assert_compiles!("postgres" => crate::service::PGConnectionPool, crate::service::PGPoolConfig);
+    assert_compiles!("sqlx" => crate::service::create_sqlx_pool);
+    assert_compiles!(
+        "redis" =>
+        crate::service::RedisConnectionPool,
+        crate::service::CheckedCurrentUser,
+        crate::service::FeatureFlagStore,
+        crate::service::QueueLagStatus,
+    );
+    assert_compiles!("postgres", "redis" => crate::service::Outbox);
+    assert_compiles!("azure" => crate::azure::credentials::default_chain, crate::azure::azure_health::KeyVaultProbe);
+    assert_compiles!("openapi" => crate::axum::ApiEndpoint, crate::service::EventSchemaRegistry);
+    assert_compiles!("redis", "openapi" => crate::service::AdminRouter);
+
+    #[test]
+    fn enabled_features() {
+        let features = enabled();
+        assert_eq!(features.contains(&"postgres"), cfg!(feature = "postgres"));
+        assert_eq!(features.contains(&"redis"), cfg!(feature = "redis"));
+        assert_eq!(features.contains(&"openapi"), cfg!(feature = "openapi"));
+        assert!(!features.contains(&"unknown"));
+    }
+}
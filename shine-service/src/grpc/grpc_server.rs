@@ -0,0 +1,113 @@
+use crate::{axum::telemetry::TelemetryService, grpc::GrpcOtelLayer};
+use std::{convert::Infallible, future::Future, net::SocketAddr};
+use thiserror::Error as ThisError;
+use tonic::{
+    body::BoxBody,
+    codegen::{
+        http::{Request, Response},
+        Service,
+    },
+    server::NamedService,
+    service::Routes,
+    transport::Server,
+};
+use tonic_health::{server::HealthReporter, ServingStatus};
+
+#[derive(Debug, ThisError)]
+pub enum GrpcError {
+    #[error(transparent)]
+    Transport(#[from] tonic::transport::Error),
+    #[error(transparent)]
+    Reflection(#[from] tonic_reflection::server::Error),
+}
+
+/// A tonic server sharing the telemetry of the axum services. The health service (`grpc.health.v1`)
+/// reports the registered services as serving until the shutdown starts, and the reflection service
+/// is added when file descriptor sets are provided.
+pub struct GrpcServer {
+    routes: Routes,
+    service_names: Vec<&'static str>,
+    file_descriptor_sets: Vec<&'static [u8]>,
+    layer: GrpcOtelLayer,
+}
+
+impl GrpcServer {
+    pub fn new(telemetry: &TelemetryService) -> Self {
+        let mut layer = GrpcOtelLayer::default();
+        if let Some(meter) = telemetry.service_meter() {
+            layer = layer.meter(meter.clone());
+        }
+
+        Self {
+            routes: Routes::default(),
+            service_names: Vec::new(),
+            file_descriptor_sets: Vec::new(),
+            layer,
+        }
+    }
+
+    #[must_use]
+    pub fn with_service<S>(mut self, service: S) -> Self
+    where
+        S: Service<Request<BoxBody>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.service_names.push(S::NAME);
+        self.routes = self.routes.add_service(service);
+        self
+    }
+
+    /// Register the encoded file descriptor set of the services (generated by `tonic-build`) for the reflection.
+    #[must_use]
+    pub fn with_file_descriptor_set(mut self, file_descriptor_set: &'static [u8]) -> Self {
+        self.file_descriptor_sets.push(file_descriptor_set);
+        self
+    }
+
+    async fn set_status(reporter: &mut HealthReporter, service_names: &[&'static str], status: ServingStatus) {
+        // the empty name is the status of the whole server
+        reporter.set_service_status("", status).await;
+        for name in service_names {
+            reporter.set_service_status(*name, status).await;
+        }
+    }
+
+    /// Serve the calls until the shutdown completes, ex. `ShutdownController::shutdown_signal`. When the
+    /// shutdown starts, the services are reported as not serving and the calls in progress are completed.
+    pub async fn serve<F>(self, addr: SocketAddr, shutdown: F) -> Result<(), GrpcError>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let (mut reporter, health_service) = tonic_health::server::health_reporter();
+        Self::set_status(&mut reporter, &self.service_names, ServingStatus::Serving).await;
+        let mut routes = self.routes.add_service(health_service);
+
+        if !self.file_descriptor_sets.is_empty() {
+            let mut reflection = tonic_reflection::server::Builder::configure()
+                .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET);
+            for file_descriptor_set in self.file_descriptor_sets {
+                reflection = reflection.register_encoded_file_descriptor_set(file_descriptor_set);
+            }
+            routes = routes.add_service(reflection.build_v1()?);
+        }
+
+        let service_names = self.service_names;
+        let shutdown = async move {
+            shutdown.await;
+            log::info!("Shutting down gRPC server");
+            Self::set_status(&mut reporter, &service_names, ServingStatus::NotServing).await;
+        };
+
+        log::info!("Starting gRPC server on {addr}");
+        Server::builder()
+            .layer(self.layer)
+            .add_routes(routes)
+            .serve_with_shutdown(addr, shutdown)
+            .await?;
+        Ok(())
+    }
+}
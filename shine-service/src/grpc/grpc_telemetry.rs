@@ -0,0 +1,175 @@
+use crate::axum::telemetry::{extract_context, TRACING_TARGET};
+use futures::future::BoxFuture;
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
+use std::{
+    error::Error as StdError,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tonic::codegen::http::{Request, Response};
+use tower::{Layer, Service};
+use tracing::{field::Empty, trace_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+
+const GRPC_STATUS_HEADER: &str = "grpc-status";
+
+/// Split the `/package.Service/Method` path of a call.
+fn rpc_service_method(path: &str) -> (&str, &str) {
+    path.trim_start_matches('/').split_once('/').unwrap_or((path, ""))
+}
+
+#[derive(Clone)]
+struct GrpcMeters {
+    request_counter: Counter<u64>,
+    request_duration: Histogram<f64>,
+    error_counter: Counter<u64>,
+}
+
+/// Layer for the tonic server to create spans and metrics from the calls, the gRPC counterpart
+/// of the `OtelLayer`.
+#[derive(Default, Clone)]
+pub struct GrpcOtelLayer {
+    meter: Option<Meter>,
+}
+
+impl GrpcOtelLayer {
+    #[must_use]
+    pub fn meter(self, meter: Meter) -> Self {
+        GrpcOtelLayer { meter: Some(meter) }
+    }
+}
+
+impl<S> Layer<S> for GrpcOtelLayer {
+    type Service = GrpcOtelService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let meters = self.meter.as_ref().map(|meter| GrpcMeters {
+            request_counter: meter.u64_counter("grpc_request_count").init(),
+            request_duration: meter.f64_histogram("grpc_request_duration").init(),
+            error_counter: meter.u64_counter("grpc_error_count").init(),
+        });
+
+        GrpcOtelService { inner, meters }
+    }
+}
+
+#[derive(Clone)]
+pub struct GrpcOtelService<S> {
+    inner: S,
+    meters: Option<GrpcMeters>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GrpcOtelService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: StdError + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (service, method) = rpc_service_method(req.uri().path());
+        let (service, method) = (service.to_string(), method.to_string());
+        let name = format!("{service}/{method}");
+
+        let span = trace_span!(
+            target: TRACING_TARGET,
+            "gRPC request",
+            rpc.system = "grpc",
+            rpc.service = %service,
+            rpc.method = %method,
+            rpc.grpc.status_code = Empty, // set on response
+            otel.name = %name,
+            otel.kind = ?opentelemetry::trace::SpanKind::Server,
+            otel.status_code = Empty, // set on response
+            exception.message = Empty, // set on transport error
+        );
+        span.set_parent(extract_context(req.headers()));
+
+        let start = Instant::now();
+        let meters = self.meters.clone();
+        let future = {
+            let _guard = span.enter();
+            self.inner.call(req)
+        };
+
+        Box::pin(
+            async move {
+                let result = future.await;
+                let span = tracing::Span::current();
+
+                // errors are sent in a trailers-only response (status in the headers), the status of
+                // the successful calls is sent in the trailers
+                let status = match &result {
+                    Ok(response) => response
+                        .headers()
+                        .get(GRPC_STATUS_HEADER)
+                        .and_then(|status| status.to_str().ok())
+                        .and_then(|status| status.parse::<i32>().ok())
+                        .unwrap_or(0),
+                    Err(err) => {
+                        span.record("exception.message", err.to_string());
+                        tonic::Code::Unavailable as i32
+                    }
+                };
+                span.record("rpc.grpc.status_code", status);
+                // the client errors (ex. not found, invalid argument) are not failures of the server
+                let is_server_error = matches!(
+                    tonic::Code::from_i32(status),
+                    tonic::Code::Unknown
+                        | tonic::Code::DeadlineExceeded
+                        | tonic::Code::ResourceExhausted
+                        | tonic::Code::Unimplemented
+                        | tonic::Code::Internal
+                        | tonic::Code::Unavailable
+                        | tonic::Code::DataLoss
+                );
+                if is_server_error {
+                    span.record("otel.status_code", "ERROR");
+                }
+
+                if let Some(meters) = meters {
+                    let attributes = [
+                        KeyValue::new("service", service),
+                        KeyValue::new("method", method),
+                        KeyValue::new("status", i64::from(status)),
+                    ];
+                    meters.request_counter.add(1, &attributes);
+                    meters
+                        .request_duration
+                        .record(start.elapsed().as_secs_f64(), &attributes);
+                    if is_server_error {
+                        meters.error_counter.add(1, &attributes);
+                    }
+                }
+
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn parse_rpc_path() {
+        assert_eq!(
+            rpc_service_method("/grpc.health.v1.Health/Check"),
+            ("grpc.health.v1.Health", "Check")
+        );
+        assert_eq!(rpc_service_method("/unknown"), ("/unknown", ""));
+    }
+}
@@ -0,0 +1,4 @@
+mod grpc_telemetry;
+pub use self::grpc_telemetry::*;
+mod grpc_server;
+pub use self::grpc_server::*;
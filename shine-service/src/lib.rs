@@ -1,4 +1,13 @@
+// allow the derive macros to refer to the crate as shine_service internally
+extern crate self as shine_service;
+
+#[cfg(feature = "aws_config")]
+pub mod aws;
 pub mod axum;
+#[cfg(feature = "azure")]
 pub mod azure;
+pub mod features;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod service;
 pub mod utils;
@@ -0,0 +1,499 @@
+use crate::{
+    axum::{
+        telemetry::TelemetryService, ApiEndpoint, ApiMethod, ApiRoute, MaintenanceMode, MaintenanceState, Problem,
+        ProblemConfig, RequiredLayers,
+    },
+    service::{CheckedCurrentUser, FeatureFlagStore, FlagRule, UserSessionCacheReader},
+    utils::DurationStr,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json, RequestPartsExt, Router,
+};
+use bb8::{ManageConnection, Pool as BB8Pool, State as BB8State};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use utoipa::{openapi::OpenApi, ToSchema};
+use uuid::Uuid;
+
+/// Header of the API key of the automated admin clients.
+pub const ADMIN_API_KEY_HEADER: &str = "x-admin-api-key";
+
+const ADMIN_TAG: &str = "admin";
+
+/// Access control of the admin endpoints, a user session with the role or one of the API keys is accepted.
+#[derive(Default)]
+pub struct AdminAuth {
+    role: Option<String>,
+    api_keys: Vec<Vec<u8>>,
+}
+
+impl AdminAuth {
+    fn is_valid_key(&self, key: &[u8]) -> bool {
+        // only the digests are stored and compared
+        let key = digest::digest(&digest::SHA256, key);
+        self.api_keys.iter().any(|valid| valid.as_slice() == key.as_ref())
+    }
+}
+
+/// The authenticated caller of an admin endpoint.
+#[derive(Clone, Debug)]
+pub enum AdminCaller {
+    User(Uuid),
+    ApiKey,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminCaller
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(auth) = parts
+            .extract::<Extension<Arc<AdminAuth>>>()
+            .await
+            .expect("Missing AdminAuth extension");
+
+        if let Some(key) = parts.headers.get(ADMIN_API_KEY_HEADER) {
+            return if auth.is_valid_key(key.as_bytes()) {
+                Ok(AdminCaller::ApiKey)
+            } else {
+                Err(Problem::unauthorized().with_detail("Invalid API key").into_response())
+            };
+        }
+
+        let has_session = parts.extensions.get::<Arc<UserSessionCacheReader>>().is_some();
+        let Some(role) = auth.role.as_ref().filter(|_| has_session) else {
+            return Err(Problem::unauthorized().into_response());
+        };
+        let user = parts
+            .extract::<CheckedCurrentUser>()
+            .await
+            .map_err(IntoResponse::into_response)?;
+        if !user.has_role(role) {
+            return Err(Problem::forbidden()
+                .with_detail(format!("Missing role: {role}"))
+                .into_response());
+        }
+        Ok(AdminCaller::User(user.user_id))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceFilter {
+    pub filter: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStatistics {
+    pub name: String,
+    pub connections: u32,
+    pub idle_connections: u32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStatisticsResponse {
+    pub pools: Vec<PoolStatistics>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureFlagsResponse {
+    pub flags: HashMap<String, FlagRule>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceStatus {
+    pub state: Option<MaintenanceState>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceRequest {
+    pub message: String,
+    /// Retry-After hint of the rejected requests, ex: `"5m"`, 60 seconds by default.
+    pub retry_after: Option<DurationStr>,
+}
+
+type PoolState = Arc<dyn Fn() -> BB8State + Send + Sync>;
+
+/// Opt-in router of the operational endpoints, only the configured components are exposed:
+///  - GET, PUT /admin/telemetry/filter
+///  - GET /admin/metrics
+///  - GET /admin/pools
+///  - GET /admin/flags, PUT, DELETE /admin/flags/:flag
+///  - GET, PUT, DELETE /admin/maintenance
+pub struct AdminRouter {
+    auth: AdminAuth,
+    telemetry: Option<TelemetryService>,
+    pools: Vec<(String, PoolState)>,
+    feature_flags: Option<Arc<FeatureFlagStore>>,
+    maintenance: Option<MaintenanceMode>,
+    routes: Vec<Router>,
+    required_layers: RequiredLayers,
+}
+
+impl Default for AdminRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdminRouter {
+    pub fn new() -> Self {
+        Self {
+            auth: AdminAuth::default(),
+            telemetry: None,
+            pools: Vec::new(),
+            feature_flags: None,
+            maintenance: None,
+            routes: Vec::new(),
+            required_layers: RequiredLayers::new(),
+        }
+    }
+
+    /// Role required from the users, without a role only the API keys are accepted.
+    #[must_use]
+    pub fn with_role(mut self, role: &str) -> Self {
+        self.auth.role = Some(role.to_string());
+        self
+    }
+
+    /// Accept an API key in the `x-admin-api-key` header, ex. for the deployment automation.
+    #[must_use]
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        let key = digest::digest(&digest::SHA256, api_key.as_bytes());
+        self.auth.api_keys.push(key.as_ref().to_vec());
+        self
+    }
+
+    #[must_use]
+    pub fn with_telemetry(self, telemetry: TelemetryService) -> Self {
+        Self {
+            telemetry: Some(telemetry),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_pool<M: ManageConnection>(mut self, name: &str, pool: BB8Pool<M>) -> Self {
+        self.pools.push((name.to_string(), Arc::new(move || pool.state())));
+        self
+    }
+
+    #[must_use]
+    pub fn with_feature_flags(self, feature_flags: Arc<FeatureFlagStore>) -> Self {
+        Self {
+            feature_flags: Some(feature_flags),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_maintenance(self, maintenance: MaintenanceMode) -> Self {
+        Self {
+            maintenance: Some(maintenance),
+            ..self
+        }
+    }
+
+    /// Mount the admin routes of the other components (ex. `OutboxDeadLetters::into_router`), their
+    /// handlers are authorized by the `AdminCaller` extractor with the same role and API keys.
+    #[must_use]
+    pub fn with_routes(mut self, routes: Router) -> Self {
+        self.routes.push(routes);
+        self
+    }
+
+    /// Register the requirements of the admin endpoints and the provided `AdminAuth` in the registry of the
+    /// service, see `ShineService::required_layers`.
+    #[must_use]
+    pub fn with_required_layers(self, required_layers: RequiredLayers) -> Self {
+        Self {
+            required_layers,
+            ..self
+        }
+    }
+
+    fn endpoint<S, H, T>(method: ApiMethod, path: &str, operation_id: &str, handler: H) -> ApiEndpoint<S>
+    where
+        S: Clone + Send + Sync + 'static,
+        H: axum::handler::Handler<T, S>,
+        T: 'static,
+    {
+        ApiEndpoint::new(method, path.to_string(), handler)
+            .with_operation_id(operation_id)
+            .with_tag(ADMIN_TAG)
+            .with_policy("adminRoleOrApiKey")
+            .with_problem_response(&[StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN])
+            .requires::<Arc<AdminAuth>>()
+            .requires::<ProblemConfig>()
+    }
+
+    pub fn into_router<S>(self, mut doc: Option<&mut OpenApi>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let layers = self.required_layers.clone();
+        let mut router = Router::new();
+
+        if let Some(telemetry) = self.telemetry {
+            let get_filter = {
+                let telemetry = telemetry.clone();
+                move |_: AdminCaller| async move {
+                    match telemetry.get_configuration() {
+                        Ok(filter) => Json(TraceFilter { filter }).into_response(),
+                        Err(err) => Problem::not_found().with_detail(err).into_response(),
+                    }
+                }
+            };
+            let set_filter = {
+                let telemetry = telemetry.clone();
+                move |caller: AdminCaller, Json(body): Json<TraceFilter>| async move {
+                    log::info!("Trace filter set to {} by {caller:?}", body.filter);
+                    match telemetry.set_configuration(body.filter) {
+                        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                        Err(err) => Problem::bad_request("invalid-filter").with_detail(err).into_response(),
+                    }
+                }
+            };
+            let metrics = {
+                let telemetry = telemetry.clone();
+                move |_: AdminCaller| async move { telemetry.metrics() }
+            };
+            let has_tenant_metrics = telemetry.has_tenant_metrics();
+            let tenant_metrics = move |_: AdminCaller, Path(tenant): Path<String>| async move {
+                match telemetry.tenant_metrics(&tenant) {
+                    Some(metrics) => metrics.into_response(),
+                    None => Problem::not_found().into_response(),
+                }
+            };
+
+            router = router
+                .add_required_api(
+                    Self::endpoint(ApiMethod::Get, "/admin/telemetry/filter", "getTraceFilter", get_filter)
+                        .with_description("Get the active trace filter.")
+                        .with_json_response::<TraceFilter>(StatusCode::OK)
+                        .with_problem_response(&[StatusCode::NOT_FOUND]),
+                    doc.as_deref_mut(),
+                    &layers,
+                )
+                .add_required_api(
+                    Self::endpoint(ApiMethod::Put, "/admin/telemetry/filter", "setTraceFilter", set_filter)
+                        .with_description("Replace the trace filter, it requires the reconfigure to be enabled.")
+                        .with_json_request::<TraceFilter>()
+                        .with_status_response(StatusCode::NO_CONTENT, "Filter updated")
+                        .with_problem_response(&[StatusCode::BAD_REQUEST]),
+                    doc.as_deref_mut(),
+                    &layers,
+                )
+                .add_required_api(
+                    Self::endpoint(ApiMethod::Get, "/admin/metrics", "getMetrics", metrics)
+                        .with_description("Snapshot of the metrics in the prometheus text format.")
+                        .with_page_response("Metrics"),
+                    doc.as_deref_mut(),
+                    &layers,
+                );
+
+            if has_tenant_metrics {
+                router = router.add_required_api(
+                    Self::endpoint(ApiMethod::Get, "/metrics/:tenant", "getTenantMetrics", tenant_metrics)
+                        .with_description("Snapshot of the metrics of a tenant in the prometheus text format.")
+                        .with_page_response("Metrics")
+                        .with_problem_response(&[StatusCode::NOT_FOUND]),
+                    doc.as_deref_mut(),
+                    &layers,
+                );
+            }
+        }
+
+        if !self.pools.is_empty() {
+            let pools = Arc::new(self.pools);
+            let get_pools = move |_: AdminCaller| async move {
+                let pools = pools
+                    .iter()
+                    .map(|(name, state)| {
+                        let state = state();
+                        PoolStatistics {
+                            name: name.clone(),
+                            connections: state.connections,
+                            idle_connections: state.idle_connections,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                Json(PoolStatisticsResponse { pools })
+            };
+
+            router = router.add_required_api(
+                Self::endpoint(ApiMethod::Get, "/admin/pools", "getPoolStatistics", get_pools)
+                    .with_description("Statistics of the connection pools.")
+                    .with_json_response::<PoolStatisticsResponse>(StatusCode::OK),
+                doc.as_deref_mut(),
+                &layers,
+            );
+        }
+
+        if let Some(store) = self.feature_flags {
+            let get_flags = {
+                let store = store.clone();
+                move |_: AdminCaller, Extension(problem_config): Extension<ProblemConfig>| async move {
+                    store.invalidate();
+                    match store.rules().await {
+                        Ok(rules) => Json(FeatureFlagsResponse {
+                            flags: rules.as_ref().clone(),
+                        })
+                        .into_response(),
+                        Err(err) => {
+                            Problem::internal_error(&problem_config, "Failed to load the flags", err).into_response()
+                        }
+                    }
+                }
+            };
+            let set_flag = {
+                let store = store.clone();
+                move |caller: AdminCaller,
+                      Extension(problem_config): Extension<ProblemConfig>,
+                      Path(flag): Path<String>,
+                      Json(rule): Json<FlagRule>| async move {
+                    log::info!("Feature flag {flag} set by {caller:?}");
+                    match store.set_rule(&flag, &rule).await {
+                        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                        Err(err) => {
+                            Problem::internal_error(&problem_config, "Failed to set the flag", err).into_response()
+                        }
+                    }
+                }
+            };
+            let remove_flag = move |caller: AdminCaller,
+                                    Extension(problem_config): Extension<ProblemConfig>,
+                                    Path(flag): Path<String>| async move {
+                log::info!("Feature flag {flag} removed by {caller:?}");
+                match store.remove_rule(&flag).await {
+                    Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                    Err(err) => {
+                        Problem::internal_error(&problem_config, "Failed to remove the flag", err).into_response()
+                    }
+                }
+            };
+
+            router = router
+                .add_required_api(
+                    Self::endpoint(ApiMethod::Get, "/admin/flags", "getFeatureFlags", get_flags)
+                        .with_description("The stored rules of the feature flags.")
+                        .with_json_response::<FeatureFlagsResponse>(StatusCode::OK),
+                    doc.as_deref_mut(),
+                    &layers,
+                )
+                .add_required_api(
+                    Self::endpoint(ApiMethod::Put, "/admin/flags/:flag", "setFeatureFlag", set_flag)
+                        .with_description("Set the rule of a feature flag in all the services.")
+                        .with_json_request::<FlagRule>()
+                        .with_status_response(StatusCode::NO_CONTENT, "Flag updated"),
+                    doc.as_deref_mut(),
+                    &layers,
+                )
+                .add_required_api(
+                    Self::endpoint(
+                        ApiMethod::Delete,
+                        "/admin/flags/:flag",
+                        "removeFeatureFlag",
+                        remove_flag,
+                    )
+                    .with_description("Remove the rule of a feature flag, the default of the flag is used.")
+                    .with_status_response(StatusCode::NO_CONTENT, "Flag removed"),
+                    doc.as_deref_mut(),
+                    &layers,
+                );
+        }
+
+        if let Some(maintenance) = self.maintenance {
+            let get_maintenance = {
+                let maintenance = maintenance.clone();
+                move |_: AdminCaller| async move {
+                    Json(MaintenanceStatus {
+                        state: maintenance.state(),
+                    })
+                }
+            };
+            let enable_maintenance = {
+                let maintenance = maintenance.clone();
+                move |caller: AdminCaller, Json(body): Json<MaintenanceRequest>| async move {
+                    log::info!("Maintenance mode requested by {caller:?}");
+                    let retry_after = body.retry_after.map(Duration::from).unwrap_or(Duration::from_secs(60));
+                    maintenance.enable(&body.message, retry_after);
+                    StatusCode::NO_CONTENT
+                }
+            };
+            let disable_maintenance = move |caller: AdminCaller| async move {
+                log::info!("Maintenance mode ended by {caller:?}");
+                maintenance.disable();
+                StatusCode::NO_CONTENT
+            };
+
+            router = router
+                .add_required_api(
+                    Self::endpoint(ApiMethod::Get, "/admin/maintenance", "getMaintenance", get_maintenance)
+                        .with_description("The maintenance state of the replica.")
+                        .with_json_response::<MaintenanceStatus>(StatusCode::OK),
+                    doc.as_deref_mut(),
+                    &layers,
+                )
+                .add_required_api(
+                    Self::endpoint(
+                        ApiMethod::Put,
+                        "/admin/maintenance",
+                        "enableMaintenance",
+                        enable_maintenance,
+                    )
+                    .with_description("Reject the non-operational requests of the replica with 503.")
+                    .with_json_request::<MaintenanceRequest>()
+                    .with_status_response(StatusCode::NO_CONTENT, "Maintenance enabled"),
+                    doc.as_deref_mut(),
+                    &layers,
+                )
+                .add_required_api(
+                    Self::endpoint(
+                        ApiMethod::Delete,
+                        "/admin/maintenance",
+                        "disableMaintenance",
+                        disable_maintenance,
+                    )
+                    .with_description("End the maintenance of the replica.")
+                    .with_status_response(StatusCode::NO_CONTENT, "Maintenance disabled"),
+                    doc.as_deref_mut(),
+                    &layers,
+                );
+        }
+
+        for routes in self.routes {
+            router = router.merge(routes.with_state(()));
+        }
+
+        layers.layer(router, Extension(Arc::new(self.auth)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn api_key() {
+        let router = AdminRouter::new().with_api_key("secret-key");
+        assert!(router.auth.is_valid_key(b"secret-key"));
+        assert!(!router.auth.is_valid_key(b"secret-key2"));
+        assert!(!router.auth.is_valid_key(b""));
+        assert!(!AdminAuth::default().is_valid_key(b"secret-key"));
+    }
+}
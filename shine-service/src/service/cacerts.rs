@@ -1,18 +1,79 @@
-use rustls::RootCertStore;
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use rustls::{pki_types::CertificateDer, RootCertStore};
 use rustls_native_certs::{load_native_certs, Error};
+use std::{
+    fs::File,
+    io::{self, BufReader},
+};
 use thiserror::Error as ThisError;
 
 #[derive(ThisError, Debug)]
-#[error("Failed to load native certs: {0:?}")]
-pub struct CertError(Vec<Error>);
+pub enum CertError {
+    #[error("Failed to load native certs: {0:?}")]
+    Native(Vec<Error>),
+    #[error("Failed to load CA file {0}: {1}")]
+    CaFile(String, io::Error),
+}
 
-pub fn get_root_cert_store() -> Result<RootCertStore, CertError> {
-    let mut store = RootCertStore::empty();
+/// Load the certificates of a PEM file.
+pub fn load_ca_file(path: &str) -> Result<Vec<CertificateDer<'static>>, CertError> {
+    let file = File::open(path).map_err(|err| CertError::CaFile(path.to_string(), err))?;
+    rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| CertError::CaFile(path.to_string(), err))
+}
+
+/// The native root certificates extended with the certificates of the given PEM files.
+pub fn get_root_certs(ca_files: &[&str]) -> Result<Vec<CertificateDer<'static>>, CertError> {
     let certs_result = load_native_certs();
     if !certs_result.errors.is_empty() {
-        return Err(CertError(certs_result.errors));
-    } else {
-        store.add_parsable_certificates(certs_result.certs);
-        Ok(store)
+        return Err(CertError::Native(certs_result.errors));
+    }
+
+    let mut certs = certs_result.certs;
+    for ca_file in ca_files {
+        certs.extend(load_ca_file(ca_file)?);
+    }
+    Ok(certs)
+}
+
+pub fn get_root_cert_store() -> Result<RootCertStore, CertError> {
+    get_root_cert_store_with(&[])
+}
+
+/// Root cert store of the native certificates extended with the certificates of the given PEM files.
+pub fn get_root_cert_store_with(ca_files: &[&str]) -> Result<RootCertStore, CertError> {
+    let mut store = RootCertStore::empty();
+    store.add_parsable_certificates(get_root_certs(ca_files)?);
+    Ok(store)
+}
+
+/// Encode a certificate in PEM, ex. for the clients not accepting a `RootCertStore`.
+pub fn to_pem(cert: &CertificateDer) -> String {
+    let encoded = B64.encode(cert.as_ref());
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ascii"));
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn pem_roundtrip() {
+        let cert = CertificateDer::from((0..100_u8).collect::<Vec<_>>());
+        let pem = to_pem(&cert);
+        assert!(pem.lines().all(|line| line.len() <= 64));
+
+        let certs = rustls_pemfile::certs(&mut pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(certs, vec![cert]);
     }
 }
@@ -0,0 +1,432 @@
+use crate::{
+    service::{DependencyHealth, DependencyStatus, StatusSource},
+    utils::DurationStr,
+};
+use async_trait::async_trait;
+use opentelemetry::{
+    metrics::{Counter, Gauge, Meter},
+    KeyValue,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+
+/// Number of the buckets of the rolling window.
+const WINDOW_BUCKETS: u32 = 10;
+
+fn default_failure_rate() -> f64 {
+    0.5
+}
+
+fn default_minimum_calls() -> usize {
+    10
+}
+
+fn default_window() -> DurationStr {
+    DurationStr::from_secs(60)
+}
+
+fn default_open_duration() -> DurationStr {
+    DurationStr::from_secs(30)
+}
+
+fn default_half_open_calls() -> usize {
+    3
+}
+
+#[derive(Debug, ThisError)]
+pub enum CircuitBreakerError<E> {
+    #[error("Circuit {0} is open")]
+    Open(String),
+    #[error(transparent)]
+    Inner(E),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitBreakerConfig {
+    /// Ratio of the failed calls in the window (0..1) that opens the circuit.
+    #[serde(default = "default_failure_rate")]
+    pub failure_rate: f64,
+    /// Minimum number of the calls in the window before the failure rate is evaluated.
+    #[serde(default = "default_minimum_calls")]
+    pub minimum_calls: usize,
+    /// Length of the rolling window of the failure rate.
+    #[serde(default = "default_window")]
+    pub window: DurationStr,
+    /// Time to reject the calls before the dependency is probed again.
+    #[serde(default = "default_open_duration")]
+    pub open_duration: DurationStr,
+    /// Number of the successful trial calls required to close the circuit.
+    #[serde(default = "default_half_open_calls")]
+    pub half_open_calls: usize,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_rate: default_failure_rate(),
+            minimum_calls: default_minimum_calls(),
+            window: default_window(),
+            open_duration: default_open_duration(),
+            half_open_calls: default_half_open_calls(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "halfOpen",
+        }
+    }
+
+    fn as_gauge(&self) -> u64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen => 1,
+            CircuitState::Open => 2,
+        }
+    }
+}
+
+struct Bucket {
+    start: Instant,
+    successes: usize,
+    failures: usize,
+}
+
+enum State {
+    Closed { buckets: VecDeque<Bucket> },
+    Open { until: Instant },
+    HalfOpen { in_flight: usize, successes: usize },
+}
+
+impl State {
+    fn closed() -> Self {
+        State::Closed {
+            buckets: VecDeque::new(),
+        }
+    }
+
+    fn circuit_state(&self) -> CircuitState {
+        match self {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CircuitMeters {
+    state: Gauge<u64>,
+    transitions: Counter<u64>,
+    rejected: Counter<u64>,
+}
+
+/// Protect the callers from a failing dependency: when the failure rate in the rolling window exceeds
+/// the threshold, the circuit opens and the calls are rejected immediately instead of piling up. After
+/// the open duration a few trial calls are let through (half-open), and the circuit closes if they succeed.
+pub struct CircuitBreaker {
+    name: String,
+    failure_rate: f64,
+    minimum_calls: usize,
+    bucket_length: Duration,
+    open_duration: Duration,
+    half_open_calls: usize,
+    state: Mutex<State>,
+    meters: Option<CircuitMeters>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, config: &CircuitBreakerConfig) -> Self {
+        let window: Duration = config.window.into();
+        Self {
+            name: name.to_string(),
+            failure_rate: config.failure_rate,
+            minimum_calls: config.minimum_calls.max(1),
+            bucket_length: window / WINDOW_BUCKETS,
+            open_duration: config.open_duration.into(),
+            half_open_calls: config.half_open_calls.max(1),
+            state: Mutex::new(State::closed()),
+            meters: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            meters: Some(CircuitMeters {
+                state: meter.u64_gauge("circuit_breaker_state").init(),
+                transitions: meter.u64_counter("circuit_breaker_transitions").init(),
+                rejected: meter.u64_counter("circuit_breaker_rejected").init(),
+            }),
+            ..self
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state.lock().unwrap().circuit_state()
+    }
+
+    fn transition(&self, state: &mut State, new_state: State) {
+        let (from, to) = (state.circuit_state(), new_state.circuit_state());
+        *state = new_state;
+        match to {
+            CircuitState::Open => log::warn!("Circuit {} is open ({} -> {})", self.name, from.as_str(), to.as_str()),
+            _ => log::info!("Circuit {}: {} -> {}", self.name, from.as_str(), to.as_str()),
+        }
+        if let Some(meters) = &self.meters {
+            let name = KeyValue::new("name", self.name.clone());
+            meters.state.record(to.as_gauge(), &[name.clone()]);
+            meters.transitions.add(
+                1,
+                &[
+                    name,
+                    KeyValue::new("from", from.as_str()),
+                    KeyValue::new("to", to.as_str()),
+                ],
+            );
+        }
+    }
+
+    fn open(&self, state: &mut State) {
+        let until = Instant::now() + self.open_duration;
+        self.transition(state, State::Open { until });
+    }
+
+    /// Acquire a permit for a call, the outcome of the call has to be recorded on the permit. Returns `None`
+    /// if the call is rejected.
+    pub fn try_acquire(&self) -> Option<CircuitPermit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if let State::Open { until } = *state {
+            if Instant::now() >= until {
+                self.transition(
+                    &mut state,
+                    State::HalfOpen {
+                        in_flight: 0,
+                        successes: 0,
+                    },
+                );
+            }
+        }
+
+        let trial = match &mut *state {
+            State::Closed { .. } => false,
+            State::HalfOpen { in_flight, successes } if *in_flight + *successes < self.half_open_calls => {
+                *in_flight += 1;
+                true
+            }
+            State::HalfOpen { .. } | State::Open { .. } => {
+                if let Some(meters) = &self.meters {
+                    meters.rejected.add(1, &[KeyValue::new("name", self.name.clone())]);
+                }
+                return None;
+            }
+        };
+
+        Some(CircuitPermit {
+            breaker: self,
+            trial,
+            recorded: false,
+        })
+    }
+
+    fn record(&self, trial: bool, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Closed { buckets } => {
+                let now = Instant::now();
+                let window = self.bucket_length * WINDOW_BUCKETS;
+                while buckets.front().is_some_and(|bucket| now - bucket.start >= window) {
+                    buckets.pop_front();
+                }
+                if buckets
+                    .back()
+                    .map_or(true, |bucket| now - bucket.start >= self.bucket_length)
+                {
+                    buckets.push_back(Bucket {
+                        start: now,
+                        successes: 0,
+                        failures: 0,
+                    });
+                }
+                let bucket = buckets.back_mut().unwrap();
+                if success {
+                    bucket.successes += 1;
+                } else {
+                    bucket.failures += 1;
+                }
+
+                let (successes, failures) = buckets
+                    .iter()
+                    .fold((0, 0), |(s, f), bucket| (s + bucket.successes, f + bucket.failures));
+                let calls = successes + failures;
+                if calls >= self.minimum_calls && failures as f64 >= self.failure_rate * calls as f64 {
+                    self.open(&mut state);
+                }
+            }
+            State::HalfOpen { in_flight, successes } => {
+                if trial {
+                    *in_flight = in_flight.saturating_sub(1);
+                }
+                if !success {
+                    self.open(&mut state);
+                } else if trial {
+                    *successes += 1;
+                    if *successes >= self.half_open_calls {
+                        self.transition(&mut state, State::closed());
+                    }
+                }
+            }
+            // a call started before the circuit was opened
+            State::Open { .. } => {}
+        }
+    }
+
+    fn release(&self) {
+        if let State::HalfOpen { in_flight, .. } = &mut *self.state.lock().unwrap() {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Run the call through the circuit, every error is counted as a failure.
+    pub async fn call<F, T, E>(&self, call: F) -> Result<T, CircuitBreakerError<E>>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        self.call_with(call, |_| true).await
+    }
+
+    /// Run the call through the circuit, only the errors selected by `is_failure` are counted as a failure,
+    /// ex. a not found or a validation error is not a failure of the dependency.
+    pub async fn call_with<F, T, E, P>(&self, call: F, is_failure: P) -> Result<T, CircuitBreakerError<E>>
+    where
+        F: Future<Output = Result<T, E>>,
+        P: FnOnce(&E) -> bool,
+    {
+        let Some(permit) = self.try_acquire() else {
+            return Err(CircuitBreakerError::Open(self.name.clone()));
+        };
+        let result = call.await;
+        permit.record(result.as_ref().map_or_else(|err| !is_failure(err), |_| true));
+        result.map_err(CircuitBreakerError::Inner)
+    }
+}
+
+/// Permission to make a call through an open or half-open circuit. If the permit is dropped without
+/// recording the outcome (ex. the call was cancelled), the call is not counted.
+pub struct CircuitPermit<'a> {
+    breaker: &'a CircuitBreaker,
+    trial: bool,
+    recorded: bool,
+}
+
+impl CircuitPermit<'_> {
+    pub fn record(mut self, success: bool) {
+        self.recorded = true;
+        self.breaker.record(self.trial, success);
+    }
+}
+
+impl Drop for CircuitPermit<'_> {
+    fn drop(&mut self) {
+        if !self.recorded && self.trial {
+            self.breaker.release();
+        }
+    }
+}
+
+#[async_trait]
+impl StatusSource for CircuitBreaker {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn status(&self) -> DependencyStatus {
+        let state = self.state();
+        let health = match state {
+            CircuitState::Closed => DependencyHealth::Healthy,
+            CircuitState::HalfOpen => DependencyHealth::Degraded,
+            CircuitState::Open => DependencyHealth::Unhealthy,
+        };
+        DependencyStatus::new(&self.name, "circuitBreaker", health).with_detail("state", state.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    fn config(open_duration: Duration) -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            failure_rate: 0.5,
+            minimum_calls: 4,
+            window: DurationStr::from_secs(60),
+            open_duration: open_duration.into(),
+            half_open_calls: 2,
+        }
+    }
+
+    #[test]
+    async fn open_on_failure_rate() {
+        let breaker = CircuitBreaker::new("test", &config(Duration::from_secs(60)));
+
+        for success in [true, false, true] {
+            breaker.try_acquire().unwrap().record(success);
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.try_acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let result = breaker.call(async { Ok::<_, ()>(()) }).await;
+        assert!(matches!(result, Err(CircuitBreakerError::Open(_))));
+    }
+
+    #[test]
+    async fn half_open_trials() {
+        let breaker = CircuitBreaker::new("test", &config(Duration::ZERO));
+        for _ in 0..4 {
+            breaker.try_acquire().unwrap().record(false);
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // a failed trial opens the circuit again
+        breaker.try_acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let first = breaker.try_acquire().unwrap();
+        let second = breaker.try_acquire().unwrap();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire().is_none());
+        drop(second);
+        first.record(true);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        // the ignored errors are successes for the circuit
+        let result = breaker.call_with(async { Err::<(), _>("not found") }, |_| false).await;
+        assert!(matches!(result, Err(CircuitBreakerError::Inner("not found"))));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
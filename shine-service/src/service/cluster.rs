@@ -0,0 +1,279 @@
+use crate::service::{
+    RedisConnectionError, RedisConnectionPool, ReplicaPartition, ReplicaRegistry, ReplicaRegistryHandle,
+};
+use redis::{RedisError, Script};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+use tokio::{sync::watch, task::JoinHandle};
+
+/// Acquire or extend the lease if it is free or already owned by the replica.
+const ACQUIRE_LEASE_SCRIPT: &str = r#"
+local owner = redis.call('GET', KEYS[1])
+if owner == false then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+elseif owner == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+return 0
+"#;
+
+/// Release the lease only if it is owned by the replica.
+const RELEASE_LEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+#[derive(Debug, ThisError)]
+pub enum ClusterError {
+    #[error("Failed to get redis connection")]
+    RedisPoolError(#[source] RedisConnectionError),
+    #[error("Redis error")]
+    RedisError(#[from] RedisError),
+}
+
+/// The leadership of a named election, ex. to run a singleton task on a single replica.
+#[derive(Clone)]
+pub struct Leadership {
+    name: String,
+    leader: watch::Receiver<bool>,
+}
+
+impl Leadership {
+    /// A leadership that never changes, ex. for a single replica deployment or for the tests.
+    pub fn fixed(name: &str, is_leader: bool) -> Self {
+        let (sender, leader) = watch::channel(is_leader);
+        // keep the value available after the sender is dropped
+        drop(sender);
+        Self {
+            name: name.to_string(),
+            leader,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_leader(&self) -> bool {
+        *self.leader.borrow()
+    }
+
+    /// Wait until the leadership changes. Returns false if the election has been stopped.
+    pub async fn changed(&mut self) -> bool {
+        self.leader.changed().await.is_ok()
+    }
+
+    /// Wait until this replica becomes the leader. Returns false if the election has been stopped.
+    pub async fn wait_for_leadership(&mut self) -> bool {
+        self.leader.wait_for(|leader| *leader).await.is_ok()
+    }
+}
+
+struct ClusterInner {
+    key_prefix: String,
+    replica_id: String,
+    lease: Duration,
+    redis: RedisConnectionPool,
+    acquire: Script,
+    release: Script,
+    shutdown: watch::Sender<bool>,
+    elections: Mutex<HashMap<String, Leadership>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl ClusterInner {
+    fn lease_key(&self, name: &str) -> String {
+        format!("{}leader:{}", self.key_prefix, name)
+    }
+
+    async fn try_acquire(&self, name: &str) -> Result<bool, ClusterError> {
+        let mut client = self.redis.get().await.map_err(ClusterError::RedisPoolError)?;
+        let acquired: i32 = self
+            .acquire
+            .key(self.lease_key(name))
+            .arg(&self.replica_id)
+            .arg(self.lease.as_millis() as u64)
+            .invoke_async(&mut *client)
+            .await?;
+        Ok(acquired == 1)
+    }
+
+    async fn release(&self, name: &str) -> Result<(), ClusterError> {
+        let mut client = self.redis.get().await.map_err(ClusterError::RedisPoolError)?;
+        let _: i32 = self
+            .release
+            .key(self.lease_key(name))
+            .arg(&self.replica_id)
+            .invoke_async(&mut *client)
+            .await?;
+        Ok(())
+    }
+
+    async fn run_election(self: Arc<Self>, name: String, sender: watch::Sender<bool>) {
+        let mut shutdown = self.shutdown.subscribe();
+        let renew = self.lease / 3;
+        let mut last_renewal: Option<Instant> = None;
+
+        while !*shutdown.borrow() {
+            let is_leader = match self.try_acquire(&name).await {
+                Ok(true) => {
+                    last_renewal = Some(Instant::now());
+                    true
+                }
+                Ok(false) => false,
+                Err(err) => {
+                    log::warn!("Election {name} of {} failed: {err:?}", self.replica_id);
+                    // step down before the lease could be taken over by another replica
+                    last_renewal.is_some_and(|at| at.elapsed() + renew < self.lease)
+                }
+            };
+            sender.send_if_modified(|leader| {
+                if *leader != is_leader {
+                    log::info!("Replica {} leadership of {name}: {is_leader}", self.replica_id);
+                    *leader = is_leader;
+                    true
+                } else {
+                    false
+                }
+            });
+
+            tokio::select! {
+                _ = tokio::time::sleep(renew) => {}
+                _ = shutdown.changed() => {}
+            }
+        }
+
+        if sender.send_replace(false) {
+            if let Err(err) = self.release(&name).await {
+                log::warn!(
+                    "Replica {} failed to release leadership of {name}: {err:?}",
+                    self.replica_id
+                );
+            }
+        }
+    }
+}
+
+/// Membership of the replicas and lease based leader election shared by the subsystems requiring
+/// coordination (scheduler, outbox dispatcher, cleanup jobs). The membership is tracked by the
+/// [`ReplicaRegistry`], the leases are stored in redis and renewed by the leader periodically.
+pub struct Cluster {
+    key_prefix: String,
+    replica_id: String,
+    lease: Duration,
+    registry: ReplicaRegistry,
+    redis: RedisConnectionPool,
+}
+
+impl Cluster {
+    pub fn new(key_prefix: &str, replica_id: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key_prefix: key_prefix.to_string(),
+            replica_id: replica_id.to_string(),
+            lease: Duration::from_secs(15),
+            registry: ReplicaRegistry::new(key_prefix, replica_id, redis.clone()),
+            redis,
+        }
+    }
+
+    /// Period of the heartbeat and the time after which a silent replica is dropped from the members.
+    #[must_use]
+    pub fn with_heartbeat(self, heartbeat: Duration, ttl: Duration) -> Self {
+        Self {
+            registry: self.registry.with_heartbeat(heartbeat, ttl),
+            ..self
+        }
+    }
+
+    /// Duration of the leader lease. The leader renews it at every third of the lease, and if the leader
+    /// dies, a new one is elected within a lease.
+    #[must_use]
+    pub fn with_lease(self, lease: Duration) -> Self {
+        Self {
+            lease: lease.max(Duration::from_secs(1)),
+            ..self
+        }
+    }
+
+    pub fn start(self) -> ClusterHandle {
+        let (shutdown, _) = watch::channel(false);
+        let inner = ClusterInner {
+            key_prefix: self.key_prefix,
+            replica_id: self.replica_id,
+            lease: self.lease,
+            redis: self.redis,
+            acquire: Script::new(ACQUIRE_LEASE_SCRIPT),
+            release: Script::new(RELEASE_LEASE_SCRIPT),
+            shutdown,
+            elections: Mutex::new(HashMap::new()),
+            tasks: Mutex::new(Vec::new()),
+        };
+
+        ClusterHandle {
+            inner: Arc::new(inner),
+            registry: self.registry.start(),
+        }
+    }
+}
+
+pub struct ClusterHandle {
+    inner: Arc<ClusterInner>,
+    registry: ReplicaRegistryHandle,
+}
+
+impl ClusterHandle {
+    pub fn replica_id(&self) -> &str {
+        &self.inner.replica_id
+    }
+
+    /// The live replicas as seen at the last heartbeat.
+    pub fn members(&self) -> Vec<String> {
+        self.registry
+            .partition()
+            .ring()
+            .nodes()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    pub fn partition(&self) -> ReplicaPartition {
+        self.registry.partition()
+    }
+
+    /// Join the named election. The first call starts the election, the subsequent calls share it.
+    pub fn leadership(&self, name: &str) -> Leadership {
+        let mut elections = self.inner.elections.lock().unwrap();
+        if let Some(leadership) = elections.get(name) {
+            return leadership.clone();
+        }
+
+        let (sender, leader) = watch::channel(false);
+        let leadership = Leadership {
+            name: name.to_string(),
+            leader,
+        };
+        elections.insert(name.to_string(), leadership.clone());
+        let task = tokio::spawn(self.inner.clone().run_election(name.to_string(), sender));
+        self.inner.tasks.lock().unwrap().push(task);
+        leadership
+    }
+
+    /// Give up the leaderships, stop the heartbeats and leave the cluster.
+    pub async fn shutdown(self) {
+        self.inner.shutdown.send_replace(true);
+        let tasks = std::mem::take(&mut *self.inner.tasks.lock().unwrap());
+        for task in tasks {
+            let _ = task.await;
+        }
+        self.registry.shutdown().await;
+    }
+}
@@ -0,0 +1,221 @@
+#[cfg(all(feature = "redis", feature = "openapi"))]
+use crate::service::AdminCaller;
+use crate::utils::{is_sensitive_config_key, redact_credentials, REDACTED};
+#[cfg(all(feature = "redis", feature = "openapi"))]
+use axum::{response::IntoResponse, routing::get, Json, Router};
+use config::{Map as ConfigMap, Value as ConfigValue, ValueKind as ConfigValueKind};
+use serde::Serialize;
+use std::collections::BTreeMap;
+#[cfg(all(feature = "redis", feature = "openapi"))]
+use std::sync::Arc;
+
+/// A value supplied by a config layer, the secret values are redacted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigTraceValue {
+    pub layer: String,
+    pub value: String,
+}
+
+/// The resolution history of a config key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigTraceEntry {
+    /// The layer supplying the final value.
+    pub resolved: ConfigTraceValue,
+    /// The values of the earlier layers overridden by the final one, in the order of the layers.
+    pub overridden: Vec<ConfigTraceValue>,
+}
+
+/// Summary of a config source load, ex. of a keyvault.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigSourceReport {
+    pub source: String,
+    /// The (config) keys of the loaded values.
+    pub loaded: Vec<String>,
+    /// The disabled secrets.
+    pub disabled: Vec<String>,
+    /// The skipped secrets with the reason of the failure.
+    pub skipped: Vec<(String, String)>,
+    /// Total number of the retried requests.
+    pub retries: usize,
+}
+
+/// Record of the config resolution: for every key which layer supplied the final value and
+/// which layers were overridden, and the load summary of the remote sources.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigTrace {
+    entries: BTreeMap<String, ConfigTraceEntry>,
+    sources: Vec<ConfigSourceReport>,
+}
+
+impl ConfigTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the values of the next layer, the layers have to be added in the order of precedence
+    /// (the latter overrides the former).
+    pub fn add_layer(&mut self, layer: &str, values: &ConfigMap<String, ConfigValue>) {
+        self.add_table(layer, "", false, values, &|_| true);
+    }
+
+    /// Add the values of the next layer for the keys accepted by the filter only, ex. to skip the unrelated
+    /// environment variables.
+    pub fn add_layer_filtered(
+        &mut self,
+        layer: &str,
+        values: &ConfigMap<String, ConfigValue>,
+        filter: &dyn Fn(&str) -> bool,
+    ) {
+        self.add_table(layer, "", false, values, filter);
+    }
+
+    fn add_table(
+        &mut self,
+        layer: &str,
+        prefix: &str,
+        sensitive: bool,
+        values: &ConfigMap<String, ConfigValue>,
+        filter: &dyn Fn(&str) -> bool,
+    ) {
+        for (key, value) in values {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{prefix}.{key}")
+            };
+            let sensitive = sensitive || is_sensitive_config_key(key);
+            match &value.kind {
+                ConfigValueKind::Table(table) => self.add_table(layer, &path, sensitive, table, filter),
+                _ if !filter(&path) => {}
+                _ => {
+                    let value = ConfigTraceValue {
+                        layer: layer.to_string(),
+                        value: if sensitive {
+                            REDACTED.to_string()
+                        } else {
+                            redact_credentials(&value.to_string())
+                        },
+                    };
+                    self.add_value(path, value);
+                }
+            }
+        }
+    }
+
+    fn add_value(&mut self, path: String, value: ConfigTraceValue) {
+        match self.entries.get_mut(&path) {
+            Some(entry) => {
+                let previous = std::mem::replace(&mut entry.resolved, value);
+                entry.overridden.push(previous);
+            }
+            None => {
+                self.entries.insert(
+                    path,
+                    ConfigTraceEntry {
+                        resolved: value,
+                        overridden: Vec::new(),
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ConfigTraceEntry> {
+        self.entries.get(key)
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, ConfigTraceEntry> {
+        &self.entries
+    }
+
+    pub fn add_source_report(&mut self, report: ConfigSourceReport) {
+        self.sources.push(report);
+    }
+
+    /// The load summary of the sources reporting it, ex. the skipped secrets of a keyvault.
+    pub fn source_reports(&self) -> &[ConfigSourceReport] {
+        &self.sources
+    }
+
+    /// Create the admin route exposing the (redacted) trace, it is authorized by the `AdminCaller`, see
+    /// `AdminRouter::with_routes`.
+    ///  - GET /admin/config/trace
+    #[cfg(all(feature = "redis", feature = "openapi"))]
+    pub fn into_router<S>(self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let trace = Arc::new(self);
+        let route = get(move |_: AdminCaller| async move { Json(trace.as_ref()).into_response() });
+
+        Router::new().route("/admin/config/trace", route)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    fn layer(values: &[(&str, &str)]) -> ConfigMap<String, ConfigValue> {
+        let mut db = ConfigMap::new();
+        let mut root = ConfigMap::new();
+        for (key, value) in values {
+            match key.strip_prefix("db.") {
+                Some(key) => db.insert(key.to_string(), ConfigValue::new(None, *value)),
+                None => root.insert(key.to_string(), ConfigValue::new(None, *value)),
+            };
+        }
+        if !db.is_empty() {
+            root.insert("db".to_string(), ConfigValue::new(None, ConfigValueKind::Table(db)));
+        }
+        root
+    }
+
+    #[test]
+    fn trace_overrides() {
+        let mut trace = ConfigTrace::new();
+        trace.add_layer(
+            "base",
+            &layer(&[("stage", "dev"), ("db.sqlCns", "pg://base"), ("db.pool", "4")]),
+        );
+        trace.add_layer("environment", &layer(&[("stage", "staging")]));
+        trace.add_layer("azk://vault", &layer(&[("db.sqlCns", "pg://vault")]));
+
+        let stage = trace.get("stage").unwrap();
+        assert_eq!(stage.resolved.layer, "environment");
+        assert_eq!(stage.resolved.value, "staging");
+        assert_eq!(stage.overridden.len(), 1);
+        assert_eq!(stage.overridden[0].layer, "base");
+
+        let cns = trace.get("db.sqlCns").unwrap();
+        assert_eq!(cns.resolved.layer, "azk://vault");
+        assert_eq!(cns.resolved.value, REDACTED);
+        assert_eq!(cns.overridden[0].value, REDACTED);
+
+        let pool = trace.get("db.pool").unwrap();
+        assert_eq!(pool.resolved.layer, "base");
+        assert!(pool.overridden.is_empty());
+    }
+
+    #[test]
+    fn trace_filtered_environment() {
+        let mut trace = ConfigTrace::new();
+        trace.add_layer("base", &layer(&[("db.url", "postgres://app:pwd@db/app")]));
+        trace.add_layer_filtered(
+            "environment",
+            &layer(&[("db.url", "postgres://app:env@db/app"), ("HOME", "/root")]),
+            &|path| path == "db.url",
+        );
+
+        let url = trace.get("db.url").unwrap();
+        assert_eq!(url.resolved.layer, "environment");
+        assert_eq!(url.resolved.value, "postgres://app:***@db/app");
+        assert_eq!(url.overridden[0].value, "postgres://app:***@db/app");
+        assert!(trace.get("HOME").is_none());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::axum::IntoProblem;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::{Read, Write};
+use thiserror::Error as ThisError;
+
+/// Limit of the decompressed payload to reject the compression bombs.
+const MAX_DECOMPRESSED_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, ThisError, IntoProblem)]
+pub enum CookieCodecError {
+    #[error("Failed to encode cookie: {0}")]
+    #[problem(internal = "Cookie encoding error")]
+    Encode(String),
+    #[error("Failed to decode cookie: {0}")]
+    #[problem(internal = "Cookie decoding error")]
+    Decode(String),
+    #[error("Cookie of {size} bytes exceeds the limit of {limit} bytes")]
+    #[problem(internal = "Cookie is too large")]
+    TooLarge { size: usize, limit: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CookieFormat {
+    /// Plain json, the legacy format of the cookies.
+    Json,
+    /// MessagePack with the field names, it is self-describing, thus it works with all the serde attributes.
+    MessagePack,
+}
+
+impl CookieFormat {
+    fn tag(&self) -> char {
+        match self {
+            CookieFormat::Json => 'j',
+            CookieFormat::MessagePack => 'm',
+        }
+    }
+}
+
+/// Encode the payload of the cookies. Apart from the legacy json, the encoded value is
+/// `<format><compression>.<base64 payload>`, ex. `mz.` for a compressed MessagePack. The legacy json
+/// cookies are decoded transparently with any setting, so the format can be changed without logging out
+/// the users.
+#[derive(Clone, Debug)]
+pub struct CookieCodec {
+    format: CookieFormat,
+    compression: bool,
+    max_size: usize,
+}
+
+impl Default for CookieCodec {
+    fn default() -> Self {
+        Self {
+            format: CookieFormat::Json,
+            compression: false,
+            // the browsers limit the cookie (name, value and attributes) to 4096 bytes, leave room for the
+            // name, the signature and the attributes
+            max_size: 3800,
+        }
+    }
+}
+
+impl CookieCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compact codec, compressed MessagePack.
+    pub fn compact() -> Self {
+        Self::new()
+            .with_format(CookieFormat::MessagePack)
+            .with_compression(true)
+    }
+
+    #[must_use]
+    pub fn with_format(self, format: CookieFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    /// Compress the payload when it makes the value shorter.
+    #[must_use]
+    pub fn with_compression(self, compression: bool) -> Self {
+        Self { compression, ..self }
+    }
+
+    /// Maximum length of the encoded value.
+    #[must_use]
+    pub fn with_max_size(self, max_size: usize) -> Self {
+        Self { max_size, ..self }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<String, CookieCodecError> {
+        let payload = match self.format {
+            CookieFormat::Json => serde_json::to_vec(value).map_err(|err| CookieCodecError::Encode(err.to_string()))?,
+            CookieFormat::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|err| CookieCodecError::Encode(err.to_string()))?
+            }
+        };
+
+        let compressed = if self.compression {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(&payload)
+                .and_then(|_| encoder.finish())
+                .map(Some)
+                .map_err(|err| CookieCodecError::Encode(err.to_string()))?
+        } else {
+            None
+        };
+
+        let encoded = match compressed {
+            Some(compressed) if compressed.len() < payload.len() => {
+                format!("{}z.{}", self.format.tag(), B64.encode(compressed))
+            }
+            // keep the legacy format for the plain json
+            _ if self.format == CookieFormat::Json => {
+                String::from_utf8(payload).map_err(|err| CookieCodecError::Encode(err.to_string()))?
+            }
+            _ => format!("{}0.{}", self.format.tag(), B64.encode(payload)),
+        };
+
+        if encoded.len() > self.max_size {
+            return Err(CookieCodecError::TooLarge {
+                size: encoded.len(),
+                limit: self.max_size,
+            });
+        }
+        Ok(encoded)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, value: &str) -> Result<T, CookieCodecError> {
+        let decode_error = |err: &dyn std::fmt::Display| CookieCodecError::Decode(err.to_string());
+
+        let (format, compressed, payload) = match value.split_once('.') {
+            Some(("j0", payload)) => (CookieFormat::Json, false, payload),
+            Some(("jz", payload)) => (CookieFormat::Json, true, payload),
+            Some(("m0", payload)) => (CookieFormat::MessagePack, false, payload),
+            Some(("mz", payload)) => (CookieFormat::MessagePack, true, payload),
+            _ => return serde_json::from_str(value).map_err(|err| decode_error(&err)),
+        };
+
+        let mut payload = B64.decode(payload).map_err(|err| decode_error(&err))?;
+        if compressed {
+            let mut decompressed = Vec::new();
+            DeflateDecoder::new(&payload[..])
+                .take(MAX_DECOMPRESSED_SIZE)
+                .read_to_end(&mut decompressed)
+                .map_err(|err| decode_error(&err))?;
+            payload = decompressed;
+        }
+
+        match format {
+            CookieFormat::Json => serde_json::from_slice(&payload).map_err(|err| decode_error(&err)),
+            CookieFormat::MessagePack => rmp_serde::from_slice(&payload).map_err(|err| decode_error(&err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+        user_name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        avatar: Option<String>,
+        roles: Vec<String>,
+    }
+
+    #[test]
+    fn encode_decode() {
+        let payload = Payload {
+            user_name: "user".into(),
+            avatar: None,
+            roles: vec!["Role".repeat(20); 10],
+        };
+        let legacy = serde_json::to_string(&payload).unwrap();
+
+        for codec in [
+            CookieCodec::new(),
+            CookieCodec::new().with_format(CookieFormat::MessagePack),
+            CookieCodec::compact(),
+            CookieCodec::new().with_compression(true),
+        ] {
+            let encoded = codec.encode(&payload).unwrap();
+            assert_eq!(codec.decode::<Payload>(&encoded).unwrap(), payload);
+            assert_eq!(codec.decode::<Payload>(&legacy).unwrap(), payload);
+        }
+
+        assert_eq!(CookieCodec::new().encode(&payload).unwrap(), legacy);
+        let compact = CookieCodec::compact().encode(&payload).unwrap();
+        assert!(compact.starts_with("mz."));
+        assert!(compact.len() < legacy.len());
+    }
+
+    #[test]
+    fn size_limit() {
+        let codec = CookieCodec::new().with_max_size(16);
+        assert!(codec.encode(&"short").is_ok());
+        assert!(matches!(
+            codec.encode(&"a much longer cookie value"),
+            Err(CookieCodecError::TooLarge { limit: 16, .. })
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error as ThisError;
+
+const PROD_STAGE: &str = "prod";
+
+#[derive(Debug, ThisError)]
+pub enum CookieConfigError {
+    #[error("Cookie {0}: SameSite=None requires the Secure attribute")]
+    SameSiteNoneWithoutSecure(String),
+    #[error("Cookie {0}: insecure cookies are not allowed in the {PROD_STAGE} stage")]
+    InsecureInProd(String),
+    #[error("Cookie {0}: non http-only cookies are not allowed in the {PROD_STAGE} stage")]
+    ScriptAccessibleInProd(String),
+    #[error("Cookie {0}: the name prefix requires the Secure attribute")]
+    PrefixWithoutSecure(String),
+    #[error("Cookie {0}: the __Host- prefix requires path=/ and no domain")]
+    InvalidHostPrefix(String),
+    #[error("Cookie {0}: Partitioned requires the Secure attribute")]
+    PartitionedWithoutSecure(String),
+}
+
+/// Name prefixes enforced by the browsers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CookiePrefix {
+    /// `__Host-` prefix: secure, host-only cookie with path=/
+    Host,
+    /// `__Secure-` prefix: secure cookie
+    Secure,
+}
+
+impl CookiePrefix {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CookiePrefix::Host => "__Host-",
+            CookiePrefix::Secure => "__Secure-",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    /// Allow the cookie in cross-site requests (ex. embedded clients), it requires `secure`.
+    None,
+}
+
+impl From<CookieSameSite> for SameSite {
+    fn from(value: CookieSameSite) -> Self {
+        match value {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        }
+    }
+}
+
+/// Cookie attributes, the missing values are inherited from the defaults.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CookieAttributesConfig {
+    pub same_site: Option<CookieSameSite>,
+    pub secure: Option<bool>,
+    pub http_only: Option<bool>,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub prefix: Option<CookiePrefix>,
+    /// Partitioned (CHIPS) cookie for embedded cross-site usage.
+    pub partitioned: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CookieAttributes {
+    pub same_site: CookieSameSite,
+    pub secure: bool,
+    pub http_only: bool,
+    pub domain: Option<String>,
+    pub path: String,
+    pub prefix: Option<CookiePrefix>,
+    pub partitioned: bool,
+}
+
+impl Default for CookieAttributes {
+    fn default() -> Self {
+        Self {
+            same_site: CookieSameSite::Lax,
+            secure: true,
+            http_only: true,
+            domain: None,
+            path: "/".to_string(),
+            prefix: None,
+            partitioned: false,
+        }
+    }
+}
+
+impl CookieAttributes {
+    fn merge(mut self, config: &CookieAttributesConfig) -> Self {
+        if let Some(same_site) = config.same_site {
+            self.same_site = same_site;
+        }
+        if let Some(secure) = config.secure {
+            self.secure = secure;
+        }
+        if let Some(http_only) = config.http_only {
+            self.http_only = http_only;
+        }
+        if let Some(domain) = &config.domain {
+            self.domain = Some(domain.clone());
+        }
+        if let Some(path) = &config.path {
+            self.path = path.clone();
+        }
+        if let Some(prefix) = config.prefix {
+            self.prefix = Some(prefix);
+        }
+        if let Some(partitioned) = config.partitioned {
+            self.partitioned = partitioned;
+        }
+        self
+    }
+
+    /// Return the name of the cookie with the configured prefix.
+    pub fn prefixed_name(&self, name: &str) -> String {
+        match self.prefix {
+            Some(prefix) => format!("{}{}", prefix.as_str(), name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Apply the attributes on a cookie.
+    pub fn apply<'c>(&self, cookie: Cookie<'c>) -> Cookie<'c> {
+        let mut cookie = cookie;
+        cookie.set_same_site(SameSite::from(self.same_site));
+        cookie.set_secure(self.secure);
+        cookie.set_http_only(self.http_only);
+        cookie.set_path(self.path.clone());
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        if self.partitioned {
+            cookie.set_partitioned(true);
+        }
+        cookie
+    }
+}
+
+/// Attributes of the cookies set by the service with per cookie-name overrides.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CookieConfig {
+    #[serde(flatten)]
+    pub defaults: CookieAttributesConfig,
+    #[serde(default)]
+    pub overrides: HashMap<String, CookieAttributesConfig>,
+}
+
+impl CookieConfig {
+    /// Get the resolved attributes of a cookie.
+    pub fn attributes(&self, cookie_name: &str) -> CookieAttributes {
+        let attributes = CookieAttributes::default().merge(&self.defaults);
+        match self.overrides.get(cookie_name) {
+            Some(overrides) => attributes.merge(overrides),
+            None => attributes,
+        }
+    }
+
+    /// Reject the insecure combination of the attributes. In the prod stage cookies must be secure and http-only.
+    pub fn validate(&self, stage: &str) -> Result<(), CookieConfigError> {
+        let names = std::iter::once("*").chain(self.overrides.keys().map(|name| name.as_str()));
+        for name in names {
+            let attributes = self.attributes(name);
+            if attributes.same_site == CookieSameSite::None && !attributes.secure {
+                return Err(CookieConfigError::SameSiteNoneWithoutSecure(name.to_string()));
+            }
+            if attributes.partitioned && !attributes.secure {
+                return Err(CookieConfigError::PartitionedWithoutSecure(name.to_string()));
+            }
+            if attributes.prefix.is_some() && !attributes.secure {
+                return Err(CookieConfigError::PrefixWithoutSecure(name.to_string()));
+            }
+            if attributes.prefix == Some(CookiePrefix::Host) && (attributes.path != "/" || attributes.domain.is_some())
+            {
+                return Err(CookieConfigError::InvalidHostPrefix(name.to_string()));
+            }
+            if stage == PROD_STAGE {
+                if !attributes.secure {
+                    return Err(CookieConfigError::InsecureInProd(name.to_string()));
+                }
+                if !attributes.http_only {
+                    return Err(CookieConfigError::ScriptAccessibleInProd(name.to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn cookie_overrides() {
+        let config: CookieConfig = serde_json::from_value(serde_json::json!({
+            "sameSite": "strict",
+            "domain": "example.com",
+            "overrides": {
+                "flash": { "sameSite": "none", "path": "/auth" }
+            }
+        }))
+        .unwrap();
+
+        let default = config.attributes("sid");
+        assert_eq!(default.same_site, CookieSameSite::Strict);
+        assert_eq!(default.domain.as_deref(), Some("example.com"));
+        assert_eq!(default.path, "/");
+
+        let flash = config.attributes("flash");
+        assert_eq!(flash.same_site, CookieSameSite::None);
+        assert_eq!(flash.domain.as_deref(), Some("example.com"));
+        assert_eq!(flash.path, "/auth");
+
+        assert!(config.validate("prod").is_ok());
+    }
+
+    #[test]
+    fn insecure_cookies() {
+        let config: CookieConfig = serde_json::from_value(serde_json::json!({ "secure": false })).unwrap();
+        assert!(config.validate("dev").is_ok());
+        assert!(config.validate("prod").is_err());
+
+        let config: CookieConfig = serde_json::from_value(serde_json::json!({
+            "overrides": { "flash": { "sameSite": "none", "secure": false } }
+        }))
+        .unwrap();
+        assert!(config.validate("dev").is_err());
+    }
+
+    #[test]
+    fn prefixed_and_partitioned_cookies() {
+        let config: CookieConfig = serde_json::from_value(serde_json::json!({
+            "prefix": "host",
+            "overrides": {
+                "flash": { "prefix": "secure", "sameSite": "none", "partitioned": true }
+            }
+        }))
+        .unwrap();
+        assert!(config.validate("prod").is_ok());
+
+        let sid = config.attributes("sid");
+        assert_eq!(sid.prefixed_name("sid"), "__Host-sid");
+        let flash = config.attributes("flash");
+        assert_eq!(flash.prefixed_name("flash"), "__Secure-flash");
+        assert!(flash.partitioned);
+        let cookie = flash.apply(Cookie::new("__Secure-flash", "v"));
+        assert_eq!(cookie.partitioned(), Some(true));
+
+        let config: CookieConfig = serde_json::from_value(serde_json::json!({
+            "prefix": "host",
+            "domain": "example.com"
+        }))
+        .unwrap();
+        assert!(config.validate("dev").is_err());
+    }
+}
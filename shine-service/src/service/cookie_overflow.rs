@@ -0,0 +1,226 @@
+use crate::{
+    axum::{ErrorCategory, ServiceError},
+    service::{CookieCodec, CookieCodecError, RedisConnectionError, RedisConnectionPool},
+    utils::{Entropy, SystemEntropy},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use redis::AsyncCommands;
+use ring::hmac;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+
+const REFERENCE_PREFIX: &str = "r1.";
+const MIN_SECRET_LEN: usize = 32;
+
+#[derive(Debug, ThisError)]
+pub enum CookieOverflowError {
+    #[error("Invalid overflow secret")]
+    InvalidSecret(String),
+    #[error(transparent)]
+    Codec(#[from] CookieCodecError),
+    #[error("Invalid overflow reference")]
+    InvalidReference,
+    #[error("Overflow data is missing or expired")]
+    Expired,
+    #[error("Failed to generate overflow reference")]
+    ReferenceError(String),
+    #[error("Failed to get redis connection")]
+    RedisPoolError(#[source] RedisConnectionError),
+    #[error("Redis error")]
+    RedisError(#[from] redis::RedisError),
+}
+
+impl ServiceError for CookieOverflowError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            CookieOverflowError::InvalidSecret(_) | CookieOverflowError::ReferenceError(_) => ErrorCategory::Internal,
+            CookieOverflowError::RedisPoolError(_) | CookieOverflowError::RedisError(_) => ErrorCategory::Dependency,
+            _ => ErrorCategory::User,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            CookieOverflowError::RedisPoolError(err) => err.is_retryable(),
+            CookieOverflowError::RedisError(err) => err.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+fn sign(key: &hmac::Key, id: &str, payload: &str) -> hmac::Tag {
+    let mut context = hmac::Context::with_key(key);
+    context.update(id.as_bytes());
+    context.update(b".");
+    context.update(payload.as_bytes());
+    context.sign()
+}
+
+/// Decode the base64 (url safe, no padding) encoded secret of at least 32 bytes.
+fn parse_secret(secret: &str) -> Result<hmac::Key, CookieOverflowError> {
+    let secret = B64
+        .decode(secret)
+        .map_err(|err| CookieOverflowError::InvalidSecret(format!("{err}")))?;
+    if secret.len() < MIN_SECRET_LEN {
+        return Err(CookieOverflowError::InvalidSecret(format!(
+            "secret is shorter than {MIN_SECRET_LEN} bytes"
+        )));
+    }
+    Ok(hmac::Key::new(hmac::HMAC_SHA256, &secret))
+}
+
+/// Split a reference into the id and the tag.
+fn parse_reference(value: &str) -> Option<Result<(&str, Vec<u8>), CookieOverflowError>> {
+    let reference = value.strip_prefix(REFERENCE_PREFIX)?;
+    let parsed = reference
+        .split_once('.')
+        .filter(|(id, _)| !id.is_empty() && id.bytes().all(|c| c.is_ascii_hexdigit()))
+        .and_then(|(id, tag)| Some((id, B64.decode(tag).ok()?)))
+        .ok_or(CookieOverflowError::InvalidReference);
+    Some(parsed)
+}
+
+/// Move the cookie payloads exceeding a threshold to redis. The cookie keeps only a reference and a MAC of
+/// the stored payload, the stored data expires with the cookie. The payloads below the threshold are kept in
+/// the cookie, encoded by the codec. The session cookies are resolved by the `UserSessionCacheReader`, see
+/// `with_cookie_overflow`.
+pub struct CookieOverflowStore {
+    key_prefix: String,
+    codec: CookieCodec,
+    threshold: usize,
+    ttl: Duration,
+    key: hmac::Key,
+    entropy: Arc<dyn Entropy>,
+    redis: RedisConnectionPool,
+}
+
+impl CookieOverflowStore {
+    pub fn new(key_prefix: &str, secret: &str, redis: RedisConnectionPool) -> Result<Self, CookieOverflowError> {
+        let key = parse_secret(secret)?;
+        Ok(Self {
+            key_prefix: key_prefix.to_string(),
+            codec: CookieCodec::default(),
+            threshold: 2048,
+            ttl: Duration::from_secs(24 * 60 * 60),
+            key,
+            entropy: SystemEntropy::shared(),
+            redis,
+        })
+    }
+
+    /// Codec of the payloads, the size limit of the codec does not apply to the payloads moved to redis.
+    #[must_use]
+    pub fn with_codec(self, codec: CookieCodec) -> Self {
+        Self { codec, ..self }
+    }
+
+    /// Length of the encoded payload above which it is moved to redis.
+    #[must_use]
+    pub fn with_threshold(self, threshold: usize) -> Self {
+        Self { threshold, ..self }
+    }
+
+    /// Lifetime of the stored payloads, it should match the max-age of the cookie.
+    #[must_use]
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
+
+    #[must_use]
+    pub fn with_entropy(self, entropy: Arc<dyn Entropy>) -> Self {
+        Self { entropy, ..self }
+    }
+
+    fn redis_key(&self, id: &str) -> String {
+        format!("{}cookie_overflow:{}", self.key_prefix, id)
+    }
+
+    /// Encode the payload into a cookie value.
+    pub async fn encode<T: Serialize>(&self, value: &T) -> Result<String, CookieOverflowError> {
+        match self.codec.encode(value) {
+            Ok(encoded) if encoded.len() <= self.threshold => return Ok(encoded),
+            Ok(_) | Err(CookieCodecError::TooLarge { .. }) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let payload = self.codec.clone().with_max_size(usize::MAX).encode(value)?;
+        let id = self
+            .entropy
+            .hex_token(16)
+            .map_err(|err| CookieOverflowError::ReferenceError(err.to_string()))?;
+        let tag = sign(&self.key, &id, &payload);
+
+        let mut client = self.redis.get().await.map_err(CookieOverflowError::RedisPoolError)?;
+        let _: () = client
+            .set_ex(self.redis_key(&id), &payload, self.ttl.as_secs().max(1))
+            .await?;
+        log::debug!("Cookie payload of {} bytes moved to redis", payload.len());
+
+        Ok(format!("{REFERENCE_PREFIX}{id}.{}", B64.encode(tag.as_ref())))
+    }
+
+    /// Decode a cookie value, either an inline payload or a reference to the stored one.
+    pub async fn decode<T: DeserializeOwned>(&self, value: &str) -> Result<T, CookieOverflowError> {
+        let Some(reference) = parse_reference(value) else {
+            return Ok(self.codec.decode(value)?);
+        };
+        let (id, tag) = reference?;
+
+        let mut client = self.redis.get().await.map_err(CookieOverflowError::RedisPoolError)?;
+        let payload: Option<String> = client.get(self.redis_key(id)).await?;
+        let payload = payload.ok_or(CookieOverflowError::Expired)?;
+        hmac::verify(&self.key, format!("{id}.{payload}").as_bytes(), &tag)
+            .map_err(|_| CookieOverflowError::InvalidReference)?;
+
+        Ok(self.codec.decode(&payload)?)
+    }
+
+    /// Delete the stored payload of a replaced or removed cookie, the inline values are ignored.
+    pub async fn remove(&self, value: &str) -> Result<(), CookieOverflowError> {
+        if let Some(Ok((id, _))) = parse_reference(value) {
+            let mut client = self.redis.get().await.map_err(CookieOverflowError::RedisPoolError)?;
+            let _: () = client.del(self.redis_key(id)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn secret_length() {
+        assert!(parse_secret(&B64.encode([7_u8; MIN_SECRET_LEN])).is_ok());
+        assert!(matches!(
+            parse_secret(&B64.encode([7_u8; MIN_SECRET_LEN - 1])),
+            Err(CookieOverflowError::InvalidSecret(_))
+        ));
+        assert!(matches!(parse_secret(""), Err(CookieOverflowError::InvalidSecret(_))));
+        assert!(matches!(
+            parse_secret("not base64!"),
+            Err(CookieOverflowError::InvalidSecret(_))
+        ));
+    }
+
+    #[test]
+    fn reference() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let tag = sign(&key, "00ff", "payload");
+        let value = format!("{REFERENCE_PREFIX}00ff.{}", B64.encode(tag.as_ref()));
+
+        let (id, tag) = parse_reference(&value).unwrap().unwrap();
+        assert_eq!(id, "00ff");
+        assert!(hmac::verify(&key, b"00ff.payload", &tag).is_ok());
+        assert!(hmac::verify(&key, b"00ff.tampered", &tag).is_err());
+
+        assert!(parse_reference(r#"{"m":"inline"}"#).is_none());
+        assert!(parse_reference("m0.aW5saW5l").is_none());
+        assert!(matches!(
+            parse_reference("r1.not-hex.tag"),
+            Some(Err(CookieOverflowError::InvalidReference))
+        ));
+    }
+}
@@ -1,14 +1,81 @@
-use crate::azure::azure_keyvault_config::AzureKeyvaultConfigSource;
+#[cfg(feature = "aws_config")]
+use crate::aws::aws_secrets_config::{AwsParameterStoreConfigSource, AwsSecretsManagerConfigSource};
+#[cfg(feature = "azure")]
+use crate::azure::{
+    azure_keyvault_config::{AzureKeyvaultConfigSource, AzureKeyvaultSourceConfig},
+    azure_secret_cache::SecretCache,
+    credentials::default_chain,
+};
+use crate::{
+    axum::{ErrorCategory, ServiceError},
+    service::ConfigTrace,
+    utils::redact_config_map,
+};
+#[cfg(feature = "azure")]
 use azure_core::auth::TokenCredential;
-use azure_identity::{AzureCliCredential, EnvironmentCredential, TokenCredentialOptions};
 use config::{builder::AsyncState, Config, ConfigBuilder, ConfigError, Environment, File};
 use serde::{Deserialize, Serialize};
-use std::{env, path::Path, sync::Arc};
+#[cfg(feature = "azure")]
+use std::sync::Arc;
+use std::{collections::HashSet, path::Path};
+
+/// The configuration errors are internal, except for the failing remote sources (keyvault, secret stores).
+impl ServiceError for ConfigError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            ConfigError::Foreign(_) => ErrorCategory::Dependency,
+            _ => ErrorCategory::Internal,
+        }
+    }
+}
 
 pub const DEFAULT_CONFIG_FILE: &str = "server_config.json";
 pub const DEFAULT_DEV_CONFIG_FILE: &str = "server_config.dev.json";
 pub const DEFAULT_LOCAL_CONFIG_FILE: &str = "temp/server_config.json";
 
+enum Layer<'a> {
+    Base,
+    /// The environment variables, optionally only the ones with the given prefix (`environment://PREFIX`).
+    Environment(Option<&'a str>),
+    Config(&'a str, &'a str, Option<&'a str>),
+}
+
+impl<'a> Layer<'a> {
+    fn from_layer(layer: &'a str) -> Result<Self, ConfigError> {
+        if layer == "environment" {
+            Ok(Layer::Environment(None))
+        } else if let Some(prefix) = layer.strip_prefix("environment://") {
+            Ok(Layer::Environment(Some(prefix)))
+        } else {
+            let mut tokens = layer.splitn(2, "://");
+            let schema = tokens.next().ok_or(ConfigError::FileParse {
+                uri: Some(layer.to_owned()),
+                cause: "Invalid config layer".into(),
+            })?;
+            Ok(Self::Config(schema, layer, tokens.next()))
+        }
+    }
+
+    fn name(&self, stage: &str) -> String {
+        match self {
+            Layer::Base => format!("server_config.{stage}.json"),
+            Layer::Environment(None) => "environment".to_string(),
+            Layer::Environment(Some(prefix)) => format!("environment://{prefix}"),
+            Layer::Config(_, url, _) => url.to_string(),
+        }
+    }
+}
+
+/// State shared by the layers, ex. the credentials are created once for all the keyvault layers.
+#[derive(Default)]
+struct LayerContext {
+    #[cfg(feature = "azure")]
+    azure_credentials: Option<Arc<dyn TokenCredential>>,
+    /// The keyvault sources sharing the load report with the source added to the builder.
+    #[cfg(feature = "azure")]
+    keyvault_sources: Vec<AzureKeyvaultConfigSource>,
+}
+
 /// Partial configuration required for early setup.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
@@ -17,6 +84,10 @@ pub struct CoreConfig {
     pub version: String,
     pub before_layers: Vec<String>,
     pub after_layers: Vec<String>,
+    /// Loading options of the `azk://` layers.
+    #[cfg(feature = "azure")]
+    #[serde(default)]
+    pub keyvault: AzureKeyvaultSourceConfig,
 }
 
 impl CoreConfig {
@@ -34,29 +105,7 @@ impl CoreConfig {
         Ok(cfg)
     }
 
-    pub fn create_config_builder(&self) -> Result<ConfigBuilder<AsyncState>, ConfigError> {
-        let mut builder = ConfigBuilder::<AsyncState>::default();
-
-        enum Layer<'a> {
-            Base,
-            Environment,
-            Config(&'a str, &'a str, Option<&'a str>),
-        }
-        impl<'a> Layer<'a> {
-            fn from_layer(layer: &'a str) -> Result<Self, ConfigError> {
-                if layer == "environment" {
-                    Ok(Layer::Environment)
-                } else {
-                    let mut tokens = layer.splitn(2, "://");
-                    let schema = tokens.next().ok_or(ConfigError::FileParse {
-                        uri: Some(layer.to_owned()),
-                        cause: "Invalid config layer".into(),
-                    })?;
-                    Ok(Self::Config(schema, layer, tokens.next()))
-                }
-            }
-        }
-
+    fn layers(&self) -> Result<Vec<Layer<'_>>, ConfigError> {
         let mut layers = Vec::with_capacity(self.before_layers.len() + self.after_layers.len() + 1);
         for l in self.before_layers.iter().map(|x| Layer::from_layer(x.as_str())) {
             layers.push(l?);
@@ -65,71 +114,168 @@ impl CoreConfig {
         for l in self.after_layers.iter().map(|x| Layer::from_layer(x.as_str())) {
             layers.push(l?);
         }
+        Ok(layers)
+    }
 
-        let mut azure_credentials: Option<Arc<dyn TokenCredential>> = None;
-        for layer in layers {
-            match layer {
-                Layer::Base => {
-                    builder = builder.add_source(File::from(Path::new(&format!("server_config.{}.json", self.stage))));
-                }
-                Layer::Environment => {
-                    builder = builder.add_source(Environment::default().separator("--"));
-                }
-                Layer::Config("file", url, path) => {
-                    let path = path.ok_or(ConfigError::FileParse {
-                        uri: Some(url.to_owned()),
-                        cause: "Missing file path".into(),
-                    })?;
-                    builder = builder.add_source(File::from(Path::new(path)));
-                }
-                Layer::Config("file?", url, path) => {
-                    let path = path.ok_or(ConfigError::FileParse {
-                        uri: Some(url.to_owned()),
-                        cause: "Missing file path".into(),
-                    })?;
+    #[cfg_attr(not(feature = "azure"), allow(unused_variables))]
+    fn add_layer(
+        &self,
+        mut builder: ConfigBuilder<AsyncState>,
+        layer: &Layer<'_>,
+        context: &mut LayerContext,
+    ) -> Result<ConfigBuilder<AsyncState>, ConfigError> {
+        match *layer {
+            Layer::Base => {
+                builder = builder.add_source(File::from(Path::new(&format!("server_config.{}.json", self.stage))));
+            }
+            Layer::Environment(None) => {
+                builder = builder.add_source(Environment::default().separator("--"));
+            }
+            Layer::Environment(Some(prefix)) => {
+                builder = builder.add_source(Environment::with_prefix(prefix).prefix_separator("--").separator("--"));
+            }
+            Layer::Config("file", url, path) => {
+                let path = path.ok_or(ConfigError::FileParse {
+                    uri: Some(url.to_owned()),
+                    cause: "Missing file path".into(),
+                })?;
+                builder = builder.add_source(File::from(Path::new(path)));
+            }
+            Layer::Config("file?", url, path) => {
+                let path = path.ok_or(ConfigError::FileParse {
+                    uri: Some(url.to_owned()),
+                    cause: "Missing file path".into(),
+                })?;
 
-                    if Path::new(path).exists() {
-                        log::info!("Adding optional config file {}...", path);
-                        builder = builder.add_source(File::from(Path::new(path)));
-                    }
+                if Path::new(path).exists() {
+                    log::info!("Adding optional config file {}...", path);
+                    builder = builder.add_source(File::from(Path::new(path)));
                 }
-                Layer::Config("azk", url, path) => {
-                    let path = path.ok_or(ConfigError::FileParse {
+            }
+            #[cfg(feature = "azure")]
+            Layer::Config("azk", url, path) => {
+                let path = path.ok_or(ConfigError::FileParse {
+                    uri: Some(url.to_owned()),
+                    cause: "Missing azure keyvault location".into(),
+                })?;
+                if context.azure_credentials.is_none() {
+                    let credentials = default_chain().map_err(|err| ConfigError::FileParse {
                         uri: Some(url.to_owned()),
-                        cause: "Missing azure keyvault location".into(),
+                        cause: err.into(),
                     })?;
-                    if azure_credentials.is_none() {
-                        azure_credentials = if env::var("AZURE_TENANT_ID").is_ok() {
-                            let credentials = EnvironmentCredential::create(TokenCredentialOptions::default())
-                                .map_err(|err| ConfigError::FileParse {
-                                    uri: Some(url.to_owned()),
-                                    cause: err.into(),
-                                })?;
-                            log::info!("Getting azure credentials through environment...");
-                            Some(Arc::new(credentials))
-                        } else {
-                            log::info!("Getting azure credentials through azure cli...");
-                            Some(Arc::new(AzureCliCredential::new()))
-                        };
-                    }
-                    let azure_credentials = azure_credentials.clone().unwrap();
-                    let keyvault_url = format!("https://{}", path);
-                    let keyvault = AzureKeyvaultConfigSource::new(azure_credentials.clone(), &keyvault_url)?;
-                    builder = builder.add_async_source(keyvault);
+                    context.azure_credentials = Some(credentials);
                 }
-                Layer::Config(schema, url, _) => {
-                    return Err(ConfigError::FileParse {
-                        uri: Some(url.to_owned()),
-                        cause: format!("Unsupported schema, {schema}").into(),
-                    })
+                let azure_credentials = context.azure_credentials.clone().unwrap();
+                let keyvault_url = format!("https://{}", path);
+                let mut keyvault = AzureKeyvaultConfigSource::new(azure_credentials.clone(), &keyvault_url)?
+                    .with_config(&self.keyvault);
+                if let Some(cache) = SecretCache::from_env(&self.stage, path) {
+                    log::info!(
+                        "Using offline secret cache {} for {}",
+                        cache.path().display(),
+                        keyvault_url
+                    );
+                    keyvault = keyvault.with_offline_cache(cache);
                 }
+                context.keyvault_sources.push(keyvault.clone());
+                builder = builder.add_async_source(keyvault);
+            }
+            #[cfg(feature = "aws_config")]
+            Layer::Config("aws-sm", url, prefix) => {
+                let prefix = prefix.ok_or(ConfigError::FileParse {
+                    uri: Some(url.to_owned()),
+                    cause: "Missing aws secrets manager prefix".into(),
+                })?;
+                builder = builder.add_async_source(AwsSecretsManagerConfigSource::new(prefix));
+            }
+            #[cfg(feature = "aws_config")]
+            Layer::Config("aws-ssm", url, path) => {
+                let path = path.ok_or(ConfigError::FileParse {
+                    uri: Some(url.to_owned()),
+                    cause: "Missing aws parameter store path".into(),
+                })?;
+                builder = builder.add_async_source(AwsParameterStoreConfigSource::new(path));
+            }
+            Layer::Config(schema, url, _) => {
+                return Err(ConfigError::FileParse {
+                    uri: Some(url.to_owned()),
+                    cause: format!("Unsupported schema, {schema}").into(),
+                })
             }
         }
 
+        Ok(builder)
+    }
+
+    pub fn create_config_builder(&self) -> Result<ConfigBuilder<AsyncState>, ConfigError> {
+        let mut builder = ConfigBuilder::<AsyncState>::default();
+
+        let mut context = LayerContext::default();
+        for layer in self.layers()? {
+            builder = self.add_layer(builder, &layer, &mut context)?;
+        }
+
         builder = builder
             //.set_override("stage", self.stage.clone())?
             .set_override("version", self.version.clone())?;
 
         Ok(builder)
     }
+
+    /// Resolve each layer separately and record which layer supplied the final value of the keys and the load
+    /// report of the keyvault layers (ex. the skipped secrets).
+    /// It loads all the sources once more, thus it should be used only for diagnostics.
+    /// From an environment layer without a prefix only the variables overriding the keys of the other
+    /// layers are recorded, the rest of the process environment is unrelated to the configuration.
+    pub async fn trace_config(&self) -> Result<ConfigTrace, ConfigError> {
+        let mut context = LayerContext::default();
+        let mut layers = Vec::new();
+        for layer in self.layers()? {
+            let builder = self.add_layer(ConfigBuilder::<AsyncState>::default(), &layer, &mut context)?;
+            let values = builder.build().await?.collect()?;
+            layers.push((layer, values));
+        }
+
+        // the environment variables are matched case insensitively to the keys
+        let mut known = ConfigTrace::new();
+        for (layer, values) in &layers {
+            if !matches!(layer, Layer::Environment(None)) {
+                known.add_layer(&layer.name(&self.stage), values);
+            }
+        }
+        let known: HashSet<String> = known.entries().keys().map(|key| key.to_ascii_lowercase()).collect();
+
+        let mut trace = ConfigTrace::new();
+        for (layer, values) in &layers {
+            match layer {
+                Layer::Environment(None) => trace.add_layer_filtered(&layer.name(&self.stage), values, &|path| {
+                    known.contains(&path.to_ascii_lowercase())
+                }),
+                _ => trace.add_layer(&layer.name(&self.stage), values),
+            }
+        }
+
+        let overrides = ConfigBuilder::<AsyncState>::default()
+            .set_override("version", self.version.clone())?
+            .build()
+            .await?
+            .collect()?;
+        trace.add_layer("override", &overrides);
+
+        #[cfg(feature = "azure")]
+        for source in &context.keyvault_sources {
+            if let Some(report) = source.report() {
+                trace.add_source_report(report);
+            }
+        }
+
+        Ok(trace)
+    }
+
+    /// Log the resolved configuration with the secret values scrubbed.
+    pub fn log_config(config: &Config) -> Result<(), ConfigError> {
+        let values = config.collect()?;
+        log::info!("configuration: {:#?}", redact_config_map(&values));
+        Ok(())
+    }
 }
@@ -0,0 +1,303 @@
+use crate::{
+    axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig},
+    service::{UncheckedCurrentUser, UserSessionCacheReader, UserSessionError},
+};
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::{header::HeaderName, request::Parts, Method, Request},
+    response::{IntoResponse, Response},
+    Extension, RequestPartsExt,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use futures::future::BoxFuture;
+use ring::hmac;
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+use thiserror::Error as ThisError;
+use tower::{Layer, Service};
+
+pub const CSRF_TOKEN_HEADER: &str = "x-csrf-token";
+
+#[derive(Debug, ThisError)]
+pub enum CsrfError {
+    #[error("Invalid csrf secret")]
+    InvalidSecret(String),
+    #[error("Missing csrf token")]
+    MissingToken,
+    #[error("Invalid csrf token")]
+    InvalidToken,
+    #[error("Missing session of the csrf token")]
+    MissingSession,
+    #[error("Session error")]
+    UserSessionError(#[from] UserSessionError),
+}
+
+impl IntoProblem for CsrfError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            CsrfError::UserSessionError(err) => err.into_problem(config),
+            CsrfError::InvalidSecret(err) => Problem::internal_error(config, "Invalid csrf secret", err),
+            _ => Problem::forbidden()
+                .with_detail(self.to_string())
+                .with_extension(config, format!("{:#?}", self)),
+        }
+    }
+}
+
+/// Marker extension to exempt a request from the csrf validation. Authentication layers not relying on
+/// cookies can insert it into the request extensions, the API-key authentications are exempt by their header
+/// (see `CsrfTokenGenerator::with_api_key_header`).
+#[derive(Clone, Copy, Debug)]
+pub struct CsrfExempt;
+
+/// Generate and validate synchronizer tokens bound to the session key.
+pub struct CsrfTokenGenerator {
+    key: hmac::Key,
+    api_key_headers: Vec<HeaderName>,
+}
+
+impl CsrfTokenGenerator {
+    pub fn new(secret: &str) -> Result<Self, CsrfError> {
+        let secret = B64
+            .decode(secret)
+            .map_err(|err| CsrfError::InvalidSecret(format!("{err}")))?;
+        Ok(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &secret),
+            api_key_headers: Vec::new(),
+        })
+    }
+
+    /// Exempt the requests authenticated by the API key of the header, ex. `x-admin-api-key` of the `AdminRouter`.
+    /// The authentication must not fall back to the session cookie if the header is present. A cross-site
+    /// request cannot set a custom header without the approval of the CORS policy.
+    #[must_use]
+    pub fn with_api_key_header(mut self, header: HeaderName) -> Self {
+        self.api_key_headers.push(header);
+        self
+    }
+
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    /// Validate the csrf token of the state-changing requests of all the routes, the layer also provides the
+    /// generator for the `CsrfToken` extractor.
+    pub fn into_guard_layer(self) -> CsrfLayer {
+        CsrfLayer(Arc::new(self))
+    }
+
+    fn is_exempt(&self, parts: &Parts) -> bool {
+        matches!(
+            parts.method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        ) || parts.extensions.get::<CsrfExempt>().is_some()
+            || self
+                .api_key_headers
+                .iter()
+                .any(|header| parts.headers.contains_key(header))
+    }
+
+    /// Validate the csrf token of the request unless it is exempt.
+    async fn check(&self, parts: &mut Parts) -> Result<(), CsrfError> {
+        if self.is_exempt(parts) {
+            return Ok(());
+        }
+
+        let token = parts
+            .headers
+            .get(CSRF_TOKEN_HEADER)
+            .and_then(|token| token.to_str().ok())
+            .ok_or(CsrfError::MissingToken)?
+            .to_string();
+        // a state-changing request without a session is rejected as a forged request
+        if parts.extensions.get::<Arc<UserSessionCacheReader>>().is_none() {
+            return Err(CsrfError::MissingSession);
+        }
+        let user = parts
+            .extract::<UncheckedCurrentUser>()
+            .await
+            .map_err(|_| CsrfError::MissingSession)?;
+
+        self.verify(user.key.as_bytes(), &token)
+    }
+
+    /// Create the token of the session.
+    pub fn token(&self, session_key: &[u8]) -> String {
+        B64.encode(hmac::sign(&self.key, session_key).as_ref())
+    }
+
+    pub fn verify(&self, session_key: &[u8], token: &str) -> Result<(), CsrfError> {
+        let tag = B64.decode(token).map_err(|_| CsrfError::InvalidToken)?;
+        hmac::verify(&self.key, session_key, &tag).map_err(|_| CsrfError::InvalidToken)
+    }
+}
+
+/// The csrf token of the current session to be embedded into pages and forms.
+pub struct CsrfToken(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<CsrfError>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(generator) = parts
+            .extract::<Extension<Arc<CsrfTokenGenerator>>>()
+            .await
+            .expect("Missing CsrfTokenGenerator extension");
+
+        let user = parts
+            .extract::<UncheckedCurrentUser>()
+            .await
+            .map_err(|err| problem_config.configure(CsrfError::from(err.problem)))?;
+        Ok(Self(generator.token(user.key.as_bytes())))
+    }
+}
+
+/// Extractor validating the `x-csrf-token` header for the state-changing methods of a single route, prefer
+/// the layer of `CsrfTokenGenerator::into_guard_layer` to cover all the routes.
+/// Safe methods, requests marked with `CsrfExempt` and the API-key authenticated requests pass without validation.
+pub struct CsrfGuard;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfGuard
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<CsrfError>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(generator) = parts
+            .extract::<Extension<Arc<CsrfTokenGenerator>>>()
+            .await
+            .expect("Missing CsrfTokenGenerator extension");
+
+        generator
+            .check(parts)
+            .await
+            .map_err(|err| problem_config.configure(err))?;
+        Ok(CsrfGuard)
+    }
+}
+
+/// Layer validating the csrf token of the state-changing requests, see `CsrfGuard`.
+#[derive(Clone)]
+pub struct CsrfLayer(Arc<CsrfTokenGenerator>);
+
+impl<S> Layer<S> for CsrfLayer {
+    type Service = CsrfMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CsrfMiddleware {
+            inner,
+            generator: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[must_use]
+pub struct CsrfMiddleware<S> {
+    inner: S,
+    generator: Arc<CsrfTokenGenerator>,
+}
+
+impl<S> Service<Request<Body>> for CsrfMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let generator = self.generator.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            parts.extensions.insert(generator.clone());
+
+            if let Err(err) = generator.check(&mut parts).await {
+                let problem_config = parts
+                    .extensions
+                    .get::<ProblemConfig>()
+                    .expect("Missing ProblemConfig extension");
+                return Ok(problem_config.configure(err).into_response());
+            }
+
+            inner.call(Request::from_parts(parts, body)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{
+        http::{header, StatusCode},
+        routing::post,
+        Router,
+    };
+    use shine_test::test;
+    use tower::ServiceExt;
+
+    #[test]
+    fn token_bound_to_session() {
+        let generator = CsrfTokenGenerator::new("c2VjcmV0LWZvci10aGUtY3NyZi10b2tlbnM").unwrap();
+        let token = generator.token(b"session-1");
+        assert!(generator.verify(b"session-1", &token).is_ok());
+        assert!(generator.verify(b"session-2", &token).is_err());
+        assert!(generator.verify(b"session-1", "invalid").is_err());
+    }
+    async fn call(method: Method, headers: &[(&str, &str)]) -> Response {
+        let generator = CsrfTokenGenerator::new("c2VjcmV0LWZvci10aGUtY3NyZi10b2tlbnM")
+            .unwrap()
+            .with_api_key_header(HeaderName::from_static("x-admin-api-key"));
+        let app = Router::new()
+            .route("/api/items", post(|| async { "created" }).get(|| async { "items" }))
+            .layer(generator.into_guard_layer())
+            .layer(ProblemConfig::new(false).into_layer());
+
+        let mut request = Request::builder().method(method).uri("/api/items");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    #[test]
+    async fn guard_layer() {
+        assert_eq!(call(Method::GET, &[]).await.status(), StatusCode::OK);
+        assert_eq!(
+            call(Method::POST, &[("x-admin-api-key", "key")]).await.status(),
+            StatusCode::OK
+        );
+
+        for headers in [&[][..], &[(CSRF_TOKEN_HEADER, "token")][..]] {
+            let response = call(Method::POST, headers).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/problem+json");
+        }
+    }
+}
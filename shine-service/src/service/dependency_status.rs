@@ -0,0 +1,586 @@
+use crate::axum::escape_html;
+#[cfg(all(feature = "redis", feature = "openapi"))]
+use crate::service::AdminCaller;
+#[cfg(feature = "redis")]
+use crate::service::ConsumerGroupStatus;
+use async_trait::async_trait;
+use axum::{http::StatusCode, routing::get, Json, Router};
+#[cfg(all(feature = "redis", feature = "openapi"))]
+use axum::{
+    http::{header, HeaderMap},
+    response::{Html, IntoResponse},
+};
+use bb8::{ManageConnection, Pool as BB8Pool};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "redis")]
+use tokio::sync::watch;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DependencyHealth {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+impl DependencyHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DependencyHealth::Healthy => "healthy",
+            DependencyHealth::Degraded => "degraded",
+            DependencyHealth::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+/// Status of a single downstream dependency.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyStatus {
+    pub name: String,
+    /// Kind of the dependency, ex. `postgres`, `redis`, `queue`, `circuitBreaker`.
+    pub kind: String,
+    pub health: DependencyHealth,
+    pub details: BTreeMap<String, JsonValue>,
+}
+
+impl DependencyStatus {
+    pub fn new<N: ToString, K: ToString>(name: N, kind: K, health: DependencyHealth) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            health,
+            details: BTreeMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_detail<V: Into<JsonValue>>(mut self, key: &str, value: V) -> Self {
+        self.details.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+/// A dependency reported on the status dashboard. Health checks, circuit breakers and the other
+/// components tracking a downstream service implement it to appear on the dashboard.
+#[async_trait]
+pub trait StatusSource: Send + Sync {
+    fn name(&self) -> &str;
+    async fn status(&self) -> DependencyStatus;
+}
+
+/// Report the statistics of a connection pool and probe the dependency by checking out a connection.
+pub struct PoolStatus<M: ManageConnection> {
+    name: String,
+    kind: String,
+    pool: BB8Pool<M>,
+    timeout: Duration,
+}
+
+impl<M: ManageConnection> PoolStatus<M> {
+    pub fn new(name: &str, kind: &str, pool: BB8Pool<M>) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            pool,
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+}
+
+#[async_trait]
+impl<M: ManageConnection> StatusSource for PoolStatus<M> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn status(&self) -> DependencyStatus {
+        let start = Instant::now();
+        let probe = tokio::time::timeout(self.timeout, self.pool.get()).await;
+        let latency = start.elapsed();
+        // the state is captured after the probe, to include the connection created for it
+        let state = self.pool.state();
+
+        let status = DependencyStatus::new(&self.name, &self.kind, DependencyHealth::Healthy)
+            .with_detail("connections", state.connections)
+            .with_detail("idleConnections", state.idle_connections)
+            .with_detail("probeLatencyMs", latency.as_millis() as u64);
+        match probe {
+            Ok(Ok(_)) => status,
+            Ok(Err(err)) => DependencyStatus {
+                health: DependencyHealth::Unhealthy,
+                ..status.with_detail("error", format!("{err:?}"))
+            },
+            Err(_) => DependencyStatus {
+                health: DependencyHealth::Unhealthy,
+                ..status.with_detail("error", "Connection timed out")
+            },
+        }
+    }
+}
+
+/// Report the backlog of a consumer group, the queue is degraded when the backlog exceeds the limit.
+#[cfg(feature = "redis")]
+pub struct QueueLagStatus {
+    name: String,
+    status: watch::Receiver<ConsumerGroupStatus>,
+    max_backlog: usize,
+}
+
+#[cfg(feature = "redis")]
+impl QueueLagStatus {
+    pub fn new(name: &str, status: watch::Receiver<ConsumerGroupStatus>, max_backlog: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            max_backlog,
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl StatusSource for QueueLagStatus {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn status(&self) -> DependencyStatus {
+        let current = self.status.borrow().clone();
+        let backlog = current.pending + current.lag.unwrap_or(0);
+        let health = if backlog > self.max_backlog {
+            DependencyHealth::Degraded
+        } else {
+            DependencyHealth::Healthy
+        };
+
+        DependencyStatus::new(&self.name, "queue", health)
+            .with_detail("pending", current.pending)
+            .with_detail("lag", current.lag)
+            .with_detail("consumers", current.consumers)
+            .with_detail("desiredWorkers", current.desired_workers)
+    }
+}
+
+struct ErrorRateBucket {
+    start: Instant,
+    total: u64,
+    failed: u64,
+}
+
+/// Track the outcome of the calls to a dependency over a sliding window and report the error rate.
+#[derive(Clone)]
+pub struct ErrorRateStatus {
+    name: String,
+    kind: String,
+    window: Duration,
+    min_calls: u64,
+    degraded_rate: f64,
+    unhealthy_rate: f64,
+    buckets: Arc<Mutex<VecDeque<ErrorRateBucket>>>,
+}
+
+impl ErrorRateStatus {
+    const BUCKET_COUNT: u32 = 10;
+
+    pub fn new(name: &str, kind: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            window: Duration::from_secs(5 * 60),
+            min_calls: 10,
+            degraded_rate: 0.05,
+            unhealthy_rate: 0.5,
+            buckets: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    #[must_use]
+    pub fn with_window(self, window: Duration) -> Self {
+        Self { window, ..self }
+    }
+
+    /// Error rates (0..1) above which the dependency is reported as degraded or unhealthy. The rates
+    /// are not evaluated until the window has at least `min_calls` calls.
+    #[must_use]
+    pub fn with_thresholds(self, min_calls: u64, degraded_rate: f64, unhealthy_rate: f64) -> Self {
+        Self {
+            min_calls,
+            degraded_rate,
+            unhealthy_rate,
+            ..self
+        }
+    }
+
+    fn prune(&self, buckets: &mut VecDeque<ErrorRateBucket>, now: Instant) {
+        while buckets
+            .front()
+            .is_some_and(|bucket| now.duration_since(bucket.start) > self.window)
+        {
+            buckets.pop_front();
+        }
+    }
+
+    pub fn record(&self, success: bool) {
+        let now = Instant::now();
+        let bucket_size = self.window / Self::BUCKET_COUNT;
+        let mut buckets = self.buckets.lock().unwrap();
+        self.prune(&mut buckets, now);
+
+        match buckets.back_mut() {
+            Some(bucket) if now.duration_since(bucket.start) < bucket_size => {
+                bucket.total += 1;
+                bucket.failed += u64::from(!success);
+            }
+            _ => buckets.push_back(ErrorRateBucket {
+                start: now,
+                total: 1,
+                failed: u64::from(!success),
+            }),
+        }
+    }
+
+    pub fn record_result<T, E>(&self, result: &Result<T, E>) {
+        self.record(result.is_ok());
+    }
+
+    /// The number of the calls and the failed calls in the window.
+    pub fn counts(&self) -> (u64, u64) {
+        let mut buckets = self.buckets.lock().unwrap();
+        self.prune(&mut buckets, Instant::now());
+        buckets.iter().fold((0, 0), |(total, failed), bucket| {
+            (total + bucket.total, failed + bucket.failed)
+        })
+    }
+}
+
+#[async_trait]
+impl StatusSource for ErrorRateStatus {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn status(&self) -> DependencyStatus {
+        let (total, failed) = self.counts();
+        let rate = if total > 0 { failed as f64 / total as f64 } else { 0.0 };
+        let health = if total < self.min_calls || rate <= self.degraded_rate {
+            DependencyHealth::Healthy
+        } else if rate <= self.unhealthy_rate {
+            DependencyHealth::Degraded
+        } else {
+            DependencyHealth::Unhealthy
+        };
+
+        DependencyStatus::new(&self.name, &self.kind, health)
+            .with_detail("calls", total)
+            .with_detail("failures", failed)
+            .with_detail("errorRate", rate)
+            .with_detail("windowSec", self.window.as_secs())
+    }
+}
+
+/// Cache the status of a source and limit the duration of the probe. It is used for the active probes
+/// (ex. the cloud dependencies) that are too slow or too expensive to run on each status request. A failed
+/// or timed out probe is cached as well, thus a failing dependency is not probed more often than the ttl.
+pub struct CachedStatus<S: StatusSource> {
+    source: S,
+    ttl: Duration,
+    timeout: Duration,
+    cached: tokio::sync::Mutex<Option<(Instant, DependencyStatus)>>,
+}
+
+impl<S: StatusSource> CachedStatus<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            ttl: Duration::from_secs(30),
+            timeout: Duration::from_secs(3),
+            cached: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    #[must_use]
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// Maximum time to wait for the probe, it should be shorter than the timeout of the dashboard.
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+}
+
+#[async_trait]
+impl<S: StatusSource> StatusSource for CachedStatus<S> {
+    fn name(&self) -> &str {
+        self.source.name()
+    }
+
+    async fn status(&self) -> DependencyStatus {
+        // the lock is held during the probe, the concurrent requests wait for the same probe
+        let mut cached = self.cached.lock().await;
+        if let Some((checked_at, status)) = &*cached {
+            if checked_at.elapsed() < self.ttl {
+                return status.clone().with_detail("ageSec", checked_at.elapsed().as_secs());
+            }
+        }
+
+        let status = match tokio::time::timeout(self.timeout, self.source.status()).await {
+            Ok(status) => status,
+            Err(_) => DependencyStatus::new(self.source.name(), "unknown", DependencyHealth::Unhealthy)
+                .with_detail("error", "Probe timed out"),
+        };
+        *cached = Some((Instant::now(), status.clone()));
+        status.with_detail("ageSec", 0)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusReport {
+    pub service: String,
+    /// The worst health of the dependencies.
+    pub health: DependencyHealth,
+    pub generated_at: DateTime<Utc>,
+    pub dependencies: Vec<DependencyStatus>,
+}
+
+impl StatusReport {
+    #[cfg_attr(not(all(feature = "redis", feature = "openapi")), allow(dead_code))]
+    fn to_html(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{service} status</title>\
+             <style>body{{font-family:sans-serif}}td,th{{padding:4px 8px;text-align:left;vertical-align:top}}\
+             .healthy{{color:#2e7d32}}.degraded{{color:#ef6c00}}.unhealthy{{color:#c62828}}</style></head>\
+             <body><h1>{service}: <span class=\"{health}\">{health}</span></h1><p>Generated at {at}</p>\
+             <table><tr><th>Name</th><th>Kind</th><th>Health</th><th>Details</th></tr>",
+            service = escape_html(&self.service),
+            health = self.health.as_str(),
+            at = self.generated_at.to_rfc3339(),
+        );
+        for dependency in &self.dependencies {
+            let details = dependency
+                .details
+                .iter()
+                .map(|(key, value)| format!("{}: {}", escape_html(key), escape_html(&value.to_string())))
+                .collect::<Vec<_>>()
+                .join("<br>");
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td class=\"{health}\">{health}</td><td>{details}</td></tr>",
+                escape_html(&dependency.name),
+                escape_html(&dependency.kind),
+                health = dependency.health.as_str(),
+            );
+        }
+        html.push_str("</table></body></html>");
+        html
+    }
+}
+
+/// Aggregate the status of the downstream dependencies into a single document for the on-call engineers.
+pub struct StatusDashboard {
+    service: String,
+    timeout: Duration,
+    sources: Vec<Arc<dyn StatusSource>>,
+}
+
+impl StatusDashboard {
+    pub fn new(service: &str) -> Self {
+        Self {
+            service: service.to_string(),
+            timeout: Duration::from_secs(5),
+            sources: Vec::new(),
+        }
+    }
+
+    /// Maximum time to wait for a source, the slow ones are reported as unhealthy.
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    #[must_use]
+    pub fn with_source<S: StatusSource + 'static>(mut self, source: S) -> Self {
+        self.sources.push(Arc::new(source));
+        self
+    }
+
+    #[must_use]
+    pub fn with_shared_source(mut self, source: Arc<dyn StatusSource>) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Query all the sources concurrently.
+    pub async fn report(&self) -> StatusReport {
+        let dependencies = futures::future::join_all(self.sources.iter().map(|source| async move {
+            match tokio::time::timeout(self.timeout, source.status()).await {
+                Ok(status) => status,
+                Err(_) => DependencyStatus::new(source.name(), "unknown", DependencyHealth::Unhealthy)
+                    .with_detail("error", "Status query timed out"),
+            }
+        }))
+        .await;
+
+        StatusReport {
+            service: self.service.clone(),
+            health: dependencies
+                .iter()
+                .map(|dependency| dependency.health)
+                .max()
+                .unwrap_or(DependencyHealth::Healthy),
+            generated_at: Utc::now(),
+            dependencies,
+        }
+    }
+
+    /// Create the admin route of the dashboard, it is authorized by the `AdminCaller`, see
+    /// `AdminRouter::with_routes`. The report is rendered as html when the client accepts it (ex. a browser)
+    /// and as json otherwise.
+    ///  - GET /admin/status
+    #[cfg(all(feature = "redis", feature = "openapi"))]
+    pub fn into_router<S>(self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Arc::new(self).admin_router()
+    }
+
+    /// Same as `into_router` for a shared dashboard.
+    #[cfg(all(feature = "redis", feature = "openapi"))]
+    pub fn admin_router<S>(self: &Arc<Self>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let dashboard = self.clone();
+
+        let route = get(move |_: AdminCaller, headers: HeaderMap| async move {
+            let report = dashboard.report().await;
+            let accepts_html = headers
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .is_some_and(|accept| accept.contains("text/html"));
+            if accepts_html {
+                Html(report.to_html()).into_response()
+            } else {
+                Json(report).into_response()
+            }
+        });
+
+        Router::new().route("/admin/status", route)
+    }
+
+    /// Create the public readiness route for the orchestrator. The service is ready unless a dependency is
+    /// unhealthy, only the health of the dependencies is reported, the details are available on the admin route.
+    ///  - GET /health/ready
+    pub fn readiness_router<S>(self: &Arc<Self>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let dashboard = self.clone();
+
+        let route = get(move || async move {
+            let report = dashboard.report().await;
+            let status = if report.health == DependencyHealth::Unhealthy {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+            let dependencies = report
+                .dependencies
+                .iter()
+                .map(|dependency| (dependency.name.clone(), dependency.health))
+                .collect::<BTreeMap<_, _>>();
+            (
+                status,
+                Json(serde_json::json!({ "health": report.health, "dependencies": dependencies })),
+            )
+        });
+
+        Router::new().route("/health/ready", route)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    struct SlowSource;
+
+    #[async_trait]
+    impl StatusSource for SlowSource {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        async fn status(&self) -> DependencyStatus {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            DependencyStatus::new("slow", "test", DependencyHealth::Healthy)
+        }
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    async fn aggregate_worst_health() {
+        let errors = ErrorRateStatus::new("payments", "http").with_thresholds(4, 0.1, 0.5);
+        let (_, lag) = watch::channel(ConsumerGroupStatus {
+            pending: 10,
+            lag: Some(100),
+            consumers: 1,
+            desired_workers: 2,
+        });
+        let dashboard = StatusDashboard::new("test")
+            .with_timeout(Duration::from_millis(10))
+            .with_source(errors.clone())
+            .with_source(QueueLagStatus::new("events", lag, 50));
+
+        errors.record(true);
+        errors.record(false);
+        let report = dashboard.report().await;
+        assert_eq!(report.dependencies[0].health, DependencyHealth::Healthy);
+        assert_eq!(report.dependencies[1].health, DependencyHealth::Degraded);
+        assert_eq!(report.health, DependencyHealth::Degraded);
+
+        errors.record(false);
+        errors.record(false);
+        let report = dashboard.with_source(SlowSource).report().await;
+        assert_eq!(report.dependencies[0].health, DependencyHealth::Unhealthy);
+        assert_eq!(report.dependencies[2].health, DependencyHealth::Unhealthy);
+        assert_eq!(report.health, DependencyHealth::Unhealthy);
+        assert!(report.to_html().contains("<td class=\"unhealthy\">unhealthy</td>"));
+    }
+
+    #[test]
+    async fn cached_probe() {
+        let errors = ErrorRateStatus::new("payments", "http").with_thresholds(1, 0.1, 0.5);
+        let cached = CachedStatus::new(errors.clone()).with_ttl(Duration::from_secs(60));
+
+        assert_eq!(cached.status().await.health, DependencyHealth::Healthy);
+        errors.record(false);
+        assert_eq!(cached.status().await.health, DependencyHealth::Healthy);
+        assert_eq!(errors.status().await.health, DependencyHealth::Unhealthy);
+
+        let slow = CachedStatus::new(SlowSource).with_timeout(Duration::from_millis(10));
+        let status = slow.status().await;
+        assert_eq!(status.health, DependencyHealth::Unhealthy);
+        assert_eq!(status.name, "slow");
+    }
+}
@@ -0,0 +1,342 @@
+use crate::{
+    axum::IntoProblem,
+    service::{RedisConnectionError, RedisConnectionPool},
+    utils::{serde_duration_secs, DurationStr, Entropy, SystemEntropy},
+};
+use chrono::{DateTime, Duration, Utc};
+use redis::{AsyncCommands, Script};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use shine_macros::RedisJsonValue;
+use std::sync::Arc;
+use thiserror::Error as ThisError;
+use uuid::Uuid;
+
+/// Alphabet of the user codes, vowels and look-alike characters are omitted.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+/// The random bytes at or above this limit are rejected to keep the distribution of the user code characters
+/// uniform.
+const USER_CODE_BYTE_LIMIT: u8 = (256 - 256 % USER_CODE_ALPHABET.len()) as u8;
+/// Maximum number of attempts to update a pending authorization changed concurrently.
+const MAX_UPDATE_ATTEMPTS: usize = 3;
+
+const COMPARE_AND_SET_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[2], 'KEEPTTL')
+    return 1
+end
+return 0
+"#;
+
+#[derive(Debug, ThisError, IntoProblem)]
+pub enum DeviceCodeError {
+    #[error("Failed to generate device code: {0}")]
+    #[problem(internal = "Random error")]
+    RandomError(String),
+    #[error("Unknown or expired code")]
+    #[problem(status = 404, type = "not-found")]
+    UnknownCode,
+    #[error("Authorization is already completed")]
+    #[problem(status = 409, type = "device_code_completed")]
+    AlreadyCompleted,
+    #[error("Failed to get redis connection")]
+    #[problem(internal = "Redis connection error")]
+    RedisPoolError(#[source] RedisConnectionError),
+    #[error("Redis error")]
+    #[problem(internal = "Redis error")]
+    RedisError(#[from] redis::RedisError),
+}
+
+/// Response of a device authorization request, the device_code is kept by the device
+/// and the user_code is presented to the user (optionally as a QR code of the verification uri).
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    /// Lifetime of the codes, serialized in seconds as defined by RFC 8628.
+    #[serde(serialize_with = "serde_duration_secs::serialize")]
+    pub expires_in: DurationStr,
+    /// Minimum time between the polls, serialized in seconds as defined by RFC 8628.
+    #[serde(serialize_with = "serde_duration_secs::serialize")]
+    pub interval: DurationStr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum DeviceCodeStatus {
+    Pending,
+    Approved(Uuid),
+    Denied,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, RedisJsonValue)]
+#[serde(rename_all = "camelCase")]
+struct PendingAuthorization {
+    device_code_hash: String,
+    client_name: String,
+    created_at: DateTime<Utc>,
+    status: DeviceCodeStatus,
+}
+
+/// Information presented to the user on the approval page.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceAuthorizationInfo {
+    pub user_code: String,
+    pub client_name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DevicePollResult {
+    /// The user has not completed the authorization yet
+    Pending,
+    /// The device polls too frequently
+    SlowDown,
+    /// The user approved the authorization, the code is consumed
+    Approved(Uuid),
+    /// The user denied the authorization, the code is consumed
+    Denied,
+    /// Code is not known or it has been expired
+    Expired,
+}
+
+/// Handle the device code (console, TV) login handoff with the pending authorizations stored in redis.
+pub struct DeviceCodeManager {
+    key_prefix: String,
+    ttl: Duration,
+    interval: Duration,
+    random: Arc<dyn Entropy>,
+    compare_and_set: Script,
+    redis: RedisConnectionPool,
+}
+
+impl DeviceCodeManager {
+    pub fn new(key_prefix: &str, ttl: Duration, interval: Duration, redis: RedisConnectionPool) -> Self {
+        Self {
+            key_prefix: key_prefix.to_string(),
+            ttl,
+            interval,
+            random: SystemEntropy::shared(),
+            compare_and_set: Script::new(COMPARE_AND_SET_SCRIPT),
+            redis,
+        }
+    }
+
+    /// Replace the source of the generated codes, ex. with a `SeededEntropy` in the tests.
+    #[must_use]
+    pub fn with_entropy(self, random: Arc<dyn Entropy>) -> Self {
+        Self { random, ..self }
+    }
+
+    fn device_key(&self, device_code: &str) -> (String, String) {
+        let hash = hex::encode(digest::digest(&digest::SHA256, device_code.as_bytes()));
+        let key = format!("{}device_code:{}", self.key_prefix, hash);
+        (hash, key)
+    }
+
+    /// The key of the last poll of a device, it expires after the polling interval.
+    fn poll_key(&self, device_code_hash: &str) -> String {
+        format!("{}device_poll:{}", self.key_prefix, device_code_hash)
+    }
+
+    fn user_key(&self, user_code: &str) -> String {
+        let user_code = normalize_user_code(user_code);
+        format!("{}device_user_code:{}", self.key_prefix, user_code)
+    }
+
+    fn generate_codes(&self) -> Result<(String, String), DeviceCodeError> {
+        let mut raw = [0_u8; 32];
+        self.random
+            .fill(&mut raw)
+            .map_err(|err| DeviceCodeError::RandomError(err.to_string()))?;
+        let device_code = hex::encode(raw);
+
+        // rejection sampling, the bytes above the largest multiple of the alphabet size are dropped
+        let mut user_code = String::with_capacity(USER_CODE_LENGTH);
+        while user_code.len() < USER_CODE_LENGTH {
+            let mut raw = [0_u8; USER_CODE_LENGTH];
+            self.random
+                .fill(&mut raw)
+                .map_err(|err| DeviceCodeError::RandomError(err.to_string()))?;
+            user_code.extend(
+                raw.iter()
+                    .filter(|b| **b < USER_CODE_BYTE_LIMIT)
+                    .map(|b| USER_CODE_ALPHABET[*b as usize % USER_CODE_ALPHABET.len()] as char)
+                    .take(USER_CODE_LENGTH - user_code.len()),
+            );
+        }
+        Ok((device_code, format_user_code(&user_code)))
+    }
+
+    /// Start a new device authorization.
+    pub async fn start(&self, client_name: &str) -> Result<DeviceAuthorization, DeviceCodeError> {
+        let ttl = self.ttl.num_seconds().max(1) as u64;
+        let mut client = self.redis.get().await.map_err(DeviceCodeError::RedisPoolError)?;
+
+        // in the very unlikely case of a user code collision, retry with a new code
+        for _ in 0..3 {
+            let (device_code, user_code) = self.generate_codes()?;
+            let (device_code_hash, device_key) = self.device_key(&device_code);
+            let user_key = self.user_key(&user_code);
+
+            let pending = PendingAuthorization {
+                device_code_hash,
+                client_name: client_name.to_string(),
+                created_at: Utc::now(),
+                status: DeviceCodeStatus::Pending,
+            };
+
+            let created: bool = redis::cmd("SET")
+                .arg(&user_key)
+                .arg(&pending)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl)
+                .query_async(&mut *client)
+                .await?;
+            if created {
+                let _: () = client.set_ex(&device_key, &user_key, ttl).await?;
+                return Ok(DeviceAuthorization {
+                    device_code,
+                    user_code,
+                    expires_in: DurationStr::from_secs(ttl),
+                    interval: DurationStr::from_secs(self.interval.num_seconds().max(1) as u64),
+                });
+            }
+        }
+
+        Err(DeviceCodeError::RandomError("Failed to find a unique user code".into()))
+    }
+
+    /// Get the details of the pending authorization for the approval page.
+    pub async fn find(&self, user_code: &str) -> Result<DeviceAuthorizationInfo, DeviceCodeError> {
+        let user_key = self.user_key(user_code);
+        let mut client = self.redis.get().await.map_err(DeviceCodeError::RedisPoolError)?;
+        let pending: Option<PendingAuthorization> = client.get(&user_key).await?;
+        match pending {
+            Some(pending) if pending.status == DeviceCodeStatus::Pending => Ok(DeviceAuthorizationInfo {
+                user_code: format_user_code(&normalize_user_code(user_code)),
+                client_name: pending.client_name,
+                created_at: pending.created_at,
+            }),
+            Some(_) => Err(DeviceCodeError::AlreadyCompleted),
+            None => Err(DeviceCodeError::UnknownCode),
+        }
+    }
+
+    /// Complete the pending authorization. The stored value is replaced only if it has not been changed since
+    /// it was read, thus of the concurrent approve and deny requests only one can succeed.
+    async fn complete(&self, user_code: &str, status: DeviceCodeStatus) -> Result<(), DeviceCodeError> {
+        let user_key = self.user_key(user_code);
+        let mut client = self.redis.get().await.map_err(DeviceCodeError::RedisPoolError)?;
+
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let current: Vec<u8> = client
+                .get::<_, Option<Vec<u8>>>(&user_key)
+                .await?
+                .ok_or(DeviceCodeError::UnknownCode)?;
+            let mut pending: PendingAuthorization =
+                redis::from_redis_value(&redis::Value::BulkString(current.clone()))?;
+            if pending.status != DeviceCodeStatus::Pending {
+                return Err(DeviceCodeError::AlreadyCompleted);
+            }
+
+            pending.status = status;
+            let updated: i32 = self
+                .compare_and_set
+                .key(&user_key)
+                .arg(&current)
+                .arg(&pending)
+                .invoke_async(&mut *client)
+                .await?;
+            if updated == 1 {
+                return Ok(());
+            }
+        }
+
+        Err(DeviceCodeError::AlreadyCompleted)
+    }
+
+    /// Approve the authorization on behalf of the (authenticated) user.
+    pub async fn approve(&self, user_code: &str, user_id: Uuid) -> Result<(), DeviceCodeError> {
+        self.complete(user_code, DeviceCodeStatus::Approved(user_id)).await
+    }
+
+    /// Deny the authorization.
+    pub async fn deny(&self, user_code: &str) -> Result<(), DeviceCodeError> {
+        self.complete(user_code, DeviceCodeStatus::Denied).await
+    }
+
+    /// Poll the state of the authorization by the device. Once the authorization is completed the codes are consumed.
+    pub async fn poll(&self, device_code: &str) -> Result<DevicePollResult, DeviceCodeError> {
+        let (device_code_hash, device_key) = self.device_key(device_code);
+        let mut client = self.redis.get().await.map_err(DeviceCodeError::RedisPoolError)?;
+
+        let user_key: Option<String> = client.get(&device_key).await?;
+        let user_key = match user_key {
+            Some(user_key) => user_key,
+            None => return Ok(DevicePollResult::Expired),
+        };
+        let pending: PendingAuthorization = match client.get(&user_key).await? {
+            Some(pending) => pending,
+            None => return Ok(DevicePollResult::Expired),
+        };
+        if pending.device_code_hash != device_code_hash {
+            return Ok(DevicePollResult::Expired);
+        }
+
+        match pending.status {
+            DeviceCodeStatus::Pending => {
+                // the last poll is tracked by a separate expiring key, not to overwrite a concurrent completion
+                let interval = self.interval.num_milliseconds().max(1);
+                let polled: Option<String> = redis::cmd("SET")
+                    .arg(self.poll_key(&device_code_hash))
+                    .arg(1)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(interval)
+                    .query_async(&mut *client)
+                    .await?;
+                if polled.is_none() {
+                    Ok(DevicePollResult::SlowDown)
+                } else {
+                    Ok(DevicePollResult::Pending)
+                }
+            }
+            status => {
+                let _: () = redis::pipe()
+                    .del(&device_key)
+                    .del(&user_key)
+                    .del(self.poll_key(&device_code_hash))
+                    .query_async(&mut *client)
+                    .await?;
+                match status {
+                    DeviceCodeStatus::Approved(user_id) => Ok(DevicePollResult::Approved(user_id)),
+                    _ => Ok(DevicePollResult::Denied),
+                }
+            }
+        }
+    }
+}
+
+/// Remove the formatting characters and convert to upper case.
+fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Format user code into groups of 4 characters for readability.
+fn format_user_code(user_code: &str) -> String {
+    user_code
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,336 @@
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(feature = "reqwest")]
+use std::sync::Arc;
+use thiserror::Error as ThisError;
+use url::{Host, Url};
+
+#[derive(Debug, ThisError)]
+pub enum EgressError {
+    #[error("Unsupported url scheme: {0}")]
+    UnsupportedScheme(String),
+    #[error("Missing host in url")]
+    MissingHost,
+    #[error("Host is not on the allow-list: {0}")]
+    HostNotAllowed(String),
+    #[error("Destination address is not public: {0}")]
+    PrivateAddress(IpAddr),
+    #[error("Failed to resolve host {0}")]
+    ResolveError(String, #[source] std::io::Error),
+    #[error("Host {0} did not resolve to any address")]
+    NoAddress(String),
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressConfig {
+    /// Allowed destination hosts. An entry starting with `*.` matches any subdomain.
+    /// An empty list allows any host that passes the address checks.
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// Allow plain http destinations.
+    #[serde(default)]
+    pub allow_http: bool,
+    /// Allow loopback, private and link-local destinations, intended for local development only.
+    #[serde(default)]
+    pub allow_private: bool,
+}
+
+/// Validate outbound destinations against the configured allow-list and
+/// guard user supplied urls against SSRF.
+#[derive(Clone, Debug)]
+pub struct EgressGuard {
+    allowed_hosts: Vec<String>,
+    allow_http: bool,
+    allow_private: bool,
+}
+
+impl EgressGuard {
+    pub fn new(config: &EgressConfig) -> Self {
+        Self {
+            allowed_hosts: config
+                .allowed_hosts
+                .iter()
+                .map(|host| host.trim_end_matches('.').to_ascii_lowercase())
+                .collect(),
+            allow_http: config.allow_http,
+            allow_private: config.allow_private,
+        }
+    }
+
+    fn is_host_allowed(&self, host: &str) -> bool {
+        if self.allowed_hosts.is_empty() {
+            return true;
+        }
+
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.allowed_hosts.iter().any(|allowed| {
+            if let Some(domain) = allowed.strip_prefix("*.") {
+                host.strip_suffix(domain)
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+            } else {
+                host == *allowed
+            }
+        })
+    }
+
+    fn check_address(&self, ip: IpAddr) -> Result<(), EgressError> {
+        if self.allow_private || is_public_address(ip) {
+            Ok(())
+        } else {
+            Err(EgressError::PrivateAddress(ip))
+        }
+    }
+
+    /// Check the url without name resolution. Use it for service configured destinations.
+    pub fn check_url(&self, url: &Url) -> Result<(), EgressError> {
+        match url.scheme() {
+            "https" => {}
+            "http" if self.allow_http => {}
+            scheme => return Err(EgressError::UnsupportedScheme(scheme.to_string())),
+        }
+
+        match url.host().ok_or(EgressError::MissingHost)? {
+            Host::Domain(domain) => {
+                if !self.is_host_allowed(domain) {
+                    return Err(EgressError::HostNotAllowed(domain.to_string()));
+                }
+            }
+            Host::Ipv4(ip) => {
+                if !self.is_host_allowed(&ip.to_string()) {
+                    return Err(EgressError::HostNotAllowed(ip.to_string()));
+                }
+                self.check_address(IpAddr::V4(ip))?;
+            }
+            Host::Ipv6(ip) => {
+                if !self.is_host_allowed(&ip.to_string()) {
+                    return Err(EgressError::HostNotAllowed(ip.to_string()));
+                }
+                self.check_address(IpAddr::V6(ip))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve the host and check that all of its addresses are allowed.
+    pub async fn resolve_host(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, EgressError> {
+        if !self.is_host_allowed(host) {
+            return Err(EgressError::HostNotAllowed(host.to_string()));
+        }
+
+        let addresses = tokio::net::lookup_host((host.trim_start_matches('[').trim_end_matches(']'), port))
+            .await
+            .map_err(|err| EgressError::ResolveError(host.to_string(), err))?
+            .collect::<Vec<_>>();
+
+        if addresses.is_empty() {
+            return Err(EgressError::NoAddress(host.to_string()));
+        }
+        for addr in &addresses {
+            self.check_address(addr.ip())?;
+        }
+
+        Ok(addresses)
+    }
+
+    /// Check the url and resolve the host to make sure none of the addresses points into a private network.
+    /// Use it for user supplied urls and connect to the returned addresses to avoid DNS rebinding.
+    pub async fn check_resolved_url(&self, url: &Url) -> Result<Vec<IpAddr>, EgressError> {
+        self.check_url(url)?;
+
+        let host = url.host_str().ok_or(EgressError::MissingHost)?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addresses = self.resolve_host(host, port).await?;
+        Ok(addresses.into_iter().map(|addr| addr.ip()).collect())
+    }
+
+    /// Name resolver for the reqwest clients.
+    #[cfg(feature = "reqwest")]
+    pub fn resolver(&self) -> EgressResolver {
+        EgressResolver(Arc::new(self.clone()))
+    }
+}
+
+/// Name resolver of the reqwest clients rejecting the hosts with a not allowed address. The client connects to the
+/// checked addresses, thus the host cannot be rebound to a private address between the check and the connection.
+/// Urls with an ip address host are not resolved, check them with [`EgressGuard::check_url`] before sending.
+#[cfg(feature = "reqwest")]
+#[derive(Clone, Debug)]
+pub struct EgressResolver(Arc<EgressGuard>);
+
+#[cfg(feature = "reqwest")]
+impl reqwest::dns::Resolve for EgressResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let guard = self.0.clone();
+        Box::pin(async move {
+            // the port is replaced by the client with the port of the url
+            let addresses = guard.resolve_host(name.as_str(), 0).await?;
+            let addresses: reqwest::dns::Addrs = Box::new(addresses.into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // shared address space (100.64.0.0/10)
+        || (a == 100 && (b & 0xc0) == 64)
+        // "this" network (0.0.0.0/8)
+        || a == 0
+        // IETF protocol assignments (192.0.0.0/24)
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking (198.18.0.0/15)
+        || (a == 198 && (b & 0xfe) == 18)
+        // reserved (240.0.0.0/4)
+        || a >= 240)
+}
+
+/// The IPv4 address embedded into the low 32 bits of the segments.
+fn embedded_ipv4(high: u16, low: u16) -> Ipv4Addr {
+    Ipv4Addr::from(((high as u32) << 16) | low as u32)
+}
+
+fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    if let Some(ipv4) = ip.to_ipv4_mapped() {
+        return is_public_ipv4(&ipv4);
+    }
+
+    let segments = ip.segments();
+    let first = segments[0];
+    // NAT64 (64:ff9b::/96), the translator connects to the embedded address
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_public_ipv4(&embedded_ipv4(segments[6], segments[7]));
+    }
+    // 6to4 (2002::/16), the relay connects to the embedded address
+    if first == 0x2002 {
+        return is_public_ipv4(&embedded_ipv4(segments[1], segments[2]));
+    }
+
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // deprecated IPv4-compatible addresses (::a.b.c.d)
+        || segments[..6] == [0, 0, 0, 0, 0, 0]
+        // local-use NAT64 (64:ff9b:1::/48)
+        || segments[..3] == [0x64, 0xff9b, 1]
+        // teredo (2001::/32), the embedded client address is obfuscated
+        || (first == 0x2001 && segments[1] == 0)
+        // documentation (2001:db8::/32)
+        || (first == 0x2001 && segments[1] == 0x0db8)
+        // unique local (fc00::/7)
+        || (first & 0xfe00) == 0xfc00
+        // link local (fe80::/10)
+        || (first & 0xffc0) == 0xfe80
+        // deprecated site local (fec0::/10)
+        || (first & 0xffc0) == 0xfec0)
+}
+
+/// Return if the address is routable on the public internet.
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(&ip),
+        IpAddr::V6(ip) => is_public_ipv6(&ip),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn host_allow_list() {
+        let guard = EgressGuard::new(&EgressConfig {
+            allowed_hosts: vec!["api.example.com".into(), "*.hooks.example.com".into()],
+            allow_http: false,
+            allow_private: false,
+        });
+
+        assert!(guard
+            .check_url(&Url::parse("https://api.example.com/a").unwrap())
+            .is_ok());
+        assert!(guard
+            .check_url(&Url::parse("https://x.hooks.example.com").unwrap())
+            .is_ok());
+        assert!(guard
+            .check_url(&Url::parse("https://hooks.example.com").unwrap())
+            .is_err());
+        assert!(guard
+            .check_url(&Url::parse("https://evilhooks.example.com").unwrap())
+            .is_err());
+        assert!(guard.check_url(&Url::parse("http://api.example.com").unwrap()).is_err());
+        assert!(guard.check_url(&Url::parse("ftp://api.example.com").unwrap()).is_err());
+    }
+
+    #[test]
+    fn private_addresses() {
+        let guard = EgressGuard::new(&EgressConfig::default());
+
+        for url in [
+            "https://127.0.0.1",
+            "https://10.1.2.3",
+            "https://192.168.0.1",
+            "https://169.254.169.254",
+            "https://100.64.0.1",
+            "https://[::1]",
+            "https://[fd00::1]",
+            "https://[::ffff:10.0.0.1]",
+        ] {
+            assert!(guard.check_url(&Url::parse(url).unwrap()).is_err(), "{url}");
+        }
+        assert!(guard.check_url(&Url::parse("https://8.8.8.8").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn address_ranges() {
+        let cases = [
+            ("8.8.8.8", true),
+            ("127.0.0.1", false),
+            ("0.1.2.3", false),
+            ("10.1.2.3", false),
+            ("100.64.0.1", false),
+            ("169.254.169.254", false),
+            ("172.16.0.1", false),
+            ("192.0.0.8", false),
+            ("192.0.2.1", false),
+            ("192.168.0.1", false),
+            ("198.18.0.1", false),
+            ("224.0.0.1", false),
+            ("239.255.255.250", false),
+            ("240.0.0.1", false),
+            ("255.255.255.255", false),
+            ("2001:4860:4860::8888", true),
+            ("::", false),
+            ("::1", false),
+            ("::ffff:10.0.0.1", false),
+            ("::ffff:8.8.8.8", true),
+            ("::10.0.0.1", false),
+            ("::8.8.8.8", false),
+            ("64:ff9b::a00:1", false),
+            ("64:ff9b::7f00:1", false),
+            ("64:ff9b::808:808", true),
+            ("64:ff9b:1::808:808", false),
+            ("2002:a00:1::1", false),
+            ("2002:a9fe:a9fe::1", false),
+            ("2002:808:808::1", true),
+            ("2001:0:4136:e378::1", false),
+            ("2001:db8::1", false),
+            ("fd00::1", false),
+            ("fe80::1", false),
+            ("fec0::1", false),
+            ("ff02::1", false),
+            ("ff0e::1", false),
+        ];
+        for (ip, public) in cases {
+            assert_eq!(is_public_address(ip.parse().unwrap()), public, "{ip}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{
+    service::email::{EmailError, EmailMessage, EmailSender},
+    utils::Sensitive,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use chrono::Utc;
+use reqwest::{StatusCode, Url};
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const API_VERSION: &str = "2023-03-31";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcsEmailConfig {
+    /// Endpoint of the communication resource, ex. `https://<resource>.communication.azure.com`.
+    pub endpoint: String,
+    /// Base64 encoded access key of the resource.
+    pub access_key: Sensitive<String>,
+    /// Sender address of a verified domain, ex. `no-reply@example.com`.
+    pub from: String,
+}
+
+/// Send the emails through the Azure Communication Services REST api using HMAC authentication.
+pub struct AcsEmailSender {
+    url: Url,
+    key: hmac::Key,
+    from: String,
+    client: reqwest::Client,
+}
+
+impl AcsEmailSender {
+    pub fn new(config: &AcsEmailConfig) -> Result<Self, EmailError> {
+        let mut url = Url::parse(&config.endpoint)
+            .map_err(|err| EmailError::InvalidMessage(format!("Invalid endpoint {}: {err}", config.endpoint)))?;
+        url.set_path("emails:send");
+        url.set_query(Some(&format!("api-version={API_VERSION}")));
+        let key = B64
+            .decode(config.access_key.expose())
+            .map_err(|err| EmailError::InvalidMessage(format!("Invalid access key: {err}")))?;
+
+        Ok(Self {
+            url,
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+            from: config.from.clone(),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Create the `Authorization` header, see
+    /// https://learn.microsoft.com/en-us/rest/api/communication/authentication#signing-an-http-request
+    fn authorization(&self, date: &str, content_hash: &str) -> String {
+        let host = self.url.host_str().unwrap_or_default();
+        let path_and_query = match self.url.query() {
+            Some(query) => format!("{}?{query}", self.url.path()),
+            None => self.url.path().to_string(),
+        };
+        let string_to_sign = format!("POST\n{path_and_query}\n{date};{host};{content_hash}");
+        let signature = B64.encode(hmac::sign(&self.key, string_to_sign.as_bytes()).as_ref());
+        format!("HMAC-SHA256 SignedHeaders=x-ms-date;host;x-ms-content-sha256&Signature={signature}")
+    }
+}
+
+#[async_trait]
+impl EmailSender for AcsEmailSender {
+    async fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        let recipients: Vec<_> = message.to.iter().map(|to| json!({ "address": to })).collect();
+        let body = json!({
+            "senderAddress": self.from,
+            "content": {
+                "subject": message.subject,
+                "plainText": message.text,
+                "html": message.html,
+            },
+            "recipients": { "to": recipients },
+        });
+        let body = serde_json::to_vec(&body).map_err(|err| EmailError::InvalidMessage(format!("{err}")))?;
+
+        let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        let content_hash = B64.encode(digest::digest(&digest::SHA256, &body).as_ref());
+        let authorization = self.authorization(&date, &content_hash);
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .header("x-ms-date", &date)
+            .header("x-ms-content-sha256", &content_hash)
+            .header("Authorization", authorization)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| EmailError::Transport(format!("{err}")))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let error = response.text().await.unwrap_or_default();
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            Err(EmailError::Transport(format!("{status}: {error}")))
+        } else {
+            Err(EmailError::Rejected(format!("{status}: {error}")))
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+use tracing::{field::Empty, info_span, Instrument};
+
+#[derive(Debug, ThisError)]
+pub enum EmailError {
+    #[error("Invalid email address: {0}")]
+    InvalidAddress(String),
+    #[error("Invalid email message: {0}")]
+    InvalidMessage(String),
+    #[error("Template error: {0}")]
+    Template(String),
+    /// Temporary failure (connection, throttling, server error), the sending can be retried.
+    #[error("Email transport error: {0}")]
+    Transport(String),
+    /// The provider refused the message, retrying will not help.
+    #[error("Email rejected: {0}")]
+    Rejected(String),
+}
+
+impl EmailError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, EmailError::Transport(_))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailMessage {
+    pub to: Vec<String>,
+    pub subject: String,
+    pub html: Option<String>,
+    pub text: Option<String>,
+}
+
+impl EmailMessage {
+    pub fn new<S: ToString>(to: S, subject: S) -> Self {
+        Self {
+            to: vec![to.to_string()],
+            subject: subject.to_string(),
+            html: None,
+            text: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_to<S: ToString>(mut self, to: S) -> Self {
+        self.to.push(to.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn with_html<S: ToString>(self, html: S) -> Self {
+        Self {
+            html: Some(html.to_string()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_text<S: ToString>(self, text: S) -> Self {
+        Self {
+            text: Some(text.to_string()),
+            ..self
+        }
+    }
+
+    /// Check the message before handing it to a provider.
+    pub fn validate(&self) -> Result<(), EmailError> {
+        if self.to.is_empty() {
+            return Err(EmailError::InvalidMessage("Missing recipient".into()));
+        }
+        if let Some(address) = self.to.iter().find(|to| match to.split_once('@') {
+            Some((local, domain)) => local.is_empty() || !domain.contains('.'),
+            None => true,
+        }) {
+            return Err(EmailError::InvalidAddress(address.clone()));
+        }
+        if self.html.is_none() && self.text.is_none() {
+            return Err(EmailError::InvalidMessage("Missing body".into()));
+        }
+        Ok(())
+    }
+}
+
+/// Provider independent email sending.
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, message: &EmailMessage) -> Result<(), EmailError>;
+}
+
+#[async_trait]
+impl EmailSender for Arc<dyn EmailSender> {
+    async fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        self.as_ref().send(message).await
+    }
+}
+
+/// Wrap a sender with validation, tracing and retry of the transient failures using an exponential backoff.
+pub struct TracedEmailSender<S: EmailSender> {
+    provider: &'static str,
+    sender: S,
+    max_retries: usize,
+    backoff: Duration,
+}
+
+impl<S: EmailSender> TracedEmailSender<S> {
+    pub fn new(provider: &'static str, sender: S) -> Self {
+        Self {
+            provider,
+            sender,
+            max_retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+
+    #[must_use]
+    pub fn with_retry(self, max_retries: usize, backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            backoff,
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl<S: EmailSender> EmailSender for TracedEmailSender<S> {
+    async fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        message.validate()?;
+
+        // the recipients are personal data, only their count is traced
+        let span = info_span!(
+            "email.send",
+            email.provider = self.provider,
+            email.recipients = message.to.len(),
+            email.retries = Empty,
+            otel.status_code = Empty,
+        );
+        async {
+            let mut delay = self.backoff;
+            let mut retries = 0;
+            let result = loop {
+                match self.sender.send(message).await {
+                    Err(err) if err.is_transient() && retries < self.max_retries => {
+                        log::warn!("Sending email failed, retrying in {delay:?}: {err}");
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                        retries += 1;
+                    }
+                    result => break result,
+                }
+            };
+
+            let span = tracing::Span::current();
+            span.record("email.retries", retries);
+            if let Err(err) = &result {
+                span.record("otel.status_code", "ERROR");
+                log::error!("Sending email with {} failed: {err}", self.provider);
+            }
+            result
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::service::email::MockEmailSender;
+    use shine_test::test;
+
+    #[test]
+    async fn retry_transient_errors() {
+        let mock = MockEmailSender::new();
+        mock.fail_next(EmailError::Transport("connection reset".into()));
+        let sender = TracedEmailSender::new("mock", mock.clone()).with_retry(2, Duration::from_millis(1));
+
+        let message = EmailMessage::new("jane@example.com", "Hello").with_text("Hi Jane");
+        sender.send(&message).await.unwrap();
+        assert_eq!(mock.sent(), vec![message]);
+
+        let invalid = EmailMessage::new("jane", "Hello").with_text("Hi Jane");
+        assert!(matches!(
+            sender.send(&invalid).await,
+            Err(EmailError::InvalidAddress(_))
+        ));
+
+        mock.fail_next(EmailError::Rejected("blocked".into()));
+        let message = EmailMessage::new("joe@example.com", "Hello").with_html("<p>Hi Joe</p>");
+        assert!(matches!(sender.send(&message).await, Err(EmailError::Rejected(_))));
+        assert_eq!(mock.sent().len(), 1);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::service::email::{EmailError, EmailMessage};
+use minijinja::Environment;
+use serde::Serialize;
+use std::{path::Path, sync::Arc};
+
+/// Email templates, an email `name` consists of the `name.subject`, `name.html` and optionally the
+/// `name.txt` templates.
+#[derive(Clone)]
+pub struct EmailTemplates {
+    env: Arc<Environment<'static>>,
+}
+
+impl EmailTemplates {
+    /// Load all the templates from the given folder. Template names are the relative path of the files.
+    pub fn from_folder<P: AsRef<Path>>(folder: P) -> Self {
+        let mut env = Environment::new();
+        env.set_loader(minijinja::path_loader(folder));
+        Self { env: Arc::new(env) }
+    }
+
+    pub fn with_template<N: ToString, S: ToString>(mut self, name: N, source: S) -> Result<Self, EmailError> {
+        let env = Arc::get_mut(&mut self.env).expect("EmailTemplates is already shared");
+        env.add_template_owned(name.to_string(), source.to_string())
+            .map_err(|err| EmailError::Template(format!("{err:#}")))?;
+        Ok(self)
+    }
+
+    fn render_part<T: Serialize>(&self, name: &str, context: &T) -> Result<Option<String>, EmailError> {
+        let template = match self.env.get_template(name) {
+            Ok(template) => template,
+            Err(err) if err.kind() == minijinja::ErrorKind::TemplateNotFound => return Ok(None),
+            Err(err) => return Err(EmailError::Template(format!("{err:#}"))),
+        };
+        let content = template
+            .render(context)
+            .map_err(|err| EmailError::Template(format!("{err:#}")))?;
+        Ok(Some(content))
+    }
+
+    /// Render the message for the recipient.
+    pub fn render<T: Serialize>(&self, name: &str, to: &str, context: &T) -> Result<EmailMessage, EmailError> {
+        let subject = self
+            .render_part(&format!("{name}.subject"), context)?
+            .ok_or_else(|| EmailError::Template(format!("Missing subject of {name}")))?;
+        let message = EmailMessage {
+            to: vec![to.to_string()],
+            subject: subject.trim().to_string(),
+            html: self.render_part(&format!("{name}.html"), context)?,
+            text: self.render_part(&format!("{name}.txt"), context)?,
+        };
+        message.validate()?;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use shine_test::test;
+
+    #[test]
+    fn render_email() {
+        let templates = EmailTemplates::from_folder("missing")
+            .with_template("welcome.subject", "Welcome {{ name }}")
+            .unwrap()
+            .with_template("welcome.html", "<p>Hello {{ name }}</p>")
+            .unwrap();
+        let message = templates
+            .render("welcome", "jane@example.com", &json!({ "name": "Jane" }))
+            .unwrap();
+        assert_eq!(message.subject, "Welcome Jane");
+        assert_eq!(message.html.as_deref(), Some("<p>Hello Jane</p>"));
+        assert_eq!(message.text, None);
+
+        assert!(templates.render("missing", "jane@example.com", &json!({})).is_err());
+    }
+}
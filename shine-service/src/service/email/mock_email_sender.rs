@@ -0,0 +1,64 @@
+use crate::service::email::{EmailError, EmailMessage, EmailSender};
+use async_trait::async_trait;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Default)]
+struct MockState {
+    sent: Vec<EmailMessage>,
+    failures: VecDeque<EmailError>,
+}
+
+/// Record the sent messages instead of delivering them, for the tests and the local development.
+#[derive(Clone, Default)]
+pub struct MockEmailSender {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockEmailSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail the next send with the given error.
+    pub fn fail_next(&self, error: EmailError) {
+        self.state.lock().unwrap().failures.push_back(error);
+    }
+
+    /// The successfully sent messages.
+    pub fn sent(&self) -> Vec<EmailMessage> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    /// The last message sent to the given address.
+    pub fn last_to(&self, address: &str) -> Option<EmailMessage> {
+        let state = self.state.lock().unwrap();
+        state
+            .sent
+            .iter()
+            .rev()
+            .find(|message| message.to.iter().any(|to| to == address))
+            .cloned()
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.sent.clear();
+        state.failures.clear();
+    }
+}
+
+#[async_trait]
+impl EmailSender for MockEmailSender {
+    async fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(err) = state.failures.pop_front() {
+            return Err(err);
+        }
+        log::info!("Mock email to {:?}: {}", message.to, message.subject);
+        state.sent.push(message.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+mod email_sender;
+pub use self::email_sender::*;
+mod mock_email_sender;
+pub use self::mock_email_sender::*;
+#[cfg(feature = "html_template")]
+mod email_template;
+#[cfg(feature = "html_template")]
+pub use self::email_template::*;
+#[cfg(feature = "email_smtp")]
+mod smtp_email_sender;
+#[cfg(feature = "email_smtp")]
+pub use self::smtp_email_sender::*;
+#[cfg(feature = "email_acs")]
+mod acs_email_sender;
+#[cfg(feature = "email_acs")]
+pub use self::acs_email_sender::*;
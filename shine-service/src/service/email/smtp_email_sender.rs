@@ -0,0 +1,79 @@
+use crate::{
+    service::email::{EmailError, EmailMessage, EmailSender},
+    utils::Sensitive,
+};
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SmtpEmailConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub user: String,
+    pub password: Sensitive<String>,
+    /// Sender of the messages, ex. `Shine <no-reply@example.com>`.
+    pub from: String,
+}
+
+pub struct SmtpEmailSender {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpEmailSender {
+    pub fn new(config: &SmtpEmailConfig) -> Result<Self, EmailError> {
+        let from = config
+            .from
+            .parse::<Mailbox>()
+            .map_err(|err| EmailError::InvalidAddress(format!("{}: {err}", config.from)))?;
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .map_err(|err| EmailError::Transport(format!("{err}")))?
+            .credentials(Credentials::new(config.user.clone(), config.password.expose().clone()));
+        if let Some(port) = config.port {
+            transport = transport.port(port);
+        }
+
+        Ok(Self {
+            from,
+            transport: transport.build(),
+        })
+    }
+
+    fn build_message(&self, message: &EmailMessage) -> Result<Message, EmailError> {
+        let mut builder = Message::builder().from(self.from.clone()).subject(&message.subject);
+        for to in &message.to {
+            let to = to
+                .parse::<Mailbox>()
+                .map_err(|err| EmailError::InvalidAddress(format!("{to}: {err}")))?;
+            builder = builder.to(to);
+        }
+
+        let message = match (&message.html, &message.text) {
+            (Some(html), Some(text)) => {
+                builder.multipart(MultiPart::alternative_plain_html(text.clone(), html.clone()))
+            }
+            (Some(html), None) => builder.singlepart(SinglePart::html(html.clone())),
+            (None, Some(text)) => builder.header(ContentType::TEXT_PLAIN).body(text.clone()),
+            (None, None) => return Err(EmailError::InvalidMessage("Missing body".into())),
+        };
+        message.map_err(|err| EmailError::InvalidMessage(format!("{err}")))
+    }
+}
+
+#[async_trait]
+impl EmailSender for SmtpEmailSender {
+    async fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        let message = self.build_message(message)?;
+        match self.transport.send(message).await {
+            Ok(_) => Ok(()),
+            Err(err) if err.is_permanent() => Err(EmailError::Rejected(format!("{err}"))),
+            Err(err) => Err(EmailError::Transport(format!("{err}"))),
+        }
+    }
+}
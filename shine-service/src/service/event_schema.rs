@@ -0,0 +1,400 @@
+use crate::axum::ValidationSeverity;
+#[cfg(feature = "redis")]
+use crate::service::AdminCaller;
+#[cfg(feature = "redis")]
+use axum::{response::IntoResponse, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+#[cfg(feature = "redis")]
+use std::sync::Arc;
+use thiserror::Error as ThisError;
+use utoipa::{PartialSchema, ToSchema};
+
+const COMPONENT_REF_PREFIX: &str = "#/components/schemas/";
+const MAX_SCHEMA_DEPTH: usize = 32;
+
+#[derive(Debug, ThisError)]
+pub enum EventSchemaError {
+    #[error("Unknown event type: {0}")]
+    UnknownEventType(String),
+    #[error("Unknown version {1} of event type {0}")]
+    UnknownVersion(String, u32),
+    #[error("Failed to serialize the payload of {0}: {1}")]
+    Serialize(String, String),
+    #[error("Failed to parse the payload of {0}: {1}")]
+    Parse(String, String),
+    #[error("Invalid payload for {event_type}: {}", .errors.join(", "))]
+    InvalidPayload { event_type: String, errors: Vec<String> },
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventSchemaConfig {
+    /// Strictness of the validation of the outgoing events.
+    #[serde(default)]
+    pub on_publish: ValidationSeverity,
+    /// Strictness of the validation of the incoming events.
+    #[serde(default)]
+    pub on_consume: ValidationSeverity,
+}
+
+/// A registered version of an event type.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventSchemaVersion {
+    pub version: u32,
+    pub schema: JsonValue,
+}
+
+/// A known event type with all the registered versions.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventTypeInfo {
+    pub event_type: String,
+    pub versions: Vec<EventSchemaVersion>,
+}
+
+/// Registry of the versioned json schemas of the event payloads shared by the producer and consumer services.
+/// The schemas are derived from the `ToSchema` implementations and the payloads are checked against a
+/// lightweight subset of the json schema (type, enum, properties, required, additionalProperties, items,
+/// allOf/anyOf/oneOf and component references).
+pub struct EventSchemaRegistry {
+    config: EventSchemaConfig,
+    events: BTreeMap<String, BTreeMap<u32, JsonValue>>,
+    components: BTreeMap<String, JsonValue>,
+}
+
+impl EventSchemaRegistry {
+    pub fn new(config: EventSchemaConfig) -> Self {
+        Self {
+            config,
+            events: BTreeMap::new(),
+            components: BTreeMap::new(),
+        }
+    }
+
+    /// Register a version of an event type with the schema derived from the payload type.
+    #[must_use]
+    pub fn with_event<T: ToSchema>(mut self, event_type: &str, version: u32) -> Self {
+        let mut schemas = Vec::new();
+        T::schemas(&mut schemas);
+        for (name, schema) in schemas {
+            self.components
+                .insert(name, serde_json::to_value(schema).expect("Failed to serialize schema"));
+        }
+        let schema = serde_json::to_value(T::schema()).expect("Failed to serialize schema");
+        self.with_event_schema(event_type, version, schema)
+    }
+
+    /// Register a version of an event type with an explicit json schema.
+    #[must_use]
+    pub fn with_event_schema(mut self, event_type: &str, version: u32, schema: JsonValue) -> Self {
+        self.events
+            .entry(event_type.to_string())
+            .or_default()
+            .insert(version, schema);
+        self
+    }
+
+    pub fn config(&self) -> &EventSchemaConfig {
+        &self.config
+    }
+
+    /// The latest registered version of an event type.
+    pub fn latest_version(&self, event_type: &str) -> Option<u32> {
+        self.events
+            .get(event_type)
+            .and_then(|versions| versions.keys().next_back().copied())
+    }
+
+    pub fn events(&self) -> Vec<EventTypeInfo> {
+        self.events
+            .iter()
+            .map(|(event_type, versions)| EventTypeInfo {
+                event_type: event_type.clone(),
+                versions: versions
+                    .iter()
+                    .map(|(version, schema)| EventSchemaVersion {
+                        version: *version,
+                        schema: schema.clone(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Validate a payload against a version of an event type or against any of the registered versions.
+    pub fn validate(
+        &self,
+        event_type: &str,
+        version: Option<u32>,
+        payload: &JsonValue,
+    ) -> Result<(), EventSchemaError> {
+        let versions = self
+            .events
+            .get(event_type)
+            .ok_or_else(|| EventSchemaError::UnknownEventType(event_type.to_string()))?;
+
+        let mut errors = Vec::new();
+        match version {
+            Some(version) => {
+                let schema = versions
+                    .get(&version)
+                    .ok_or_else(|| EventSchemaError::UnknownVersion(event_type.to_string(), version))?;
+                self.check(schema, payload, "$", 0, &mut errors);
+            }
+            None => {
+                // accept the payload if any of the versions matches, report the errors of the latest one
+                for schema in versions.values().rev() {
+                    let mut version_errors = Vec::new();
+                    self.check(schema, payload, "$", 0, &mut version_errors);
+                    if version_errors.is_empty() {
+                        return Ok(());
+                    }
+                    if errors.is_empty() {
+                        errors = version_errors;
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(EventSchemaError::InvalidPayload {
+                event_type: event_type.to_string(),
+                errors,
+            })
+        }
+    }
+
+    fn validate_with_severity<F>(
+        &self,
+        severity: ValidationSeverity,
+        event_type: &str,
+        version: Option<u32>,
+        payload: F,
+    ) -> Result<(), EventSchemaError>
+    where
+        F: FnOnce() -> Result<JsonValue, EventSchemaError>,
+    {
+        if severity == ValidationSeverity::Ignore {
+            return Ok(());
+        }
+
+        let result = payload().and_then(|payload| self.validate(event_type, version, &payload));
+        match (result, severity) {
+            (Err(err), ValidationSeverity::Warn) => {
+                log::warn!("{err}");
+                Ok(())
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Validate an outgoing event against the latest version of its schema.
+    pub fn validate_publish<T: Serialize>(&self, event_type: &str, payload: &T) -> Result<(), EventSchemaError> {
+        let version = self.latest_version(event_type);
+        self.validate_with_severity(self.config.on_publish, event_type, version, || {
+            serde_json::to_value(payload)
+                .map_err(|err| EventSchemaError::Serialize(event_type.to_string(), format!("{err}")))
+        })
+    }
+
+    /// Validate an incoming event against any of the known versions of its schema. The payload is validated
+    /// as received, before it is deserialized, to detect the fields unknown to (or missed by) the producer.
+    pub fn validate_consume(&self, event_type: &str, payload: &[u8]) -> Result<(), EventSchemaError> {
+        self.validate_with_severity(self.config.on_consume, event_type, None, || {
+            serde_json::from_slice(payload)
+                .map_err(|err| EventSchemaError::Parse(event_type.to_string(), format!("{err}")))
+        })
+    }
+
+    fn check(&self, schema: &JsonValue, value: &JsonValue, path: &str, depth: usize, errors: &mut Vec<String>) {
+        if depth > MAX_SCHEMA_DEPTH {
+            return;
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(JsonValue::as_str) {
+            // unresolved references are not validated
+            if let Some(schema) = reference
+                .strip_prefix(COMPONENT_REF_PREFIX)
+                .and_then(|name| self.components.get(name))
+            {
+                self.check(schema, value, path, depth + 1, errors);
+            }
+            return;
+        }
+
+        if value.is_null() && schema.get("nullable").and_then(JsonValue::as_bool) == Some(true) {
+            return;
+        }
+
+        if let Some(items) = schema.get("allOf").and_then(JsonValue::as_array) {
+            for schema in items {
+                self.check(schema, value, path, depth + 1, errors);
+            }
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(items) = schema.get(key).and_then(JsonValue::as_array) {
+                let matches = items.iter().any(|schema| {
+                    let mut item_errors = Vec::new();
+                    self.check(schema, value, path, depth + 1, &mut item_errors);
+                    item_errors.is_empty()
+                });
+                if !matches {
+                    errors.push(format!("{path}: no matching variant"));
+                }
+            }
+        }
+
+        if let Some(ty) = schema.get("type") {
+            let types: Vec<&str> = match ty {
+                JsonValue::String(ty) => vec![ty.as_str()],
+                JsonValue::Array(types) => types.iter().filter_map(JsonValue::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !types.is_empty() && !types.iter().any(|ty| is_type_of(ty, value)) {
+                errors.push(format!("{path}: expected {}", types.join(" or ")));
+                return;
+            }
+        }
+
+        if let Some(values) = schema.get("enum").and_then(JsonValue::as_array) {
+            if !values.contains(value) {
+                errors.push(format!("{path}: value is not in the enum"));
+            }
+        }
+
+        if let Some(object) = value.as_object() {
+            let properties = schema.get("properties").and_then(JsonValue::as_object);
+            if let Some(required) = schema.get("required").and_then(JsonValue::as_array) {
+                for name in required.iter().filter_map(JsonValue::as_str) {
+                    if !object.contains_key(name) {
+                        errors.push(format!("{path}.{name}: missing required property"));
+                    }
+                }
+            }
+            for (name, value) in object {
+                let path = format!("{path}.{name}");
+                match (
+                    properties.and_then(|properties| properties.get(name)),
+                    schema.get("additionalProperties"),
+                ) {
+                    (Some(schema), _) => self.check(schema, value, &path, depth + 1, errors),
+                    (None, Some(JsonValue::Bool(false))) => errors.push(format!("{path}: unknown property")),
+                    (None, Some(schema @ JsonValue::Object(_))) => self.check(schema, value, &path, depth + 1, errors),
+                    (None, _) => {}
+                }
+            }
+        }
+
+        if let (Some(items), Some(schema)) = (value.as_array(), schema.get("items")) {
+            for (index, value) in items.iter().enumerate() {
+                self.check(schema, value, &format!("{path}[{index}]"), depth + 1, errors);
+            }
+        }
+    }
+
+    /// Create a router listing the known event types with their schemas, it is authorized by the `AdminCaller`,
+    /// see `AdminRouter::with_routes`.
+    #[cfg(feature = "redis")]
+    pub fn into_router<S>(self: Arc<Self>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let route = get(move |_: AdminCaller| async move { Json(self.events()).into_response() });
+
+        Router::new().route("/admin/events", route)
+    }
+}
+
+fn is_type_of(ty: &str, value: &JsonValue) -> bool {
+    match ty {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use shine_test::test;
+
+    #[derive(Serialize, ToSchema)]
+    #[serde(rename_all = "camelCase")]
+    struct Tag {
+        name: String,
+    }
+
+    #[derive(Serialize, ToSchema)]
+    #[serde(rename_all = "camelCase")]
+    struct UserCreated {
+        user_id: String,
+        age: Option<u32>,
+        tags: Vec<Tag>,
+    }
+
+    #[test]
+    fn validate_event_payloads() {
+        let registry = EventSchemaRegistry::new(EventSchemaConfig::default())
+            .with_event::<UserCreated>("user.created", 2)
+            .with_event_schema(
+                "user.created",
+                1,
+                json!({"type": "object", "required": ["id"], "properties": {"id": {"type": "string"}}}),
+            );
+        assert_eq!(registry.latest_version("user.created"), Some(2));
+
+        let event = UserCreated {
+            user_id: "u1".into(),
+            age: None,
+            tags: vec![Tag { name: "admin".into() }],
+        };
+        registry.validate_publish("user.created", &event).unwrap();
+        registry
+            .validate_consume("user.created", json!({"id": "u1"}).to_string().as_bytes())
+            .unwrap();
+
+        let invalid = json!({"userId": 1, "tags": [{"label": "x"}]});
+        match registry.validate("user.created", Some(2), &invalid) {
+            Err(EventSchemaError::InvalidPayload { errors, .. }) => {
+                assert!(errors.contains(&"$.userId: expected string".to_string()), "{errors:?}");
+                assert!(
+                    errors.contains(&"$.tags[0].name: missing required property".to_string()),
+                    "{errors:?}"
+                );
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+        assert!(registry
+            .validate_consume("user.created", invalid.to_string().as_bytes())
+            .is_err());
+        assert!(matches!(
+            registry.validate_consume("user.created", b"{\"id\":"),
+            Err(EventSchemaError::Parse(..))
+        ));
+        assert!(matches!(
+            registry.validate_publish("order.created", &event),
+            Err(EventSchemaError::UnknownEventType(_))
+        ));
+
+        let lenient = EventSchemaRegistry::new(EventSchemaConfig {
+            on_publish: ValidationSeverity::Warn,
+            on_consume: ValidationSeverity::Ignore,
+        })
+        .with_event::<UserCreated>("user.created", 1);
+        lenient.validate_publish("user.created", &invalid).unwrap();
+        lenient
+            .validate_consume("user.created", invalid.to_string().as_bytes())
+            .unwrap();
+    }
+}
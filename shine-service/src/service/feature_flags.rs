@@ -0,0 +1,364 @@
+use crate::{
+    service::{
+        CurrentUser, MemoryCache, RedisConnectionError, RedisConnectionPool, UncheckedCurrentUser,
+        UserSessionCacheReader,
+    },
+    utils::stable_hash,
+};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, Extension, RequestPartsExt};
+use futures::StreamExt;
+use redis::{AsyncCommands, RedisError};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, convert::Infallible, sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+use tokio::sync::broadcast;
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, ThisError)]
+pub enum FeatureFlagError {
+    #[error("Failed to get redis connection")]
+    RedisPoolError(#[source] RedisConnectionError),
+    #[error("Redis error")]
+    RedisError(#[from] RedisError),
+    #[error("Invalid rule for flag {0}")]
+    InvalidRule(String, #[source] serde_json::Error),
+}
+
+/// Definition of a flag in the code, the value used when no rule is stored for the flag.
+///
+/// ```ignore
+/// const NEW_CHECKOUT: FeatureFlag = FeatureFlag::new("newCheckout", false);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeatureFlag {
+    pub name: &'static str,
+    pub default: bool,
+}
+
+impl FeatureFlag {
+    pub const fn new(name: &'static str, default: bool) -> Self {
+        Self { name, default }
+    }
+}
+
+fn default_percentage() -> u8 {
+    100
+}
+
+/// Dynamic rule of a flag stored in redis. When the flag is enabled, it is on for the listed users and roles
+/// and for the given percentage of the other users.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FlagRule {
+    pub enabled: bool,
+    #[serde(default)]
+    pub users: Vec<Uuid>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Rollout percentage (0-100), the users are assigned to the rollout by a hash of the user id and
+    /// the flag name, thus a user gets the same result in all the services. The anonymous users get the
+    /// flag only at 100%.
+    #[serde(default = "default_percentage")]
+    pub percentage: u8,
+}
+
+impl FlagRule {
+    pub fn evaluate(&self, flag: &str, context: &FlagContext) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.percentage >= 100 {
+            return true;
+        }
+        let Some(user_id) = context.user_id else {
+            return false;
+        };
+        if self.users.contains(&user_id) || context.roles.iter().any(|role| self.roles.contains(role)) {
+            return true;
+        }
+        let bucket = stable_hash(format!("{flag}:{user_id}").as_bytes()) % 100;
+        bucket < u64::from(self.percentage)
+    }
+}
+
+/// The subject of the flag evaluation.
+#[derive(Clone, Debug, Default)]
+pub struct FlagContext {
+    pub user_id: Option<Uuid>,
+    pub roles: Vec<String>,
+}
+
+impl FlagContext {
+    pub fn anonymous() -> Self {
+        Self::default()
+    }
+
+    pub fn for_user(user: &CurrentUser) -> Self {
+        Self {
+            user_id: Some(user.user_id),
+            roles: user.roles.clone(),
+        }
+    }
+}
+
+type FlagRules = Arc<HashMap<String, FlagRule>>;
+
+/// Rules of the flags stored in a redis hash. The rules are cached locally and the cache is invalidated
+/// through a pub/sub channel when a rule is updated, the name of the changed flag is also notified to the
+/// local subscribers.
+pub struct FeatureFlagStore {
+    key: String,
+    channel: String,
+    redis: RedisConnectionPool,
+    cache: MemoryCache<(), FlagRules>,
+    changes: broadcast::Sender<String>,
+}
+
+impl FeatureFlagStore {
+    pub fn new(key_prefix: &str, redis: RedisConnectionPool) -> Self {
+        let (changes, _) = broadcast::channel(16);
+        Self {
+            key: format!("{key_prefix}feature_flags"),
+            channel: format!("{key_prefix}feature_flags:changed"),
+            redis,
+            cache: MemoryCache::new("feature_flags", 1).with_ttl(DEFAULT_CACHE_TTL),
+            changes,
+        }
+    }
+
+    /// Maximum age of the cached rules, it limits the delay of a change if a notification is lost.
+    #[must_use]
+    pub fn with_cache_ttl(self, cache_ttl: Duration) -> Self {
+        Self {
+            cache: MemoryCache::new("feature_flags", 1).with_ttl(cache_ttl),
+            ..self
+        }
+    }
+
+    /// The local cache of the rules, ex. to expose its statistics.
+    pub fn cache(&self) -> &MemoryCache<(), FlagRules> {
+        &self.cache
+    }
+
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    /// Drop the cached rules, they are reloaded from redis on the next access.
+    pub fn invalidate(&self) {
+        self.cache.clear();
+    }
+
+    /// Receive the name of the changed flags.
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.changes.subscribe()
+    }
+
+    pub async fn rules(&self) -> Result<FlagRules, FeatureFlagError> {
+        if let Some(rules) = self.cache.get(&()) {
+            return Ok(rules);
+        }
+
+        let mut client = self.redis.get().await.map_err(FeatureFlagError::RedisPoolError)?;
+        let values: HashMap<String, String> = client.hgetall(&self.key).await?;
+        let mut rules = HashMap::with_capacity(values.len());
+        for (name, value) in values {
+            // a broken rule should not disable the other flags
+            match serde_json::from_str::<FlagRule>(&value) {
+                Ok(rule) => {
+                    rules.insert(name, rule);
+                }
+                Err(err) => log::error!("{:?}", FeatureFlagError::InvalidRule(name, err)),
+            }
+        }
+
+        let rules = Arc::new(rules);
+        self.cache.insert((), rules.clone());
+        Ok(rules)
+    }
+
+    /// Evaluate a flag, the default of the flag is used if the rules are not available.
+    pub async fn is_enabled(&self, flag: &FeatureFlag, context: &FlagContext) -> bool {
+        match self.rules().await {
+            Ok(rules) => evaluate(&rules, flag, context),
+            Err(err) => {
+                log::warn!("Failed to load feature flags, using the defaults: {err:?}");
+                flag.default
+            }
+        }
+    }
+
+    /// Set the rule of a flag in all the services.
+    pub async fn set_rule(&self, flag: &str, rule: &FlagRule) -> Result<(), FeatureFlagError> {
+        let value = serde_json::to_string(rule).map_err(|err| FeatureFlagError::InvalidRule(flag.to_string(), err))?;
+        let mut client = self.redis.get().await.map_err(FeatureFlagError::RedisPoolError)?;
+        log::info!("Setting feature flag {flag}: {value}");
+        let _: () = redis::pipe()
+            .hset(&self.key, flag, value)
+            .publish(&self.channel, flag)
+            .query_async(&mut *client)
+            .await?;
+        self.changed(flag);
+        Ok(())
+    }
+
+    /// Remove the rule of a flag, the default of the flag is used in all the services.
+    pub async fn remove_rule(&self, flag: &str) -> Result<(), FeatureFlagError> {
+        let mut client = self.redis.get().await.map_err(FeatureFlagError::RedisPoolError)?;
+        log::info!("Removing feature flag {flag}");
+        let _: () = redis::pipe()
+            .hdel(&self.key, flag)
+            .publish(&self.channel, flag)
+            .query_async(&mut *client)
+            .await?;
+        self.changed(flag);
+        Ok(())
+    }
+
+    fn changed(&self, flag: &str) {
+        self.invalidate();
+        // it is not an error if there are no subscribers
+        let _ = self.changes.send(flag.to_string());
+    }
+
+    /// Subscribe to the rule changes to invalidate the cache immediately.
+    pub async fn start_listener(self: &Arc<Self>, redis_cns: &str) -> Result<(), RedisError> {
+        let client = redis::Client::open(redis_cns)?;
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(&self.channel).await?;
+
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut messages = pubsub.into_on_message();
+            while let Some(message) = messages.next().await {
+                let flag: String = message.get_payload().unwrap_or_default();
+                log::info!("Feature flag {flag} changed");
+                store.changed(&flag);
+            }
+            log::warn!("Feature flag listener stopped");
+        });
+        Ok(())
+    }
+}
+
+fn evaluate(rules: &HashMap<String, FlagRule>, flag: &FeatureFlag, context: &FlagContext) -> bool {
+    rules
+        .get(flag.name)
+        .map(|rule| rule.evaluate(flag.name, context))
+        .unwrap_or(flag.default)
+}
+
+/// Extractor of the flags evaluated for the current user (or anonymous). If the rules cannot be loaded,
+/// the defaults of the flags are used.
+pub struct Flags {
+    rules: FlagRules,
+    context: FlagContext,
+}
+
+impl Flags {
+    pub fn is_enabled(&self, flag: &FeatureFlag) -> bool {
+        evaluate(&self.rules, flag, &self.context)
+    }
+
+    pub fn context(&self) -> &FlagContext {
+        &self.context
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Flags
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(store) = parts
+            .extract::<Extension<Arc<FeatureFlagStore>>>()
+            .await
+            .expect("Missing FeatureFlagStore extension");
+
+        // the flags are not a security boundary, the user of the cookie is used without refreshing the session
+        let has_session = parts.extensions.get::<Arc<UserSessionCacheReader>>().is_some();
+        let context = if has_session {
+            parts
+                .extract::<UncheckedCurrentUser>()
+                .await
+                .map(|user| FlagContext::for_user(&user))
+                .unwrap_or_default()
+        } else {
+            FlagContext::anonymous()
+        };
+
+        let rules = store.rules().await.unwrap_or_else(|err| {
+            log::warn!("Failed to load feature flags, using the defaults: {err:?}");
+            Arc::new(HashMap::new())
+        });
+        Ok(Self { rules, context })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn evaluate_rules() {
+        let flag = FeatureFlag::new("newCheckout", false);
+        let user = FlagContext {
+            user_id: Some(Uuid::new_v4()),
+            roles: vec!["Beta".into()],
+        };
+
+        let mut rules = HashMap::new();
+        assert!(!evaluate(&rules, &flag, &user));
+        assert!(evaluate(&rules, &FeatureFlag::new("other", true), &user));
+
+        let rule: FlagRule = serde_json::from_str(r#"{ "enabled": true }"#).unwrap();
+        rules.insert(flag.name.to_string(), rule);
+        assert!(evaluate(&rules, &flag, &user));
+        assert!(evaluate(&rules, &flag, &FlagContext::anonymous()));
+
+        let rule = FlagRule {
+            enabled: true,
+            users: Vec::new(),
+            roles: vec!["Beta".into()],
+            percentage: 0,
+        };
+        assert!(rule.evaluate(flag.name, &user));
+        assert!(!rule.evaluate(flag.name, &FlagContext::anonymous()));
+        assert!(!FlagRule { enabled: false, ..rule }.evaluate(flag.name, &user));
+    }
+
+    #[test]
+    fn percentage_rollout() {
+        let rule = FlagRule {
+            enabled: true,
+            users: Vec::new(),
+            roles: Vec::new(),
+            percentage: 30,
+        };
+        let enabled = (0..1000)
+            .map(|_| FlagContext {
+                user_id: Some(Uuid::new_v4()),
+                roles: Vec::new(),
+            })
+            .filter(|context| rule.evaluate("flag", context))
+            .count();
+        assert!((200..400).contains(&enabled), "enabled: {enabled}");
+
+        // the result is stable for a user
+        let context = FlagContext {
+            user_id: Some(Uuid::new_v4()),
+            roles: Vec::new(),
+        };
+        let first = rule.evaluate("flag", &context);
+        assert!((0..10).all(|_| rule.evaluate("flag", &context) == first));
+    }
+}
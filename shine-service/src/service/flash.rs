@@ -0,0 +1,142 @@
+use crate::{
+    axum::{ConfiguredProblem, ProblemConfig},
+    service::{session_flash_key, UncheckedCurrentUser, UserSessionCacheReader, UserSessionError},
+};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, Extension, RequestPartsExt};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+
+/// Lifetime of the messages not consumed by a request.
+const FLASH_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FlashLevel {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlashMessage {
+    #[serde(rename = "l")]
+    pub level: FlashLevel,
+    #[serde(rename = "m")]
+    pub message: String,
+}
+
+/// One-shot messages of the session stored in redis next to the session data. Messages added in a request are
+/// available on the next request of the session, they are consumed (deleted) when the `Flash` is extracted.
+/// Without a session no messages are available and the added messages are dropped.
+pub struct Flash {
+    reader: Arc<UserSessionCacheReader>,
+    key: Option<String>,
+    messages: Vec<FlashMessage>,
+}
+
+impl Flash {
+    /// Messages added by the previous request.
+    pub fn messages(&self) -> &[FlashMessage] {
+        &self.messages
+    }
+
+    pub fn take_messages(&mut self) -> Vec<FlashMessage> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// Add a message for the next request.
+    pub async fn add<S: ToString>(&self, level: FlashLevel, message: S) -> Result<(), UserSessionError> {
+        let Some(key) = &self.key else {
+            log::debug!("Flash message dropped without a session");
+            return Ok(());
+        };
+
+        let message = FlashMessage {
+            level,
+            message: message.to_string(),
+        };
+        let message = serde_json::to_string(&message).expect("Flash message serialization failed");
+        let mut client = self
+            .reader
+            .redis
+            .get()
+            .await
+            .map_err(UserSessionError::RedisPoolError)?;
+        let _: () = redis::pipe()
+            .atomic()
+            .rpush(key, message)
+            .ignore()
+            .expire(key, FLASH_TTL.as_secs() as i64)
+            .ignore()
+            .query_async(&mut *client)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn add_info<S: ToString>(&self, message: S) -> Result<(), UserSessionError> {
+        self.add(FlashLevel::Info, message).await
+    }
+
+    pub async fn add_success<S: ToString>(&self, message: S) -> Result<(), UserSessionError> {
+        self.add(FlashLevel::Success, message).await
+    }
+
+    pub async fn add_warning<S: ToString>(&self, message: S) -> Result<(), UserSessionError> {
+        self.add(FlashLevel::Warning, message).await
+    }
+
+    pub async fn add_error<S: ToString>(&self, message: S) -> Result<(), UserSessionError> {
+        self.add(FlashLevel::Error, message).await
+    }
+}
+
+/// Consume the messages of the session, the malformed messages are skipped.
+async fn consume(reader: &UserSessionCacheReader, key: &str) -> Result<Vec<FlashMessage>, UserSessionError> {
+    let mut client = reader.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+    let (messages,): (Vec<String>,) = redis::pipe()
+        .atomic()
+        .lrange(key, 0, -1)
+        .del(key)
+        .ignore()
+        .query_async(&mut *client)
+        .await?;
+    Ok(messages
+        .iter()
+        .filter_map(|message| serde_json::from_str(message).ok())
+        .collect())
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Flash
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<UserSessionError>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(reader) = parts
+            .extract::<Extension<Arc<UserSessionCacheReader>>>()
+            .await
+            .expect("Missing UserSessionCacheReader extension");
+
+        let key = parts
+            .extract::<UncheckedCurrentUser>()
+            .await
+            .ok()
+            .map(|user| session_flash_key(&reader.key_prefix, &user.user_id, &user.key_hash()));
+        let messages = match &key {
+            Some(key) => consume(&reader, key)
+                .await
+                .map_err(|err| problem_config.configure(err))?,
+            None => Vec::new(),
+        };
+
+        Ok(Self { reader, key, messages })
+    }
+}
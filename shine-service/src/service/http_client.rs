@@ -0,0 +1,327 @@
+use crate::{
+    axum::telemetry::{TraceContext, TRACING_TARGET},
+    service::{
+        cacerts::{get_root_cert_store, CertError},
+        CircuitBreaker, CircuitBreakerConfig, EgressConfig, EgressError, EgressGuard,
+    },
+    utils::DurationStr,
+};
+use opentelemetry::metrics::Meter;
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    redirect, Client, IntoUrl, Method, Request, RequestBuilder, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error as ThisError;
+use tracing::{field::Empty, info_span, Instrument, Span};
+
+fn default_connect_timeout() -> DurationStr {
+    DurationStr::from_secs(5)
+}
+
+fn default_request_timeout() -> DurationStr {
+    DurationStr::from_secs(30)
+}
+
+fn default_max_retries() -> usize {
+    2
+}
+
+fn default_retry_backoff() -> DurationStr {
+    DurationStr::from_millis(200)
+}
+
+#[derive(Debug, ThisError)]
+pub enum HttpClientError {
+    #[error(transparent)]
+    CertError(#[from] CertError),
+    #[error("Failed to create http client")]
+    ClientBuild(#[source] reqwest::Error),
+    #[error("Circuit is open for host {0}")]
+    CircuitOpen(String),
+    #[error(transparent)]
+    Egress(#[from] EgressError),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpClientConfig {
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: DurationStr,
+    /// Timeout of a single attempt, including the read of the response body.
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: DurationStr,
+    /// Number of the retries of the idempotent requests on connection errors, timeouts and
+    /// `429`, `502`, `503`, `504` responses.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: usize,
+    /// Delay before the first retry, doubled for each further retry.
+    #[serde(default = "default_retry_backoff")]
+    pub retry_backoff: DurationStr,
+    /// Circuit breaker of the hosts, the connection errors and the `5xx` responses are failures.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Restrict the destinations of the calls, including the redirects. A host is connected only through
+    /// the addresses passing the checks.
+    #[serde(default)]
+    pub egress: Option<EgressConfig>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: default_connect_timeout(),
+            request_timeout: default_request_timeout(),
+            max_retries: default_max_retries(),
+            retry_backoff: default_retry_backoff(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            egress: None,
+        }
+    }
+}
+
+const MAX_REDIRECTS: usize = 10;
+
+fn guarded_redirect(guard: EgressGuard) -> redirect::Policy {
+    redirect::Policy::custom(move |attempt| {
+        if let Err(err) = guard.check_url(attempt.url()) {
+            attempt.error(err)
+        } else if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.stop()
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+fn is_retryable(result: &Result<Response, reqwest::Error>) -> bool {
+    match result {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(err) => err.is_connect() || err.is_timeout(),
+    }
+}
+
+/// Client of the outgoing http calls. The calls are traced (and the trace context is propagated
+/// in the W3C headers), the idempotent requests are retried and the failing hosts are isolated
+/// by a circuit breaker. The client is cheap to clone.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    max_retries: usize,
+    retry_backoff: Duration,
+    circuit_breaker: CircuitBreakerConfig,
+    circuits: Arc<Mutex<HashMap<String, Arc<CircuitBreaker>>>>,
+    meter: Option<Meter>,
+    egress: Option<EgressGuard>,
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpClientConfig) -> Result<Self, HttpClientError> {
+        let certs = get_root_cert_store()?;
+        let tls_config = rustls::ClientConfig::builder()
+            .with_root_certificates(certs)
+            .with_no_client_auth();
+        let mut builder = Client::builder()
+            .use_preconfigured_tls(tls_config)
+            .connect_timeout(config.connect_timeout.into())
+            .timeout(config.request_timeout.into());
+        let egress = config.egress.as_ref().map(EgressGuard::new);
+        if let Some(guard) = &egress {
+            builder = builder
+                .dns_resolver(Arc::new(guard.resolver()))
+                .redirect(guarded_redirect(guard.clone()));
+        }
+        let client = builder.build().map_err(HttpClientError::ClientBuild)?;
+
+        Ok(Self {
+            client,
+            max_retries: config.max_retries,
+            retry_backoff: config.retry_backoff.into(),
+            circuit_breaker: config.circuit_breaker.clone(),
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+            meter: None,
+            egress,
+        })
+    }
+
+    /// Record the state changes of the circuit breakers.
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            meter: Some(meter.clone()),
+            ..self
+        }
+    }
+
+    /// The circuit breaker of a host, ex. to report it on the status dashboard.
+    pub fn circuit(&self, host: &str) -> Arc<CircuitBreaker> {
+        let mut circuits = self.circuits.lock().unwrap();
+        circuits
+            .entry(host.to_string())
+            .or_insert_with(|| {
+                let circuit = CircuitBreaker::new(&format!("http:{host}"), &self.circuit_breaker);
+                let circuit = match &self.meter {
+                    Some(meter) => circuit.with_meter(meter),
+                    None => circuit,
+                };
+                Arc::new(circuit)
+            })
+            .clone()
+    }
+
+    /// The underlying client, the calls sent directly are neither traced nor retried and the urls with an ip
+    /// address host are not checked by the egress guard.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Start building a request, send it with [`HttpClient::send`].
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, HttpClientError> {
+        self.execute(request.build()?).await
+    }
+
+    pub async fn execute(&self, request: Request) -> Result<Response, HttpClientError> {
+        if let Some(guard) = &self.egress {
+            guard.check_url(request.url())?;
+        }
+
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        let method = request.method().clone();
+        // the query may contain secrets, leave it out from the span
+        let mut url = request.url().clone();
+        url.set_query(None);
+
+        let span = info_span!(
+            target: TRACING_TARGET,
+            "HTTP client request",
+            http.request.method = %method,
+            url.full = %url,
+            server.address = %host,
+            http.response.status_code = Empty, // set on response
+            http.request.resend_count = Empty, // set on retry
+            otel.name = format!("{method} {host}"),
+            otel.kind = ?opentelemetry::trace::SpanKind::Client,
+            otel.status_code = Empty, // set on response
+            exception.message = Empty, // set on error
+        );
+
+        self.execute_with_retry(request, &host, &span)
+            .instrument(span.clone())
+            .await
+    }
+
+    async fn execute_with_retry(
+        &self,
+        mut request: Request,
+        host: &str,
+        span: &Span,
+    ) -> Result<Response, HttpClientError> {
+        if let Some(context) = TraceContext::from_span(span) {
+            for (key, value) in context.iter() {
+                if let (Ok(key), Ok(value)) = (HeaderName::try_from(key), HeaderValue::try_from(value)) {
+                    request.headers_mut().insert(key, value);
+                }
+            }
+        }
+
+        let circuit = self.circuit(host);
+        let retryable = is_idempotent(request.method());
+        let mut retries = 0;
+        let mut delay = self.retry_backoff;
+        loop {
+            let Some(permit) = circuit.try_acquire() else {
+                let err = HttpClientError::CircuitOpen(host.to_string());
+                span.record("otel.status_code", "ERROR");
+                span.record("exception.message", err.to_string());
+                return Err(err);
+            };
+
+            // a request with a streaming body cannot be cloned, it is sent only once
+            let next = (retryable && retries < self.max_retries)
+                .then(|| request.try_clone())
+                .flatten();
+            let result = self.client.execute(request).await;
+
+            let success = match &result {
+                Ok(response) => !response.status().is_server_error(),
+                Err(_) => false,
+            };
+            permit.record(success);
+
+            match next {
+                Some(next) if is_retryable(&result) => {
+                    retries += 1;
+                    span.record("http.request.resend_count", retries);
+                    log::debug!(
+                        "Retrying the call to {host} in {delay:?} ({retries}/{})",
+                        self.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    request = next;
+                }
+                _ => {
+                    match &result {
+                        Ok(response) => {
+                            span.record("http.response.status_code", response.status().as_u16());
+                            if !success {
+                                span.record("otel.status_code", "ERROR");
+                            }
+                        }
+                        Err(err) => {
+                            span.record("otel.status_code", "ERROR");
+                            span.record("exception.message", err.to_string());
+                        }
+                    }
+                    return Ok(result?);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn retry_only_idempotent() {
+        assert!(is_idempotent(&Method::GET));
+        assert!(is_idempotent(&Method::PUT));
+        assert!(!is_idempotent(&Method::POST));
+        assert!(!is_idempotent(&Method::PATCH));
+    }
+}
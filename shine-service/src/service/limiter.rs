@@ -0,0 +1,245 @@
+use crate::service::{RedisConnectionError, RedisConnectionPool};
+use async_trait::async_trait;
+use redis::Script;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+
+#[derive(Debug, ThisError)]
+pub enum LimiterError {
+    #[error("Failed to get redis connection")]
+    RedisPoolError(#[source] RedisConnectionError),
+    #[error("Redis error")]
+    RedisError(#[from] redis::RedisError),
+    #[error("Invalid limiter configuration: {0}")]
+    InvalidConfig(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenBucketConfig {
+    /// Maximum number of tokens, the size of the allowed burst.
+    pub capacity: u32,
+    /// Number of tokens added per second.
+    pub refill_per_second: f64,
+}
+
+impl TokenBucketConfig {
+    /// Check if the bucket can be refilled, it requires a positive capacity and refill rate.
+    pub fn validate(&self) -> Result<(), LimiterError> {
+        if self.capacity == 0 {
+            return Err(LimiterError::InvalidConfig("capacity must be positive".into()));
+        }
+        if !(self.refill_per_second.is_finite() && self.refill_per_second > 0.0) {
+            return Err(LimiterError::InvalidConfig(format!(
+                "refillPerSecond must be a positive number, got {}",
+                self.refill_per_second
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LimiterDecision {
+    pub allowed: bool,
+    /// Remaining (whole) tokens after the request.
+    pub remaining: u32,
+    /// Time until the request could be allowed, None if it was allowed.
+    pub retry_after: Option<Duration>,
+}
+
+impl LimiterDecision {
+    fn new(config: &TokenBucketConfig, allowed: bool, tokens: f64, cost: u32) -> Self {
+        let retry_after = if allowed {
+            None
+        } else {
+            let missing = (cost as f64 - tokens).max(0.0);
+            Some(Duration::try_from_secs_f64(missing / config.refill_per_second).unwrap_or(Duration::MAX))
+        };
+        Self {
+            allowed,
+            remaining: tokens.floor().max(0.0) as u32,
+            retry_after,
+        }
+    }
+}
+
+/// Rate limiting of arbitrary actions identified by a key.
+#[async_trait]
+pub trait Limiter: Send + Sync {
+    /// Try to consume `cost` tokens from the bucket of the key.
+    async fn try_acquire(&self, key: &str, cost: u32) -> Result<LimiterDecision, LimiterError>;
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn take(&mut self, config: &TokenBucketConfig, now: Instant, cost: u32) -> LimiterDecision {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.refill_per_second).min(config.capacity as f64);
+        self.updated = now;
+
+        let allowed = self.tokens >= cost as f64;
+        if allowed {
+            self.tokens -= cost as f64;
+        }
+        LimiterDecision::new(config, allowed, self.tokens, cost)
+    }
+}
+
+/// Token bucket limiter for a single instance.
+pub struct MemoryLimiter {
+    config: TokenBucketConfig,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl MemoryLimiter {
+    pub fn new(config: TokenBucketConfig) -> Result<Self, LimiterError> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Remove the full buckets to release memory.
+    pub fn cleanup(&self) {
+        let now = Instant::now();
+        let config = &self.config;
+        self.buckets.lock().unwrap().retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * config.refill_per_second < config.capacity as f64
+        });
+    }
+}
+
+#[async_trait]
+impl Limiter for MemoryLimiter {
+    async fn try_acquire(&self, key: &str, cost: u32) -> Result<LimiterDecision, LimiterError> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.config.capacity as f64,
+            updated: now,
+        });
+        Ok(bucket.take(&self.config, now, cost))
+    }
+}
+
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local cost = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) + tonumber(time[2]) / 1000000
+local data = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(data[1]) or capacity
+local ts = tonumber(data[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+local allowed = 0
+if tokens >= cost then
+    tokens = tokens - cost
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', tostring(now))
+redis.call('EXPIRE', KEYS[1], math.ceil(capacity / rate) + 1)
+return { allowed, tostring(tokens) }
+"#;
+
+/// Token bucket limiter shared by all the instances through redis.
+pub struct RedisLimiter {
+    config: TokenBucketConfig,
+    key_prefix: String,
+    script: Script,
+    redis: RedisConnectionPool,
+}
+
+impl RedisLimiter {
+    pub fn new(config: TokenBucketConfig, key_prefix: &str, redis: RedisConnectionPool) -> Result<Self, LimiterError> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            key_prefix: key_prefix.to_string(),
+            script: Script::new(TOKEN_BUCKET_SCRIPT),
+            redis,
+        })
+    }
+}
+
+#[async_trait]
+impl Limiter for RedisLimiter {
+    async fn try_acquire(&self, key: &str, cost: u32) -> Result<LimiterDecision, LimiterError> {
+        let mut client = self.redis.get().await.map_err(LimiterError::RedisPoolError)?;
+        let (allowed, tokens): (i32, String) = self
+            .script
+            .key(format!("{}limiter:{}", self.key_prefix, key))
+            .arg(self.config.capacity)
+            .arg(self.config.refill_per_second)
+            .arg(cost)
+            .invoke_async(&mut *client)
+            .await?;
+        let tokens = tokens.parse::<f64>().unwrap_or(0.0);
+        Ok(LimiterDecision::new(&self.config, allowed == 1, tokens, cost))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn token_bucket() {
+        let config = TokenBucketConfig {
+            capacity: 3,
+            refill_per_second: 1.0,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 3.0,
+            updated: start,
+        };
+
+        assert!(bucket.take(&config, start, 2).allowed);
+        let decision = bucket.take(&config, start, 2);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 1);
+        assert_eq!(decision.retry_after, Some(Duration::from_secs(1)));
+
+        let decision = bucket.take(&config, start + Duration::from_secs(1), 2);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+
+        // refill is capped by the capacity
+        let decision = bucket.take(&config, start + Duration::from_secs(100), 0);
+        assert_eq!(decision.remaining, 3);
+    }
+
+    #[test]
+    fn invalid_config() {
+        for (capacity, refill_per_second) in [(0, 1.0), (3, 0.0), (3, -1.0), (3, f64::NAN), (3, f64::INFINITY)] {
+            let config = TokenBucketConfig {
+                capacity,
+                refill_per_second,
+            };
+            assert!(
+                matches!(MemoryLimiter::new(config), Err(LimiterError::InvalidConfig(_))),
+                "{capacity}, {refill_per_second}"
+            );
+        }
+        assert!(MemoryLimiter::new(TokenBucketConfig {
+            capacity: 3,
+            refill_per_second: 0.5,
+        })
+        .is_ok());
+    }
+}
@@ -0,0 +1,363 @@
+#[cfg(all(feature = "redis", feature = "openapi"))]
+use crate::service::AdminCaller;
+#[cfg(all(feature = "redis", feature = "openapi"))]
+use axum::{routing::get, Json, Router};
+use opentelemetry::{
+    metrics::{Counter, Meter},
+    KeyValue,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> u64 + Send + Sync>;
+
+struct Entry<V> {
+    value: V,
+    weight: u64,
+    inserted: Instant,
+    accessed: Instant,
+    tick: u64,
+}
+
+struct Store<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Access order of the keys, the least recently used is the first.
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    weight: u64,
+}
+
+#[derive(Clone)]
+struct CacheMeters {
+    hit: Counter<u64>,
+    miss: Counter<u64>,
+    eviction: Counter<u64>,
+}
+
+/// Statistics of a cache, it does not contain any key or value, thus it can be exposed safely.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryCacheStats {
+    pub name: String,
+    pub entries: usize,
+    pub weight: u64,
+    pub max_weight: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// In-memory cache with time to live, time to idle and weighted capacity. When the capacity is exceeded
+/// the least recently used entries are evicted.
+pub struct MemoryCache<K, V> {
+    name: String,
+    max_weight: u64,
+    ttl: Option<Duration>,
+    tti: Option<Duration>,
+    weigher: Weigher<K, V>,
+    meters: Option<CacheMeters>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    store: Mutex<Store<K, V>>,
+}
+
+impl<K, V> MemoryCache<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    /// Create a cache holding at most `max_weight` entries. Use `with_weigher` to limit by size instead.
+    pub fn new(name: &str, max_weight: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            max_weight,
+            ttl: None,
+            tti: None,
+            weigher: Arc::new(|_, _| 1),
+            meters: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            store: Mutex::new(Store {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+                weight: 0,
+            }),
+        }
+    }
+
+    /// Expire the entries after the given time from the insertion.
+    #[must_use]
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self { ttl: Some(ttl), ..self }
+    }
+
+    /// Expire the entries after the given time from the last access.
+    #[must_use]
+    pub fn with_tti(self, tti: Duration) -> Self {
+        Self { tti: Some(tti), ..self }
+    }
+
+    /// Set the function calculating the weight of an entry.
+    #[must_use]
+    pub fn with_weigher<F>(self, weigher: F) -> Self
+    where
+        F: Fn(&K, &V) -> u64 + Send + Sync + 'static,
+    {
+        Self {
+            weigher: Arc::new(weigher),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            meters: Some(CacheMeters {
+                hit: meter.u64_counter("memory_cache_hit").init(),
+                miss: meter.u64_counter("memory_cache_miss").init(),
+                eviction: meter.u64_counter("memory_cache_eviction").init(),
+            }),
+            ..self
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_expired(&self, entry: &Entry<V>, now: Instant) -> bool {
+        self.ttl.is_some_and(|ttl| now.duration_since(entry.inserted) >= ttl)
+            || self.tti.is_some_and(|tti| now.duration_since(entry.accessed) >= tti)
+    }
+
+    fn remove_entry(store: &mut Store<K, V>, key: &K) -> Option<Entry<V>> {
+        let entry = store.entries.remove(key)?;
+        store.order.remove(&entry.tick);
+        store.weight -= entry.weight;
+        Some(entry)
+    }
+
+    fn record(&self, counter: &AtomicU64, meter: impl Fn(&CacheMeters) -> &Counter<u64>, count: u64) {
+        if count == 0 {
+            return;
+        }
+        counter.fetch_add(count, Ordering::Relaxed);
+        if let Some(meters) = &self.meters {
+            meter(meters).add(count, &[KeyValue::new("cache", self.name.clone())]);
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let now = Instant::now();
+        let mut store = self.store.lock().unwrap();
+
+        let expired = match store.entries.get(key) {
+            Some(entry) => self.is_expired(entry, now),
+            None => {
+                drop(store);
+                self.record(&self.misses, |m| &m.miss, 1);
+                return None;
+            }
+        };
+        if expired {
+            Self::remove_entry(&mut store, key);
+            drop(store);
+            self.record(&self.evictions, |m| &m.eviction, 1);
+            self.record(&self.misses, |m| &m.miss, 1);
+            return None;
+        }
+
+        store.tick += 1;
+        let tick = store.tick;
+        let entry = store.entries.get_mut(key).unwrap();
+        let old_tick = entry.tick;
+        entry.tick = tick;
+        entry.accessed = now;
+        let value = entry.value.clone();
+        store.order.remove(&old_tick);
+        store.order.insert(tick, key.clone());
+        drop(store);
+
+        self.record(&self.hits, |m| &m.hit, 1);
+        Some(value)
+    }
+
+    /// Insert an entry. Entries heavier than the capacity are not stored.
+    pub fn insert(&self, key: K, value: V) {
+        let weight = (self.weigher)(&key, &value);
+        let now = Instant::now();
+        let mut store = self.store.lock().unwrap();
+
+        Self::remove_entry(&mut store, &key);
+        if weight > self.max_weight {
+            return;
+        }
+
+        let mut evicted = 0;
+        while store.weight + weight > self.max_weight {
+            let Some((_, lru)) = store.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = store.entries.remove(&lru) {
+                store.weight -= entry.weight;
+                evicted += 1;
+            }
+        }
+
+        store.tick += 1;
+        let tick = store.tick;
+        store.order.insert(tick, key.clone());
+        store.weight += weight;
+        store.entries.insert(
+            key,
+            Entry {
+                value,
+                weight,
+                inserted: now,
+                accessed: now,
+                tick,
+            },
+        );
+        drop(store);
+
+        self.record(&self.evictions, |m| &m.eviction, evicted);
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut store = self.store.lock().unwrap();
+        Self::remove_entry(&mut store, key).map(|entry| entry.value)
+    }
+
+    pub fn clear(&self) {
+        let mut store = self.store.lock().unwrap();
+        store.entries.clear();
+        store.order.clear();
+        store.weight = 0;
+    }
+
+    /// Remove the expired entries. The expired entries are also removed lazily on access.
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        let mut store = self.store.lock().unwrap();
+        let expired: Vec<K> = store
+            .entries
+            .iter()
+            .filter(|(_, entry)| self.is_expired(entry, now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            Self::remove_entry(&mut store, key);
+        }
+        drop(store);
+
+        self.record(&self.evictions, |m| &m.eviction, expired.len() as u64);
+    }
+
+    pub fn stats(&self) -> MemoryCacheStats {
+        let store = self.store.lock().unwrap();
+        MemoryCacheStats {
+            name: self.name.clone(),
+            entries: store.entries.len(),
+            weight: store.weight,
+            max_weight: self.max_weight,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Type erased access to the statistics of the caches for the inspection endpoint.
+pub trait CacheInspect: Send + Sync {
+    fn stats(&self) -> MemoryCacheStats;
+}
+
+impl<K, V> CacheInspect for MemoryCache<K, V>
+where
+    K: Clone + Eq + Hash + Send,
+    V: Clone + Send,
+{
+    fn stats(&self) -> MemoryCacheStats {
+        MemoryCache::stats(self)
+    }
+}
+
+/// Create the admin route listing the statistics of the caches, it is authorized by the `AdminCaller`, see
+/// `AdminRouter::with_routes`.
+///  - GET /admin/caches
+#[cfg(all(feature = "redis", feature = "openapi"))]
+pub fn memory_cache_router<S>(caches: Vec<Arc<dyn CacheInspect>>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let route = get(move |_: AdminCaller| async move {
+        let stats: Vec<_> = caches.iter().map(|cache| cache.stats()).collect();
+        Json(stats)
+    });
+
+    Router::new().route("/admin/caches", route)
+}
+
+impl<K, V> std::fmt::Debug for MemoryCache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryCache")
+            .field("name", &self.name)
+            .field("max_weight", &self.max_weight)
+            .field("ttl", &self.ttl)
+            .field("tti", &self.tti)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let cache = MemoryCache::<u32, String>::new("test", 2);
+        cache.insert(1, "one".into());
+        cache.insert(2, "two".into());
+        assert_eq!(cache.get(&1).as_deref(), Some("one"));
+        cache.insert(3, "three".into());
+
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1).as_deref(), Some("one"));
+        assert_eq!(cache.get(&3).as_deref(), Some("three"));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.evictions), (2, 1));
+    }
+
+    #[test]
+    fn weighted_capacity() {
+        let cache = MemoryCache::<u32, String>::new("test", 10).with_weigher(|_, v| v.len() as u64);
+        cache.insert(1, "12345".into());
+        cache.insert(2, "1234".into());
+        cache.insert(3, "123".into());
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.stats().weight, 7);
+
+        cache.insert(4, "12345678901".into());
+        assert_eq!(cache.get(&4), None);
+    }
+
+    #[test]
+    fn expire_by_ttl() {
+        let cache = MemoryCache::<u32, u32>::new("test", 10).with_ttl(Duration::ZERO);
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -1,14 +1,125 @@
 mod core_config;
 pub use self::core_config::*;
+mod config_trace;
+pub use self::config_trace::*;
+mod cookie_config;
+pub use self::cookie_config::*;
+mod cookie_codec;
+pub use self::cookie_codec::*;
+#[cfg(feature = "redis")]
+mod cookie_overflow;
+#[cfg(feature = "redis")]
+pub use self::cookie_overflow::*;
 mod session_key;
 pub use self::session_key::*;
+#[cfg(feature = "redis")]
 mod user_session;
+#[cfg(feature = "redis")]
 pub use self::user_session::*;
+#[cfg(feature = "redis")]
+mod session_epoch;
+#[cfg(feature = "redis")]
+pub use self::session_epoch::*;
+#[cfg(feature = "redis")]
+mod session_inspector;
+#[cfg(feature = "redis")]
+pub use self::session_inspector::*;
+#[cfg(feature = "redis")]
+mod session_manager;
+#[cfg(feature = "redis")]
+pub use self::session_manager::*;
+#[cfg(feature = "redis")]
+mod proxy_identity;
+#[cfg(feature = "redis")]
+pub use self::proxy_identity::*;
+#[cfg(feature = "redis")]
+mod csrf;
+#[cfg(feature = "redis")]
+pub use self::csrf::*;
+#[cfg(feature = "redis")]
+mod device_code;
+#[cfg(feature = "redis")]
+pub use self::device_code::*;
+#[cfg(feature = "redis")]
+mod flash;
+#[cfg(feature = "redis")]
+pub use self::flash::*;
+#[cfg(feature = "redis")]
+mod feature_flags;
+#[cfg(feature = "redis")]
+pub use self::feature_flags::*;
+mod replica_affinity;
+pub use self::replica_affinity::*;
 mod client_fingerprint;
 pub use self::client_fingerprint::*;
+mod tenant;
+pub use self::tenant::*;
+mod egress_guard;
+pub use self::egress_guard::*;
+#[cfg(feature = "redis")]
 mod redis;
+#[cfg(feature = "redis")]
 pub use self::redis::*;
+#[cfg(feature = "redis")]
+mod redis_scan;
+#[cfg(feature = "redis")]
+pub use self::redis_scan::*;
+#[cfg(feature = "redis")]
+mod redis_migration;
+#[cfg(feature = "redis")]
+pub use self::redis_migration::*;
+#[cfg(feature = "openapi")]
+mod event_schema;
+#[cfg(feature = "openapi")]
+pub use self::event_schema::*;
+#[cfg(feature = "redis")]
+mod redis_stream;
+#[cfg(feature = "redis")]
+pub use self::redis_stream::*;
+#[cfg(feature = "redis")]
+mod redis_stream_dead_letter;
+#[cfg(feature = "redis")]
+pub use self::redis_stream_dead_letter::*;
+#[cfg(feature = "redis")]
+mod redis_consumer_group;
+#[cfg(feature = "redis")]
+pub use self::redis_consumer_group::*;
+mod memory_cache;
+pub use self::memory_cache::*;
+#[cfg(feature = "redis")]
+mod limiter;
+#[cfg(feature = "redis")]
+pub use self::limiter::*;
+#[cfg(feature = "redis")]
+mod replica_registry;
+#[cfg(feature = "redis")]
+pub use self::replica_registry::*;
+#[cfg(feature = "redis")]
+mod cluster;
+#[cfg(feature = "redis")]
+pub use self::cluster::*;
+#[cfg(feature = "redis")]
+mod scheduler;
+#[cfg(feature = "redis")]
+pub use self::scheduler::*;
+mod startup;
+pub use self::startup::*;
+mod dependency_status;
+pub use self::dependency_status::*;
+mod circuit_breaker;
+pub use self::circuit_breaker::*;
+#[cfg(feature = "postgres")]
 mod postgres;
+#[cfg(feature = "postgres")]
 pub use self::postgres::*;
+#[cfg(all(feature = "redis", feature = "openapi"))]
+mod admin_router;
+#[cfg(all(feature = "redis", feature = "openapi"))]
+pub use self::admin_router::*;
+mod service_builder;
+pub use self::service_builder::*;
 
 pub mod cacerts;
+pub mod email;
+#[cfg(feature = "http_client")]
+pub mod http_client;
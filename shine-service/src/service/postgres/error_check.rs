@@ -1,7 +1,65 @@
+use crate::axum::{ErrorCategory, Problem, ProblemConfig, ServiceError};
+use axum::http::StatusCode;
 use tokio_postgres::error::SqlState;
 
+/// Classification of the database errors that are usually handled by the services.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PGErrorKind {
+    UniqueViolation {
+        constraint: Option<String>,
+    },
+    ForeignKeyViolation {
+        constraint: Option<String>,
+    },
+    CheckViolation {
+        constraint: Option<String>,
+    },
+    NotNullViolation {
+        column: Option<String>,
+    },
+    SerializationFailure,
+    Deadlock,
+    /// Any other database error with the SQLSTATE code
+    Database(String),
+    /// Not a database error (connection, conversion, etc.)
+    Other,
+}
+
+impl PGErrorKind {
+    /// Label of the kind for the metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PGErrorKind::UniqueViolation { .. } => "unique_violation",
+            PGErrorKind::ForeignKeyViolation { .. } => "foreign_key_violation",
+            PGErrorKind::CheckViolation { .. } => "check_violation",
+            PGErrorKind::NotNullViolation { .. } => "not_null_violation",
+            PGErrorKind::SerializationFailure => "serialization_failure",
+            PGErrorKind::Deadlock => "deadlock",
+            PGErrorKind::Database(_) => "database",
+            PGErrorKind::Other => "other",
+        }
+    }
+}
+
 pub trait PGErrorChecks {
     fn is_constraint(&self, table: &str, constraint: &str) -> bool;
+
+    /// Check if the error is a unique violation of the given constraint.
+    fn is_unique_violation(&self, constraint: &str) -> bool;
+
+    /// Check if the error is a foreign key violation of the given constraint.
+    fn is_foreign_key_violation(&self, constraint: &str) -> bool;
+
+    /// Check if the error is a check violation of the given constraint.
+    fn is_check_violation(&self, constraint: &str) -> bool;
+
+    /// Check if the transaction failed due to a serialization failure.
+    fn is_serialization_failure(&self) -> bool;
+
+    /// Check if the transaction failed due to a serialization failure or a deadlock and it could be retried.
+    fn is_transaction_conflict(&self) -> bool;
+
+    fn kind(&self) -> PGErrorKind;
 }
 
 impl PGErrorChecks for tokio_postgres::Error {
@@ -30,4 +88,120 @@ impl PGErrorChecks for tokio_postgres::Error {
         }
         false
     }
+
+    fn is_unique_violation(&self, constraint: &str) -> bool {
+        self.as_db_error()
+            .is_some_and(|err| &SqlState::UNIQUE_VIOLATION == err.code() && err.constraint() == Some(constraint))
+    }
+
+    fn is_foreign_key_violation(&self, constraint: &str) -> bool {
+        self.as_db_error()
+            .is_some_and(|err| &SqlState::FOREIGN_KEY_VIOLATION == err.code() && err.constraint() == Some(constraint))
+    }
+
+    fn is_check_violation(&self, constraint: &str) -> bool {
+        self.as_db_error()
+            .is_some_and(|err| &SqlState::CHECK_VIOLATION == err.code() && err.constraint() == Some(constraint))
+    }
+
+    fn is_serialization_failure(&self) -> bool {
+        self.code() == Some(&SqlState::T_R_SERIALIZATION_FAILURE)
+    }
+
+    fn is_transaction_conflict(&self) -> bool {
+        self.code() == Some(&SqlState::T_R_SERIALIZATION_FAILURE)
+            || self.code() == Some(&SqlState::T_R_DEADLOCK_DETECTED)
+    }
+
+    fn kind(&self) -> PGErrorKind {
+        let err = match self.as_db_error() {
+            Some(err) => err,
+            None => return PGErrorKind::Other,
+        };
+
+        let code = err.code();
+        let constraint = err.constraint().map(|c| c.to_string());
+        if &SqlState::UNIQUE_VIOLATION == code {
+            PGErrorKind::UniqueViolation { constraint }
+        } else if &SqlState::FOREIGN_KEY_VIOLATION == code {
+            PGErrorKind::ForeignKeyViolation { constraint }
+        } else if &SqlState::CHECK_VIOLATION == code {
+            PGErrorKind::CheckViolation { constraint }
+        } else if &SqlState::NOT_NULL_VIOLATION == code {
+            PGErrorKind::NotNullViolation {
+                column: err.column().map(|c| c.to_string()),
+            }
+        } else if &SqlState::T_R_SERIALIZATION_FAILURE == code {
+            PGErrorKind::SerializationFailure
+        } else if &SqlState::T_R_DEADLOCK_DETECTED == code {
+            PGErrorKind::Deadlock
+        } else {
+            PGErrorKind::Database(code.code().to_string())
+        }
+    }
+}
+
+/// The constraint violations are user errors, the transaction conflicts and the connection failures are
+/// retryable dependency errors, anything else is internal.
+impl ServiceError for tokio_postgres::Error {
+    fn category(&self) -> ErrorCategory {
+        match self.kind() {
+            PGErrorKind::UniqueViolation { .. }
+            | PGErrorKind::ForeignKeyViolation { .. }
+            | PGErrorKind::CheckViolation { .. }
+            | PGErrorKind::NotNullViolation { .. } => ErrorCategory::User,
+            PGErrorKind::SerializationFailure | PGErrorKind::Deadlock => ErrorCategory::Dependency,
+            PGErrorKind::Database(code) if is_unavailable_class(&code) => ErrorCategory::Dependency,
+            PGErrorKind::Database(_) => ErrorCategory::Internal,
+            PGErrorKind::Other if self.is_closed() => ErrorCategory::Dependency,
+            PGErrorKind::Other => ErrorCategory::Internal,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self.kind() {
+            PGErrorKind::SerializationFailure | PGErrorKind::Deadlock => true,
+            PGErrorKind::Database(code) => is_unavailable_class(&code),
+            PGErrorKind::Other => self.is_closed(),
+            _ => false,
+        }
+    }
+
+    fn to_problem(&self, config: &ProblemConfig) -> Problem {
+        match self.kind() {
+            PGErrorKind::UniqueViolation { constraint } => Problem::conflict("constraint-violation")
+                .with_detail("The resource already exists")
+                .with_extension(config, constraint),
+            PGErrorKind::SerializationFailure | PGErrorKind::Deadlock => Problem::conflict("transaction-conflict"),
+            _ => match self.category() {
+                ErrorCategory::User => {
+                    Problem::bad_request("constraint-violation").with_extension(config, self.to_string())
+                }
+                ErrorCategory::Dependency => Problem::new(StatusCode::SERVICE_UNAVAILABLE, "dependency-unavailable")
+                    .with_detail("Database is unavailable")
+                    .with_extension(config, self.to_string()),
+                ErrorCategory::Internal => Problem::internal_error(config, "Database error", self),
+            },
+        }
+    }
+}
+
+/// Connection exception (08), insufficient resources (53) and operator intervention (57) classes.
+fn is_unavailable_class(code: &str) -> bool {
+    code.starts_with("08") || code.starts_with("53") || code.starts_with("57")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn unavailable_classes() {
+        assert!(is_unavailable_class(SqlState::CONNECTION_FAILURE.code()));
+        assert!(is_unavailable_class(SqlState::TOO_MANY_CONNECTIONS.code()));
+        assert!(is_unavailable_class(SqlState::ADMIN_SHUTDOWN.code()));
+        assert!(!is_unavailable_class(SqlState::UNIQUE_VIOLATION.code()));
+        assert!(!is_unavailable_class(SqlState::SYNTAX_ERROR.code()));
+    }
 }
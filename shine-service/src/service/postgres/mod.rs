@@ -1,12 +1,30 @@
 mod query_builder;
 
 pub use self::query_builder::*;
+pub use shine_macros::checked_sql;
 mod error_check;
 pub use self::error_check::*;
 mod pg_connection;
 pub use self::pg_connection::*;
 mod pg_type;
 pub use self::pg_type::*;
+mod pg_on_conflict;
+pub use self::pg_on_conflict::*;
+mod pg_tenant;
+pub use self::pg_tenant::*;
+#[cfg(feature = "sqlx")]
+mod sqlx_pool;
+#[cfg(feature = "sqlx")]
+pub use self::sqlx_pool::*;
+// the outbox relies on the redis leadership and sinks
+#[cfg(feature = "redis")]
+mod outbox;
+#[cfg(feature = "redis")]
+pub use self::outbox::*;
+#[cfg(feature = "redis")]
+mod outbox_dead_letter;
+#[cfg(feature = "redis")]
+pub use self::outbox_dead_letter::*;
 
 /// Create a prepared SQL statements
 #[macro_export]
@@ -70,7 +88,7 @@ macro_rules! pg_query {
                 T: $crate::service::PGRawConnection
             {
                 let statement = self.statement(client).await?;
-                let rows = client.query(&statement, &[$($pid,)*]).await?;
+                let rows = client.traced_query(stringify!($id), &statement, &[$($pid,)*]).await?;
 
                 rows.into_iter().map(|row| row.try_get(&stringify!($rid))).collect::<Result<Vec<_>,_>>()
             }
@@ -85,7 +103,7 @@ macro_rules! pg_query {
                 T: $crate::service::PGRawConnection
             {
                 let statement = self.statement(client).await?;
-                let row = client.query_one(&statement, &[$($pid,)*]).await?;
+                let row = client.traced_query_one(stringify!($id), &statement, &[$($pid,)*]).await?;
                 let value: $rty = row.try_get(&stringify!($rid))?;
                 Ok(value)
             }
@@ -100,7 +118,7 @@ macro_rules! pg_query {
                 T: $crate::service::PGRawConnection
             {
                 let statement = self.statement(client).await?;
-                client.query_opt(&statement, &[$($pid,)*])
+                client.traced_query_opt(stringify!($id), &statement, &[$($pid,)*])
                     .await?
                     .map(|r| r.try_get(&stringify!($rid)))
                     .transpose()
@@ -126,7 +144,7 @@ macro_rules! pg_query {
                 T: $crate::service::PGRawConnection
             {
                 let statement = self.statement(client).await?;
-                let rows = client.query(&statement, &[$($pid,)*]).await?;
+                let rows = client.traced_query(stringify!($id), &statement, &[$($pid,)*]).await?;
 
                 rows.into_iter()
                     .map(|row| <$oty as postgres_from_row::FromRow>::try_from_row(&row))
@@ -144,7 +162,7 @@ macro_rules! pg_query {
             {
                 let statement = self.statement(client).await?;
                 let row = client
-                    .query_one(&statement, &[$($pid,)*])
+                    .traced_query_one(stringify!($id), &statement, &[$($pid,)*])
                     .await?;
                 <$oty as postgres_from_row::FromRow>::try_from_row(&row)
             }
@@ -159,7 +177,7 @@ macro_rules! pg_query {
                 T: $crate::service::PGRawConnection
             {
                 let statement = self.statement(client).await?;
-                client.query_opt(&statement, &[$($pid,)*])
+                client.traced_query_opt(stringify!($id), &statement, &[$($pid,)*])
                     .await?
                     .map(|row| <$oty as postgres_from_row::FromRow>::try_from_row(&row) )
                     .transpose()
@@ -184,7 +202,7 @@ macro_rules! pg_query {
                 T: $crate::service::PGRawConnection
             {
                 let statement = self.statement(client).await?;
-                client.execute(&statement, &[$($pid,)*]).await
+                client.traced_execute(stringify!($id), &statement, &[$($pid,)*]).await
             }
         }
     };
@@ -0,0 +1,341 @@
+#[cfg(feature = "http_client")]
+use crate::service::http_client::HttpClient;
+use crate::{
+    axum::telemetry::TraceContext,
+    service::{Leadership, PGConnectionError, PGConnectionPool, PGError, PGTransaction, RedisConnectionPool},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::{error::Error as StdError, sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+use tokio::task::JoinHandle;
+use tracing::{info_span, Instrument};
+
+pub type OutboxSinkError = Box<dyn StdError + Send + Sync>;
+
+#[derive(Debug, ThisError)]
+pub enum OutboxError {
+    #[error("Failed to get postgres connection")]
+    PGPoolError(#[source] PGConnectionError),
+    #[error(transparent)]
+    PGError(#[from] PGError),
+    #[error("Failed to serialize event payload")]
+    SerializeError(#[from] serde_json::Error),
+}
+
+/// An event stored in the outbox table.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub topic: String,
+    pub payload: JsonValue,
+    pub created_at: DateTime<Utc>,
+    pub attempts: i32,
+    /// Trace context of the transaction storing the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_context: Option<TraceContext>,
+}
+
+/// Destination of the dispatched events. Events are delivered at least once, thus sinks
+/// (or their consumers) should be idempotent using the id of the event.
+#[async_trait]
+pub trait OutboxSink: Send + Sync {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), OutboxSinkError>;
+}
+
+/// Transactional outbox: events are stored in the same transaction as the business data
+/// and a dispatcher forwards them to the sink.
+#[derive(Clone, Debug)]
+pub struct Outbox {
+    pub(crate) table: String,
+    pub(crate) max_attempts: i32,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new("outbox")
+    }
+}
+
+impl Outbox {
+    pub fn new(table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            max_attempts: 10,
+        }
+    }
+
+    /// Events failing to be published this many times are moved to the dead-letters.
+    #[must_use]
+    pub fn with_max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1) as i32,
+            ..self
+        }
+    }
+
+    /// The schema of the outbox table to be included in the migrations of the service.
+    pub fn schema(&self) -> String {
+        format!(
+            r#"CREATE TABLE IF NOT EXISTS {table} (
+    id BIGSERIAL PRIMARY KEY,
+    topic TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    trace_context JSONB
+);
+CREATE INDEX IF NOT EXISTS {table}_pending_idx ON {table} (id) WHERE delivered_at IS NULL;"#,
+            table = self.table
+        )
+    }
+
+    /// Store an event in the outbox, it is dispatched only if the transaction is committed.
+    /// The trace context of the current span is stored to link the publishing to the trace.
+    pub async fn enqueue<T>(
+        &self,
+        transaction: &PGTransaction<'_>,
+        topic: &str,
+        payload: &T,
+    ) -> Result<i64, OutboxError>
+    where
+        T: Serialize,
+    {
+        let payload = serde_json::to_value(payload)?;
+        let trace_context = TraceContext::current().map(serde_json::to_value).transpose()?;
+        let sql = format!(
+            "INSERT INTO {} (topic, payload, trace_context) VALUES ($1, $2, $3) RETURNING id",
+            self.table
+        );
+        let row = transaction.query_one(&sql, &[&topic, &payload, &trace_context]).await?;
+        Ok(row.try_get("id")?)
+    }
+}
+
+/// Poll the outbox table and publish the pending events to the sink.
+pub struct OutboxDispatcher {
+    outbox: Outbox,
+    postgres: PGConnectionPool,
+    sink: Arc<dyn OutboxSink>,
+    batch_size: i64,
+    poll_interval: Duration,
+    leadership: Option<Leadership>,
+}
+
+impl OutboxDispatcher {
+    pub fn new<S>(outbox: Outbox, postgres: PGConnectionPool, sink: S) -> Self
+    where
+        S: OutboxSink + 'static,
+    {
+        Self {
+            outbox,
+            postgres,
+            sink: Arc::new(sink),
+            batch_size: 100,
+            poll_interval: Duration::from_secs(1),
+            leadership: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_batch_size(self, batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1) as i64,
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_poll_interval(self, poll_interval: Duration) -> Self {
+        Self { poll_interval, ..self }
+    }
+
+    /// Dispatch only on the leader replica, the events are published in order by a single replica.
+    #[must_use]
+    pub fn with_leadership(self, leadership: Leadership) -> Self {
+        Self {
+            leadership: Some(leadership),
+            ..self
+        }
+    }
+
+    /// Publish a batch of pending events and return the number of delivered events.
+    /// The rows are locked while publishing, thus multiple dispatchers can run in parallel, but then there is
+    /// no ordering guarantee: a dispatcher skips the events locked by the others and a failed event is retried
+    /// after the later events of the other dispatchers. Use a single dispatcher if the order matters.
+    pub async fn dispatch_once(&self) -> Result<usize, OutboxError> {
+        let table = &self.outbox.table;
+        let mut client = self.postgres.get().await.map_err(OutboxError::PGPoolError)?;
+        let transaction = client.transaction().await?;
+
+        let sql = format!(
+            "SELECT id, topic, payload, created_at, attempts, trace_context FROM {table} \
+             WHERE delivered_at IS NULL AND attempts < $2 ORDER BY id LIMIT $1 FOR UPDATE SKIP LOCKED"
+        );
+        let events = transaction
+            .query(&sql, &[&self.batch_size, &self.outbox.max_attempts])
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(OutboxEvent {
+                    id: row.try_get("id")?,
+                    topic: row.try_get("topic")?,
+                    payload: row.try_get("payload")?,
+                    created_at: row.try_get("created_at")?,
+                    attempts: row.try_get("attempts")?,
+                    trace_context: row
+                        .try_get::<_, Option<JsonValue>>("trace_context")?
+                        .and_then(|context| serde_json::from_value(context).ok()),
+                })
+            })
+            .collect::<Result<Vec<_>, PGError>>()?;
+
+        let delivered_sql = format!("UPDATE {table} SET delivered_at = now(), attempts = attempts + 1 WHERE id = $1");
+        let failed_sql = format!("UPDATE {table} SET attempts = attempts + 1, last_error = $2 WHERE id = $1");
+        let mut delivered = 0;
+        for event in &events {
+            // the publishing is traced as a new trace linked to the trace of the enqueuing transaction
+            let span = info_span!(
+                parent: None,
+                "outbox.publish",
+                messaging.destination.name = %event.topic,
+                messaging.message.id = event.id,
+            );
+            if let Some(trace_context) = &event.trace_context {
+                trace_context.link_to(&span);
+            }
+            match self.sink.publish(event).instrument(span).await {
+                Ok(()) => {
+                    transaction.execute(&delivered_sql, &[&event.id]).await?;
+                    delivered += 1;
+                }
+                Err(err) => {
+                    log::warn!("Failed to publish outbox event {} ({}): {err}", event.id, event.topic);
+                    transaction.execute(&failed_sql, &[&event.id, &err.to_string()]).await?;
+                    // keep the order of the batch, the rest is retried in the next round
+                    break;
+                }
+            }
+        }
+
+        transaction.commit().await?;
+        Ok(delivered)
+    }
+
+    /// Start polling the outbox in the background.
+    pub fn start(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Some(leadership) = &mut self.leadership {
+                    if !leadership.is_leader() {
+                        log::debug!("Outbox {} is dispatched by another replica", self.outbox.table);
+                        if !leadership.wait_for_leadership().await {
+                            log::warn!("Election {} stopped, outbox dispatch ends", leadership.name());
+                            break;
+                        }
+                    }
+                }
+
+                match self.dispatch_once().await {
+                    Ok(delivered) if delivered as i64 >= self.batch_size => continue,
+                    Ok(_) => {}
+                    Err(err) => log::error!("Outbox dispatch failed: {err:?}"),
+                }
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        })
+    }
+}
+
+/// Publish the events to a redis stream (XADD) named by the topic of the event.
+pub struct RedisStreamOutboxSink {
+    key_prefix: String,
+    redis: RedisConnectionPool,
+    max_len: Option<usize>,
+}
+
+impl RedisStreamOutboxSink {
+    pub fn new(key_prefix: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key_prefix: key_prefix.to_string(),
+            redis,
+            max_len: None,
+        }
+    }
+
+    /// Trim the streams approximately to the given length.
+    #[must_use]
+    pub fn with_max_len(self, max_len: usize) -> Self {
+        Self {
+            max_len: Some(max_len),
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl OutboxSink for RedisStreamOutboxSink {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), OutboxSinkError> {
+        let mut client = self.redis.get().await?;
+        let key = format!("{}{}", self.key_prefix, event.topic);
+
+        let mut cmd = redis::cmd("XADD");
+        cmd.arg(&key);
+        if let Some(max_len) = self.max_len {
+            cmd.arg("MAXLEN").arg("~").arg(max_len);
+        }
+        cmd.arg("*")
+            .arg("id")
+            .arg(event.id)
+            .arg("payload")
+            .arg(serde_json::to_string(&event.payload)?);
+        // forward the origin of the event, the consumers link their processing to it
+        if let Some(trace_context) = &event.trace_context {
+            cmd.arg("trace").arg(trace_context);
+        }
+        let _: String = cmd.query_async(&mut *client).await?;
+        Ok(())
+    }
+}
+
+/// Publish the events by posting them to a webhook. The destination is checked by the egress guard of the client.
+#[cfg(feature = "http_client")]
+pub struct WebhookOutboxSink {
+    url: String,
+    client: HttpClient,
+}
+
+#[cfg(feature = "http_client")]
+impl WebhookOutboxSink {
+    pub fn new(url: &str, client: HttpClient) -> Self {
+        Self {
+            url: url.to_string(),
+            client,
+        }
+    }
+}
+
+#[cfg(feature = "http_client")]
+#[async_trait]
+impl OutboxSink for WebhookOutboxSink {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), OutboxSinkError> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("content-type", "application/json")
+            .header("idempotency-key", event.id.to_string());
+        if let Some(traceparent) = event.trace_context.as_ref().and_then(|context| context.traceparent()) {
+            request = request.header("traceparent", traceparent);
+        }
+        self.client
+            .send(request.body(serde_json::to_vec(event)?))
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+#[cfg(feature = "openapi")]
+use crate::service::AdminCaller;
+use crate::{
+    axum::{IntoProblem, Problem, ProblemConfig},
+    service::{Outbox, OutboxError, PGConnectionPool, PGError},
+    utils::redact_json,
+};
+#[cfg(feature = "openapi")]
+use axum::{
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "openapi")]
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+#[cfg(feature = "openapi")]
+use std::sync::Arc;
+use tokio_postgres::Row;
+
+const MAX_LIST_LIMIT: i64 = 1000;
+
+impl IntoProblem for OutboxError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            OutboxError::PGPoolError(err) => Problem::internal_error(config, "Postgres connection error", err),
+            OutboxError::PGError(err) => Problem::internal_error(config, "Postgres error", err),
+            OutboxError::SerializeError(err) => Problem::internal_error(config, "Serialization error", err),
+        }
+    }
+}
+
+/// An event of the outbox that could not be delivered within the allowed number of attempts.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub id: i64,
+    pub topic: String,
+    pub created_at: DateTime<Utc>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    /// The payload with the sensitive fields redacted, present only when a single message is queried.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<JsonValue>,
+}
+
+impl DeadLetter {
+    fn from_row(row: &Row, with_payload: bool) -> Result<Self, PGError> {
+        let payload = if with_payload {
+            Some(redact_json(&row.try_get::<_, JsonValue>("payload")?))
+        } else {
+            None
+        };
+        Ok(Self {
+            id: row.try_get("id")?,
+            topic: row.try_get("topic")?,
+            created_at: row.try_get("created_at")?,
+            attempts: row.try_get("attempts")?,
+            last_error: row.try_get("last_error")?,
+            payload,
+        })
+    }
+}
+
+/// Inspect, requeue and purge the dead-lettered events of an outbox.
+pub struct OutboxDeadLetters {
+    outbox: Outbox,
+    postgres: PGConnectionPool,
+}
+
+impl OutboxDeadLetters {
+    pub fn new(outbox: Outbox, postgres: PGConnectionPool) -> Self {
+        Self { outbox, postgres }
+    }
+
+    /// List the dead-letters ordered by id, starting after the given id.
+    pub async fn list(&self, after: Option<i64>, limit: usize) -> Result<Vec<DeadLetter>, OutboxError> {
+        let client = self.postgres.get().await.map_err(OutboxError::PGPoolError)?;
+        let sql = format!(
+            "SELECT id, topic, created_at, attempts, last_error FROM {} \
+             WHERE delivered_at IS NULL AND attempts >= $1 AND id > $2 ORDER BY id LIMIT $3",
+            self.outbox.table
+        );
+        let limit = (limit as i64).clamp(1, MAX_LIST_LIMIT);
+        let rows = client
+            .query(&sql, &[&self.outbox.max_attempts, &after.unwrap_or(0), &limit])
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| DeadLetter::from_row(row, false))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Get a dead-letter with its (redacted) payload.
+    pub async fn find(&self, id: i64) -> Result<Option<DeadLetter>, OutboxError> {
+        let client = self.postgres.get().await.map_err(OutboxError::PGPoolError)?;
+        let sql = format!(
+            "SELECT id, topic, payload, created_at, attempts, last_error FROM {} \
+             WHERE delivered_at IS NULL AND attempts >= $1 AND id = $2",
+            self.outbox.table
+        );
+        let row = client.query_opt(&sql, &[&self.outbox.max_attempts, &id]).await?;
+        Ok(row.map(|row| DeadLetter::from_row(&row, true)).transpose()?)
+    }
+
+    /// Reset the attempts of the selected dead-letters to have them dispatched again.
+    pub async fn requeue(&self, ids: &[i64]) -> Result<u64, OutboxError> {
+        let client = self.postgres.get().await.map_err(OutboxError::PGPoolError)?;
+        let sql = format!(
+            "UPDATE {} SET attempts = 0, last_error = NULL \
+             WHERE delivered_at IS NULL AND attempts >= $1 AND id = ANY($2)",
+            self.outbox.table
+        );
+        Ok(client.execute(&sql, &[&self.outbox.max_attempts, &ids]).await?)
+    }
+
+    /// Delete the selected dead-letters, or all of them if no ids are given.
+    pub async fn purge(&self, ids: Option<&[i64]>) -> Result<u64, OutboxError> {
+        let client = self.postgres.get().await.map_err(OutboxError::PGPoolError)?;
+        let table = &self.outbox.table;
+        let count = match ids {
+            Some(ids) => {
+                let sql = format!("DELETE FROM {table} WHERE delivered_at IS NULL AND attempts >= $1 AND id = ANY($2)");
+                client.execute(&sql, &[&self.outbox.max_attempts, &ids]).await?
+            }
+            None => {
+                let sql = format!("DELETE FROM {table} WHERE delivered_at IS NULL AND attempts >= $1");
+                client.execute(&sql, &[&self.outbox.max_attempts]).await?
+            }
+        };
+        Ok(count)
+    }
+
+    /// Create the admin routes, they are authorized by the `AdminCaller`, see `AdminRouter::with_routes`.
+    /// Each action is logged with the `audit` target.
+    ///  - GET /admin/outbox/dead-letters?after=ID&limit=N
+    ///  - GET /admin/outbox/dead-letters/:id
+    ///  - POST /admin/outbox/dead-letters/requeue { ids }
+    ///  - POST /admin/outbox/dead-letters/purge { ids? }
+    #[cfg(feature = "openapi")]
+    pub fn into_router<S>(self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        #[derive(Deserialize)]
+        struct ListQuery {
+            after: Option<i64>,
+            limit: Option<usize>,
+        }
+
+        #[derive(Deserialize)]
+        struct SelectionRequest {
+            ids: Option<Vec<i64>>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ActionResponse {
+            affected: u64,
+        }
+
+        fn into_response<T: Serialize>(config: &ProblemConfig, result: Result<T, OutboxError>) -> Response {
+            match result {
+                Ok(value) => Json(value).into_response(),
+                Err(err) => err.into_problem(config).into_response(),
+            }
+        }
+
+        let dead_letters = Arc::new(self);
+
+        let list_route = {
+            let dead_letters = dead_letters.clone();
+            get(
+                move |Extension(config): Extension<ProblemConfig>,
+                      caller: AdminCaller,
+                      Query(query): Query<ListQuery>| async move {
+                    log::info!(target: "audit", "{caller:?} listed the outbox dead-letters");
+                    let limit = query.limit.unwrap_or(100);
+                    into_response(&config, dead_letters.list(query.after, limit).await)
+                },
+            )
+        };
+
+        let find_route = {
+            let dead_letters = dead_letters.clone();
+            get(
+                move |Extension(config): Extension<ProblemConfig>, caller: AdminCaller, Path(id): Path<i64>| async move {
+                    log::info!(target: "audit", "{caller:?} viewed the outbox dead-letter {id}");
+                    match dead_letters.find(id).await {
+                        Ok(Some(dead_letter)) => Json(dead_letter).into_response(),
+                        Ok(None) => Problem::not_found().into_response(),
+                        Err(err) => err.into_problem(&config).into_response(),
+                    }
+                },
+            )
+        };
+
+        let requeue_route = {
+            let dead_letters = dead_letters.clone();
+            post(
+                move |Extension(config): Extension<ProblemConfig>,
+                      caller: AdminCaller,
+                      Json(request): Json<SelectionRequest>| async move {
+                    let ids = request.ids.unwrap_or_default();
+                    log::info!(target: "audit", "{caller:?} requeued the outbox dead-letters {ids:?}");
+                    let result = dead_letters.requeue(&ids).await;
+                    into_response(&config, result.map(|affected| ActionResponse { affected }))
+                },
+            )
+        };
+
+        let purge_route = post(
+            move |Extension(config): Extension<ProblemConfig>,
+                  caller: AdminCaller,
+                  Json(request): Json<SelectionRequest>| async move {
+                log::info!(target: "audit", "{caller:?} purged the outbox dead-letters {:?}", request.ids);
+                let result = dead_letters.purge(request.ids.as_deref()).await;
+                into_response(&config, result.map(|affected| ActionResponse { affected }))
+            },
+        );
+
+        Router::new()
+            .route("/admin/outbox/dead-letters", list_route)
+            .route("/admin/outbox/dead-letters/:id", find_route)
+            .route("/admin/outbox/dead-letters/requeue", requeue_route)
+            .route("/admin/outbox/dead-letters/purge", purge_route)
+    }
+}
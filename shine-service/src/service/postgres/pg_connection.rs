@@ -1,15 +1,48 @@
-use crate::service::cacerts::{get_root_cert_store, CertError};
+use crate::{
+    service::{
+        cacerts::{get_root_cert_store, CertError},
+        PGErrorChecks,
+    },
+    utils::{find_error_source, DurationStr, Entropy, SystemEntropy},
+};
 use bb8::{ManageConnection, Pool as BB8Pool, PooledConnection, RunError};
 use bb8_postgres::PostgresConnectionManager;
+use futures::future::BoxFuture;
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::future::Future;
 use std::ops::Deref;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, ops::DerefMut};
 use thiserror::Error as ThisError;
 use tokio::sync::RwLock;
-use tokio_postgres::{Config as PGConfig, GenericClient, Statement};
+use tokio_postgres::{types::ToSql, Config as PGConfig, GenericClient, IsolationLevel, Row, Statement, ToStatement};
 use tokio_postgres_rustls::MakeRustlsConnect;
+use tracing::{debug_span, field::Empty, Instrument};
+
+/// Maximum number of attempts of a transaction in run_transaction.
+const MAX_TRANSACTION_ATTEMPTS: usize = 5;
+/// Upper bound of the delay before the first retry of a transaction, doubled for each further retry.
+const TRANSACTION_RETRY_BACKOFF: Duration = Duration::from_millis(20);
+
+/// Random delay before the retry of a conflicting transaction (full jitter), thus the conflicting
+/// transactions are not retried in lockstep.
+fn transaction_retry_delay(entropy: &dyn Entropy, attempt: usize) -> Duration {
+    let max_delay = TRANSACTION_RETRY_BACKOFF * (1 << attempt.saturating_sub(1).min(8)) as u32;
+    let mut raw = [0_u8; 4];
+    let ratio = match entropy.fill(&mut raw) {
+        Ok(()) => u32::from_le_bytes(raw) as f64 / u32::MAX as f64,
+        Err(_) => 1.0,
+    };
+    max_delay.mul_f64(ratio)
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PGStatementId(usize);
@@ -23,10 +56,187 @@ where
 {
     prepared_statements: Arc<RwLock<HashMap<usize, Statement>>>,
     prepared_statement_id: Arc<AtomicUsize>,
+    observer: Arc<PGQueryObserver>,
+    /// The session state (ex. `search_path`) was altered, the connection is dropped instead of being returned
+    /// to the pool until it is restored.
+    session_modified: bool,
     client: T,
 }
 
+/// Number of the rows returned or affected by a query.
+trait PGRowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl PGRowCount for Vec<Row> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl PGRowCount for Row {
+    fn row_count(&self) -> u64 {
+        1
+    }
+}
+
+impl PGRowCount for Option<Row> {
+    fn row_count(&self) -> u64 {
+        self.is_some() as u64
+    }
+}
+
+impl PGRowCount for u64 {
+    fn row_count(&self) -> u64 {
+        *self
+    }
+}
+
+/// Metrics and slow query detection of the traced queries shared by the connections of a pool.
+#[derive(Clone, Default)]
+struct PGQueryObserver {
+    query_duration: Option<Histogram<f64>>,
+    query_rows: Option<Histogram<u64>>,
+    query_errors: Option<Counter<u64>>,
+    slow_query_count: Option<Counter<u64>>,
+    slow_query_threshold: Option<Duration>,
+}
+
+impl PGQueryObserver {
+    fn observe(&self, operation: &'static str, name: &str, duration: Duration, rows: Result<u64, &PGError>) {
+        if let Some(query_duration) = &self.query_duration {
+            query_duration.record(
+                duration.as_secs_f64(),
+                &[
+                    KeyValue::new("db.operation", operation),
+                    KeyValue::new("db.statement", name.to_string()),
+                ],
+            );
+        }
+
+        match rows {
+            Ok(rows) => {
+                if let Some(query_rows) = &self.query_rows {
+                    query_rows.record(
+                        rows,
+                        &[
+                            KeyValue::new("db.operation", operation),
+                            KeyValue::new("db.statement", name.to_string()),
+                        ],
+                    );
+                }
+            }
+            Err(err) => {
+                if let Some(query_errors) = &self.query_errors {
+                    query_errors.add(
+                        1,
+                        &[
+                            KeyValue::new("db.statement", name.to_string()),
+                            KeyValue::new("error.type", err.kind().as_str()),
+                        ],
+                    );
+                }
+            }
+        }
+
+        if self.slow_query_threshold.is_some_and(|threshold| duration > threshold) {
+            let rows = rows.ok();
+            log::warn!(
+                target: "pg.slow_query",
+                "Slow query: statement: {name}, operation: {operation}, duration: {}ms, rows: {rows:?}",
+                duration.as_millis()
+            );
+            if let Some(slow_query_count) = &self.slow_query_count {
+                slow_query_count.add(1, &[KeyValue::new("db.statement", name.to_string())]);
+            }
+        }
+    }
+}
+
 impl<T: PGRawConnection> PGConnection<T> {
+    /// Run a query in a span carrying the name of the statement and record its duration.
+    async fn traced<F, R>(&self, operation: &'static str, name: &str, query: F) -> Result<R, PGError>
+    where
+        F: Future<Output = Result<R, PGError>>,
+        R: PGRowCount,
+    {
+        let span = debug_span!(
+            "pg.query",
+            db.system = "postgresql",
+            db.operation = operation,
+            db.statement = name,
+            db.duration_ms = Empty,
+            otel.status_code = Empty,
+        );
+
+        let start = Instant::now();
+        let result = query.instrument(span.clone()).await;
+        let duration = start.elapsed();
+
+        span.record("db.duration_ms", duration.as_secs_f64() * 1000.0);
+        if result.is_err() {
+            span.record("otel.status_code", "ERROR");
+        }
+        let rows = result.as_ref().map(PGRowCount::row_count);
+        self.observer.observe(operation, name, duration, rows);
+        result
+    }
+
+    /// Traced version of `query`, the name identifies the statement in the traces and metrics.
+    pub async fn traced_query<S>(
+        &self,
+        name: &str,
+        statement: &S,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, PGError>
+    where
+        S: ?Sized + ToStatement + Sync + Send,
+    {
+        self.traced("query", name, self.client.query(statement, params)).await
+    }
+
+    /// Traced version of `query_one`, the name identifies the statement in the traces and metrics.
+    pub async fn traced_query_one<S>(
+        &self,
+        name: &str,
+        statement: &S,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Row, PGError>
+    where
+        S: ?Sized + ToStatement + Sync + Send,
+    {
+        self.traced("query_one", name, self.client.query_one(statement, params))
+            .await
+    }
+
+    /// Traced version of `query_opt`, the name identifies the statement in the traces and metrics.
+    pub async fn traced_query_opt<S>(
+        &self,
+        name: &str,
+        statement: &S,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Option<Row>, PGError>
+    where
+        S: ?Sized + ToStatement + Sync + Send,
+    {
+        self.traced("query_opt", name, self.client.query_opt(statement, params))
+            .await
+    }
+
+    /// Traced version of `execute`, the name identifies the statement in the traces and metrics.
+    pub async fn traced_execute<S>(
+        &self,
+        name: &str,
+        statement: &S,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, PGError>
+    where
+        S: ?Sized + ToStatement + Sync + Send,
+    {
+        self.traced("execute", name, self.client.execute(statement, params))
+            .await
+    }
+
     #[inline]
     pub async fn create_statement(&self, prepared: Statement) -> PGStatementId {
         let id = self.prepared_statement_id.fetch_add(1, Ordering::Relaxed);
@@ -51,19 +261,90 @@ impl<T: PGRawConnection> PGConnection<T> {
         Ok(PGConnection {
             prepared_statements: self.prepared_statements.clone(),
             prepared_statement_id: self.prepared_statement_id.clone(),
+            observer: self.observer.clone(),
+            session_modified: false,
             client: self.client.transaction().await?,
         })
     }
 }
 
 impl PGConnection<PGRawClient> {
-    fn new(pg_client: PGRawClient, prepared_statement_id: Arc<AtomicUsize>) -> Self {
+    fn new(pg_client: PGRawClient, prepared_statement_id: Arc<AtomicUsize>, observer: Arc<PGQueryObserver>) -> Self {
         Self {
             client: pg_client,
             prepared_statement_id,
+            observer,
+            session_modified: false,
             prepared_statements: Arc::new(RwLock::new(HashMap::default())),
         }
     }
+
+    /// Mark the session state of the connection as altered (or restored), a pooled connection with an
+    /// altered session is considered broken and it is not reused.
+    pub(crate) fn set_session_modified(&mut self, modified: bool) {
+        self.session_modified = modified;
+    }
+
+    #[inline]
+    pub async fn transaction_with_isolation(
+        &mut self,
+        isolation: PGIsolationLevel,
+    ) -> Result<PGConnection<PGRawTransaction<'_>>, PGError> {
+        Ok(PGConnection {
+            prepared_statements: self.prepared_statements.clone(),
+            prepared_statement_id: self.prepared_statement_id.clone(),
+            observer: self.observer.clone(),
+            session_modified: false,
+            client: self
+                .client
+                .build_transaction()
+                .isolation_level(isolation)
+                .start()
+                .await?,
+        })
+    }
+
+    /// Run the action in a transaction with the given isolation level. The transaction is committed
+    /// if the action succeeds and rolled back otherwise. When the transaction fails due to a serialization
+    /// failure or a deadlock, the action is retried a bounded number of times after a randomized, exponentially
+    /// growing delay.
+    /// The postgres error is found through the source chain of the error, thus errors wrapping
+    /// a PGError should expose it as a `source` (and not as `transparent`).
+    pub async fn run_transaction<F, R, E>(&mut self, isolation: PGIsolationLevel, mut action: F) -> Result<R, E>
+    where
+        F: for<'c, 't> FnMut(&'c mut PGTransaction<'t>) -> BoxFuture<'c, Result<R, E>>,
+        E: StdError + From<PGError> + 'static,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+
+            let mut transaction = self.transaction_with_isolation(isolation).await?;
+            let result = match action(&mut transaction).await {
+                Ok(value) => transaction.commit().await.map(|_| value).map_err(E::from),
+                Err(err) => {
+                    if let Err(rollback_err) = transaction.rollback().await {
+                        log::warn!("Failed to rollback transaction: {rollback_err:?}");
+                    }
+                    Err(err)
+                }
+            };
+
+            match result {
+                Err(err)
+                    if attempt < MAX_TRANSACTION_ATTEMPTS
+                        && find_error_source::<PGError>(&err).is_some_and(|err| err.is_transaction_conflict()) =>
+                {
+                    let delay = transaction_retry_delay(&SystemEntropy::new(), attempt);
+                    log::info!(
+                        "Transaction conflict, retrying in {delay:?} ({attempt}/{MAX_TRANSACTION_ATTEMPTS}): {err}"
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
 }
 
 impl<'a> PGConnection<PGRawTransaction<'a>> {
@@ -95,6 +376,8 @@ impl<T: PGRawConnection> DerefMut for PGConnection<T> {
 pub struct PGConnectionManager {
     connection_manager: PostgresConnectionManager<MakeRustlsConnect>,
     prepared_statement_id: Arc<AtomicUsize>,
+    session_settings: Option<String>,
+    observer: PGQueryObserver,
 }
 
 impl PGConnectionManager {
@@ -102,6 +385,51 @@ impl PGConnectionManager {
         Self {
             connection_manager: PostgresConnectionManager::new(config, tls),
             prepared_statement_id: Arc::new(AtomicUsize::new(1)),
+            session_settings: None,
+            observer: PGQueryObserver::default(),
+        }
+    }
+
+    /// Record the duration and the row count of the traced queries in the `db.client.operation.duration`
+    /// and `db.client.response.returned_rows` histograms, count the failed queries by the kind of the error in
+    /// `db_query_error_count` and the slow queries in `db_slow_query_count`.
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            observer: PGQueryObserver {
+                query_duration: Some(
+                    meter
+                        .f64_histogram("db.client.operation.duration")
+                        .with_unit("s")
+                        .init(),
+                ),
+                query_rows: Some(meter.u64_histogram("db.client.response.returned_rows").init()),
+                query_errors: Some(meter.u64_counter("db_query_error_count").init()),
+                slow_query_count: Some(meter.u64_counter("db_slow_query_count").init()),
+                ..self.observer
+            },
+            ..self
+        }
+    }
+
+    /// Log the traced queries running longer than the threshold.
+    #[must_use]
+    pub fn with_slow_query_threshold(self, threshold: Duration) -> Self {
+        Self {
+            observer: PGQueryObserver {
+                slow_query_threshold: Some(threshold),
+                ..self.observer
+            },
+            ..self
+        }
+    }
+
+    /// Statements executed on each new connection, ex: `SET statement_timeout = 5000`.
+    #[must_use]
+    pub fn with_session_settings(self, session_settings: Option<String>) -> Self {
+        Self {
+            session_settings,
+            ..self
         }
     }
 }
@@ -112,7 +440,14 @@ impl bb8::ManageConnection for PGConnectionManager {
 
     async fn connect(&self) -> Result<Self::Connection, Self::Error> {
         let conn = self.connection_manager.connect().await?;
-        Ok(PGConnection::new(conn, self.prepared_statement_id.clone()))
+        if let Some(session_settings) = &self.session_settings {
+            conn.batch_execute(session_settings).await?;
+        }
+        Ok(PGConnection::new(
+            conn,
+            self.prepared_statement_id.clone(),
+            Arc::new(self.observer.clone()),
+        ))
     }
 
     async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
@@ -120,7 +455,7 @@ impl bb8::ManageConnection for PGConnectionManager {
     }
 
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
-        self.connection_manager.has_broken(&mut conn.client)
+        conn.session_modified || self.connection_manager.has_broken(&mut conn.client)
     }
 }
 
@@ -129,6 +464,7 @@ pub type PGConnectionPool = BB8Pool<PGConnectionManager>;
 pub type PGPooledConnection<'a> = PooledConnection<'a, PGConnectionManager>;
 pub type PGError = tokio_postgres::Error;
 pub type PGStatement = tokio_postgres::Statement;
+pub type PGIsolationLevel = IsolationLevel;
 
 pub type PGRawClient = tokio_postgres::Client;
 pub type PGRawTransaction<'a> = tokio_postgres::Transaction<'a>;
@@ -146,20 +482,161 @@ pub enum PGCreatePoolError {
     CertError(#[source] CertError),
 }
 
+fn default_max_size() -> u32 {
+    10
+}
+
+/// Settings of the connection pool and the session of the pooled connections.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PGPoolConfig {
+    /// Maximum number of the connections in the pool.
+    #[serde(default = "default_max_size")]
+    pub max_size: u32,
+    /// Name of the application shown in `pg_stat_activity`.
+    pub application_name: Option<String>,
+    /// Log the statements running longer than this duration as slow queries.
+    pub slow_query_threshold: Option<DurationStr>,
+    /// Abort the statements running longer than this duration.
+    pub statement_timeout: Option<DurationStr>,
+    /// Terminate the sessions idle within a transaction longer than this duration.
+    pub idle_in_transaction_session_timeout: Option<DurationStr>,
+}
+
+impl Default for PGPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_max_size(),
+            application_name: None,
+            slow_query_threshold: None,
+            statement_timeout: None,
+            idle_in_transaction_session_timeout: None,
+        }
+    }
+}
+
+impl PGPoolConfig {
+    /// Create the default configuration with the service name as the application name.
+    pub fn new(service_name: &str) -> Self {
+        Self {
+            application_name: Some(service_name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Use the service name as the application name if it is not set explicitly.
+    #[must_use]
+    pub fn with_default_application_name(self, service_name: &str) -> Self {
+        Self {
+            application_name: self.application_name.or_else(|| Some(service_name.to_string())),
+            ..self
+        }
+    }
+
+    pub(crate) fn session_settings(&self) -> Option<String> {
+        let mut settings = Vec::new();
+        if let Some(timeout) = self.statement_timeout {
+            settings.push(format!("SET statement_timeout = {}", timeout.as_duration().as_millis()));
+        }
+        if let Some(timeout) = self.idle_in_transaction_session_timeout {
+            settings.push(format!(
+                "SET idle_in_transaction_session_timeout = {}",
+                timeout.as_duration().as_millis()
+            ));
+        }
+        if settings.is_empty() {
+            None
+        } else {
+            Some(settings.join("; "))
+        }
+    }
+}
+
 pub async fn create_postgres_pool(cns: &str) -> Result<PGConnectionPool, PGCreatePoolError> {
+    create_postgres_pool_with_config(cns, &PGPoolConfig::default(), None).await
+}
+
+/// Create a pool with the given settings, the query durations are recorded if a meter is provided.
+pub async fn create_postgres_pool_with_config(
+    cns: &str,
+    config: &PGPoolConfig,
+    meter: Option<&Meter>,
+) -> Result<PGConnectionPool, PGCreatePoolError> {
     let certs = get_root_cert_store().map_err(PGCreatePoolError::CertError)?;
     let tls_config = rustls::ClientConfig::builder()
         .with_root_certificates(certs)
         .with_no_client_auth();
     let tls = MakeRustlsConnect::new(tls_config);
 
-    let pg_config = PGConfig::from_str(cns)?;
-    log::debug!("Postgresql config: {pg_config:#?}");
-    let postgres_manager = PGConnectionManager::new(pg_config, tls);
+    let mut pg_config = PGConfig::from_str(cns)?;
+    if let Some(application_name) = &config.application_name {
+        pg_config.application_name(application_name);
+    }
+    log::debug!(
+        "Postgresql config: hosts: {:?}, ports: {:?}, dbname: {:?}, user: {:?}",
+        pg_config.get_hosts(),
+        pg_config.get_ports(),
+        pg_config.get_dbname(),
+        pg_config.get_user()
+    );
+    let mut postgres_manager =
+        PGConnectionManager::new(pg_config, tls).with_session_settings(config.session_settings());
+    if let Some(meter) = meter {
+        postgres_manager = postgres_manager.with_meter(meter);
+    }
+    if let Some(threshold) = config.slow_query_threshold {
+        postgres_manager = postgres_manager.with_slow_query_threshold(threshold.into());
+    }
     let postgres = bb8::Pool::builder()
-        .max_size(10) // Set the maximum number of connections in the pool
+        .max_size(config.max_size)
         .build(postgres_manager)
         .await?;
 
-    Ok(postgres) 
- }
+    Ok(postgres)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::SeededEntropy;
+    use shine_test::test;
+
+    #[test]
+    fn row_count() {
+        assert_eq!(PGRowCount::row_count(&7_u64), 7);
+        assert_eq!(PGRowCount::row_count(&Vec::<Row>::new()), 0);
+        assert_eq!(PGRowCount::row_count(&Option::<Row>::None), 0);
+    }
+
+    #[test]
+    fn transaction_retry_backoff() {
+        let entropy = SeededEntropy::new(7);
+        for attempt in 1..MAX_TRANSACTION_ATTEMPTS {
+            let max_delay = TRANSACTION_RETRY_BACKOFF * (1 << (attempt - 1));
+            let delays = (0..16)
+                .map(|_| transaction_retry_delay(&entropy, attempt))
+                .collect::<Vec<_>>();
+            assert!(delays.iter().all(|delay| *delay <= max_delay), "{attempt}: {delays:?}");
+            assert!(delays.iter().any(|delay| *delay != delays[0]), "{attempt}: {delays:?}");
+        }
+    }
+
+    #[test]
+    fn pool_session_settings() {
+        assert_eq!(PGPoolConfig::default().session_settings(), None);
+
+        let config = PGPoolConfig {
+            statement_timeout: Some(DurationStr::from_secs(5)),
+            idle_in_transaction_session_timeout: Some(DurationStr::from_secs(60)),
+            ..PGPoolConfig::new("identity")
+        };
+        assert_eq!(config.application_name.as_deref(), Some("identity"));
+        assert_eq!(
+            config.session_settings().as_deref(),
+            Some("SET statement_timeout = 5000; SET idle_in_transaction_session_timeout = 60000")
+        );
+
+        let config = PGPoolConfig::default().with_default_application_name("builder");
+        assert_eq!(config.application_name.as_deref(), Some("builder"));
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+/// The conflict resolution clause of an `INSERT` statement.
+/// ex: `format!("INSERT INTO users (id, name) VALUES ($1, $2) {}", PGOnConflict::do_update_excluded(["id"], ["name"]))`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PGOnConflict {
+    /// `ON CONFLICT (columns) DO NOTHING`, without columns any constraint violation is ignored.
+    DoNothing { columns: Vec<String> },
+    /// `ON CONFLICT (columns) DO UPDATE SET set_exprs`
+    DoUpdate {
+        columns: Vec<String>,
+        set_exprs: Vec<String>,
+    },
+}
+
+impl PGOnConflict {
+    pub fn do_nothing<I, S>(columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        Self::DoNothing {
+            columns: columns.into_iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// Update the conflicting row with the given expressions, ex: `counter = users.counter + 1`.
+    /// Without any expression there is nothing to update and it falls back to `DO NOTHING`, note that
+    /// the `RETURNING` clause returns no row for the ignored inserts.
+    pub fn do_update<I, S, E, X>(columns: I, set_exprs: E) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+        E: IntoIterator<Item = X>,
+        X: ToString,
+    {
+        let columns = columns.into_iter().map(|c| c.to_string()).collect();
+        let set_exprs: Vec<String> = set_exprs.into_iter().map(|e| e.to_string()).collect();
+        if set_exprs.is_empty() {
+            Self::DoNothing { columns }
+        } else {
+            Self::DoUpdate { columns, set_exprs }
+        }
+    }
+
+    /// Update the listed columns of the conflicting row with the values of the rejected insert.
+    pub fn do_update_excluded<I, S, U, C>(columns: I, update_columns: U) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+        U: IntoIterator<Item = C>,
+        C: AsRef<str>,
+    {
+        let set_exprs = update_columns.into_iter().map(|c| {
+            let c = c.as_ref();
+            format!("{c} = EXCLUDED.{c}")
+        });
+        Self::do_update(columns, set_exprs)
+    }
+}
+
+impl fmt::Display for PGOnConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn target(f: &mut fmt::Formatter<'_>, columns: &[String]) -> fmt::Result {
+            f.write_str("ON CONFLICT")?;
+            if !columns.is_empty() {
+                write!(f, " ({})", columns.join(", "))?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Self::DoNothing { columns } => {
+                target(f, columns)?;
+                f.write_str(" DO NOTHING")
+            }
+            Self::DoUpdate { columns, set_exprs } => {
+                target(f, columns)?;
+                write!(f, " DO UPDATE SET {}", set_exprs.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn on_conflict_clause() {
+        assert_eq!(
+            PGOnConflict::do_nothing(Vec::<String>::new()).to_string(),
+            "ON CONFLICT DO NOTHING"
+        );
+        assert_eq!(
+            PGOnConflict::do_nothing(["id"]).to_string(),
+            "ON CONFLICT (id) DO NOTHING"
+        );
+        assert_eq!(
+            PGOnConflict::do_update(["id", "kind"], ["counter = t.counter + 1"]).to_string(),
+            "ON CONFLICT (id, kind) DO UPDATE SET counter = t.counter + 1"
+        );
+        assert_eq!(
+            PGOnConflict::do_update_excluded(["id"], ["name", "email"]).to_string(),
+            "ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, email = EXCLUDED.email"
+        );
+        assert_eq!(
+            PGOnConflict::do_update_excluded(["id"], Vec::<String>::new()).to_string(),
+            "ON CONFLICT (id) DO NOTHING"
+        );
+    }
+}
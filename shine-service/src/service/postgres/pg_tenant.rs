@@ -0,0 +1,401 @@
+use crate::{
+    axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig},
+    service::{
+        create_postgres_pool_with_config, MemoryCache, PGClient, PGConnectionError, PGConnectionPool,
+        PGCreatePoolError, PGError, PGPooledConnection, PostgresServiceConfig, TenantError, TenantId,
+    },
+};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, Extension, RequestPartsExt};
+use futures::future::BoxFuture;
+use opentelemetry::metrics::Meter;
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error as ThisError;
+use tokio::{
+    runtime::Handle,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+const DEFAULT_MAX_CONNECTIONS: usize = 4;
+
+#[derive(Debug, ThisError)]
+pub enum PGTenantError {
+    #[error("Unknown tenant: {0}")]
+    UnknownTenant(String),
+    #[error("Invalid schema name: {0}")]
+    InvalidSchema(String),
+    #[error("Failed to get pg connection")]
+    PGPoolError(#[source] PGConnectionError),
+    #[error(transparent)]
+    PGError(#[from] PGError),
+    #[error(transparent)]
+    Tenant(#[from] TenantError),
+}
+
+impl IntoProblem for PGTenantError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            PGTenantError::UnknownTenant(_) => Problem::forbidden().with_detail(self.to_string()),
+            PGTenantError::InvalidSchema(_) => Problem::internal_error(config, "Invalid tenant schema", self),
+            PGTenantError::PGPoolError(err) => Problem::internal_error(config, "Postgres connection error", err),
+            PGTenantError::PGError(err) => Problem::internal_error(config, "Postgres error", err),
+            PGTenantError::Tenant(err) => err.into_problem(config),
+        }
+    }
+}
+
+/// Isolation settings of a tenant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PGTenant {
+    pub tenant_id: String,
+    pub schema: String,
+    /// Maximum number of the concurrent connections of the tenant.
+    pub max_connections: usize,
+}
+
+fn is_valid_schema(schema: &str) -> bool {
+    !schema.is_empty()
+        && schema.len() <= 63
+        && schema.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && schema
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn tenant_max_connections(max_connections: Option<i32>) -> usize {
+    max_connections
+        .and_then(|max| usize::try_from(max).ok())
+        .filter(|max| *max > 0)
+        .unwrap_or(DEFAULT_MAX_CONNECTIONS)
+}
+
+/// Route the connections of the tenants requiring stronger isolation to their own schema.
+/// The tenants are registered in the `public.tenant_schemas` table and cached locally. The `search_path` of the
+/// connections is reset when they are released, a connection failing the reset is closed instead of being reused.
+pub struct PGTenantRouter {
+    pool: PGConnectionPool,
+    cache: MemoryCache<String, PGTenant>,
+    /// The connection limit of the tenants along with the size it was created with.
+    limits: Mutex<HashMap<String, (usize, Arc<Semaphore>)>>,
+}
+
+impl PGTenantRouter {
+    /// The schema of the tenant registry to be included in the migrations of the service.
+    pub const SCHEMA: &'static str = r#"
+CREATE TABLE IF NOT EXISTS public.tenant_schemas (
+    tenant_id TEXT PRIMARY KEY,
+    schema_name TEXT NOT NULL UNIQUE,
+    max_connections INTEGER
+);
+"#;
+
+    pub fn new(pool: PGConnectionPool) -> Self {
+        Self {
+            pool,
+            cache: MemoryCache::new("pg_tenants", 10_000).with_ttl(Duration::from_secs(60)),
+            limits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Find the tenant in the registry.
+    pub async fn find_tenant(&self, tenant_id: &str) -> Result<PGTenant, PGTenantError> {
+        let key = tenant_id.to_string();
+        if let Some(tenant) = self.cache.get(&key) {
+            return Ok(tenant);
+        }
+
+        let client = self.pool.get().await.map_err(PGTenantError::PGPoolError)?;
+        let row = client
+            .query_opt(
+                "SELECT schema_name, max_connections FROM public.tenant_schemas WHERE tenant_id = $1",
+                &[&tenant_id],
+            )
+            .await?
+            .ok_or_else(|| PGTenantError::UnknownTenant(key.clone()))?;
+        let schema: String = row.try_get(0)?;
+        let max_connections: Option<i32> = row.try_get(1)?;
+        if !is_valid_schema(&schema) {
+            return Err(PGTenantError::InvalidSchema(schema));
+        }
+
+        let tenant = PGTenant {
+            tenant_id: key.clone(),
+            schema,
+            max_connections: tenant_max_connections(max_connections),
+        };
+        self.cache.insert(key, tenant.clone());
+        Ok(tenant)
+    }
+
+    /// List all the registered tenants.
+    pub async fn list_tenants(&self) -> Result<Vec<PGTenant>, PGTenantError> {
+        let client = self.pool.get().await.map_err(PGTenantError::PGPoolError)?;
+        let rows = client
+            .query(
+                "SELECT tenant_id, schema_name, max_connections FROM public.tenant_schemas ORDER BY tenant_id",
+                &[],
+            )
+            .await?;
+
+        let mut tenants = Vec::with_capacity(rows.len());
+        for row in rows {
+            let schema: String = row.try_get(1)?;
+            if !is_valid_schema(&schema) {
+                return Err(PGTenantError::InvalidSchema(schema));
+            }
+            let max_connections: Option<i32> = row.try_get(2)?;
+            tenants.push(PGTenant {
+                tenant_id: row.try_get(0)?,
+                schema,
+                max_connections: tenant_max_connections(max_connections),
+            });
+        }
+        Ok(tenants)
+    }
+
+    /// Drop the cached registry entry and the connection limit of a tenant.
+    pub fn invalidate(&self, tenant_id: &str) {
+        self.cache.remove(&tenant_id.to_string());
+        self.limits.lock().unwrap().remove(tenant_id);
+    }
+
+    /// Get the connection limit of the tenant. When the limit of the registry has changed, a new semaphore
+    /// replaces the old one, the connections in use still count against the old limit until released.
+    fn limit(&self, tenant: &PGTenant) -> Arc<Semaphore> {
+        let mut limits = self.limits.lock().unwrap();
+        match limits.get(&tenant.tenant_id) {
+            Some((max_connections, semaphore)) if *max_connections == tenant.max_connections => semaphore.clone(),
+            _ => {
+                let semaphore = Arc::new(Semaphore::new(tenant.max_connections));
+                limits.insert(tenant.tenant_id.clone(), (tenant.max_connections, semaphore.clone()));
+                semaphore
+            }
+        }
+    }
+
+    async fn connect(&self, tenant: &PGTenant) -> Result<PGTenantConnection, PGTenantError> {
+        let permit = self
+            .limit(tenant)
+            .acquire_owned()
+            .await
+            .expect("Tenant semaphore is never closed");
+        let mut client = self.pool.get_owned().await.map_err(PGTenantError::PGPoolError)?;
+        // until the search_path is reset, the connection is discarded instead of being returned to the pool
+        client.set_session_modified(true);
+        let connection = PGTenantConnection {
+            client: Some(client),
+            permit: Some(permit),
+        };
+        // the schema name is validated, it is safe to use it as an identifier
+        connection
+            .batch_execute(&format!("SET search_path TO \"{}\"", tenant.schema))
+            .await?;
+        Ok(connection)
+    }
+
+    /// Get a connection with the `search_path` set to the schema of the tenant.
+    pub async fn get(&self, tenant_id: &str) -> Result<PGTenantConnection, PGTenantError> {
+        let tenant = self.find_tenant(tenant_id).await?;
+        self.connect(&tenant).await
+    }
+
+    /// Run the migration (or any maintenance) action on the schema of all the tenants. The schema
+    /// is created if it does not exist yet.
+    pub async fn for_each_tenant<F>(&self, mut action: F) -> Result<(), PGTenantError>
+    where
+        F: for<'c> FnMut(&'c PGTenant, &'c mut PGClient) -> BoxFuture<'c, Result<(), PGTenantError>>,
+    {
+        for tenant in self.list_tenants().await? {
+            log::info!("Processing schema {} of tenant {}", tenant.schema, tenant.tenant_id);
+            let mut client = self.connect(&tenant).await?;
+            client
+                .batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS \"{}\"", tenant.schema))
+                .await?;
+            action(&tenant, &mut **client).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Dedicated pools of the tenants requiring a separate database, keyed by the tenant id in the configuration.
+pub struct PGTenantPools {
+    pools: HashMap<String, PGConnectionPool>,
+}
+
+impl PGTenantPools {
+    pub async fn new(
+        config: &HashMap<String, PostgresServiceConfig>,
+        meter: Option<&Meter>,
+    ) -> Result<Self, PGCreatePoolError> {
+        let mut pools = HashMap::with_capacity(config.len());
+        for (tenant_id, tenant_config) in config {
+            let pool = create_postgres_pool_with_config(&tenant_config.cns, &tenant_config.pool, meter).await?;
+            pools.insert(tenant_id.clone(), pool);
+        }
+        Ok(Self { pools })
+    }
+
+    pub fn tenants(&self) -> impl Iterator<Item = &str> {
+        self.pools.keys().map(|tenant_id| tenant_id.as_str())
+    }
+
+    pub fn pool(&self, tenant_id: &str) -> Result<&PGConnectionPool, PGTenantError> {
+        self.pools
+            .get(tenant_id)
+            .ok_or_else(|| PGTenantError::UnknownTenant(tenant_id.to_string()))
+    }
+
+    /// Get a connection from the pool of the tenant.
+    pub async fn get(&self, tenant_id: &str) -> Result<PGTenantConnection, PGTenantError> {
+        let client = self
+            .pool(tenant_id)?
+            .get_owned()
+            .await
+            .map_err(PGTenantError::PGPoolError)?;
+        Ok(PGTenantConnection {
+            client: Some(client),
+            permit: None,
+        })
+    }
+}
+
+/// Database access of the tenants, either a schema in a shared database or a dedicated pool for each tenant.
+pub enum PGTenantDatabase {
+    Schema(PGTenantRouter),
+    Pools(PGTenantPools),
+}
+
+impl PGTenantDatabase {
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    /// Get a connection to the data of the tenant.
+    pub async fn get(&self, tenant_id: &str) -> Result<PGTenantConnection, PGTenantError> {
+        match self {
+            PGTenantDatabase::Schema(router) => router.get(tenant_id).await,
+            PGTenantDatabase::Pools(pools) => pools.get(tenant_id).await,
+        }
+    }
+}
+
+/// Pooled connection of a tenant, with a schema it counts against the connection limit of the tenant until
+/// dropped. On drop the `search_path` is reset in the background before the connection is returned to the pool,
+/// if the reset fails (or there is no runtime to run it) the connection is closed.
+pub struct PGTenantConnection {
+    client: Option<PGPooledConnection<'static>>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Deref for PGTenantConnection {
+    type Target = PGPooledConnection<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().expect("Connection is taken only on drop")
+    }
+}
+
+impl DerefMut for PGTenantConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().expect("Connection is taken only on drop")
+    }
+}
+
+impl Drop for PGTenantConnection {
+    fn drop(&mut self) {
+        // only the connections routed to a schema hold a permit
+        let (Some(mut client), Some(permit)) = (self.client.take(), self.permit.take()) else {
+            return;
+        };
+
+        match Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    match client.batch_execute("RESET search_path").await {
+                        Ok(()) => client.set_session_modified(false),
+                        Err(err) => log::warn!("Closing a tenant connection, failed to reset the search_path: {err}"),
+                    }
+                    drop(client);
+                    drop(permit);
+                });
+            }
+            Err(_) => log::warn!("Closing a tenant connection, no runtime to reset the search_path"),
+        }
+    }
+}
+
+/// Extract a connection to the data of the tenant of the request, it requires the `TenantResolver` and the
+/// `PGTenantDatabase` layers.
+///
+/// ```ignore
+/// let router = router
+///     .layer(TenantResolver::new(&config.tenant)?.into_layer())
+///     .layer(PGTenantDatabase::Schema(PGTenantRouter::new(pool)).into_layer());
+///
+/// async fn handler(TenantDb(client): TenantDb) {
+///     let rows = client.query("SELECT * FROM items", &[]).await?;
+/// }
+/// ```
+pub struct TenantDb(pub PGTenantConnection);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for TenantDb
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<PGTenantError>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(database) = parts
+            .extract::<Extension<Arc<PGTenantDatabase>>>()
+            .await
+            .expect("Missing PGTenantDatabase extension");
+
+        let tenant_id = TenantId::from_request_parts(parts, state)
+            .await
+            .map_err(|err| problem_config.configure(PGTenantError::Tenant(err.problem)))?;
+        let client = database
+            .get(&tenant_id)
+            .await
+            .map_err(|err| problem_config.configure(err))?;
+        Ok(TenantDb(client))
+    }
+}
+
+impl Deref for TenantDb {
+    type Target = PGTenantConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for TenantDb {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn schema_name_validation() {
+        assert!(is_valid_schema("tenant_42"));
+        assert!(is_valid_schema("_private"));
+        assert!(!is_valid_schema(""));
+        assert!(!is_valid_schema("1tenant"));
+        assert!(!is_valid_schema("tenant\"; DROP TABLE x; --"));
+        assert!(!is_valid_schema("Tenant"));
+    }
+}
@@ -1,4 +1,10 @@
-use tokio_postgres::types::Type;
+use crate::service::PGConvertError;
+use bytes::{BufMut, BytesMut};
+use chrono::Duration;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value as JsonValue;
+use std::fmt;
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, Json, ToSql, Type};
 use uuid::Uuid;
 
 pub trait ToPGType {
@@ -27,3 +33,190 @@ impl ToPGType for Uuid {
 impl ToPGType for &str {
     const PG_TYPE: Type = Type::VARCHAR;
 }
+
+impl ToPGType for JsonValue {
+    const PG_TYPE: Type = Type::JSONB;
+}
+
+impl<T> ToPGType for PGJson<T> {
+    const PG_TYPE: Type = Type::JSONB;
+}
+
+/// Types that can be bound as an element of a postgres array.
+pub trait ToPGArrayType {
+    const PG_ARRAY_TYPE: Type;
+}
+
+impl<T> ToPGType for Vec<T>
+where
+    T: ToPGArrayType,
+{
+    const PG_TYPE: Type = <T as ToPGArrayType>::PG_ARRAY_TYPE;
+}
+
+impl ToPGArrayType for bool {
+    const PG_ARRAY_TYPE: Type = Type::BOOL_ARRAY;
+}
+
+impl ToPGArrayType for i16 {
+    const PG_ARRAY_TYPE: Type = Type::INT2_ARRAY;
+}
+
+impl ToPGArrayType for i32 {
+    const PG_ARRAY_TYPE: Type = Type::INT4_ARRAY;
+}
+
+impl ToPGArrayType for i64 {
+    const PG_ARRAY_TYPE: Type = Type::INT8_ARRAY;
+}
+
+impl ToPGArrayType for String {
+    const PG_ARRAY_TYPE: Type = Type::TEXT_ARRAY;
+}
+
+impl ToPGArrayType for &str {
+    const PG_ARRAY_TYPE: Type = Type::TEXT_ARRAY;
+}
+
+impl ToPGArrayType for Uuid {
+    const PG_ARRAY_TYPE: Type = Type::UUID_ARRAY;
+}
+
+impl ToPGArrayType for JsonValue {
+    const PG_ARRAY_TYPE: Type = Type::JSONB_ARRAY;
+}
+
+impl ToPGType for PGInterval {
+    const PG_TYPE: Type = Type::INTERVAL;
+}
+
+/// Wrapper to store any serializable type in a `jsonb` (or `json`) column.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PGJson<T>(pub T);
+
+impl<T> PGJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for PGJson<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> ToSql for PGJson<T>
+where
+    T: Serialize + fmt::Debug,
+{
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, PGConvertError> {
+        Json(&self.0).to_sql(ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <Json<T> as ToSql>::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a, T> FromSql<'a> for PGJson<T>
+where
+    T: DeserializeOwned,
+{
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, PGConvertError> {
+        Json::<T>::from_sql(ty, raw).map(|json| Self(json.0))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <Json<T> as FromSql>::accepts(ty)
+    }
+}
+
+const MICROS_PER_DAY: i64 = 24 * 60 * 60 * 1_000_000;
+/// Number of days in a month when an interval is converted into a duration, it matches the
+/// `justify_days` function of postgres.
+const DAYS_PER_MONTH: i64 = 30;
+
+/// Wrapper to store a `chrono::Duration` as a postgres `interval`.
+/// The months of the loaded intervals are converted as 30 days.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PGInterval(pub Duration);
+
+impl From<Duration> for PGInterval {
+    fn from(duration: Duration) -> Self {
+        Self(duration)
+    }
+}
+
+impl From<PGInterval> for Duration {
+    fn from(interval: PGInterval) -> Self {
+        interval.0
+    }
+}
+
+impl ToSql for PGInterval {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, PGConvertError> {
+        let micros = self.0.num_microseconds().ok_or("Interval out of range")?;
+        let days = i32::try_from(micros / MICROS_PER_DAY)?;
+        out.put_i64(micros % MICROS_PER_DAY);
+        out.put_i32(days);
+        out.put_i32(0);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for PGInterval {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, PGConvertError> {
+        let raw: [u8; 16] = raw.try_into().map_err(|_| "Invalid interval length")?;
+        let micros = i64::from_be_bytes(raw[0..8].try_into()?);
+        let days = i32::from_be_bytes(raw[8..12].try_into()?) as i64;
+        let months = i32::from_be_bytes(raw[12..16].try_into()?) as i64;
+
+        let days = months * DAYS_PER_MONTH + days;
+        let micros = days
+            .checked_mul(MICROS_PER_DAY)
+            .and_then(|days| days.checked_add(micros))
+            .ok_or("Interval out of range")?;
+        Ok(Self(Duration::microseconds(micros)))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn interval_roundtrip() {
+        let duration = Duration::days(3) + Duration::hours(5) + Duration::microseconds(7);
+        let mut buffer = BytesMut::new();
+        PGInterval(duration).to_sql(&Type::INTERVAL, &mut buffer).unwrap();
+        assert_eq!(buffer.len(), 16);
+        assert_eq!(&buffer[8..12], &3_i32.to_be_bytes());
+
+        let loaded = PGInterval::from_sql(&Type::INTERVAL, &buffer).unwrap();
+        assert_eq!(loaded.0, duration);
+    }
+
+    #[test]
+    fn interval_months() {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&0_i64.to_be_bytes());
+        raw.extend_from_slice(&1_i32.to_be_bytes());
+        raw.extend_from_slice(&2_i32.to_be_bytes());
+        let loaded = PGInterval::from_sql(&Type::INTERVAL, &raw).unwrap();
+        assert_eq!(loaded.0, Duration::days(61));
+    }
+}
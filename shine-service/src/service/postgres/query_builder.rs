@@ -1,4 +1,6 @@
-use tokio_postgres::types::ToSql;
+use crate::service::{PGConnection, PGError, PGRawConnection};
+use tokio_postgres::{types::ToSql, Row};
+use tracing::{debug_span, Instrument, Span};
 
 pub trait AndWhere<const N: usize> {
     fn into_statement(self, builder: &mut QueryBuilder<'_>);
@@ -67,6 +69,43 @@ where
     }
 }
 
+/// A built query fragment with its own bindings to be embedded into another query.
+pub struct SubQuery<'a> {
+    stmt: String,
+    params: Vec<&'a (dyn ToSql + Sync)>,
+}
+
+impl<'a> SubQuery<'a> {
+    /// Create a fragment from a statement using the `$1..$N` placeholders for the given bindings.
+    pub fn new<S: ToString>(stmt: S, params: Vec<&'a (dyn ToSql + Sync)>) -> Self {
+        Self {
+            stmt: stmt.to_string(),
+            params,
+        }
+    }
+
+    /// Shift the placeholder ids of the fragment to start from `first_id`.
+    fn renumber(&self, first_id: usize) -> String {
+        let mut stmt = String::with_capacity(self.stmt.len());
+        let mut chars = self.stmt.chars().peekable();
+        while let Some(c) = chars.next() {
+            stmt.push(c);
+            if c != '$' {
+                continue;
+            }
+            let mut id = String::new();
+            while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {
+                id.push(d);
+            }
+            match id.parse::<usize>() {
+                Ok(id) if id > 0 => stmt.push_str(&(id + first_id - 1).to_string()),
+                _ => stmt.push_str(&id),
+            }
+        }
+        stmt
+    }
+}
+
 pub struct QueryBuilder<'a> {
     params: Vec<&'a (dyn ToSql + Sync)>,
     bind_id: usize,
@@ -96,6 +135,52 @@ impl<'a> QueryBuilder<'a> {
         self.params.extend_from_slice(&p);
     }
 
+    /// Add the condition with its own bindings only if `enabled` is true.
+    pub fn and_where_if<F, const N: usize>(&mut self, enabled: bool, condition: F, p: [&'a (dyn ToSql + Sync); N])
+    where
+        F: AndWhere<N>,
+    {
+        if enabled {
+            self.and_where(condition, p);
+        }
+    }
+
+    /// Add the condition built from an optional value, it is skipped when the value is `None`.
+    pub fn and_where_some<T, F>(&mut self, value: &'a Option<T>, condition: F)
+    where
+        T: ToSql + Sync,
+        F: AndWhere<1>,
+    {
+        if let Some(value) = value {
+            self.and_where(condition, [value]);
+        }
+    }
+
+    /// Add a condition embedding a sub-query, the placeholders of the sub-query are renumbered to
+    /// follow the bindings of this builder.
+    /// ex: `builder.and_where_sub_query(|sub| format!("id IN ({sub})"), filter.into_sub_query())`
+    pub fn and_where_sub_query<F>(&mut self, condition: F, sub_query: SubQuery<'a>)
+    where
+        F: FnOnce(&str) -> String,
+    {
+        let sub_stmt = sub_query.renumber(self.bind_id);
+        let and_condition = (condition)(&sub_stmt);
+        if let Some(condition) = &mut self.condition {
+            condition.push_str(" AND ");
+            condition.push_str(&and_condition);
+        } else {
+            self.condition = Some(and_condition);
+        }
+        self.bind_id += sub_query.params.len();
+        self.params.extend(sub_query.params);
+    }
+
+    /// Build the query as a fragment to be embedded into another query.
+    pub fn into_sub_query(self) -> SubQuery<'a> {
+        let (stmt, params) = self.build();
+        SubQuery { stmt, params }
+    }
+
     pub fn order_by(&mut self, order: &str) {
         if let Some(order_by) = &mut self.order_by {
             order_by.push_str(", ");
@@ -127,4 +212,133 @@ impl<'a> QueryBuilder<'a> {
 
         (stmt, self.params)
     }
+
+    /// Name of a built statement in the metrics, a stable hash of the statement text. The statements of the
+    /// builder are dynamic, thus the text can't be used as an attribute without exploding the cardinality.
+    pub fn statement_name(stmt: &str) -> String {
+        // FNV-1a, the names have to be stable across the releases for the dashboards
+        let hash = stmt.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        format!("query_builder_{hash:016x}")
+    }
+
+    /// Span of the query execution. Only the number of the bindings is recorded, the values are
+    /// never exposed in the traces.
+    fn span(operation: &'static str, stmt: &str, params: usize) -> Span {
+        debug_span!(
+            "pg.query_builder",
+            db.system = "postgresql",
+            db.operation = operation,
+            db.statement = stmt,
+            db.params = params
+        )
+    }
+
+    /// Build and execute the query returning exactly one row.
+    pub async fn fetch_one<T>(self, client: &PGConnection<T>) -> Result<Row, PGError>
+    where
+        T: PGRawConnection,
+    {
+        let (stmt, params) = self.build();
+        let span = Self::span("fetch_one", &stmt, params.len());
+        client
+            .traced_query_one(&Self::statement_name(&stmt), stmt.as_str(), &params)
+            .instrument(span)
+            .await
+    }
+
+    /// Build and execute the query returning at most one row.
+    pub async fn fetch_optional<T>(self, client: &PGConnection<T>) -> Result<Option<Row>, PGError>
+    where
+        T: PGRawConnection,
+    {
+        let (stmt, params) = self.build();
+        let span = Self::span("fetch_optional", &stmt, params.len());
+        client
+            .traced_query_opt(&Self::statement_name(&stmt), stmt.as_str(), &params)
+            .instrument(span)
+            .await
+    }
+
+    /// Build and execute the query returning all the rows.
+    pub async fn fetch_all<T>(self, client: &PGConnection<T>) -> Result<Vec<Row>, PGError>
+    where
+        T: PGRawConnection,
+    {
+        let (stmt, params) = self.build();
+        let span = Self::span("fetch_all", &stmt, params.len());
+        client
+            .traced_query(&Self::statement_name(&stmt), stmt.as_str(), &params)
+            .instrument(span)
+            .await
+    }
+
+    /// Build and execute the statement returning the number of the affected rows.
+    pub async fn execute<T>(self, client: &PGConnection<T>) -> Result<u64, PGError>
+    where
+        T: PGRawConnection,
+    {
+        let (stmt, params) = self.build();
+        let span = Self::span("execute", &stmt, params.len());
+        client
+            .traced_execute(&Self::statement_name(&stmt), stmt.as_str(), &params)
+            .instrument(span)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn conditional_where() {
+        let name: Option<String> = None;
+        let age: Option<i32> = Some(18);
+        let id = 1_i64;
+
+        let mut builder = QueryBuilder::new("SELECT * FROM users");
+        builder.and_where(|b| format!("id = ${b}"), [&id]);
+        builder.and_where_some(&name, |b| format!("name = ${b}"));
+        builder.and_where_some(&age, |b| format!("age > ${b}"));
+        builder.and_where_if(false, || "deleted = false".to_string(), []);
+        let (stmt, params) = builder.build();
+
+        assert_eq!(stmt, "SELECT * FROM users WHERE id = $1 AND age > $2");
+        assert_eq!(params.len(), 2);
+    }
+
+    #[test]
+    fn sub_query_composition() {
+        let role = "admin".to_string();
+        let active = true;
+        let name = "joe".to_string();
+
+        let mut sub = QueryBuilder::new("SELECT user_id FROM roles");
+        sub.and_where(|b| format!("role = ${b}"), [&role]);
+        sub.and_where(|b| format!("active = ${b}"), [&active]);
+
+        let mut builder = QueryBuilder::new("SELECT * FROM users");
+        builder.and_where(|b| format!("name = ${b}"), [&name]);
+        builder.and_where_sub_query(|sub| format!("id IN ({sub})"), sub.into_sub_query());
+        builder.and_where(|b| format!("name <> ${b}"), [&name]);
+        let (stmt, params) = builder.build();
+
+        assert_eq!(
+            stmt,
+            "SELECT * FROM users WHERE name = $1 AND id IN (SELECT user_id FROM roles WHERE role = $2 AND active = $3) AND name <> $4"
+        );
+        assert_eq!(params.len(), 4);
+    }
+
+    #[test]
+    fn statement_names() {
+        let name = QueryBuilder::statement_name("SELECT * FROM users WHERE id = $1");
+        assert_eq!(name, QueryBuilder::statement_name("SELECT * FROM users WHERE id = $1"));
+        assert_ne!(name, QueryBuilder::statement_name("SELECT * FROM users WHERE id = $2"));
+        assert!(name.starts_with("query_builder_"));
+        assert_eq!(QueryBuilder::statement_name(""), "query_builder_cbf29ce484222325");
+    }
 }
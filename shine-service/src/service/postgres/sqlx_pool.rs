@@ -0,0 +1,30 @@
+use crate::service::PGPoolConfig;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Executor, PgPool,
+};
+use std::str::FromStr;
+
+/// Create a sqlx pool with the settings of the tokio-postgres pools, the session settings are applied on each
+/// new connection. The slow query threshold is not supported, the queries of sqlx are not traced by the crate.
+pub async fn create_sqlx_pool(cns: &str, config: &PGPoolConfig) -> Result<PgPool, sqlx::Error> {
+    let mut options = PgConnectOptions::from_str(cns)?;
+    if let Some(application_name) = &config.application_name {
+        options = options.application_name(application_name);
+    }
+
+    let session_settings = config.session_settings();
+    PgPoolOptions::new()
+        .max_connections(config.max_size)
+        .after_connect(move |connection, _| {
+            let session_settings = session_settings.clone();
+            Box::pin(async move {
+                if let Some(session_settings) = session_settings {
+                    connection.execute(session_settings.as_str()).await?;
+                }
+                Ok(())
+            })
+        })
+        .connect_with(options)
+        .await
+}
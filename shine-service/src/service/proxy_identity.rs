@@ -0,0 +1,509 @@
+use crate::{
+    axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig},
+    service::{CurrentUser, SessionKey},
+    utils::Sensitive,
+};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap},
+    Extension, RequestPartsExt,
+};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, ops, sync::Arc};
+use thiserror::Error as ThisError;
+use uuid::Uuid;
+
+#[derive(Debug, ThisError)]
+pub enum ProxyIdentityError {
+    #[error("Request was not sent by the trusted proxy")]
+    UntrustedProxy,
+    #[error("Missing identity header: {0}")]
+    MissingIdentity(String),
+    #[error("Invalid identity header: {0}")]
+    InvalidIdentity(String),
+    #[error("The gateway did not forward a session")]
+    MissingSession,
+}
+
+impl IntoProblem for ProxyIdentityError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        Problem::unauthorized()
+            .with_detail(self.to_string())
+            .with_extension(config, format!("{:#?}", self))
+    }
+}
+
+/// Namespace of the user ids derived from the external ids of the gateway.
+const USER_ID_NAMESPACE: Uuid = Uuid::from_u128(0x6b1c_52a4_08f3_4f0e_9d2e_5c7a_3e41_b8d0);
+
+fn default_secret_header() -> String {
+    "x-proxy-secret".to_string()
+}
+
+fn default_client_cert_header() -> String {
+    "x-forwarded-client-cert".to_string()
+}
+
+fn default_user_id_namespace() -> Uuid {
+    USER_ID_NAMESPACE
+}
+
+fn default_user_id_header() -> String {
+    "x-forwarded-user".to_string()
+}
+
+fn default_name_header() -> String {
+    "x-forwarded-preferred-username".to_string()
+}
+
+fn default_roles_header() -> String {
+    "x-forwarded-groups".to_string()
+}
+
+/// Proof that a request was forwarded by the gateway.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum ProxyTrust {
+    /// Secret shared with the gateway, it has to be sent in the `header` of each request.
+    #[serde(rename_all = "camelCase")]
+    SharedSecret {
+        secret: Sensitive<String>,
+        #[serde(default = "default_secret_header")]
+        header: String,
+    },
+
+    /// The gateway authenticates with a client certificate (mTLS). The certificate is verified by the TLS
+    /// terminating ingress and its details are forwarded in the `header` in the format of the Envoy
+    /// `x-forwarded-client-cert` header. The SHA-256 hash of the certificate has to match one of the
+    /// `fingerprints` (hex encoded, `:` separators are allowed).
+    #[serde(rename_all = "camelCase")]
+    ClientCertificate {
+        fingerprints: Vec<String>,
+        #[serde(default = "default_client_cert_header")]
+        header: String,
+        /// Secret keying the session ids of the gateway.
+        session_secret: Sensitive<String>,
+    },
+}
+
+/// Headers of the authenticated identity injected by the fronting gateway (ex. oauth2-proxy, Azure Easy Auth).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyIdentityConfig {
+    pub trust: ProxyTrust,
+    /// Header of the unique (and stable) id of the user.
+    #[serde(default = "default_user_id_header")]
+    pub user_id_header: String,
+    /// Namespace of the (UUIDv5) user ids derived from the external ids, the gateways of separate identity
+    /// providers should use separate namespaces.
+    #[serde(default = "default_user_id_namespace")]
+    pub user_id_namespace: Uuid,
+    /// Header of the display name, the user id is used if missing.
+    #[serde(default = "default_name_header")]
+    pub name_header: String,
+    /// Header of the comma separated list of roles (groups).
+    #[serde(default = "default_roles_header")]
+    pub roles_header: String,
+    /// Header of the session id of the gateway. Without it the session bound features are not available.
+    pub session_id_header: Option<String>,
+    /// Header of the start of the session, in RFC 3339 format or as unix timestamp (seconds).
+    pub session_start_header: Option<String>,
+}
+
+/// Session of the gateway.
+#[derive(Clone, Debug)]
+pub struct ProxySession {
+    /// Derived from the session id of the gateway, it is stable for the session.
+    pub key: SessionKey,
+    pub session_start: DateTime<Utc>,
+}
+
+/// The identity forwarded by the gateway.
+#[derive(Clone, Debug)]
+pub struct ProxyIdentity {
+    pub user_id: Uuid,
+    pub name: String,
+    pub roles: Vec<String>,
+    /// Present only if the gateway forwards the id and the start of its session.
+    pub session: Option<ProxySession>,
+}
+
+impl ProxyIdentity {
+    /// Map the identity into a `CurrentUser`, it requires the session of the gateway.
+    pub fn to_current_user(&self) -> Result<CurrentUser, ProxyIdentityError> {
+        let session = self.session.as_ref().ok_or(ProxyIdentityError::MissingSession)?;
+        Ok(CurrentUser {
+            user_id: self.user_id,
+            key: session.key,
+            session_start: session.session_start,
+            name: self.name.clone(),
+            roles: self.roles.clone(),
+            fingerprint: String::new(),
+            version: 0,
+            claims: HashMap::new(),
+        })
+    }
+}
+
+enum Trust {
+    SharedSecret {
+        key: hmac::Key,
+        tag: hmac::Tag,
+        header: String,
+    },
+    ClientCertificate {
+        fingerprints: Vec<String>,
+        header: String,
+    },
+}
+
+/// Validate the identity headers of the trusted gateway. The identity maps into a `CurrentUser` only if the
+/// gateway forwards the id and the start of its session.
+/// The gateway must strip these headers from the incoming requests, as the shared secret (or the client
+/// certificate) only proves that the request was forwarded by the gateway.
+pub struct ProxyIdentityValidator {
+    trust: Trust,
+    session_key: hmac::Key,
+    config: ProxyIdentityConfig,
+}
+
+impl ProxyIdentityValidator {
+    pub fn new(config: ProxyIdentityConfig) -> Self {
+        let (trust, session_secret) = match &config.trust {
+            ProxyTrust::SharedSecret { secret, header } => {
+                let key = hmac::Key::new(hmac::HMAC_SHA256, b"proxy-identity");
+                let tag = hmac::sign(&key, secret.expose().as_bytes());
+                let header = header.clone();
+                (Trust::SharedSecret { key, tag, header }, secret)
+            }
+            ProxyTrust::ClientCertificate {
+                fingerprints,
+                header,
+                session_secret,
+            } => {
+                let fingerprints = fingerprints.iter().map(|f| Self::normalize_fingerprint(f)).collect();
+                let header = header.clone();
+                (Trust::ClientCertificate { fingerprints, header }, session_secret)
+            }
+        };
+        let session_key = hmac::Key::new(hmac::HMAC_SHA256, session_secret.expose().as_bytes());
+        Self {
+            trust,
+            session_key,
+            config,
+        }
+    }
+
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<Option<&'a str>, ProxyIdentityError> {
+        headers
+            .get(name)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map(str::trim)
+                    .map_err(|_| ProxyIdentityError::InvalidIdentity(name.to_string()))
+            })
+            .transpose()
+    }
+
+    /// Map an external id into a user id of the namespace of the gateway. The uuid shaped external ids are
+    /// also mapped, thus they cannot collide with the ids of the other identity sources.
+    fn user_id(&self, id: &str) -> Uuid {
+        Uuid::new_v5(&self.config.user_id_namespace, id.as_bytes())
+    }
+
+    fn normalize_fingerprint(fingerprint: &str) -> String {
+        fingerprint.replace(':', "").to_ascii_lowercase()
+    }
+
+    /// Hash of the client certificate of the immediate peer from an `x-forwarded-client-cert` header. Each
+    /// proxy appends an element, thus the last element is checked.
+    fn client_cert_hash(value: &str) -> Option<String> {
+        let element = value.rsplit(',').next()?;
+        element.split(';').find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            key.eq_ignore_ascii_case("hash")
+                .then(|| Self::normalize_fingerprint(value.trim_matches('"')))
+        })
+    }
+
+    fn verify_trust(&self, headers: &HeaderMap) -> Result<(), ProxyIdentityError> {
+        match &self.trust {
+            Trust::SharedSecret { key, tag, header } => {
+                // compare the secrets in constant time through their tags
+                let secret = Self::header(headers, header)?.ok_or(ProxyIdentityError::UntrustedProxy)?;
+                hmac::verify(key, secret.as_bytes(), tag.as_ref()).map_err(|_| ProxyIdentityError::UntrustedProxy)
+            }
+            Trust::ClientCertificate { fingerprints, header } => {
+                let hash = Self::header(headers, header)?
+                    .and_then(Self::client_cert_hash)
+                    .ok_or(ProxyIdentityError::UntrustedProxy)?;
+                if fingerprints.contains(&hash) {
+                    Ok(())
+                } else {
+                    Err(ProxyIdentityError::UntrustedProxy)
+                }
+            }
+        }
+    }
+
+    fn optional_header<'a>(headers: &'a HeaderMap, name: Option<&str>) -> Result<Option<&'a str>, ProxyIdentityError> {
+        match name {
+            Some(name) => Ok(Self::header(headers, name)?.filter(|value| !value.is_empty())),
+            None => Ok(None),
+        }
+    }
+
+    fn session_start(value: &str) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(value)
+            .map(|start| start.with_timezone(&Utc))
+            .ok()
+            .or_else(|| DateTime::<Utc>::from_timestamp(value.parse().ok()?, 0))
+    }
+
+    /// The session key is keyed by the shared secret, thus it cannot be derived from the public session id.
+    fn session(&self, headers: &HeaderMap) -> Result<Option<ProxySession>, ProxyIdentityError> {
+        let session_id = Self::optional_header(headers, self.config.session_id_header.as_deref())?;
+        let session_start = Self::optional_header(headers, self.config.session_start_header.as_deref())?;
+        let (session_id, session_start) = match (session_id, session_start) {
+            (Some(session_id), Some(session_start)) => (session_id, session_start),
+            (None, None) => return Ok(None),
+            (None, Some(_)) => {
+                let header = self.config.session_id_header.clone().unwrap_or_default();
+                return Err(ProxyIdentityError::MissingIdentity(header));
+            }
+            (Some(_), None) => {
+                let header = self.config.session_start_header.clone().unwrap_or_default();
+                return Err(ProxyIdentityError::MissingIdentity(header));
+            }
+        };
+
+        let session_start = Self::session_start(session_start).ok_or_else(|| {
+            ProxyIdentityError::InvalidIdentity(self.config.session_start_header.clone().unwrap_or_default())
+        })?;
+        let mut key = [0_u8; 16];
+        key.copy_from_slice(&hmac::sign(&self.session_key, session_id.as_bytes()).as_ref()[..16]);
+        Ok(Some(ProxySession {
+            key: SessionKey::from_bytes(key),
+            session_start,
+        }))
+    }
+
+    pub fn validate(&self, headers: &HeaderMap) -> Result<ProxyIdentity, ProxyIdentityError> {
+        self.verify_trust(headers)?;
+
+        let external_id = Self::header(headers, &self.config.user_id_header)?
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| ProxyIdentityError::MissingIdentity(self.config.user_id_header.clone()))?;
+        let name = Self::header(headers, &self.config.name_header)?
+            .filter(|name| !name.is_empty())
+            .unwrap_or(external_id);
+        let roles = Self::header(headers, &self.config.roles_header)?
+            .map(|roles| {
+                roles
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|role| !role.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(ProxyIdentity {
+            user_id: self.user_id(external_id),
+            name: name.to_string(),
+            roles,
+            session: self.session(headers)?,
+        })
+    }
+}
+
+/// The user authenticated by the trusted gateway.
+pub struct ProxyCurrentUser(ProxyIdentity);
+
+impl ProxyCurrentUser {
+    pub fn into_identity(self) -> ProxyIdentity {
+        self.0
+    }
+}
+
+impl ops::Deref for ProxyCurrentUser {
+    type Target = ProxyIdentity;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ProxyCurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<ProxyIdentityError>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(validator) = parts
+            .extract::<Extension<Arc<ProxyIdentityValidator>>>()
+            .await
+            .expect("Missing ProxyIdentityValidator extension");
+
+        let user = validator
+            .validate(&parts.headers)
+            .map_err(|err| problem_config.configure(err))?;
+        Ok(ProxyCurrentUser(user))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+    use shine_test::test;
+
+    const CERT_HASH: &str = "6c5f3b1e9a0d4c2b8e7f1a3d5c9b0e2f4a6c8e0b2d4f6a8c0e2b4d6f8a0c2e4b";
+
+    fn validator_with(trust: ProxyTrust) -> ProxyIdentityValidator {
+        ProxyIdentityValidator::new(ProxyIdentityConfig {
+            trust,
+            user_id_header: default_user_id_header(),
+            user_id_namespace: default_user_id_namespace(),
+            name_header: default_name_header(),
+            roles_header: default_roles_header(),
+            session_id_header: Some("x-forwarded-session".to_string()),
+            session_start_header: Some("x-forwarded-session-start".to_string()),
+        })
+    }
+
+    fn validator() -> ProxyIdentityValidator {
+        validator_with(ProxyTrust::SharedSecret {
+            secret: Sensitive::new("gateway-secret".to_string()),
+            header: default_secret_header(),
+        })
+    }
+
+    #[test]
+    fn validate_proxy_identity() {
+        let validator = validator();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-user", HeaderValue::from_static("jane@example.com"));
+        headers.insert("x-forwarded-groups", HeaderValue::from_static("admin, tools,"));
+        assert!(matches!(
+            validator.validate(&headers),
+            Err(ProxyIdentityError::UntrustedProxy)
+        ));
+
+        headers.insert("x-proxy-secret", HeaderValue::from_static("wrong"));
+        assert!(matches!(
+            validator.validate(&headers),
+            Err(ProxyIdentityError::UntrustedProxy)
+        ));
+
+        headers.insert("x-proxy-secret", HeaderValue::from_static("gateway-secret"));
+        let user = validator.validate(&headers).unwrap();
+        assert_eq!(user.name, "jane@example.com");
+        assert_eq!(user.roles, vec!["admin".to_string(), "tools".to_string()]);
+        assert_eq!(user.user_id, validator.validate(&headers).unwrap().user_id);
+        assert!(user.session.is_none());
+        assert!(matches!(
+            user.to_current_user(),
+            Err(ProxyIdentityError::MissingSession)
+        ));
+
+        headers.remove("x-forwarded-user");
+        assert!(matches!(
+            validator.validate(&headers),
+            Err(ProxyIdentityError::MissingIdentity(_))
+        ));
+    }
+
+    #[test]
+    fn namespaced_user_id() {
+        let validator = validator();
+        let external_id = "0b7f4a2e-3c1d-4e5f-8a9b-0c1d2e3f4a5b";
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-proxy-secret", HeaderValue::from_static("gateway-secret"));
+        headers.insert("x-forwarded-user", HeaderValue::from_static(external_id));
+        let user = validator.validate(&headers).unwrap();
+        assert_ne!(user.user_id, Uuid::parse_str(external_id).unwrap());
+        assert_eq!(user.user_id, Uuid::new_v5(&USER_ID_NAMESPACE, external_id.as_bytes()));
+    }
+
+    #[test]
+    fn client_certificate_trust() {
+        let validator = validator_with(ProxyTrust::ClientCertificate {
+            fingerprints: vec![CERT_HASH.to_ascii_uppercase()],
+            header: default_client_cert_header(),
+            session_secret: Sensitive::new("session-secret".to_string()),
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-user", HeaderValue::from_static("jane@example.com"));
+        assert!(matches!(
+            validator.validate(&headers),
+            Err(ProxyIdentityError::UntrustedProxy)
+        ));
+
+        let gateway = format!("By=spiffe://cluster/gateway;Hash={CERT_HASH};Subject=\"CN=gateway\"");
+        let forwarded = format!("{gateway},Hash=0123;Subject=\"CN=client\"");
+        headers.insert("x-forwarded-client-cert", HeaderValue::from_str(&forwarded).unwrap());
+        assert!(matches!(
+            validator.validate(&headers),
+            Err(ProxyIdentityError::UntrustedProxy)
+        ));
+
+        headers.insert("x-forwarded-client-cert", HeaderValue::from_str(&gateway).unwrap());
+        let user = validator.validate(&headers).unwrap();
+        assert_eq!(user.name, "jane@example.com");
+    }
+
+    #[test]
+    fn proxy_session() {
+        let validator = validator();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-proxy-secret", HeaderValue::from_static("gateway-secret"));
+        headers.insert("x-forwarded-user", HeaderValue::from_static("jane@example.com"));
+        headers.insert("x-forwarded-session", HeaderValue::from_static("session-1"));
+        assert!(matches!(
+            validator.validate(&headers),
+            Err(ProxyIdentityError::MissingIdentity(_))
+        ));
+
+        headers.insert("x-forwarded-session-start", HeaderValue::from_static("yesterday"));
+        assert!(matches!(
+            validator.validate(&headers),
+            Err(ProxyIdentityError::InvalidIdentity(_))
+        ));
+
+        headers.insert(
+            "x-forwarded-session-start",
+            HeaderValue::from_static("2024-05-01T10:00:00Z"),
+        );
+        let user = validator.validate(&headers).unwrap().to_current_user().unwrap();
+        assert_eq!(user.session_start.timestamp(), 1_714_557_600);
+        headers.insert("x-forwarded-session-start", HeaderValue::from_static("1714557600"));
+        let same_session = validator.validate(&headers).unwrap().to_current_user().unwrap();
+        assert_eq!(same_session.session_start, user.session_start);
+        assert_eq!(same_session.key, user.key);
+
+        headers.insert("x-forwarded-session", HeaderValue::from_static("session-2"));
+        let other_session = validator.validate(&headers).unwrap().to_current_user().unwrap();
+        assert_eq!(other_session.user_id, user.user_id);
+        assert_ne!(other_session.key, user.key);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::axum::{ErrorCategory, ServiceError};
 use bb8::{ManageConnection, Pool as BB8Pool, PooledConnection, RunError};
 
 pub use bb8_redis::RedisConnectionManager;
@@ -7,6 +8,17 @@ pub type RedisConnectionError = RunError<<RedisConnectionManager as ManageConnec
 pub type RedisConnectionPool = BB8Pool<RedisConnectionManager>;
 pub type RedisPooledConnection<'a> = PooledConnection<'a, RedisConnectionManager>;
 
+/// All the redis errors are dependency errors, the connection and timeout failures are retryable.
+impl ServiceError for redis::RedisError {
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Dependency
+    }
+
+    fn is_retryable(&self) -> bool {
+        self.is_timeout() || self.is_connection_dropped() || self.is_connection_refusal() || self.is_io_error()
+    }
+}
+
 pub async fn create_redis_pool(cns: &str) -> Result<RedisConnectionPool, RedisConnectionError> {
     let redis_manager = RedisConnectionManager::new(cns)?;
     let redis = bb8::Pool::builder()
@@ -0,0 +1,288 @@
+use crate::{
+    service::{RedisConnectionPool, RedisStreamConsumer, RedisStreamError, StreamMessage},
+    utils::DurationStr,
+};
+use opentelemetry::{
+    metrics::{Gauge, Meter},
+    KeyValue,
+};
+use redis::{FromRedisValue, Value as RedisValue};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error as StdError, future::Future, sync::Arc, time::Duration};
+use tokio::{sync::watch, task::JoinHandle};
+
+fn default_workers() -> usize {
+    1
+}
+
+fn default_batch_size() -> usize {
+    10
+}
+
+fn default_claim_idle() -> DurationStr {
+    DurationStr::from_secs(60)
+}
+
+fn default_max_deliveries() -> usize {
+    5
+}
+
+fn default_monitor_interval() -> DurationStr {
+    DurationStr::from_secs(15)
+}
+
+fn default_target_lag() -> usize {
+    1000
+}
+
+/// Declarative settings of the consumers of a stream.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerGroupConfig {
+    pub stream: String,
+    pub group: String,
+    /// Number of the workers (consumers) started by a replica.
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Idle time after which the pending messages of the other consumers are claimed.
+    #[serde(default = "default_claim_idle")]
+    pub claim_idle: DurationStr,
+    /// Maximum number of the deliveries of a message before it is moved to the dead letter stream.
+    #[serde(default = "default_max_deliveries")]
+    pub max_deliveries: usize,
+    /// The stream receiving the rejected messages, defaults to `{stream}:dead-letter`.
+    pub dead_letter: Option<String>,
+    /// Period of the lag measurement.
+    #[serde(default = "default_monitor_interval")]
+    pub monitor_interval: DurationStr,
+    /// Number of the waiting messages a single worker is expected to keep up with, used for the
+    /// scaling signal.
+    #[serde(default = "default_target_lag")]
+    pub target_lag_per_worker: usize,
+}
+
+/// Backlog of a consumer group and the derived scaling signal.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerGroupStatus {
+    /// Number of the messages delivered but not acknowledged yet.
+    pub pending: usize,
+    /// Number of the messages not delivered to the group yet (unknown before redis 7).
+    pub lag: Option<usize>,
+    /// Number of the consumers registered in the group across the replicas.
+    pub consumers: usize,
+    /// Number of the workers required to keep up with the backlog.
+    pub desired_workers: usize,
+}
+
+impl ConsumerGroupStatus {
+    fn new(pending: usize, lag: Option<usize>, consumers: usize, target_lag_per_worker: usize) -> Self {
+        let backlog = pending + lag.unwrap_or(0);
+        let desired_workers = backlog.div_ceil(target_lag_per_worker.max(1)).max(1);
+        Self {
+            pending,
+            lag,
+            consumers,
+            desired_workers,
+        }
+    }
+}
+
+struct ConsumerGroupMetrics {
+    pending: Gauge<u64>,
+    lag: Gauge<u64>,
+    desired_workers: Gauge<u64>,
+}
+
+/// Run a configured number of stream consumers in a consumer group and report the backlog of the group.
+/// The `desired_workers` of the status (and metric) can drive an external autoscaler.
+pub struct ConsumerGroupManager<T> {
+    config: ConsumerGroupConfig,
+    replica_id: String,
+    redis: RedisConnectionPool,
+    metrics: Option<ConsumerGroupMetrics>,
+    _phantom: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T> ConsumerGroupManager<T>
+where
+    T: FromRedisValue + Send + 'static,
+{
+    /// Create the manager, the consumers are named by the replica id and the index of the worker.
+    pub fn new(config: ConsumerGroupConfig, replica_id: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            config,
+            replica_id: replica_id.to_string(),
+            redis,
+            metrics: None,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            metrics: Some(ConsumerGroupMetrics {
+                pending: meter.u64_gauge("stream_consumer_pending").init(),
+                lag: meter.u64_gauge("stream_consumer_lag").init(),
+                desired_workers: meter.u64_gauge("stream_consumer_desired_workers").init(),
+            }),
+            ..self
+        }
+    }
+
+    /// Query the backlog of the group.
+    pub async fn status(&self) -> Result<ConsumerGroupStatus, RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let groups: Vec<HashMap<String, RedisValue>> = redis::cmd("XINFO")
+            .arg("GROUPS")
+            .arg(&self.config.stream)
+            .query_async(&mut *client)
+            .await?;
+
+        let group = groups.into_iter().find(|group| {
+            group
+                .get("name")
+                .and_then(|name| String::from_redis_value(name).ok())
+                .is_some_and(|name| name == self.config.group)
+        });
+        let Some(group) = group else {
+            return Ok(ConsumerGroupStatus::new(0, None, 0, self.config.target_lag_per_worker));
+        };
+
+        let field = |name: &str| {
+            group
+                .get(name)
+                .and_then(|value| Option::<usize>::from_redis_value(value).ok())
+                .flatten()
+        };
+        Ok(ConsumerGroupStatus::new(
+            field("pending").unwrap_or(0),
+            field("lag"),
+            field("consumers").unwrap_or(0),
+            self.config.target_lag_per_worker,
+        ))
+    }
+
+    fn record(&self, status: &ConsumerGroupStatus) {
+        if let Some(metrics) = &self.metrics {
+            let attributes = [
+                KeyValue::new("stream", self.config.stream.clone()),
+                KeyValue::new("group", self.config.group.clone()),
+            ];
+            metrics.pending.record(status.pending as u64, &attributes);
+            if let Some(lag) = status.lag {
+                metrics.lag.record(lag as u64, &attributes);
+            }
+            metrics
+                .desired_workers
+                .record(status.desired_workers as u64, &attributes);
+        }
+    }
+
+    /// Start the workers and the backlog monitoring.
+    pub fn start<F, Fut, E>(self, handler: F) -> ConsumerGroupHandle
+    where
+        F: Fn(StreamMessage<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send,
+        E: StdError + Send,
+    {
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let handler = Arc::new(handler);
+
+        let workers = (0..self.config.workers.max(1))
+            .map(|index| {
+                let consumer = format!("{}-{}", self.replica_id, index);
+                let handler = handler.clone();
+                RedisStreamConsumer::<T>::new(&self.config.stream, &self.config.group, &consumer, self.redis.clone())
+                    .with_batch_size(self.config.batch_size)
+                    .with_claim_idle(self.config.claim_idle.into())
+                    .with_max_deliveries(self.config.max_deliveries)
+                    .with_dead_letter(Some(
+                        self.config
+                            .dead_letter
+                            .clone()
+                            .unwrap_or_else(|| format!("{}:dead-letter", self.config.stream)),
+                    ))
+                    .start_with_shutdown(move |message| handler.as_ref()(message), shutdown.clone())
+            })
+            .collect();
+
+        let status = Arc::new(watch::channel(ConsumerGroupStatus::default()).0);
+        let monitor = {
+            let status = status.clone();
+            let mut shutdown = shutdown.clone();
+            let interval = self.config.monitor_interval.as_duration().max(Duration::from_secs(1));
+            tokio::spawn(async move {
+                loop {
+                    match self.status().await {
+                        Ok(current) => {
+                            self.record(&current);
+                            status.send_replace(current);
+                        }
+                        Err(err) => log::warn!("Failed to query the status of {}: {err:?}", self.config.stream),
+                    }
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {}
+                        _ = shutdown.changed() => break,
+                    }
+                }
+            })
+        };
+
+        ConsumerGroupHandle {
+            shutdown: shutdown_sender,
+            status,
+            workers,
+            monitor,
+        }
+    }
+}
+
+pub struct ConsumerGroupHandle {
+    shutdown: watch::Sender<bool>,
+    status: Arc<watch::Sender<ConsumerGroupStatus>>,
+    workers: Vec<JoinHandle<()>>,
+    monitor: JoinHandle<()>,
+}
+
+impl ConsumerGroupHandle {
+    /// The last measured backlog of the group.
+    pub fn status(&self) -> ConsumerGroupStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Watch the measured backlog of the group, ex. for the status dashboard.
+    pub fn status_watch(&self) -> watch::Receiver<ConsumerGroupStatus> {
+        self.status.subscribe()
+    }
+
+    /// Stop the workers after their current batch. The consumers without pending messages leave
+    /// the group, the pending messages of the others are claimed by the remaining members.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        for worker in self.workers {
+            if let Err(err) = worker.await {
+                log::error!("Stream worker failed: {err:?}");
+            }
+        }
+        let _ = self.monitor.await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn desired_workers() {
+        assert_eq!(ConsumerGroupStatus::new(0, None, 1, 100).desired_workers, 1);
+        assert_eq!(ConsumerGroupStatus::new(50, Some(100), 1, 100).desired_workers, 2);
+        assert_eq!(ConsumerGroupStatus::new(0, Some(1000), 1, 100).desired_workers, 10);
+        assert_eq!(ConsumerGroupStatus::new(10, Some(10), 1, 0).desired_workers, 20);
+    }
+}
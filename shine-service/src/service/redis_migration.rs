@@ -0,0 +1,316 @@
+use crate::service::{RedisConnectionError, RedisConnectionPool, ScanCursor, ScanCursorError};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use redis::Script;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Replace the value only if it has not been changed since it was read, the expiration is kept.
+const COMPARE_AND_SET_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[2], 'KEEPTTL')
+    return 1
+end
+return 0
+"#;
+
+/// Replace the value of a hash field only if it has not been changed since it was read.
+const HASH_COMPARE_AND_SET_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then
+    redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+    return 1
+end
+return 0
+"#;
+
+#[derive(Debug, ThisError)]
+pub enum RedisMigrationError {
+    #[error("Failed to get redis connection")]
+    RedisPoolError(#[source] RedisConnectionError),
+    #[error("Redis error")]
+    RedisError(#[from] redis::RedisError),
+    #[error(transparent)]
+    ScanCursorError(#[from] ScanCursorError),
+    #[error("Failed to transcode value: {0}")]
+    Transcode(String),
+    #[error("Invalid migration configuration: {0}")]
+    InvalidConfig(String),
+}
+
+/// Conversion of the stored values into a new format.
+pub trait RedisValueTranscoder: Send + Sync {
+    /// Return the value in the new format or None if the value is already in the new format.
+    fn transcode(&self, value: &[u8]) -> Result<Option<Vec<u8>>, RedisMigrationError>;
+}
+
+impl<F> RedisValueTranscoder for F
+where
+    F: Fn(&[u8]) -> Result<Option<Vec<u8>>, RedisMigrationError> + Send + Sync,
+{
+    fn transcode(&self, value: &[u8]) -> Result<Option<Vec<u8>>, RedisMigrationError> {
+        (self)(value)
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub fn is_gzip(value: &[u8]) -> bool {
+    value.starts_with(&GZIP_MAGIC)
+}
+
+pub fn gzip_encode(value: &[u8]) -> Result<Vec<u8>, RedisMigrationError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(value)
+        .and_then(|_| encoder.finish())
+        .map_err(|err| RedisMigrationError::Transcode(format!("{err}")))
+}
+
+pub fn gzip_decode(value: &[u8]) -> Result<Vec<u8>, RedisMigrationError> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(value)
+        .read_to_end(&mut decoded)
+        .map_err(|err| RedisMigrationError::Transcode(format!("{err}")))?;
+    Ok(decoded)
+}
+
+/// Get the json payload of a stored value, the gzip compressed values are decompressed. It is used by the
+/// `RedisJsonValue` derive, thus the json values can be read both before and after a `GzipTranscoder` migration.
+pub fn redis_json_payload(value: &[u8]) -> Result<Cow<'_, [u8]>, RedisMigrationError> {
+    if is_gzip(value) {
+        gzip_decode(value).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(value))
+    }
+}
+
+/// Compress the plain values with gzip, the compressed values are left intact. Only the values read through
+/// the `RedisJsonValue` derive are decompressed transparently, the pattern of the migration should not match
+/// any other key.
+pub struct GzipTranscoder;
+
+impl RedisValueTranscoder for GzipTranscoder {
+    fn transcode(&self, value: &[u8]) -> Result<Option<Vec<u8>>, RedisMigrationError> {
+        if is_gzip(value) {
+            Ok(None)
+        } else {
+            gzip_encode(value).map(Some)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedisMigrationProgress {
+    /// Number of the visited keys.
+    pub scanned: usize,
+    /// Number of the re-encoded values.
+    pub migrated: usize,
+    /// Number of the values already in the new format, neither strings nor hashes or removed during the migration.
+    pub skipped: usize,
+    /// Number of the values failed to transcode or changed concurrently.
+    pub failed: usize,
+    /// Token to resume the migration, None when the migration has completed.
+    pub token: Option<String>,
+}
+
+/// Re-encode the string values and the hash fields of a keyspace into a new format at a controlled rate.
+/// The values are replaced atomically only if they were not changed in the meantime and the
+/// expiration of the keys is preserved.
+pub struct RedisValueMigration {
+    pattern: String,
+    batch_size: usize,
+    max_keys_per_second: Option<u32>,
+    script: Script,
+    hash_script: Script,
+    redis: RedisConnectionPool,
+}
+
+impl RedisValueMigration {
+    /// Create a migration for the keys matching the pattern, ex: `{prefix}session:*:data`
+    pub fn new(pattern: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_keys_per_second: None,
+            script: Script::new(COMPARE_AND_SET_SCRIPT),
+            hash_script: Script::new(HASH_COMPARE_AND_SET_SCRIPT),
+            redis,
+        }
+    }
+
+    /// Number of the keys of a page, it must be positive.
+    #[must_use]
+    pub fn with_batch_size(self, batch_size: usize) -> Self {
+        Self { batch_size, ..self }
+    }
+
+    /// Limit the number of the processed keys per second.
+    #[must_use]
+    pub fn with_rate(self, max_keys_per_second: u32) -> Self {
+        Self {
+            max_keys_per_second: Some(max_keys_per_second),
+            ..self
+        }
+    }
+
+    /// Run the migration, optionally resuming it from the token of a previous (interrupted) run.
+    /// The progress is reported after each page.
+    pub async fn run<T, P>(
+        &self,
+        transcoder: &T,
+        resume_token: Option<&str>,
+        mut on_progress: P,
+    ) -> Result<RedisMigrationProgress, RedisMigrationError>
+    where
+        T: RedisValueTranscoder,
+        P: FnMut(&RedisMigrationProgress),
+    {
+        if self.batch_size == 0 {
+            return Err(RedisMigrationError::InvalidConfig("batch size must be positive".into()));
+        }
+
+        let mut cursor = ScanCursor::keys(Some(&self.pattern)).with_count(self.batch_size);
+        if let Some(token) = resume_token {
+            cursor = cursor.resume(token)?;
+        }
+
+        let mut progress = RedisMigrationProgress::default();
+        loop {
+            let started_at = Instant::now();
+            let keys: Vec<String> = {
+                let mut client = self.redis.get().await.map_err(RedisMigrationError::RedisPoolError)?;
+                match cursor.next_page(&mut *client).await? {
+                    Some(keys) => keys,
+                    None => break,
+                }
+            };
+
+            for key in &keys {
+                progress.scanned += 1;
+                match self.migrate_key(transcoder, key).await {
+                    Ok(true) => progress.migrated += 1,
+                    Ok(false) => progress.skipped += 1,
+                    Err(RedisMigrationError::Transcode(err)) => {
+                        log::warn!("Failed to migrate {key}: {err}");
+                        progress.failed += 1;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+
+            progress.token = cursor.token();
+            log::info!(
+                "Redis migration of {}: scanned: {}, migrated: {}, skipped: {}, failed: {}",
+                self.pattern,
+                progress.scanned,
+                progress.migrated,
+                progress.skipped,
+                progress.failed
+            );
+            on_progress(&progress);
+
+            if let Some(rate) = self.max_keys_per_second.filter(|rate| *rate > 0) {
+                let budget = Duration::from_secs_f64(keys.len() as f64 / rate as f64);
+                let elapsed = started_at.elapsed();
+                if budget > elapsed {
+                    tokio::time::sleep(budget - elapsed).await;
+                }
+            }
+        }
+
+        Ok(progress)
+    }
+
+    /// Migrate a single key, return if any value was replaced.
+    async fn migrate_key<T>(&self, transcoder: &T, key: &str) -> Result<bool, RedisMigrationError>
+    where
+        T: RedisValueTranscoder,
+    {
+        let mut client = self.redis.get().await.map_err(RedisMigrationError::RedisPoolError)?;
+
+        let key_type: String = redis::cmd("TYPE").arg(key).query_async(&mut *client).await?;
+        match key_type.as_str() {
+            "string" => {
+                let value: Option<Vec<u8>> = redis::cmd("GET").arg(key).query_async(&mut *client).await?;
+                let Some(value) = value else {
+                    return Ok(false);
+                };
+                let Some(encoded) = transcoder.transcode(&value)? else {
+                    return Ok(false);
+                };
+
+                let replaced: i32 = self
+                    .script
+                    .key(key)
+                    .arg(value)
+                    .arg(encoded)
+                    .invoke_async(&mut *client)
+                    .await?;
+                Self::check_replaced(replaced)?;
+                Ok(true)
+            }
+            "hash" => {
+                let fields: Vec<(Vec<u8>, Vec<u8>)> = redis::cmd("HGETALL").arg(key).query_async(&mut *client).await?;
+                let mut migrated = false;
+                for (field, value) in fields {
+                    let Some(encoded) = transcoder.transcode(&value)? else {
+                        continue;
+                    };
+
+                    let replaced: i32 = self
+                        .hash_script
+                        .key(key)
+                        .arg(field)
+                        .arg(value)
+                        .arg(encoded)
+                        .invoke_async(&mut *client)
+                        .await?;
+                    Self::check_replaced(replaced)?;
+                    migrated = true;
+                }
+                Ok(migrated)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn check_replaced(replaced: i32) -> Result<(), RedisMigrationError> {
+        if replaced == 1 {
+            Ok(())
+        } else {
+            Err(RedisMigrationError::Transcode(
+                "Value changed during the migration".into(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn gzip_transcoder() {
+        let plain = br#"{"name":"user","roles":[]}"#;
+        let encoded = GzipTranscoder.transcode(plain).unwrap().unwrap();
+        assert!(is_gzip(&encoded));
+        assert!(GzipTranscoder.transcode(&encoded).unwrap().is_none());
+        assert_eq!(gzip_decode(&encoded).unwrap(), plain);
+    }
+
+    #[test]
+    fn json_payload() {
+        let plain = br#"{"name":"user","roles":[]}"#;
+        assert!(matches!(redis_json_payload(plain).unwrap(), Cow::Borrowed(_)));
+        let encoded = gzip_encode(plain).unwrap();
+        assert_eq!(&redis_json_payload(&encoded).unwrap()[..], plain);
+    }
+}
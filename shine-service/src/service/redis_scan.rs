@@ -0,0 +1,192 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use redis::{aio::ConnectionLike, FromRedisValue, RedisError};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error as ThisError;
+
+const DEFAULT_SCAN_COUNT: usize = 100;
+
+#[derive(Debug, ThisError)]
+pub enum ScanCursorError {
+    #[error("Invalid cursor token")]
+    InvalidToken,
+    #[error("Cursor token belongs to a different scan")]
+    TokenMismatch,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum ScanCommand {
+    #[serde(rename = "k")]
+    Keys,
+    #[serde(rename = "h")]
+    Hash(String),
+    #[serde(rename = "s")]
+    Set(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScanToken {
+    #[serde(rename = "c")]
+    command: ScanCommand,
+    #[serde(rename = "p")]
+    pattern: Option<String>,
+    #[serde(rename = "i")]
+    cursor: u64,
+}
+
+/// Iterate over a (large) keyspace with SCAN, HSCAN or SSCAN a page at a time.
+/// The iteration can be suspended and resumed through an opaque token.
+#[derive(Clone, Debug)]
+pub struct ScanCursor {
+    command: ScanCommand,
+    pattern: Option<String>,
+    count: usize,
+    delay: Option<Duration>,
+    cursor: u64,
+    started: bool,
+}
+
+impl ScanCursor {
+    fn new(command: ScanCommand, pattern: Option<&str>) -> Self {
+        Self {
+            command,
+            pattern: pattern.map(|p| p.to_string()),
+            count: DEFAULT_SCAN_COUNT,
+            delay: None,
+            cursor: 0,
+            started: false,
+        }
+    }
+
+    /// Scan the keys of the database (SCAN).
+    pub fn keys(pattern: Option<&str>) -> Self {
+        Self::new(ScanCommand::Keys, pattern)
+    }
+
+    /// Scan the field-value pairs of a hash (HSCAN).
+    pub fn hash(key: &str, pattern: Option<&str>) -> Self {
+        Self::new(ScanCommand::Hash(key.to_string()), pattern)
+    }
+
+    /// Scan the members of a set (SSCAN).
+    pub fn set(key: &str, pattern: Option<&str>) -> Self {
+        Self::new(ScanCommand::Set(key.to_string()), pattern)
+    }
+
+    /// Hint for the number of elements returned in a page.
+    #[must_use]
+    pub fn with_count(self, count: usize) -> Self {
+        Self { count, ..self }
+    }
+
+    /// Wait between the consecutive pages to limit the load on the server.
+    #[must_use]
+    pub fn with_delay(self, delay: Duration) -> Self {
+        Self {
+            delay: Some(delay),
+            ..self
+        }
+    }
+
+    /// Resume the iteration from a token returned by a previous scan with the same parameters.
+    pub fn resume(self, token: &str) -> Result<Self, ScanCursorError> {
+        let raw = B64.decode(token).map_err(|_| ScanCursorError::InvalidToken)?;
+        let token: ScanToken = serde_json::from_slice(&raw).map_err(|_| ScanCursorError::InvalidToken)?;
+        if token.command != self.command || token.pattern != self.pattern {
+            return Err(ScanCursorError::TokenMismatch);
+        }
+
+        Ok(Self {
+            cursor: token.cursor,
+            started: true,
+            ..self
+        })
+    }
+
+    /// Return if the iteration has completed.
+    pub fn is_finished(&self) -> bool {
+        self.started && self.cursor == 0
+    }
+
+    /// Token to resume the iteration later, None if the iteration has completed.
+    pub fn token(&self) -> Option<String> {
+        if self.is_finished() {
+            return None;
+        }
+
+        let token = ScanToken {
+            command: self.command.clone(),
+            pattern: self.pattern.clone(),
+            cursor: self.cursor,
+        };
+        Some(B64.encode(serde_json::to_vec(&token).expect("Scan token encoding failed")))
+    }
+
+    /// Query the next page. For hashes use `(field, value)` tuples as the item type.
+    /// Pages may be empty even if the iteration is not completed. Returns None when the iteration has completed.
+    pub async fn next_page<C, T>(&mut self, client: &mut C) -> Result<Option<Vec<T>>, RedisError>
+    where
+        C: ConnectionLike + Send,
+        T: FromRedisValue,
+    {
+        if self.is_finished() {
+            return Ok(None);
+        }
+        if self.started {
+            if let Some(delay) = self.delay {
+                tokio::time::sleep(delay).await;
+            }
+        }
+
+        let mut cmd = match &self.command {
+            ScanCommand::Keys => redis::cmd("SCAN"),
+            ScanCommand::Hash(key) => {
+                let mut cmd = redis::cmd("HSCAN");
+                cmd.arg(key);
+                cmd
+            }
+            ScanCommand::Set(key) => {
+                let mut cmd = redis::cmd("SSCAN");
+                cmd.arg(key);
+                cmd
+            }
+        };
+        cmd.arg(self.cursor);
+        if let Some(pattern) = &self.pattern {
+            cmd.arg("MATCH").arg(pattern);
+        }
+        cmd.arg("COUNT").arg(self.count);
+
+        let (cursor, items): (u64, Vec<T>) = cmd.query_async(client).await?;
+        self.cursor = cursor;
+        self.started = true;
+        Ok(Some(items))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn resume_token() {
+        let mut cursor = ScanCursor::hash("session:1", Some("*"));
+        assert!(!cursor.is_finished());
+        cursor.cursor = 42;
+        cursor.started = true;
+
+        let token = cursor.token().unwrap();
+        let resumed = ScanCursor::hash("session:1", Some("*")).resume(&token).unwrap();
+        assert_eq!(resumed.cursor, 42);
+        assert!(!resumed.is_finished());
+
+        assert!(ScanCursor::hash("session:2", Some("*")).resume(&token).is_err());
+        assert!(ScanCursor::keys(Some("*")).resume(&token).is_err());
+        assert!(ScanCursor::keys(None).resume("invalid").is_err());
+
+        cursor.cursor = 0;
+        assert!(cursor.is_finished());
+        assert!(cursor.token().is_none());
+    }
+}
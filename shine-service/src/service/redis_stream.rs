@@ -0,0 +1,493 @@
+#[cfg(feature = "openapi")]
+use crate::service::{EventSchemaError, EventSchemaRegistry};
+use crate::{
+    axum::telemetry::TraceContext,
+    service::{RedisConnectionError, RedisConnectionPool},
+};
+use redis::{
+    streams::{
+        StreamAutoClaimOptions, StreamAutoClaimReply, StreamId, StreamPendingCountReply, StreamReadOptions,
+        StreamReadReply,
+    },
+    AsyncCommands, FromRedisValue, RedisError, ToRedisArgs,
+};
+#[cfg(feature = "openapi")]
+use serde::Serialize;
+use std::{error::Error as StdError, future::Future, marker::PhantomData, sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{info_span, Instrument};
+
+pub(crate) const PAYLOAD_FIELD: &str = "payload";
+pub(crate) const TRACE_FIELD: &str = "trace";
+pub(crate) const SOURCE_ID_FIELD: &str = "sourceId";
+pub(crate) const REASON_FIELD: &str = "reason";
+const DEFAULT_MAX_DELIVERIES: usize = 5;
+
+#[derive(Debug, ThisError)]
+pub enum RedisStreamError {
+    #[error("Failed to get redis connection")]
+    RedisPoolError(#[source] RedisConnectionError),
+    #[error("Redis error")]
+    RedisError(#[from] RedisError),
+    #[cfg(feature = "openapi")]
+    #[error(transparent)]
+    SchemaError(#[from] EventSchemaError),
+}
+
+type PayloadValidator<T> = Arc<dyn Fn(&T) -> Result<(), RedisStreamError> + Send + Sync>;
+/// Validation of the payloads as received, before they are deserialized.
+type RawPayloadValidator = Arc<dyn Fn(&[u8]) -> Result<(), RedisStreamError> + Send + Sync>;
+
+/// A message read from a stream.
+#[derive(Clone, Debug)]
+pub struct StreamMessage<T> {
+    pub id: String,
+    /// Number of times the message was delivered, 1 for the new messages.
+    pub delivery_count: usize,
+    pub payload: T,
+    /// Trace context of the producer, the processing span is linked to it.
+    pub trace_context: Option<TraceContext>,
+}
+
+/// Append messages to a redis stream. The payload is stored in a single field, use the `RedisJsonValue`
+/// derive to store it as json. The trace context of the current span is stored along the payload.
+pub struct RedisStreamProducer<T> {
+    key: String,
+    max_len: Option<usize>,
+    validator: Option<PayloadValidator<T>>,
+    redis: RedisConnectionPool,
+    _phantom: PhantomData<fn(&T)>,
+}
+
+impl<T> RedisStreamProducer<T>
+where
+    T: ToRedisArgs + Send + Sync,
+{
+    pub fn new(key: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key: key.to_string(),
+            max_len: None,
+            validator: None,
+            redis,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Trim the stream approximately to the given length.
+    #[must_use]
+    pub fn with_max_len(self, max_len: usize) -> Self {
+        Self {
+            max_len: Some(max_len),
+            ..self
+        }
+    }
+
+    /// Validate the payloads against the latest schema of the event type before publishing.
+    #[cfg(feature = "openapi")]
+    #[must_use]
+    pub fn with_schema(self, registry: Arc<EventSchemaRegistry>, event_type: &str) -> Self
+    where
+        T: Serialize,
+    {
+        let event_type = event_type.to_string();
+        Self {
+            validator: Some(Arc::new(move |payload: &T| {
+                Ok(registry.validate_publish(&event_type, payload)?)
+            })),
+            ..self
+        }
+    }
+
+    /// Append a message and return its id.
+    pub async fn add(&self, payload: &T) -> Result<String, RedisStreamError> {
+        if let Some(validator) = &self.validator {
+            validator(payload)?;
+        }
+
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let mut cmd = redis::cmd("XADD");
+        cmd.arg(&self.key);
+        if let Some(max_len) = self.max_len {
+            cmd.arg("MAXLEN").arg("~").arg(max_len);
+        }
+        cmd.arg("*").arg(PAYLOAD_FIELD).arg(payload);
+        if let Some(trace_context) = TraceContext::current() {
+            cmd.arg(TRACE_FIELD).arg(trace_context);
+        }
+        Ok(cmd.query_async(&mut *client).await?)
+    }
+}
+
+/// A message that cannot be processed, it is acknowledged and moved to the dead letter stream.
+struct RejectedMessage {
+    id: String,
+    payload: Option<Vec<u8>>,
+    trace: Option<Vec<u8>>,
+    reason: String,
+}
+
+impl RejectedMessage {
+    fn new(entry: &StreamId, reason: String) -> Self {
+        Self {
+            id: entry.id.clone(),
+            payload: entry.get::<Vec<u8>>(PAYLOAD_FIELD),
+            trace: entry.get::<Vec<u8>>(TRACE_FIELD),
+            reason,
+        }
+    }
+}
+
+/// Consume a redis stream as a member of a consumer group. The messages not acknowledged
+/// by a (crashed) consumer for `claim_idle` are claimed and processed again by the other members.
+/// The malformed messages and the messages delivered more than `max_deliveries` times are acknowledged
+/// and moved to the dead letter stream (`{key}:dead-letter` by default) not to be claimed forever.
+pub struct RedisStreamConsumer<T> {
+    key: String,
+    group: String,
+    consumer: String,
+    batch_size: usize,
+    block: Duration,
+    claim_idle: Duration,
+    max_deliveries: usize,
+    dead_letter: Option<String>,
+    validator: Option<RawPayloadValidator>,
+    redis: RedisConnectionPool,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> RedisStreamConsumer<T>
+where
+    T: FromRedisValue + Send + 'static,
+{
+    pub fn new(key: &str, group: &str, consumer: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key: key.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            batch_size: 10,
+            block: Duration::from_secs(5),
+            claim_idle: Duration::from_secs(60),
+            max_deliveries: DEFAULT_MAX_DELIVERIES,
+            dead_letter: Some(format!("{key}:dead-letter")),
+            validator: None,
+            redis,
+            _phantom: PhantomData,
+        }
+    }
+
+    #[must_use]
+    pub fn with_batch_size(self, batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            ..self
+        }
+    }
+
+    /// Maximum time to wait for new messages in a single read.
+    #[must_use]
+    pub fn with_block(self, block: Duration) -> Self {
+        Self { block, ..self }
+    }
+
+    /// Idle time after which the pending messages of the other consumers are claimed.
+    #[must_use]
+    pub fn with_claim_idle(self, claim_idle: Duration) -> Self {
+        Self { claim_idle, ..self }
+    }
+
+    /// Maximum number of the deliveries of a message, a message failing more times is rejected.
+    #[must_use]
+    pub fn with_max_deliveries(self, max_deliveries: usize) -> Self {
+        Self {
+            max_deliveries: max_deliveries.max(1),
+            ..self
+        }
+    }
+
+    /// The stream receiving the rejected messages, with None they are only acknowledged (dropped).
+    #[must_use]
+    pub fn with_dead_letter(self, dead_letter: Option<String>) -> Self {
+        Self { dead_letter, ..self }
+    }
+
+    /// Validate the payloads against the known schemas of the event type, the invalid messages are rejected
+    /// as the malformed ones. The payload is validated as received, before it is deserialized.
+    #[cfg(feature = "openapi")]
+    #[must_use]
+    pub fn with_schema(self, registry: Arc<EventSchemaRegistry>, event_type: &str) -> Self {
+        let event_type = event_type.to_string();
+        Self {
+            validator: Some(Arc::new(move |payload: &[u8]| {
+                Ok(registry.validate_consume(&event_type, payload)?)
+            })),
+            ..self
+        }
+    }
+
+    fn parse_messages(
+        &self,
+        ids: Vec<StreamId>,
+        delivery_counts: &[usize],
+    ) -> (Vec<StreamMessage<T>>, Vec<RejectedMessage>) {
+        let mut messages = Vec::with_capacity(ids.len());
+        let mut rejected = Vec::new();
+        for (entry, delivery_count) in ids.into_iter().zip(delivery_counts) {
+            if *delivery_count > self.max_deliveries {
+                let reason = format!("Delivered {delivery_count} times");
+                rejected.push(RejectedMessage::new(&entry, reason));
+                continue;
+            }
+
+            if let Some(validator) = &self.validator {
+                let raw = entry.get::<Vec<u8>>(PAYLOAD_FIELD).unwrap_or_default();
+                if let Err(err) = validator(&raw) {
+                    rejected.push(RejectedMessage::new(&entry, format!("Invalid payload: {err}")));
+                    continue;
+                }
+            }
+            let Some(payload) = entry.get::<T>(PAYLOAD_FIELD) else {
+                rejected.push(RejectedMessage::new(&entry, "Malformed payload".into()));
+                continue;
+            };
+
+            messages.push(StreamMessage {
+                trace_context: entry.get::<TraceContext>(TRACE_FIELD),
+                id: entry.id,
+                delivery_count: *delivery_count,
+                payload,
+            });
+        }
+        (messages, rejected)
+    }
+
+    /// Acknowledge the rejected messages and move them to the dead letter stream in a single transaction.
+    async fn reject(&self, rejected: Vec<RejectedMessage>) -> Result<(), RedisStreamError> {
+        if rejected.is_empty() {
+            return Ok(());
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for message in &rejected {
+            log::warn!(
+                "Rejecting message {} of stream {}: {}",
+                message.id,
+                self.key,
+                message.reason
+            );
+            if let Some(dead_letter) = &self.dead_letter {
+                let cmd = pipe.cmd("XADD").arg(dead_letter).arg("*");
+                if let Some(payload) = &message.payload {
+                    cmd.arg(PAYLOAD_FIELD).arg(payload);
+                }
+                if let Some(trace) = &message.trace {
+                    cmd.arg(TRACE_FIELD).arg(trace);
+                }
+                cmd.arg(SOURCE_ID_FIELD)
+                    .arg(&message.id)
+                    .arg(REASON_FIELD)
+                    .arg(&message.reason)
+                    .ignore();
+            }
+        }
+        let ids = rejected.iter().map(|message| message.id.as_str()).collect::<Vec<_>>();
+        pipe.xack(&self.key, &self.group, &ids).ignore();
+
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let _: () = pipe.query_async(&mut *client).await?;
+        Ok(())
+    }
+
+    /// Create the consumer group (and the stream) if it does not exist yet.
+    pub async fn ensure_group(&self) -> Result<(), RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let result: Result<(), RedisError> = client.xgroup_create_mkstream(&self.key, &self.group, "$").await;
+        match result {
+            Err(err) if err.code() == Some("BUSYGROUP") => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    /// Read the new messages of the group.
+    pub async fn read(&self) -> Result<Vec<StreamMessage<T>>, RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(self.batch_size)
+            .block(self.block.as_millis() as usize);
+        let reply: Option<StreamReadReply> = client.xread_options(&[&self.key], &[">"], &options).await?;
+        let ids: Vec<StreamId> = reply
+            .map(|reply| reply.keys.into_iter().flat_map(|key| key.ids).collect())
+            .unwrap_or_default();
+        drop(client);
+
+        let delivery_counts = vec![1; ids.len()];
+        let (messages, rejected) = self.parse_messages(ids, &delivery_counts);
+        self.reject(rejected).await?;
+        Ok(messages)
+    }
+
+    /// Take over the messages left pending by the other consumers for too long.
+    pub async fn claim_pending(&self) -> Result<Vec<StreamMessage<T>>, RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let options = StreamAutoClaimOptions::default().count(self.batch_size);
+        let reply: StreamAutoClaimReply = client
+            .xautoclaim_options(
+                &self.key,
+                &self.group,
+                &self.consumer,
+                self.claim_idle.as_millis() as usize,
+                "0-0",
+                options,
+            )
+            .await?;
+        if reply.claimed.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // the delivery counter is incremented by the claim, query the current values
+        let mut pipe = redis::pipe();
+        for entry in &reply.claimed {
+            pipe.xpending_count(&self.key, &self.group, &entry.id, &entry.id, 1);
+        }
+        let pending: Vec<StreamPendingCountReply> = pipe.query_async(&mut *client).await?;
+        let delivery_counts = pending
+            .iter()
+            .map(|pending| pending.ids.first().map(|id| id.times_delivered).unwrap_or(1))
+            .collect::<Vec<_>>();
+        drop(client);
+
+        let (messages, rejected) = self.parse_messages(reply.claimed, &delivery_counts);
+        self.reject(rejected).await?;
+        Ok(messages)
+    }
+
+    /// Acknowledge the processed messages.
+    pub async fn ack(&self, ids: &[String]) -> Result<(), RedisStreamError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let _: usize = client.xack(&self.key, &self.group, ids).await?;
+        Ok(())
+    }
+
+    /// Remove the consumer from the group if it has no pending messages, otherwise the messages are
+    /// left to be claimed by the other members. Return if the consumer was removed.
+    pub async fn leave_group(&self) -> Result<bool, RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let pending: (usize, Option<String>, Option<String>, Option<Vec<(String, usize)>>) = redis::cmd("XPENDING")
+            .arg(&self.key)
+            .arg(&self.group)
+            .query_async(&mut *client)
+            .await?;
+        let own_pending = pending
+            .3
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(consumer, _)| *consumer == self.consumer)
+            .map(|(_, count)| count)
+            .unwrap_or(0);
+        if own_pending > 0 {
+            log::info!(
+                "Consumer {} leaves {} pending message(s) in stream {} to be claimed",
+                self.consumer,
+                own_pending,
+                self.key
+            );
+            return Ok(false);
+        }
+
+        let _: usize = redis::cmd("XGROUP")
+            .arg("DELCONSUMER")
+            .arg(&self.key)
+            .arg(&self.group)
+            .arg(&self.consumer)
+            .query_async(&mut *client)
+            .await?;
+        Ok(true)
+    }
+
+    /// Start processing the messages. A message is acknowledged when the handler succeeds,
+    /// failed messages stay pending and they are retried after the `claim_idle` period until the
+    /// `max_deliveries` is reached.
+    pub fn start<F, Fut, E>(self, handler: F) -> JoinHandle<()>
+    where
+        F: Fn(StreamMessage<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send,
+        E: StdError + Send,
+    {
+        let (_, shutdown) = watch::channel(false);
+        self.start_with_shutdown(handler, shutdown)
+    }
+
+    /// Start processing the messages until a shutdown is signaled. The batch in progress is completed
+    /// and the consumer leaves the group before the task finishes.
+    pub fn start_with_shutdown<F, Fut, E>(self, handler: F, shutdown: watch::Receiver<bool>) -> JoinHandle<()>
+    where
+        F: Fn(StreamMessage<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send,
+        E: StdError + Send,
+    {
+        tokio::spawn(async move {
+            while let Err(err) = self.ensure_group().await {
+                log::error!(
+                    "Failed to create consumer group {} for {}: {err:?}",
+                    self.group,
+                    self.key
+                );
+                tokio::time::sleep(self.block).await;
+            }
+
+            while !*shutdown.borrow() {
+                let messages = match self.claim_pending().await {
+                    Ok(messages) if !messages.is_empty() => Ok(messages),
+                    Ok(_) => self.read().await,
+                    Err(err) => Err(err),
+                };
+
+                let messages = match messages {
+                    Ok(messages) => messages,
+                    Err(err) => {
+                        log::error!("Failed to read stream {}: {err:?}", self.key);
+                        tokio::time::sleep(self.block).await;
+                        continue;
+                    }
+                };
+
+                let mut processed = Vec::with_capacity(messages.len());
+                for message in messages {
+                    let id = message.id.clone();
+                    // each message starts a new trace linked to the trace of the producer
+                    let span = info_span!(
+                        parent: None,
+                        "stream.process",
+                        messaging.destination.name = %self.key,
+                        messaging.message.id = %id,
+                        messaging.consumer.group.name = %self.group,
+                    );
+                    if let Some(trace_context) = &message.trace_context {
+                        trace_context.link_to(&span);
+                    }
+                    match handler(message).instrument(span).await {
+                        Ok(()) => processed.push(id),
+                        Err(err) => log::warn!("Failed to handle message {id} of stream {}: {err}", self.key),
+                    }
+                }
+
+                if let Err(err) = self.ack(&processed).await {
+                    log::error!("Failed to acknowledge messages of stream {}: {err:?}", self.key);
+                }
+            }
+
+            if let Err(err) = self.leave_group().await {
+                log::warn!(
+                    "Consumer {} failed to leave stream {}: {err:?}",
+                    self.consumer,
+                    self.key
+                );
+            }
+        })
+    }
+}
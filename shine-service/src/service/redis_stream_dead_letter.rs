@@ -0,0 +1,329 @@
+#[cfg(feature = "openapi")]
+use crate::{
+    axum::{IntoProblem, Problem, ProblemConfig},
+    service::AdminCaller,
+};
+use crate::{
+    service::{
+        redis_json_payload, RedisConnectionPool, RedisStreamError, PAYLOAD_FIELD, REASON_FIELD, SOURCE_ID_FIELD,
+        TRACE_FIELD,
+    },
+    utils::{redact_json, REDACTED},
+};
+#[cfg(feature = "openapi")]
+use axum::{
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use redis::{
+    streams::{StreamId, StreamRangeReply},
+    AsyncCommands, Script,
+};
+#[cfg(feature = "openapi")]
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+#[cfg(feature = "openapi")]
+use std::sync::Arc;
+
+const MAX_LIST_LIMIT: usize = 1000;
+
+/// Move a dead-letter back to the source stream if it has a payload, only the payload and the trace are kept.
+const REQUEUE_SCRIPT: &str = r#"
+local entries = redis.call('XRANGE', KEYS[1], ARGV[1], ARGV[1])
+if #entries == 0 then
+    return 0
+end
+local fields = entries[1][2]
+local message = {}
+local has_payload = false
+for i = 1, #fields, 2 do
+    if fields[i] == ARGV[2] or fields[i] == ARGV[3] then
+        has_payload = has_payload or fields[i] == ARGV[2]
+        table.insert(message, fields[i])
+        table.insert(message, fields[i + 1])
+    end
+end
+if not has_payload then
+    return 0
+end
+redis.call('XADD', KEYS[2], '*', unpack(message))
+redis.call('XDEL', KEYS[1], ARGV[1])
+return 1
+"#;
+
+#[cfg(feature = "openapi")]
+impl IntoProblem for RedisStreamError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        match self {
+            RedisStreamError::RedisPoolError(err) => Problem::internal_error(config, "Redis connection error", err),
+            RedisStreamError::RedisError(err) => Problem::internal_error(config, "Redis error", err),
+            RedisStreamError::SchemaError(err) => Problem::internal_error(config, "Event schema error", err),
+        }
+    }
+}
+
+/// A message moved to the dead letter stream by a `RedisStreamConsumer`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamDeadLetter {
+    pub id: String,
+    /// The id of the message in the source stream.
+    pub source_id: Option<String>,
+    pub reason: Option<String>,
+    /// The payload with the sensitive fields redacted, present only when a single message is queried.
+    /// The payloads that are not json are fully redacted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<JsonValue>,
+}
+
+impl StreamDeadLetter {
+    fn from_entry(entry: &StreamId, with_payload: bool) -> Self {
+        let payload = if with_payload {
+            entry.get::<Vec<u8>>(PAYLOAD_FIELD).map(|payload| {
+                redis_json_payload(&payload)
+                    .ok()
+                    .and_then(|payload| serde_json::from_slice::<JsonValue>(&payload).ok())
+                    .map(|payload| redact_json(&payload))
+                    .unwrap_or_else(|| JsonValue::String(REDACTED.to_string()))
+            })
+        } else {
+            None
+        };
+        Self {
+            id: entry.id.clone(),
+            source_id: entry.get(SOURCE_ID_FIELD),
+            reason: entry.get(REASON_FIELD),
+            payload,
+        }
+    }
+}
+
+/// Inspect, requeue and purge the dead-lettered messages of a redis stream, the counterpart of the
+/// `OutboxDeadLetters` for the streams.
+pub struct RedisStreamDeadLetters {
+    key: String,
+    dead_letter: String,
+    requeue_script: Script,
+    redis: RedisConnectionPool,
+}
+
+impl RedisStreamDeadLetters {
+    /// Create the inspector of the stream, the dead letter stream is `{key}:dead-letter` as for the consumer.
+    pub fn new(key: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key: key.to_string(),
+            dead_letter: format!("{key}:dead-letter"),
+            requeue_script: Script::new(REQUEUE_SCRIPT),
+            redis,
+        }
+    }
+
+    /// Use the same dead letter stream as given for the `RedisStreamConsumer::with_dead_letter`.
+    #[must_use]
+    pub fn with_dead_letter(self, dead_letter: &str) -> Self {
+        Self {
+            dead_letter: dead_letter.to_string(),
+            ..self
+        }
+    }
+
+    /// List the dead-letters ordered by id, starting after the given id.
+    pub async fn list(&self, after: Option<&str>, limit: usize) -> Result<Vec<StreamDeadLetter>, RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let start = after.map(|id| format!("({id}")).unwrap_or_else(|| "-".to_string());
+        let limit = limit.clamp(1, MAX_LIST_LIMIT);
+        let reply: StreamRangeReply = client.xrange_count(&self.dead_letter, start, "+", limit).await?;
+        Ok(reply
+            .ids
+            .iter()
+            .map(|entry| StreamDeadLetter::from_entry(entry, false))
+            .collect())
+    }
+
+    /// Get a dead-letter with its (redacted) payload.
+    pub async fn find(&self, id: &str) -> Result<Option<StreamDeadLetter>, RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let reply: StreamRangeReply = client.xrange_count(&self.dead_letter, id, id, 1).await?;
+        Ok(reply.ids.first().map(|entry| StreamDeadLetter::from_entry(entry, true)))
+    }
+
+    /// Append the selected dead-letters to the source stream as new messages and remove them from the
+    /// dead letter stream. The dead-letters without a payload are kept.
+    pub async fn requeue(&self, ids: &[String]) -> Result<u64, RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let mut count = 0;
+        for id in ids {
+            let requeued: u64 = self
+                .requeue_script
+                .key(&self.dead_letter)
+                .key(&self.key)
+                .arg(id)
+                .arg(PAYLOAD_FIELD)
+                .arg(TRACE_FIELD)
+                .invoke_async(&mut *client)
+                .await?;
+            count += requeued;
+        }
+        Ok(count)
+    }
+
+    /// Delete the selected dead-letters, or all of them if no ids are given.
+    pub async fn purge(&self, ids: Option<&[String]>) -> Result<u64, RedisStreamError> {
+        let mut client = self.redis.get().await.map_err(RedisStreamError::RedisPoolError)?;
+        let count = match ids {
+            Some([]) => 0,
+            Some(ids) => client.xdel(&self.dead_letter, ids).await?,
+            None => {
+                let (count,): (u64,) = redis::pipe()
+                    .atomic()
+                    .xlen(&self.dead_letter)
+                    .del(&self.dead_letter)
+                    .ignore()
+                    .query_async(&mut *client)
+                    .await?;
+                count
+            }
+        };
+        Ok(count)
+    }
+
+    /// Create the admin routes, they are authorized by the `AdminCaller`, see `AdminRouter::with_routes`.
+    /// Each action is logged with the `audit` target.
+    ///  - GET /admin/streams/{key}/dead-letters?after=ID&limit=N
+    ///  - GET /admin/streams/{key}/dead-letters/:id
+    ///  - POST /admin/streams/{key}/dead-letters/requeue { ids }
+    ///  - POST /admin/streams/{key}/dead-letters/purge { ids? }
+    #[cfg(feature = "openapi")]
+    pub fn into_router<S>(self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        #[derive(Deserialize)]
+        struct ListQuery {
+            after: Option<String>,
+            limit: Option<usize>,
+        }
+
+        #[derive(Deserialize)]
+        struct SelectionRequest {
+            ids: Option<Vec<String>>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ActionResponse {
+            affected: u64,
+        }
+
+        fn into_response<T: Serialize>(config: &ProblemConfig, result: Result<T, RedisStreamError>) -> Response {
+            match result {
+                Ok(value) => Json(value).into_response(),
+                Err(err) => err.into_problem(config).into_response(),
+            }
+        }
+
+        let path = format!("/admin/streams/{}/dead-letters", self.key);
+        let dead_letters = Arc::new(self);
+
+        let list_route = {
+            let dead_letters = dead_letters.clone();
+            get(
+                move |Extension(config): Extension<ProblemConfig>,
+                      caller: AdminCaller,
+                      Query(query): Query<ListQuery>| async move {
+                    log::info!(target: "audit", "{caller:?} listed the dead-letters of {}", dead_letters.key);
+                    let limit = query.limit.unwrap_or(100);
+                    into_response(&config, dead_letters.list(query.after.as_deref(), limit).await)
+                },
+            )
+        };
+
+        let find_route = {
+            let dead_letters = dead_letters.clone();
+            get(
+                move |Extension(config): Extension<ProblemConfig>, caller: AdminCaller, Path(id): Path<String>| async move {
+                    log::info!(target: "audit", "{caller:?} viewed the dead-letter {id} of {}", dead_letters.key);
+                    match dead_letters.find(&id).await {
+                        Ok(Some(dead_letter)) => Json(dead_letter).into_response(),
+                        Ok(None) => Problem::not_found().into_response(),
+                        Err(err) => err.into_problem(&config).into_response(),
+                    }
+                },
+            )
+        };
+
+        let requeue_route = {
+            let dead_letters = dead_letters.clone();
+            post(
+                move |Extension(config): Extension<ProblemConfig>,
+                      caller: AdminCaller,
+                      Json(request): Json<SelectionRequest>| async move {
+                    let ids = request.ids.unwrap_or_default();
+                    log::info!(target: "audit", "{caller:?} requeued the dead-letters {ids:?} of {}", dead_letters.key);
+                    let result = dead_letters.requeue(&ids).await;
+                    into_response(&config, result.map(|affected| ActionResponse { affected }))
+                },
+            )
+        };
+
+        let purge_route = post(
+            move |Extension(config): Extension<ProblemConfig>,
+                  caller: AdminCaller,
+                  Json(request): Json<SelectionRequest>| async move {
+                log::info!(target: "audit", "{caller:?} purged the dead-letters {:?} of {}", request.ids, dead_letters.key);
+                let result = dead_letters.purge(request.ids.as_deref()).await;
+                into_response(&config, result.map(|affected| ActionResponse { affected }))
+            },
+        );
+
+        Router::new()
+            .route(&path, list_route)
+            .route(&format!("{path}/:id"), find_route)
+            .route(&format!("{path}/requeue"), requeue_route)
+            .route(&format!("{path}/purge"), purge_route)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use redis::Value as RedisValue;
+    use shine_test::test;
+    use std::collections::HashMap;
+
+    fn entry(fields: &[(&str, &[u8])]) -> StreamId {
+        StreamId {
+            id: "1-0".to_string(),
+            map: fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), RedisValue::BulkString(value.to_vec())))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn redacted_payload() {
+        let dead_letter = StreamDeadLetter::from_entry(
+            &entry(&[
+                (PAYLOAD_FIELD, br#"{"userId":"u1","password":"secret"}"#),
+                (SOURCE_ID_FIELD, b"5-0"),
+                (REASON_FIELD, b"Delivered 5 times"),
+            ]),
+            true,
+        );
+        assert_eq!(dead_letter.source_id.as_deref(), Some("5-0"));
+        assert_eq!(dead_letter.reason.as_deref(), Some("Delivered 5 times"));
+        let payload = dead_letter.payload.unwrap();
+        assert_eq!(payload["userId"], "u1");
+        assert_eq!(payload["password"], REDACTED);
+
+        let dead_letter = StreamDeadLetter::from_entry(&entry(&[(PAYLOAD_FIELD, b"not json")]), true);
+        assert_eq!(dead_letter.payload, Some(JsonValue::String(REDACTED.to_string())));
+
+        let dead_letter = StreamDeadLetter::from_entry(&entry(&[(PAYLOAD_FIELD, b"{}")]), false);
+        assert!(dead_letter.payload.is_none());
+    }
+}
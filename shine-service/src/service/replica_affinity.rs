@@ -0,0 +1,165 @@
+use crate::service::CookieAttributes;
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, Extension, RequestPartsExt};
+use axum_extra::extract::{cookie::Cookie, CookieJar};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use ring::hmac;
+use std::{convert::Infallible, sync::Arc};
+use thiserror::Error as ThisError;
+
+pub const AFFINITY_COOKIE: &str = "rpa";
+pub const AFFINITY_HEADER: &str = "x-replica-affinity";
+
+#[derive(Debug, ThisError)]
+pub enum ReplicaAffinityError {
+    #[error("Invalid affinity secret")]
+    InvalidSecret(String),
+    #[error("Invalid affinity token")]
+    InvalidToken,
+}
+
+/// Result of resolving the affinity hint of a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AffinityTarget {
+    /// No (valid) hint was sent, the request can be served by any replica.
+    None,
+    /// The hint points to this replica, the in-memory state is available.
+    Local,
+    /// The hint points to another replica. The load balancer could not route the request there,
+    /// thus the replica is most likely gone and the state has to be recovered.
+    Remote(String),
+}
+
+/// Signed replica hint used by the load balancer to route the reconnects (websocket, long running
+/// operations) back to the replica holding the in-memory state.
+pub struct ReplicaAffinity {
+    replica_id: String,
+    key: hmac::Key,
+    cookie: CookieAttributes,
+}
+
+impl ReplicaAffinity {
+    pub fn new(replica_id: &str, secret: &str) -> Result<Self, ReplicaAffinityError> {
+        let secret = B64
+            .decode(secret)
+            .map_err(|err| ReplicaAffinityError::InvalidSecret(format!("{err}")))?;
+        Ok(Self {
+            replica_id: replica_id.to_string(),
+            key: hmac::Key::new(hmac::HMAC_SHA256, &secret),
+            cookie: CookieAttributes::default(),
+        })
+    }
+
+    #[must_use]
+    pub fn with_cookie_attributes(self, cookie: CookieAttributes) -> Self {
+        Self { cookie, ..self }
+    }
+
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    pub fn replica_id(&self) -> &str {
+        &self.replica_id
+    }
+
+    /// Create the hint pointing to this replica.
+    pub fn token(&self) -> String {
+        let tag = hmac::sign(&self.key, self.replica_id.as_bytes());
+        format!("{}.{}", B64.encode(&self.replica_id), B64.encode(tag.as_ref()))
+    }
+
+    /// Verify the hint and return the replica it points to.
+    pub fn verify(&self, token: &str) -> Result<String, ReplicaAffinityError> {
+        let (replica, tag) = token.split_once('.').ok_or(ReplicaAffinityError::InvalidToken)?;
+        let replica = B64.decode(replica).map_err(|_| ReplicaAffinityError::InvalidToken)?;
+        let tag = B64.decode(tag).map_err(|_| ReplicaAffinityError::InvalidToken)?;
+        hmac::verify(&self.key, &replica, &tag).map_err(|_| ReplicaAffinityError::InvalidToken)?;
+        String::from_utf8(replica).map_err(|_| ReplicaAffinityError::InvalidToken)
+    }
+
+    /// Resolve the hint, the invalid tokens are ignored.
+    pub fn resolve(&self, token: Option<&str>) -> AffinityTarget {
+        match token.map(|token| self.verify(token)) {
+            None => AffinityTarget::None,
+            Some(Err(err)) => {
+                log::debug!("Ignoring affinity hint: {err}");
+                AffinityTarget::None
+            }
+            Some(Ok(replica)) if replica == self.replica_id => AffinityTarget::Local,
+            Some(Ok(replica)) => AffinityTarget::Remote(replica),
+        }
+    }
+
+    /// Create the cookie pointing to this replica.
+    pub fn create_cookie(&self) -> Cookie<'static> {
+        let cookie = Cookie::new(self.cookie.prefixed_name(AFFINITY_COOKIE), self.token());
+        self.cookie.apply(cookie)
+    }
+}
+
+/// Extractor of the affinity hint. The header takes precedence over the cookie as it is used by the
+/// clients (ex. websocket) that cannot rely on cookies.
+pub struct Affinity {
+    pub target: AffinityTarget,
+    affinity: Arc<ReplicaAffinity>,
+}
+
+impl Affinity {
+    /// Return if the state of the client has to be recovered as the original replica is gone.
+    pub fn is_fallback(&self) -> bool {
+        matches!(self.target, AffinityTarget::Remote(_))
+    }
+
+    /// The token pointing to this replica to be returned in the header or in a message.
+    pub fn token(&self) -> String {
+        self.affinity.token()
+    }
+
+    /// Update the cookie jar to point to this replica.
+    pub fn update_cookie(&self, jar: CookieJar) -> CookieJar {
+        if self.target == AffinityTarget::Local {
+            jar
+        } else {
+            jar.add(self.affinity.create_cookie())
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Affinity
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(affinity) = parts
+            .extract::<Extension<Arc<ReplicaAffinity>>>()
+            .await
+            .expect("Missing ReplicaAffinity extension");
+
+        let header = parts
+            .headers
+            .get(AFFINITY_HEADER)
+            .and_then(|token| token.to_str().ok())
+            .map(|token| token.to_string());
+        let token = match header {
+            Some(token) => Some(token),
+            None => {
+                let jar = parts.extract::<CookieJar>().await?;
+                let name = affinity.cookie.prefixed_name(AFFINITY_COOKIE);
+                jar.get(&name).map(|cookie| cookie.value().to_string())
+            }
+        };
+
+        let target = affinity.resolve(token.as_deref());
+        if let AffinityTarget::Remote(replica) = &target {
+            log::info!(
+                "Replica {replica} is not available, falling back to {}",
+                affinity.replica_id
+            );
+        }
+
+        Ok(Self { target, affinity })
+    }
+}
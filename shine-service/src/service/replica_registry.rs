@@ -0,0 +1,177 @@
+use crate::{
+    service::{RedisConnectionError, RedisConnectionPool},
+    utils::{ConsistentHashRing, DEFAULT_VIRTUAL_NODES},
+};
+use chrono::Utc;
+use redis::{AsyncCommands, RedisError};
+use std::{sync::Arc, time::Duration};
+use thiserror::Error as ThisError;
+use tokio::{sync::watch, task::JoinHandle};
+
+#[derive(Debug, ThisError)]
+pub enum ReplicaRegistryError {
+    #[error("Failed to get redis connection")]
+    RedisPoolError(#[source] RedisConnectionError),
+    #[error("Redis error")]
+    RedisError(#[from] RedisError),
+}
+
+/// The shard of the work owned by a replica, updated as the replicas join and leave.
+#[derive(Clone)]
+pub struct ReplicaPartition {
+    replica_id: String,
+    ring: watch::Receiver<Arc<ConsistentHashRing>>,
+}
+
+impl ReplicaPartition {
+    /// A partition with a fixed ring, ex. for a single replica or for the tests.
+    pub fn fixed(replica_id: &str, ring: ConsistentHashRing) -> Self {
+        let (sender, ring) = watch::channel(Arc::new(ring));
+        // keep the value available after the sender is dropped
+        drop(sender);
+        Self {
+            replica_id: replica_id.to_string(),
+            ring,
+        }
+    }
+
+    pub fn replica_id(&self) -> &str {
+        &self.replica_id
+    }
+
+    pub fn ring(&self) -> Arc<ConsistentHashRing> {
+        self.ring.borrow().clone()
+    }
+
+    /// Check if the key (ex. a user or tenant id) belongs to this replica. With an empty ring (the
+    /// replicas are not known yet) nothing is owned.
+    pub fn owns<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.ring.borrow().owns(&self.replica_id, key)
+    }
+
+    /// Wait until the set of the replicas changes.
+    pub async fn changed(&mut self) -> bool {
+        self.ring.changed().await.is_ok()
+    }
+}
+
+/// Discover the live replicas through heartbeats in redis and partition the work among them with a
+/// consistent hash ring. The replicas are stored in a sorted set scored by the time of the last heartbeat.
+pub struct ReplicaRegistry {
+    key: String,
+    replica_id: String,
+    heartbeat: Duration,
+    ttl: Duration,
+    virtual_nodes: usize,
+    redis: RedisConnectionPool,
+}
+
+impl ReplicaRegistry {
+    pub fn new(key_prefix: &str, replica_id: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key: format!("{key_prefix}replicas"),
+            replica_id: replica_id.to_string(),
+            heartbeat: Duration::from_secs(5),
+            ttl: Duration::from_secs(20),
+            virtual_nodes: DEFAULT_VIRTUAL_NODES,
+            redis,
+        }
+    }
+
+    /// Period of the heartbeat and the time after which a silent replica is dropped.
+    #[must_use]
+    pub fn with_heartbeat(self, heartbeat: Duration, ttl: Duration) -> Self {
+        Self {
+            heartbeat,
+            ttl: ttl.max(heartbeat),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_virtual_nodes(self, virtual_nodes: usize) -> Self {
+        Self { virtual_nodes, ..self }
+    }
+
+    /// Register this replica, drop the expired ones and return the live replicas.
+    pub async fn heartbeat(&self) -> Result<Vec<String>, ReplicaRegistryError> {
+        let mut client = self.redis.get().await.map_err(ReplicaRegistryError::RedisPoolError)?;
+        let now = Utc::now().timestamp_millis();
+        let expired = now - self.ttl.as_millis() as i64;
+
+        let (_, _, replicas): (usize, usize, Vec<String>) = redis::pipe()
+            .atomic()
+            .zadd(&self.key, &self.replica_id, now)
+            .zrembyscore(&self.key, "-inf", expired)
+            .zrange(&self.key, 0, -1)
+            .query_async(&mut *client)
+            .await?;
+        Ok(replicas)
+    }
+
+    /// Remove this replica, the others take over its partition at their next heartbeat.
+    pub async fn leave(&self) -> Result<(), ReplicaRegistryError> {
+        let mut client = self.redis.get().await.map_err(ReplicaRegistryError::RedisPoolError)?;
+        let _: usize = client.zrem(&self.key, &self.replica_id).await?;
+        Ok(())
+    }
+
+    /// Start the heartbeats and keep the partition of the replica up to date.
+    pub fn start(self) -> ReplicaRegistryHandle {
+        let (ring_sender, ring) = watch::channel(Arc::new(ConsistentHashRing::new(self.virtual_nodes)));
+        let (shutdown, mut shutdown_receiver) = watch::channel(false);
+        let partition = ReplicaPartition {
+            replica_id: self.replica_id.clone(),
+            ring,
+        };
+
+        let task = tokio::spawn(async move {
+            let mut replicas = Vec::new();
+            loop {
+                match self.heartbeat().await {
+                    Ok(current) if current != replicas => {
+                        log::info!("Replicas of {} changed: {current:?}", self.key);
+                        ring_sender
+                            .send_replace(Arc::new(ConsistentHashRing::with_nodes(self.virtual_nodes, &current)));
+                        replicas = current;
+                    }
+                    Ok(_) => {}
+                    Err(err) => log::warn!("Replica {} heartbeat failed: {err:?}", self.replica_id),
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(self.heartbeat) => {}
+                    _ = shutdown_receiver.changed() => break,
+                }
+            }
+
+            if let Err(err) = self.leave().await {
+                log::warn!("Replica {} failed to leave {}: {err:?}", self.replica_id, self.key);
+            }
+        });
+
+        ReplicaRegistryHandle {
+            partition,
+            shutdown,
+            task,
+        }
+    }
+}
+
+pub struct ReplicaRegistryHandle {
+    partition: ReplicaPartition,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl ReplicaRegistryHandle {
+    pub fn partition(&self) -> ReplicaPartition {
+        self.partition.clone()
+    }
+
+    /// Stop the heartbeats and leave the registry.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        let _ = self.task.await;
+    }
+}
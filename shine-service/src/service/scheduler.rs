@@ -0,0 +1,230 @@
+use crate::service::{Leadership, RedisConnectionPool, ReplicaPartition};
+use chrono::Utc;
+use cron::Schedule;
+use futures::future::BoxFuture;
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
+use std::{error::Error as StdError, str::FromStr, sync::Arc, time::Instant};
+use thiserror::Error as ThisError;
+use tokio::task::JoinHandle;
+use tracing::{info_span, Instrument};
+use uuid::Uuid;
+
+pub type JobError = Box<dyn StdError + Send + Sync>;
+pub type JobFuture = BoxFuture<'static, Result<(), JobError>>;
+
+#[derive(Debug, ThisError)]
+pub enum SchedulerError {
+    #[error("Invalid schedule for job {0}")]
+    InvalidSchedule(String, #[source] cron::error::Error),
+    #[error("Job {0} is already registered")]
+    DuplicateJob(String),
+}
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    action: Arc<dyn Fn() -> JobFuture + Send + Sync>,
+    /// Partitioned jobs run on every replica, each processing its own shard.
+    partitioned: bool,
+}
+
+#[derive(Clone)]
+struct JobMeters {
+    duration: Histogram<f64>,
+    success: Counter<u64>,
+    failure: Counter<u64>,
+}
+
+/// Run async jobs on cron schedules. The replicas coordinate through redis so that each
+/// occurrence of a job is executed by a single replica only, either by locking the occurrences
+/// or, when a leadership is given, by running the jobs on the leader.
+pub struct Scheduler {
+    key_prefix: String,
+    instance_id: String,
+    redis: RedisConnectionPool,
+    meters: Option<JobMeters>,
+    leadership: Option<Leadership>,
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    pub fn new(key_prefix: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key_prefix: key_prefix.to_string(),
+            instance_id: Uuid::new_v4().to_string(),
+            redis,
+            meters: None,
+            leadership: None,
+            jobs: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            meters: Some(JobMeters {
+                duration: meter.f64_histogram("scheduler_job_duration").init(),
+                success: meter.u64_counter("scheduler_job_success").init(),
+                failure: meter.u64_counter("scheduler_job_failure").init(),
+            }),
+            ..self
+        }
+    }
+
+    /// Run the (not partitioned) jobs only on the leader replica instead of locking each occurrence.
+    #[must_use]
+    pub fn with_leadership(self, leadership: Leadership) -> Self {
+        Self {
+            leadership: Some(leadership),
+            ..self
+        }
+    }
+
+    /// Register a job with a cron expression including the seconds, ex: `0 */5 * * * *`.
+    pub fn with_job<F>(mut self, name: &str, schedule: &str, action: F) -> Result<Self, SchedulerError>
+    where
+        F: Fn() -> JobFuture + Send + Sync + 'static,
+    {
+        if self.jobs.iter().any(|job| job.name == name) {
+            return Err(SchedulerError::DuplicateJob(name.to_string()));
+        }
+        let schedule =
+            Schedule::from_str(schedule).map_err(|err| SchedulerError::InvalidSchedule(name.to_string(), err))?;
+        self.jobs.push(Job {
+            name: name.to_string(),
+            schedule,
+            action: Arc::new(action),
+            partitioned: false,
+        });
+        Ok(self)
+    }
+
+    /// Register a job running on all the replicas at the same time. The action gets the partition of the
+    /// replica to process only its own shard of the work (ex. the users owned by the replica).
+    pub fn with_partitioned_job<F>(
+        mut self,
+        name: &str,
+        schedule: &str,
+        partition: ReplicaPartition,
+        action: F,
+    ) -> Result<Self, SchedulerError>
+    where
+        F: Fn(ReplicaPartition) -> JobFuture + Send + Sync + 'static,
+    {
+        if self.jobs.iter().any(|job| job.name == name) {
+            return Err(SchedulerError::DuplicateJob(name.to_string()));
+        }
+        let schedule =
+            Schedule::from_str(schedule).map_err(|err| SchedulerError::InvalidSchedule(name.to_string(), err))?;
+        self.jobs.push(Job {
+            name: name.to_string(),
+            schedule,
+            action: Arc::new(move || action(partition.clone())),
+            partitioned: true,
+        });
+        Ok(self)
+    }
+
+    /// Try to acquire the lock of an occurrence of a job.
+    async fn try_lock(&self, job: &Job, occurrence: i64) -> bool {
+        if let Some(leadership) = &self.leadership {
+            return leadership.is_leader();
+        }
+
+        let key = format!("{}scheduler:{}:{}", self.key_prefix, job.name, occurrence);
+        let mut client = match self.redis.get().await {
+            Ok(client) => client,
+            Err(err) => {
+                log::error!("Job {} skipped, failed to get redis connection: {err:?}", job.name);
+                return false;
+            }
+        };
+
+        // keep the lock for a while to prevent the late replicas from running the same occurrence
+        let result: Result<bool, _> = redis::cmd("SET")
+            .arg(&key)
+            .arg(&self.instance_id)
+            .arg("NX")
+            .arg("EX")
+            .arg(24 * 60 * 60)
+            .query_async(&mut *client)
+            .await;
+        match result {
+            Ok(locked) => locked,
+            Err(err) => {
+                log::error!("Job {} skipped, failed to acquire lock: {err:?}", job.name);
+                false
+            }
+        }
+    }
+
+    /// Run the job in a new trace. Jobs processing traced items (ex. events) can link the span to the
+    /// originating traces with `link_trace_contexts(&Span::current(), ...)`.
+    async fn run_job(&self, job: &Job) {
+        let span = info_span!(parent: None, "scheduler.job", job.name = %job.name);
+        let start = Instant::now();
+        let result = (job.action)().instrument(span).await;
+        let duration = start.elapsed().as_secs_f64();
+
+        let attributes = [KeyValue::new("job", job.name.clone())];
+        if let Some(meters) = &self.meters {
+            meters.duration.record(duration, &attributes);
+        }
+        match result {
+            Ok(()) => {
+                log::info!("Job {} completed in {duration:.3}s", job.name);
+                if let Some(meters) = &self.meters {
+                    meters.success.add(1, &attributes);
+                }
+            }
+            Err(err) => {
+                log::error!("Job {} failed in {duration:.3}s: {err:?}", job.name);
+                if let Some(meters) = &self.meters {
+                    meters.failure.add(1, &attributes);
+                }
+            }
+        }
+    }
+
+    /// Start the jobs on the tokio runtime.
+    pub fn start(self) -> Vec<JoinHandle<()>> {
+        let Scheduler {
+            key_prefix,
+            instance_id,
+            redis,
+            meters,
+            leadership,
+            jobs,
+        } = self;
+        let scheduler = Arc::new(Scheduler {
+            key_prefix,
+            instance_id,
+            redis,
+            meters,
+            leadership,
+            jobs: Vec::new(),
+        });
+
+        jobs.into_iter()
+            .map(|job| {
+                let scheduler = scheduler.clone();
+                tokio::spawn(async move {
+                    while let Some(next) = job.schedule.upcoming(Utc).next() {
+                        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                        tokio::time::sleep(wait).await;
+
+                        if job.partitioned || scheduler.try_lock(&job, next.timestamp()).await {
+                            scheduler.run_job(&job).await;
+                        } else {
+                            log::debug!("Job {} at {next} is handled by another replica", job.name);
+                        }
+                    }
+                    log::warn!("Job {} has no more occurrences", job.name);
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,373 @@
+#[cfg(all(feature = "redis", feature = "openapi"))]
+use crate::service::AdminRouter;
+#[cfg(any(feature = "postgres", feature = "redis"))]
+use crate::service::PoolStatus;
+#[cfg(feature = "postgres")]
+use crate::service::{create_postgres_pool_with_config, PGConnectionPool, PGCreatePoolError, PGPoolConfig};
+#[cfg(feature = "redis")]
+use crate::service::{
+    create_redis_pool, CookieConfig, CookieConfigError, RedisConnectionError, RedisConnectionPool,
+    SessionVersionTolerance, UserSessionCacheReader, UserSessionError,
+};
+use crate::{
+    axum::{
+        telemetry::{TelemetryBuildError, TelemetryConfig, TelemetryService},
+        MissingLayersError, ProblemConfig, ProblemFallback, RequiredLayers, ShutdownController,
+    },
+    service::{CoreConfig, StatusDashboard, StatusSource, TenantConfig, TenantError, TenantResolver},
+    utils::DurationStr,
+};
+use axum::{http::StatusCode, routing::get, Extension, Router};
+use config::ConfigError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error as ThisError;
+
+fn default_drain_period() -> DurationStr {
+    DurationStr::from_secs(30)
+}
+
+#[derive(Debug, ThisError)]
+pub enum ServiceBuildError {
+    #[error("Failed to load configuration")]
+    Config(#[from] ConfigError),
+    #[error("Failed to initialize telemetry")]
+    Telemetry(#[from] TelemetryBuildError),
+    #[error(transparent)]
+    MissingLayers(#[from] MissingLayersError),
+    #[error("Invalid tenant configuration")]
+    Tenant(#[from] TenantError),
+    #[cfg(feature = "postgres")]
+    #[error("Failed to create postgres pool")]
+    Postgres(#[source] PGCreatePoolError),
+    #[cfg(feature = "redis")]
+    #[error("Failed to create redis pool")]
+    Redis(#[source] RedisConnectionError),
+    #[cfg(feature = "redis")]
+    #[error("User session requires redis")]
+    MissingRedis,
+    #[cfg(feature = "redis")]
+    #[error("Failed to create user session validator")]
+    UserSession(#[from] UserSessionError),
+    #[cfg(feature = "redis")]
+    #[error("Invalid cookie configuration")]
+    Cookie(#[from] CookieConfigError),
+}
+
+#[cfg(feature = "postgres")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostgresServiceConfig {
+    pub cns: String,
+    #[serde(default)]
+    pub pool: PGPoolConfig,
+}
+
+#[cfg(feature = "redis")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSessionServiceConfig {
+    pub cookie_secret: String,
+    pub name_suffix: Option<String>,
+    /// Prefix of the session keys in redis, it has to match the identity service.
+    pub key_prefix: String,
+    #[serde(default)]
+    pub cookie: CookieConfig,
+    /// Accept the session data versions of the cookie ahead of the cache, ex. with a replicated cache.
+    pub version_tolerance: Option<SessionVersionTolerance>,
+}
+
+/// The common part of the configuration of the services. The services usually embed it into their own
+/// configuration with `#[serde(flatten)]`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShineServiceConfig {
+    pub telemetry: TelemetryConfig,
+    /// Include the internal details of the errors in the problem responses, for development only.
+    #[serde(default)]
+    pub full_problem_response: bool,
+    #[cfg(feature = "postgres")]
+    pub postgres: Option<PostgresServiceConfig>,
+    #[cfg(feature = "redis")]
+    pub redis_cns: Option<String>,
+    #[cfg(feature = "redis")]
+    pub user_session: Option<UserSessionServiceConfig>,
+    /// Time to wait for the active connections to close on shutdown.
+    #[serde(default = "default_drain_period")]
+    pub drain_period: DurationStr,
+    /// Resolve the tenant of the requests for the `TenantId` and `TenantContext` extractors.
+    pub tenant: Option<TenantConfig>,
+}
+
+/// Bootstrap of a service: load the configuration layers, then create the telemetry, the connection pools
+/// and the session validator from the common configuration.
+///
+/// ```ignore
+/// let builder = ShineServiceBuilder::new("my-service").with_admin_role("SuperAdmin");
+/// let (core_config, config) = builder.load_config::<AppConfig>(&stage).await?;
+/// let service = builder.build(&core_config, &config.service).await?;
+/// let state = AppState::new(service.postgres().cloned(), service.redis().cloned());
+/// let (router, shutdown) = service.into_router(Router::new().nest("/api", api_routes).with_state(state))?;
+/// axum::serve(listener, router).with_graceful_shutdown(shutdown.shutdown_signal()).await?;
+/// ```
+pub struct ShineServiceBuilder {
+    service_name: &'static str,
+    admin_role: Option<String>,
+    status_sources: Vec<Arc<dyn StatusSource>>,
+    problem_fallback: bool,
+}
+
+impl ShineServiceBuilder {
+    pub fn new(service_name: &'static str) -> Self {
+        Self {
+            service_name,
+            admin_role: None,
+            status_sources: Vec::new(),
+            problem_fallback: true,
+        }
+    }
+
+    /// Role required for the admin endpoints, ex. the status dashboard. Without a role, the admin
+    /// endpoints are not added, but the dependencies are still reported by the readiness check.
+    #[must_use]
+    pub fn with_admin_role(self, admin_role: &str) -> Self {
+        Self {
+            admin_role: Some(admin_role.to_string()),
+            ..self
+        }
+    }
+
+    /// Replace the 404 and 405 responses with problem responses, enabled by default. Disable it when the
+    /// application router has its own fallback.
+    #[must_use]
+    pub fn with_problem_fallback(self, enabled: bool) -> Self {
+        Self {
+            problem_fallback: enabled,
+            ..self
+        }
+    }
+
+    /// Add a dependency to the status dashboard and to the readiness check, the pools created by the builder
+    /// are added automatically.
+    #[must_use]
+    pub fn with_status_source(mut self, source: Arc<dyn StatusSource>) -> Self {
+        self.status_sources.push(source);
+        self
+    }
+
+    /// Load the core configuration of the stage and resolve the configuration layers.
+    pub async fn load_config<C>(&self, stage: &str) -> Result<(CoreConfig, C), ServiceBuildError>
+    where
+        C: DeserializeOwned,
+    {
+        let core_config = CoreConfig::new(stage)?;
+        let config = core_config.create_config_builder()?.build().await?;
+        CoreConfig::log_config(&config)?;
+        let config = config.try_deserialize::<C>()?;
+        Ok((core_config, config))
+    }
+
+    pub async fn build(
+        self,
+        core_config: &CoreConfig,
+        config: &ShineServiceConfig,
+    ) -> Result<ShineService, ServiceBuildError> {
+        log::info!(
+            "Starting {} ({}) in stage {}",
+            self.service_name,
+            core_config.version,
+            core_config.stage
+        );
+
+        let telemetry = TelemetryService::new(self.service_name, &config.telemetry).await?;
+        let mut dashboard = StatusDashboard::new(self.service_name);
+
+        #[cfg(feature = "postgres")]
+        let postgres = match &config.postgres {
+            Some(postgres) => {
+                let pool_config = postgres.pool.clone().with_default_application_name(self.service_name);
+                let pool = create_postgres_pool_with_config(&postgres.cns, &pool_config, telemetry.service_meter())
+                    .await
+                    .map_err(ServiceBuildError::Postgres)?;
+                dashboard = dashboard.with_source(PoolStatus::new("postgres", "postgres", pool.clone()));
+                Some(pool)
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "redis")]
+        let redis = match &config.redis_cns {
+            Some(cns) => {
+                let pool = create_redis_pool(cns).await.map_err(ServiceBuildError::Redis)?;
+                dashboard = dashboard.with_source(PoolStatus::new("redis", "redis", pool.clone()));
+                Some(pool)
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "redis")]
+        let user_session = match &config.user_session {
+            Some(session) => {
+                session.cookie.validate(&core_config.stage)?;
+                let redis = redis.clone().ok_or(ServiceBuildError::MissingRedis)?;
+                let reader = UserSessionCacheReader::new(
+                    session.name_suffix.as_deref(),
+                    &session.cookie_secret,
+                    &session.key_prefix,
+                    redis,
+                )?
+                .with_cookie_config(session.cookie.clone());
+                let reader = match &session.version_tolerance {
+                    Some(tolerance) => reader.with_version_tolerance(tolerance.clone()),
+                    None => reader,
+                };
+                let reader = match telemetry.service_meter() {
+                    Some(meter) => reader.with_meter(meter),
+                    None => reader,
+                };
+                Some(reader)
+            }
+            None => None,
+        };
+
+        for source in self.status_sources {
+            dashboard = dashboard.with_shared_source(source);
+        }
+
+        let tenant = config.tenant.as_ref().map(TenantResolver::new).transpose()?;
+
+        let problem_fallback = self.problem_fallback.then(|| match telemetry.service_meter() {
+            Some(meter) => ProblemFallback::new().with_meter(meter),
+            None => ProblemFallback::new(),
+        });
+
+        Ok(ShineService {
+            telemetry,
+            problem_config: ProblemConfig::new(config.full_problem_response),
+            #[cfg(feature = "postgres")]
+            postgres,
+            #[cfg(feature = "redis")]
+            redis,
+            #[cfg(feature = "redis")]
+            user_session,
+            shutdown: ShutdownController::new(config.drain_period.into()),
+            dashboard: Arc::new(dashboard),
+            admin_role: self.admin_role,
+            problem_fallback,
+            tenant,
+            required_layers: RequiredLayers::new(),
+        })
+    }
+}
+
+/// The common components of a service created by the `ShineServiceBuilder`.
+pub struct ShineService {
+    telemetry: TelemetryService,
+    problem_config: ProblemConfig,
+    #[cfg(feature = "postgres")]
+    postgres: Option<PGConnectionPool>,
+    #[cfg(feature = "redis")]
+    redis: Option<RedisConnectionPool>,
+    #[cfg(feature = "redis")]
+    user_session: Option<UserSessionCacheReader>,
+    shutdown: ShutdownController,
+    dashboard: Arc<StatusDashboard>,
+    #[cfg_attr(not(all(feature = "redis", feature = "openapi")), allow(dead_code))]
+    admin_role: Option<String>,
+    problem_fallback: Option<ProblemFallback>,
+    tenant: Option<TenantResolver>,
+    required_layers: RequiredLayers,
+}
+
+impl ShineService {
+    pub fn telemetry(&self) -> &TelemetryService {
+        &self.telemetry
+    }
+
+    pub fn problem_config(&self) -> &ProblemConfig {
+        &self.problem_config
+    }
+
+    #[cfg(feature = "postgres")]
+    pub fn postgres(&self) -> Option<&PGConnectionPool> {
+        self.postgres.as_ref()
+    }
+
+    #[cfg(feature = "redis")]
+    pub fn redis(&self) -> Option<&RedisConnectionPool> {
+        self.redis.as_ref()
+    }
+
+    /// Registry of the required and provided extensions of the router, it is checked by `into_router`. Add the
+    /// endpoints with `ApiRoute::add_required_api` and the extension layers with `RequiredLayers::layer`.
+    pub fn required_layers(&self) -> &RequiredLayers {
+        &self.required_layers
+    }
+
+    pub fn shutdown(&self) -> &ShutdownController {
+        &self.shutdown
+    }
+
+    pub fn dashboard(&self) -> &Arc<StatusDashboard> {
+        &self.dashboard
+    }
+
+    /// Create the opt-in admin router with the telemetry (including the `/metrics/:tenant` scrape) and the pools
+    /// of the service, protected by the admin role. The feature flags, the maintenance mode and the API keys can be added to it by the service.
+    #[cfg(all(feature = "redis", feature = "openapi"))]
+    pub fn admin_router(&self) -> AdminRouter {
+        let mut admin = AdminRouter::new()
+            .with_telemetry(self.telemetry.clone())
+            .with_required_layers(self.required_layers.clone());
+        if let Some(role) = &self.admin_role {
+            admin = admin.with_role(role);
+        }
+        #[cfg(feature = "postgres")]
+        if let Some(postgres) = &self.postgres {
+            admin = admin.with_pool("postgres", postgres.clone());
+        }
+        if let Some(redis) = &self.redis {
+            admin = admin.with_pool("redis", redis.clone());
+        }
+        admin
+    }
+
+    /// Add the common routes (`/health`, `/health/ready`, `/admin/status`) and the layers
+    /// (telemetry, problem config, user session, tenant, shutdown) to the routes of the service. It fails if an
+    /// endpoint requires an extension that is not provided by any layer, see `RequiredLayers`. Unless disabled in
+    /// the builder, the fallback of the routes is replaced by `ProblemFallback`.
+    pub fn into_router(self, app: Router) -> Result<(Router, ShutdownController), ServiceBuildError> {
+        let layers = self.required_layers;
+
+        let mut router = app
+            .route("/health", get(|| async { StatusCode::OK }))
+            .merge(self.dashboard.readiness_router());
+        #[cfg(all(feature = "redis", feature = "openapi"))]
+        if let Some(admin_role) = &self.admin_role {
+            let dashboard = AdminRouter::new()
+                .with_role(admin_role)
+                .with_required_layers(layers.clone())
+                .with_routes(self.dashboard.admin_router());
+            router = router.merge(dashboard.into_router(None));
+        }
+        if let Some(fallback) = self.problem_fallback {
+            router = fallback.install(router);
+        }
+
+        #[cfg(feature = "redis")]
+        if let Some(user_session) = self.user_session {
+            router = layers.layer(router, user_session.into_layer());
+        }
+        if let Some(tenant) = self.tenant {
+            router = layers.layer(router, tenant.into_layer());
+        }
+        let router = layers.layer(router, self.shutdown.clone().into_layer());
+        let router = layers.layer(router, self.problem_config.into_layer());
+        let router = layers
+            .layer(router, Extension(self.telemetry.clone()))
+            .layer(self.telemetry.create_layer());
+        layers.check()?;
+
+        Ok((router, self.shutdown))
+    }
+}
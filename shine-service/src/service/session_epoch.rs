@@ -0,0 +1,135 @@
+use crate::service::{MemoryCache, RedisConnectionPool};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use redis::{aio::ConnectionLike, AsyncCommands, RedisError};
+use std::{sync::Arc, time::Duration};
+
+const EPOCH_CACHE_TTL: Duration = Duration::from_secs(30);
+/// The stored epochs below this value are in seconds, they were written before the millisecond precision.
+const MIN_EPOCH_MILLIS: i64 = 100_000_000_000;
+
+/// Global session epoch: the sessions created before the epoch are rejected.
+/// It is stored in redis (in unix milliseconds), cached locally and the cache is invalidated through a
+/// pub/sub channel when the epoch is updated.
+pub struct SessionEpoch {
+    key: String,
+    channel: String,
+    cache: MemoryCache<(), Option<DateTime<Utc>>>,
+}
+
+impl SessionEpoch {
+    pub fn new(key_prefix: &str) -> Self {
+        Self {
+            key: format!("{key_prefix}session:epoch"),
+            channel: format!("{key_prefix}session:epoch:changed"),
+            cache: MemoryCache::new("session_epoch", 1).with_ttl(EPOCH_CACHE_TTL),
+        }
+    }
+
+    /// The local cache of the epoch, ex. to expose its statistics.
+    pub fn cache(&self) -> &MemoryCache<(), Option<DateTime<Utc>>> {
+        &self.cache
+    }
+
+    /// Drop the cached epoch, it is reloaded from redis on the next access.
+    pub fn invalidate(&self) {
+        self.cache.clear();
+    }
+
+    fn from_stored(timestamp: i64) -> Option<DateTime<Utc>> {
+        if timestamp < MIN_EPOCH_MILLIS {
+            DateTime::<Utc>::from_timestamp(timestamp, 0)
+        } else {
+            DateTime::<Utc>::from_timestamp_millis(timestamp)
+        }
+    }
+
+    /// The epoch is rounded up to the next millisecond, thus the sessions created before it are rejected even
+    /// if their creation time has a finer precision.
+    fn to_stored(epoch: &DateTime<Utc>) -> i64 {
+        let millis = epoch.timestamp_millis();
+        if epoch.timestamp_subsec_nanos() % 1_000_000 == 0 {
+            millis
+        } else {
+            millis + 1
+        }
+    }
+
+    /// Get the current epoch.
+    pub async fn get<C>(&self, client: &mut C) -> Result<Option<DateTime<Utc>>, RedisError>
+    where
+        C: ConnectionLike + Send,
+    {
+        if let Some(epoch) = self.cache.get(&()) {
+            return Ok(epoch);
+        }
+
+        let timestamp: Option<i64> = client.get(&self.key).await?;
+        let epoch = timestamp.and_then(Self::from_stored);
+        self.cache.insert((), epoch);
+        Ok(epoch)
+    }
+
+    /// Invalidate all the sessions created before the given time in all the services.
+    pub async fn set(&self, redis: &RedisConnectionPool, epoch: DateTime<Utc>) -> Result<(), RedisError> {
+        let mut client = redis.get().await.map_err(|err| match err {
+            bb8::RunError::User(err) => err,
+            bb8::RunError::TimedOut => RedisError::from((redis::ErrorKind::IoError, "Redis connection timeout")),
+        })?;
+        log::warn!("Setting global session epoch to {epoch}");
+        let timestamp = Self::to_stored(&epoch);
+        let _: () = redis::pipe()
+            .set(&self.key, timestamp)
+            .publish(&self.channel, timestamp)
+            .query_async(&mut *client)
+            .await?;
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Subscribe to the epoch changes to invalidate the cache immediately.
+    pub async fn start_listener(self: &Arc<Self>, redis_cns: &str) -> Result<(), RedisError> {
+        let client = redis::Client::open(redis_cns)?;
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(&self.channel).await?;
+
+        let epoch = self.clone();
+        tokio::spawn(async move {
+            let mut messages = pubsub.into_on_message();
+            while messages.next().await.is_some() {
+                log::info!("Global session epoch changed");
+                epoch.invalidate();
+            }
+            log::warn!("Session epoch listener stopped");
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn epoch_precision() {
+        let epoch = DateTime::parse_from_rfc3339("2024-05-01T10:00:00.250400Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let stored = SessionEpoch::to_stored(&epoch);
+        let restored = SessionEpoch::from_stored(stored).unwrap();
+        assert!(restored > epoch);
+        assert!(restored - epoch < chrono::Duration::milliseconds(1));
+
+        // a session created in the same second before the epoch is rejected
+        let created_at = DateTime::parse_from_rfc3339("2024-05-01T10:00:00.100Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(created_at < restored);
+
+        // the epochs stored in seconds are still accepted
+        let legacy = SessionEpoch::from_stored(epoch.timestamp()).unwrap();
+        assert_eq!(legacy.timestamp(), epoch.timestamp());
+        assert_eq!(legacy.timestamp_subsec_nanos(), 0);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::service::{
+    indexed_session_key_hashes, parse_session_sentinel_key, session_redis_keys, RedisConnectionPool, ScanCursor,
+    SessionData, SessionSentinel, UserSessionError,
+};
+#[cfg(feature = "openapi")]
+use crate::{
+    axum::{IntoProblem, ProblemConfig},
+    service::AdminCaller,
+};
+#[cfg(feature = "openapi")]
+use axum::{
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionLike, AsyncCommands};
+#[cfg(feature = "openapi")]
+use serde::Deserialize;
+use serde::Serialize;
+#[cfg(feature = "openapi")]
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+const MAX_RECENT_SESSIONS: usize = 1000;
+
+/// Redacted view of a session for the support teams.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSummary {
+    pub user_id: Uuid,
+    /// Prefix of the hashed session key to identify the session without revealing it.
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub fingerprint: String,
+    pub version: Option<i32>,
+    pub name: Option<String>,
+    pub is_email_confirmed: Option<bool>,
+    pub roles: Vec<String>,
+}
+
+fn redact(value: &str) -> String {
+    format!("{}...", value.chars().take(8).collect::<String>())
+}
+
+/// Query the sessions stored in the redis cache using the layout of the session validator.
+pub struct SessionInspector {
+    key_prefix: String,
+    redis: RedisConnectionPool,
+    scan_delay: Duration,
+}
+
+impl SessionInspector {
+    pub fn new(key_prefix: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key_prefix: key_prefix.to_string(),
+            redis,
+            scan_delay: Duration::from_millis(10),
+        }
+    }
+
+    async fn load<C>(
+        &self,
+        client: &mut C,
+        user_id: Uuid,
+        key_hash: &str,
+    ) -> Result<Option<SessionSummary>, UserSessionError>
+    where
+        C: ConnectionLike + AsyncCommands + Send,
+    {
+        let (sentinel_key, key) = session_redis_keys(&self.key_prefix, &user_id, key_hash);
+
+        let (sentinel, data_versions): (Option<SessionSentinel>, Vec<i32>) =
+            redis::pipe().get(sentinel_key).hkeys(&key).query_async(client).await?;
+        let sentinel = match sentinel {
+            Some(sentinel) => sentinel,
+            None => return Ok(None),
+        };
+
+        let version = data_versions.into_iter().max();
+        let data: Option<SessionData> = match version {
+            Some(version) => client.hget(&key, format!("{version}")).await?,
+            None => None,
+        };
+
+        Ok(Some(SessionSummary {
+            user_id,
+            key_hash: redact(key_hash),
+            created_at: sentinel.created_at,
+            fingerprint: redact(&sentinel.fingerprint),
+            version,
+            name: data.as_ref().map(|d| d.name.clone()),
+            is_email_confirmed: data.as_ref().map(|d| d.is_email_confirmed),
+            roles: data.map(|d| d.roles).unwrap_or_default(),
+        }))
+    }
+
+    async fn scan(&self, pattern: &str, limit: usize) -> Result<Vec<SessionSummary>, UserSessionError> {
+        let mut client = self.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+
+        let mut keys = Vec::new();
+        let mut cursor = ScanCursor::keys(Some(pattern)).with_delay(self.scan_delay);
+        while let Some(page) = cursor.next_page::<_, String>(&mut *client).await? {
+            keys.extend(
+                page.iter()
+                    .filter_map(|key| parse_session_sentinel_key(&self.key_prefix, key)),
+            );
+            if keys.len() >= limit {
+                break;
+            }
+        }
+
+        self.load_all(&mut *client, keys).await
+    }
+
+    async fn load_all<C>(
+        &self,
+        client: &mut C,
+        keys: Vec<(Uuid, String)>,
+    ) -> Result<Vec<SessionSummary>, UserSessionError>
+    where
+        C: ConnectionLike + AsyncCommands + Send,
+    {
+        let mut sessions = Vec::with_capacity(keys.len());
+        for (user_id, key_hash) in keys {
+            if let Some(session) = self.load(client, user_id, &key_hash).await? {
+                sessions.push(session);
+            }
+        }
+        sessions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(sessions)
+    }
+
+    /// Find the active sessions of a user through the session index, see `UserSessionManager::index_session`.
+    pub async fn find_by_user(&self, user_id: Uuid) -> Result<Vec<SessionSummary>, UserSessionError> {
+        let mut client = self.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+        let keys = indexed_session_key_hashes(&mut *client, &self.key_prefix, user_id)
+            .await?
+            .into_iter()
+            .map(|key_hash| (user_id, key_hash))
+            .collect();
+        self.load_all(&mut *client, keys).await
+    }
+
+    /// List the most recently created sessions across the users. It scans the keyspace, the number of the
+    /// inspected sessions is limited.
+    pub async fn list_recent(&self, limit: usize) -> Result<Vec<SessionSummary>, UserSessionError> {
+        let pattern = format!("{}session:*:*:openness", self.key_prefix);
+        let mut sessions = self.scan(&pattern, MAX_RECENT_SESSIONS).await?;
+        sessions.truncate(limit);
+        Ok(sessions)
+    }
+
+    /// Create the admin routes, they are authorized by the `AdminCaller`, see `AdminRouter::with_routes`.
+    ///  - GET /admin/sessions/user/:user_id
+    ///  - GET /admin/sessions/recent?limit=N
+    #[cfg(feature = "openapi")]
+    pub fn into_router<S>(self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        #[derive(Deserialize)]
+        struct RecentQuery {
+            limit: Option<usize>,
+        }
+
+        fn into_response(config: &ProblemConfig, result: Result<Vec<SessionSummary>, UserSessionError>) -> Response {
+            match result {
+                Ok(sessions) => Json(sessions).into_response(),
+                Err(err) => err.into_problem(config).into_response(),
+            }
+        }
+
+        let inspector = Arc::new(self);
+
+        let user_route = {
+            let inspector = inspector.clone();
+            get(
+                move |Extension(config): Extension<ProblemConfig>, _: AdminCaller, Path(user_id): Path<Uuid>| async move {
+                    into_response(&config, inspector.find_by_user(user_id).await)
+                },
+            )
+        };
+
+        let recent_route = get(
+            move |Extension(config): Extension<ProblemConfig>, _: AdminCaller, Query(query): Query<RecentQuery>| async move {
+                let limit = query.limit.unwrap_or(100).min(MAX_RECENT_SESSIONS);
+                into_response(&config, inspector.list_recent(limit).await)
+            },
+        );
+
+        Router::new()
+            .route("/admin/sessions/user/:user_id", user_route)
+            .route("/admin/sessions/recent", recent_route)
+    }
+}
@@ -1,4 +1,4 @@
-use ring::rand::SecureRandom;
+use crate::utils::Entropy;
 use thiserror::Error as ThisError;
 
 #[derive(Debug, ThisError)]
@@ -19,14 +19,18 @@ impl std::fmt::Debug for SessionKey {
 }
 
 impl SessionKey {
-    pub fn new_random(random: &dyn SecureRandom) -> Result<Self, SessionKeyError> {
+    pub fn new_random(random: &dyn Entropy) -> Result<Self, SessionKeyError> {
         let mut raw = [0_u8; 16];
         random
             .fill(&mut raw)
-            .map_err(|err| SessionKeyError::KeyError(format!("{err:#?}")))?;
+            .map_err(|err| SessionKeyError::KeyError(err.to_string()))?;
         Ok(Self(raw))
     }
 
+    pub fn from_bytes(raw: [u8; 16]) -> Self {
+        Self(raw)
+    }
+
     /// Create from a single string usually used on the API.
     pub fn from_hex(hex_key: &str) -> Result<Self, SessionKeyError> {
         let mut raw = [0_u8; 16];
@@ -0,0 +1,167 @@
+use crate::service::{
+    session_index_key, session_redis_keys, RedisConnectionPool, SessionData, SessionSentinel, UserSessionError,
+};
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionLike, AsyncCommands};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Return the key hashes of the sessions of a user from the session index. The expired sessions are pruned
+/// from the index.
+pub(crate) async fn indexed_session_key_hashes<C>(
+    client: &mut C,
+    key_prefix: &str,
+    user_id: Uuid,
+) -> Result<Vec<String>, UserSessionError>
+where
+    C: ConnectionLike + AsyncCommands + Send,
+{
+    let index_key = session_index_key(key_prefix, &user_id);
+    let key_hashes: Vec<String> = client.smembers(&index_key).await?;
+    if key_hashes.is_empty() {
+        return Ok(key_hashes);
+    }
+
+    let mut pipe = redis::pipe();
+    for key_hash in &key_hashes {
+        let (sentinel_key, _) = session_redis_keys(key_prefix, &user_id, key_hash);
+        pipe.exists(sentinel_key);
+    }
+    let exists: Vec<bool> = pipe.query_async(client).await?;
+
+    let (active, expired): (Vec<_>, Vec<_>) = key_hashes.into_iter().zip(exists).partition(|(_, exists)| *exists);
+    if !expired.is_empty() {
+        let expired: Vec<String> = expired.into_iter().map(|(key_hash, _)| key_hash).collect();
+        let _: usize = client.srem(&index_key, expired).await?;
+    }
+    Ok(active.into_iter().map(|(key_hash, _)| key_hash).collect())
+}
+
+/// An active session of a user.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveSession {
+    /// Hash of the session key, it identifies the session for the termination.
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub fingerprint: String,
+    pub version: Option<i32>,
+    pub name: Option<String>,
+    pub roles: Vec<String>,
+}
+
+/// Terminate the sessions stored in the redis cache using the layout of the session validator. A terminated
+/// session is rejected by the `CheckedCurrentUser` extractor on the next request.
+///
+/// The identity service owns the sessions, the manager is meant for the admin endpoints and the "log out
+/// everywhere" features of the services sharing the session cache. The sessions of a user are found through
+/// the session index (instead of scanning the keyspace), thus the identity service has to register the new
+/// sessions with `index_session`.
+pub struct UserSessionManager {
+    key_prefix: String,
+    redis: RedisConnectionPool,
+}
+
+impl UserSessionManager {
+    pub fn new(key_prefix: &str, redis: RedisConnectionPool) -> Self {
+        Self {
+            key_prefix: key_prefix.to_string(),
+            redis,
+        }
+    }
+
+    /// Add a session to the session index of the user. The expired sessions are pruned when the index is read.
+    pub async fn index_session(&self, user_id: Uuid, key_hash: &str) -> Result<(), UserSessionError> {
+        let mut client = self.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+        let _: usize = client
+            .sadd(session_index_key(&self.key_prefix, &user_id), key_hash)
+            .await?;
+        Ok(())
+    }
+
+    async fn session_key_hashes(&self, user_id: Uuid) -> Result<Vec<String>, UserSessionError> {
+        let mut client = self.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+        indexed_session_key_hashes(&mut *client, &self.key_prefix, user_id).await
+    }
+
+    /// List the active sessions of a user, the most recent first.
+    pub async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<ActiveSession>, UserSessionError> {
+        let key_hashes = self.session_key_hashes(user_id).await?;
+        let mut client = self.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+
+        let mut sessions = Vec::with_capacity(key_hashes.len());
+        for key_hash in key_hashes {
+            let (sentinel_key, key) = session_redis_keys(&self.key_prefix, &user_id, &key_hash);
+            let (sentinel, data_versions): (Option<SessionSentinel>, Vec<i32>) = redis::pipe()
+                .get(sentinel_key)
+                .hkeys(&key)
+                .query_async(&mut *client)
+                .await?;
+            // expired since the index was read
+            let Some(sentinel) = sentinel else {
+                continue;
+            };
+
+            let version = data_versions.into_iter().max();
+            let data: Option<SessionData> = match version {
+                Some(version) => client.hget(&key, format!("{version}")).await?,
+                None => None,
+            };
+            sessions.push(ActiveSession {
+                key_hash,
+                created_at: sentinel.created_at,
+                fingerprint: sentinel.fingerprint,
+                version,
+                name: data.as_ref().map(|d| d.name.clone()),
+                roles: data.map(|d| d.roles).unwrap_or_default(),
+            });
+        }
+
+        sessions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(sessions)
+    }
+
+    /// Terminate a session by deleting its sentinel and data, returns false if the session was not found.
+    pub async fn terminate(&self, user_id: Uuid, key_hash: &str) -> Result<bool, UserSessionError> {
+        let (sentinel_key, key) = session_redis_keys(&self.key_prefix, &user_id, key_hash);
+        let index_key = session_index_key(&self.key_prefix, &user_id);
+        let mut client = self.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+        let (deleted,): (usize,) = redis::pipe()
+            .del(&[sentinel_key, key])
+            .srem(index_key, key_hash)
+            .ignore()
+            .query_async(&mut *client)
+            .await?;
+        if deleted > 0 {
+            log::info!("Session of user {user_id} terminated");
+        }
+        Ok(deleted > 0)
+    }
+
+    /// Terminate all the sessions of a user except the given one (ex. the current session for the "log out
+    /// from the other devices" feature), returns the number of the terminated sessions.
+    pub async fn terminate_all(&self, user_id: Uuid, keep_key_hash: Option<&str>) -> Result<usize, UserSessionError> {
+        let key_hashes = self.session_key_hashes(user_id).await?;
+        let index_key = session_index_key(&self.key_prefix, &user_id);
+        let mut client = self.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+
+        let mut terminated = 0;
+        for key_hash in key_hashes
+            .iter()
+            .filter(|key_hash| Some(key_hash.as_str()) != keep_key_hash)
+        {
+            let (sentinel_key, key) = session_redis_keys(&self.key_prefix, &user_id, key_hash);
+            let (deleted,): (usize,) = redis::pipe()
+                .del(&[sentinel_key, key])
+                .srem(&index_key, key_hash)
+                .ignore()
+                .query_async(&mut *client)
+                .await?;
+            if deleted > 0 {
+                terminated += 1;
+            }
+        }
+        log::info!("{terminated} session(s) of user {user_id} terminated");
+        Ok(terminated)
+    }
+}
@@ -0,0 +1,270 @@
+use futures::{
+    future::BoxFuture,
+    stream::{FuturesUnordered, StreamExt},
+};
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+    error::Error as StdError,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use thiserror::Error as ThisError;
+use tracing::{info_span, Instrument};
+
+pub type ComponentError = Box<dyn StdError + Send + Sync>;
+type ComponentFuture = BoxFuture<'static, Result<(), ComponentError>>;
+
+#[derive(Debug, ThisError)]
+pub enum StartupError {
+    #[error("Component {0} is already registered")]
+    DuplicateComponent(String),
+    #[error("Component {0} depends on the unknown component {1}")]
+    UnknownDependency(String, String),
+    #[error("Dependency cycle between the components: {0:?}")]
+    Cycle(Vec<String>),
+    #[error("Component {0} failed to initialize")]
+    Failed(String, #[source] ComponentError),
+}
+
+/// The values shared between the components, ex. a component creating a connection pool inserts it and the
+/// dependent components get it from here.
+#[derive(Clone, Default)]
+pub struct StartupContext {
+    values: Arc<RwLock<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>>,
+}
+
+impl StartupContext {
+    pub fn insert<T: Send + Sync + 'static>(&self, value: T) {
+        self.values.write().unwrap().insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        let value = self.values.read().unwrap().get(&TypeId::of::<T>())?.clone();
+        value.downcast::<T>().ok()
+    }
+
+    /// Get a value inserted by a dependency.
+    pub fn require<T: Send + Sync + 'static>(&self) -> Result<Arc<T>, ComponentError> {
+        self.get::<T>()
+            .ok_or_else(|| format!("Missing startup value {}", std::any::type_name::<T>()).into())
+    }
+}
+
+struct Component {
+    name: String,
+    dependencies: Vec<String>,
+    init: Box<dyn FnOnce(StartupContext) -> ComponentFuture + Send>,
+}
+
+/// Initialization time of the components in the order of the completion.
+#[derive(Clone, Debug, Default)]
+pub struct StartupReport {
+    pub components: Vec<(String, Duration)>,
+    pub total: Duration,
+}
+
+/// Initialize the components of a service in the order of their dependencies, the independent components are
+/// initialized concurrently. Each component runs in a `startup` span to find the slow steps.
+///
+/// ```ignore
+/// let (context, report) = StartupGraph::new()
+///     .with_component("telemetry", &[], |ctx| async move { ... })?
+///     .with_component("db", &["telemetry"], |ctx| async move { ctx.insert(pool); Ok(()) })?
+///     .with_component("redis", &["telemetry"], |ctx| async move { ... })?
+///     .with_component("migrations", &["db"], |ctx| async move { ctx.require::<PGConnectionPool>()?; ... })?
+///     .run()
+///     .await?;
+/// ```
+#[derive(Default)]
+pub struct StartupGraph {
+    context: StartupContext,
+    components: Vec<Component>,
+}
+
+impl StartupGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from an existing context with some values already provided.
+    pub fn with_context(context: StartupContext) -> Self {
+        Self {
+            context,
+            components: Vec::new(),
+        }
+    }
+
+    pub fn with_component<F, Fut>(mut self, name: &str, dependencies: &[&str], init: F) -> Result<Self, StartupError>
+    where
+        F: FnOnce(StartupContext) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), ComponentError>> + Send + 'static,
+    {
+        if self.components.iter().any(|component| component.name == name) {
+            return Err(StartupError::DuplicateComponent(name.to_string()));
+        }
+        self.components.push(Component {
+            name: name.to_string(),
+            dependencies: dependencies.iter().map(|dep| dep.to_string()).collect(),
+            init: Box::new(move |context| Box::pin(init(context))),
+        });
+        Ok(self)
+    }
+
+    /// Check that all the dependencies are registered and they are free of cycles.
+    fn validate(&self) -> Result<(), StartupError> {
+        let names: HashSet<&str> = self.components.iter().map(|c| c.name.as_str()).collect();
+        for component in &self.components {
+            if let Some(dep) = component.dependencies.iter().find(|dep| !names.contains(dep.as_str())) {
+                return Err(StartupError::UnknownDependency(component.name.clone(), dep.clone()));
+            }
+        }
+
+        let mut done = HashSet::new();
+        loop {
+            let ready: Vec<&str> = self
+                .components
+                .iter()
+                .filter(|c| !done.contains(c.name.as_str()))
+                .filter(|c| c.dependencies.iter().all(|dep| done.contains(dep.as_str())))
+                .map(|c| c.name.as_str())
+                .collect();
+            if ready.is_empty() {
+                break;
+            }
+            done.extend(ready);
+        }
+
+        if done.len() == self.components.len() {
+            Ok(())
+        } else {
+            let mut cycle: Vec<String> = names.difference(&done).map(|name| name.to_string()).collect();
+            cycle.sort();
+            Err(StartupError::Cycle(cycle))
+        }
+    }
+
+    /// Initialize all the components, the first failure aborts the startup.
+    pub async fn run(self) -> Result<(StartupContext, StartupReport), StartupError> {
+        self.validate()?;
+
+        let start = Instant::now();
+        let context = self.context;
+        let mut pending = self.components;
+        let mut done = HashSet::new();
+        let mut report = StartupReport::default();
+        let mut running = FuturesUnordered::new();
+
+        while !pending.is_empty() || !running.is_empty() {
+            let (ready, waiting): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|c: &Component| c.dependencies.iter().all(|dep| done.contains(dep)));
+            pending = waiting;
+
+            for component in ready {
+                let span = info_span!("startup", component = component.name);
+                let future = (component.init)(context.clone());
+                let name = component.name;
+                running.push(
+                    async move {
+                        let start = Instant::now();
+                        let result = future.await;
+                        (name, start.elapsed(), result)
+                    }
+                    .instrument(span),
+                );
+            }
+
+            // validation ensures that there is always something running here
+            let Some((name, duration, result)) = running.next().await else {
+                break;
+            };
+            match result {
+                Ok(()) => {
+                    log::info!("Component {name} initialized in {}ms", duration.as_millis());
+                    done.insert(name.clone());
+                    report.components.push((name, duration));
+                }
+                Err(err) => {
+                    log::error!(
+                        "Component {name} failed to initialize in {}ms: {err}",
+                        duration.as_millis()
+                    );
+                    return Err(StartupError::Failed(name, err));
+                }
+            }
+        }
+
+        report.total = start.elapsed();
+        log::info!("Startup completed in {}ms", report.total.as_millis());
+        Ok((context, report))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    async fn initialize_in_dependency_order() {
+        let (context, report) = StartupGraph::new()
+            .with_component("routers", &["migrations", "redis"], |ctx| async move {
+                let version = ctx.require::<u32>()?;
+                ctx.insert(format!("routers:{version}"));
+                Ok(())
+            })
+            .unwrap()
+            .with_component("telemetry", &[], |_| async move { Ok(()) })
+            .unwrap()
+            .with_component("db", &["telemetry"], |_| async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(())
+            })
+            .unwrap()
+            .with_component("redis", &["telemetry"], |_| async move { Ok(()) })
+            .unwrap()
+            .with_component("migrations", &["db"], |ctx| async move {
+                ctx.insert(3_u32);
+                Ok(())
+            })
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        let order: Vec<_> = report.components.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(order, ["telemetry", "redis", "db", "migrations", "routers"]);
+        assert_eq!(
+            context.get::<String>().as_deref().map(String::as_str),
+            Some("routers:3")
+        );
+    }
+
+    #[test]
+    async fn reject_invalid_graph() {
+        let result = StartupGraph::new()
+            .with_component("a", &["b"], |_| async move { Ok(()) })
+            .unwrap()
+            .with_component("b", &["a"], |_| async move { Ok(()) })
+            .unwrap()
+            .run()
+            .await;
+        assert!(matches!(result, Err(StartupError::Cycle(cycle)) if cycle == ["a", "b"]));
+
+        let result = StartupGraph::new()
+            .with_component("a", &["missing"], |_| async move { Ok(()) })
+            .unwrap()
+            .run()
+            .await;
+        assert!(matches!(result, Err(StartupError::UnknownDependency(..))));
+
+        let result = StartupGraph::new()
+            .with_component("a", &[], |_| async move { Err("boom".into()) })
+            .unwrap()
+            .run()
+            .await;
+        assert!(matches!(result, Err(StartupError::Failed(name, _)) if name == "a"));
+    }
+}
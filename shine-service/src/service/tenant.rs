@@ -0,0 +1,454 @@
+use crate::axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig};
+#[cfg(feature = "redis")]
+use crate::service::{CheckedCurrentUser, CurrentUser, UserSessionCacheReader};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+    Extension, RequestPartsExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, ops, sync::Arc};
+use thiserror::Error as ThisError;
+
+const MAX_TENANT_ID_LENGTH: usize = 64;
+const DEFAULT_TENANT_HEADER: &str = "x-tenant-id";
+const DEFAULT_TENANT_CLAIM: &str = "tenantId";
+
+#[derive(Debug, ThisError)]
+pub enum TenantError {
+    #[error("Missing tenant")]
+    Missing,
+    #[error("Invalid tenant id: {0}")]
+    Invalid(String),
+    #[error("Tenant {0} does not match the tenant of the session")]
+    Mismatch(String),
+}
+
+impl IntoProblem for TenantError {
+    fn into_problem(self, _config: &ProblemConfig) -> Problem {
+        match self {
+            TenantError::Missing => Problem::bad_request("tenant-missing").with_detail(self.to_string()),
+            TenantError::Invalid(_) => Problem::bad_request("tenant-invalid").with_detail(self.to_string()),
+            TenantError::Mismatch(_) => Problem::forbidden().with_detail(self.to_string()),
+        }
+    }
+}
+
+/// Identifier of a tenant, it consists of at most 64 ascii alphanumeric, `-` and `_` characters.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct TenantId(String);
+
+impl TenantId {
+    pub fn new<S: ToString>(tenant_id: S) -> Result<Self, TenantError> {
+        let tenant_id = tenant_id.to_string();
+        if tenant_id.is_empty()
+            || tenant_id.len() > MAX_TENANT_ID_LENGTH
+            || !tenant_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            Err(TenantError::Invalid(tenant_id))
+        } else {
+            Ok(Self(tenant_id))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl fmt::Display for TenantId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ops::Deref for TenantId {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+fn default_tenant_header() -> String {
+    DEFAULT_TENANT_HEADER.to_string()
+}
+
+fn default_sources() -> Vec<TenantSource> {
+    vec![TenantSource::Claim {
+        name: DEFAULT_TENANT_CLAIM.to_string(),
+    }]
+}
+
+/// Location of the tenant id in the request.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum TenantSource {
+    /// The first label of the host below the base domain, ex. `acme` of `acme.example.com`.
+    #[serde(rename_all = "camelCase")]
+    Subdomain { base_domain: String },
+    /// A request header, any client can set it. It is safe only behind a gateway replacing (or stripping) the
+    /// header of the incoming requests, or together with a claim source verifying it.
+    #[serde(rename_all = "camelCase")]
+    Header {
+        #[serde(default = "default_tenant_header")]
+        name: String,
+    },
+    /// A claim of the (validated) session populated by the identity service, it requires the user session layer.
+    /// When a session is present, the tenant id of the other sources has to match the claim.
+    #[serde(rename_all = "camelCase")]
+    Claim { name: String },
+}
+
+impl TenantSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TenantSource::Subdomain { .. } => "subdomain",
+            TenantSource::Header { .. } => "header",
+            TenantSource::Claim { .. } => "claim",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantConfig {
+    /// The sources checked in order, the first present tenant id is used. With a listed claim source, a tenant id
+    /// of the request (header, subdomain) of an authenticated user is rejected unless the tenant claim of the
+    /// session matches it.
+    /// Defaults to the `tenantId` claim of the session.
+    #[serde(default = "default_sources")]
+    pub sources: Vec<TenantSource>,
+    /// Tenant of the requests without a tenant id, ex. for a single tenant deployment.
+    pub default_tenant: Option<String>,
+}
+
+impl Default for TenantConfig {
+    fn default() -> Self {
+        Self {
+            sources: default_sources(),
+            default_tenant: None,
+        }
+    }
+}
+
+/// The tenant of the request, it is resolved by the first `TenantContext` or `TenantId` extractor and cached in
+/// the request extensions.
+#[derive(Clone, Debug)]
+pub struct TenantContext {
+    pub tenant_id: TenantId,
+    /// The source of the tenant id, ex. `header` or `default`.
+    pub source: &'static str,
+}
+
+/// Resolve the tenant of the requests from the sources of the configuration.
+///
+/// ```ignore
+/// let router = router.layer(TenantResolver::new(&config.tenant)?.into_layer());
+///
+/// async fn handler(tenant: TenantId, TenantDb(client): TenantDb) {
+///     let rows = client.query("SELECT * FROM items", &[]).await?;
+/// }
+/// ```
+pub struct TenantResolver {
+    sources: Vec<TenantSource>,
+    default_tenant: Option<TenantId>,
+}
+
+impl TenantResolver {
+    pub fn new(config: &TenantConfig) -> Result<Self, TenantError> {
+        let sources = config
+            .sources
+            .iter()
+            .map(|source| match source {
+                TenantSource::Subdomain { base_domain } => TenantSource::Subdomain {
+                    base_domain: base_domain.trim_start_matches('.').to_ascii_lowercase(),
+                },
+                source => source.clone(),
+            })
+            .collect();
+        let default_tenant = config.default_tenant.as_ref().map(TenantId::new).transpose()?;
+        Ok(Self {
+            sources,
+            default_tenant,
+        })
+    }
+
+    pub fn into_layer(self) -> Extension<Arc<Self>> {
+        Extension(Arc::new(self))
+    }
+
+    fn subdomain<'a>(host: &'a str, base_domain: &str) -> Option<&'a str> {
+        let host = host.split(':').next()?;
+        let label = host.strip_suffix(base_domain)?.strip_suffix('.')?;
+        (!label.is_empty() && !label.contains('.')).then_some(label)
+    }
+
+    #[cfg(feature = "redis")]
+    fn has_claim_source(&self) -> bool {
+        self.sources
+            .iter()
+            .any(|source| matches!(source, TenantSource::Claim { .. }))
+    }
+
+    /// The validated session of the request, it is cached in the request extensions for the claim sources.
+    #[cfg(feature = "redis")]
+    async fn session(parts: &mut Parts) -> Option<CurrentUser> {
+        #[derive(Clone)]
+        struct TenantSession(Option<CurrentUser>);
+
+        if let Some(TenantSession(user)) = parts.extensions.get::<TenantSession>() {
+            return user.clone();
+        }
+        let user = if parts.extensions.get::<Arc<UserSessionCacheReader>>().is_some() {
+            parts
+                .extract::<CheckedCurrentUser>()
+                .await
+                .ok()
+                .map(|user| user.into_user())
+        } else {
+            None
+        };
+        parts.extensions.insert(TenantSession(user.clone()));
+        user
+    }
+
+    async fn find(&self, parts: &mut Parts, source: &TenantSource) -> Option<String> {
+        match source {
+            TenantSource::Subdomain { base_domain } => {
+                let host = parts
+                    .uri
+                    .host()
+                    .or_else(|| parts.headers.get(header::HOST).and_then(|host| host.to_str().ok()))?;
+                Self::subdomain(&host.to_ascii_lowercase(), base_domain).map(|label| label.to_string())
+            }
+            TenantSource::Header { name } => parts
+                .headers
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty()),
+            #[cfg(feature = "redis")]
+            TenantSource::Claim { name } => {
+                let user = Self::session(parts).await?;
+                user.claim_str(name).map(|value| value.to_string())
+            }
+            #[cfg(not(feature = "redis"))]
+            TenantSource::Claim { .. } => None,
+        }
+    }
+
+    /// Select the first present tenant id of the sources. With a session, a tenant id taken from the request must
+    /// match the claims of the session, thus a user cannot switch to another tenant by altering the request.
+    fn select(&self, found: &[Option<String>], has_session: bool) -> Result<TenantContext, TenantError> {
+        let selected = self
+            .sources
+            .iter()
+            .zip(found)
+            .find_map(|(source, tenant_id)| Some((source, tenant_id.as_ref()?)));
+        let Some((source, tenant_id)) = selected else {
+            return match &self.default_tenant {
+                Some(tenant_id) => Ok(TenantContext {
+                    tenant_id: tenant_id.clone(),
+                    source: "default",
+                }),
+                None => Err(TenantError::Missing),
+            };
+        };
+
+        let tenant_id = TenantId::new(tenant_id)?;
+        if has_session && !matches!(source, TenantSource::Claim { .. }) {
+            let mismatch = self
+                .sources
+                .iter()
+                .zip(found)
+                .filter(|(source, _)| matches!(source, TenantSource::Claim { .. }))
+                .any(|(_, claim)| claim.as_deref() != Some(tenant_id.as_str()));
+            if mismatch {
+                return Err(TenantError::Mismatch(tenant_id.into_string()));
+            }
+        }
+
+        Ok(TenantContext {
+            tenant_id,
+            source: source.as_str(),
+        })
+    }
+
+    /// Find the tenant of the request.
+    pub async fn resolve(&self, parts: &mut Parts) -> Result<TenantContext, TenantError> {
+        let mut found = Vec::with_capacity(self.sources.len());
+        for source in &self.sources {
+            found.push(self.find(parts, source).await);
+        }
+        #[cfg(feature = "redis")]
+        let has_session = self.has_claim_source() && Self::session(parts).await.is_some();
+        #[cfg(not(feature = "redis"))]
+        let has_session = false;
+        self.select(&found, has_session)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for TenantContext
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<TenantError>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(context) = parts.extensions.get::<TenantContext>() {
+            return Ok(context.clone());
+        }
+
+        let Extension(problem_config) = parts
+            .extract::<Extension<ProblemConfig>>()
+            .await
+            .expect("Missing ProblemConfig extension");
+        let Extension(resolver) = parts
+            .extract::<Extension<Arc<TenantResolver>>>()
+            .await
+            .expect("Missing TenantResolver extension");
+
+        let context = resolver
+            .resolve(parts)
+            .await
+            .map_err(|err| problem_config.configure(err))?;
+        parts.extensions.insert(context.clone());
+        Ok(context)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for TenantId
+where
+    S: Send + Sync,
+{
+    type Rejection = ConfiguredProblem<TenantError>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let context = TenantContext::from_request_parts(parts, state).await?;
+        Ok(context.tenant_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::Request;
+    use shine_test::test;
+
+    fn parts(host: &str, tenant: Option<&str>) -> Parts {
+        let mut request = Request::builder().uri("/api/users").header(header::HOST, host);
+        if let Some(tenant) = tenant {
+            request = request.header(DEFAULT_TENANT_HEADER, tenant);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn tenant_id_validation() {
+        assert!(TenantId::new("acme-42_eu").is_ok());
+        assert!(TenantId::new("").is_err());
+        assert!(TenantId::new("acme.com").is_err());
+        assert!(TenantId::new("a".repeat(65)).is_err());
+    }
+
+    #[test]
+    async fn resolve_in_order() {
+        let config: TenantConfig = serde_json::from_value(serde_json::json!({
+            "sources": [
+                { "type": "subdomain", "baseDomain": "Example.com" },
+                { "type": "header" }
+            ]
+        }))
+        .unwrap();
+        let resolver = TenantResolver::new(&config).unwrap();
+
+        let tenant = resolver
+            .resolve(&mut parts("acme.example.com:8080", Some("other")))
+            .await
+            .unwrap();
+        assert_eq!((tenant.tenant_id.as_str(), tenant.source), ("acme", "subdomain"));
+
+        let tenant = resolver
+            .resolve(&mut parts("a.b.example.com", Some("other")))
+            .await
+            .unwrap();
+        assert_eq!((tenant.tenant_id.as_str(), tenant.source), ("other", "header"));
+
+        assert!(matches!(
+            resolver.resolve(&mut parts("example.com", None)).await,
+            Err(TenantError::Missing)
+        ));
+        assert!(matches!(
+            resolver.resolve(&mut parts("example.com", Some("a/b"))).await,
+            Err(TenantError::Invalid(_))
+        ));
+
+        let config = TenantConfig {
+            sources: vec![TenantSource::Header {
+                name: default_tenant_header(),
+            }],
+            default_tenant: Some("main".into()),
+        };
+        let resolver = TenantResolver::new(&config).unwrap();
+        let tenant = resolver.resolve(&mut parts("example.com", None)).await.unwrap();
+        assert_eq!((tenant.tenant_id.as_str(), tenant.source), ("main", "default"));
+    }
+
+    #[test]
+    fn claim_by_default() {
+        let resolver = TenantResolver::new(&TenantConfig::default()).unwrap();
+        assert!(matches!(resolver.sources.as_slice(), [TenantSource::Claim { name }] if name == "tenantId"));
+
+        let tenant = resolver.select(&[Some("acme".into())], true).unwrap();
+        assert_eq!((tenant.tenant_id.as_str(), tenant.source), ("acme", "claim"));
+        assert!(matches!(resolver.select(&[None], true), Err(TenantError::Missing)));
+        assert!(matches!(resolver.select(&[None], false), Err(TenantError::Missing)));
+    }
+
+    #[test]
+    fn request_tenant_matches_session() {
+        let config: TenantConfig = serde_json::from_value(serde_json::json!({
+            "sources": [
+                { "type": "header" },
+                { "type": "claim", "name": "tenantId" }
+            ]
+        }))
+        .unwrap();
+        let resolver = TenantResolver::new(&config).unwrap();
+
+        let tenant = resolver
+            .select(&[Some("acme".into()), Some("acme".into())], true)
+            .unwrap();
+        assert_eq!((tenant.tenant_id.as_str(), tenant.source), ("acme", "header"));
+        // without a session the header is accepted
+        let tenant = resolver.select(&[Some("acme".into()), None], false).unwrap();
+        assert_eq!((tenant.tenant_id.as_str(), tenant.source), ("acme", "header"));
+        // a session without a tenant claim cannot pick a tenant
+        assert!(matches!(
+            resolver.select(&[Some("acme".into()), None], true),
+            Err(TenantError::Mismatch(_))
+        ));
+        let tenant = resolver.select(&[None, Some("acme".into())], true).unwrap();
+        assert_eq!((tenant.tenant_id.as_str(), tenant.source), ("acme", "claim"));
+
+        let err = resolver
+            .select(&[Some("other".into()), Some("acme".into())], true)
+            .unwrap_err();
+        assert!(matches!(&err, TenantError::Mismatch(tenant_id) if tenant_id == "other"));
+        assert_eq!(
+            err.into_problem(&ProblemConfig::new(false)).status(),
+            axum::http::StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -1,19 +1,33 @@
 use crate::{
-    axum::{ConfiguredProblem, IntoProblem, Problem, ProblemConfig},
+    axum::{AccessLogUser, ConfiguredProblem, ErrorCategory, IntoProblem, Problem, ProblemConfig, ServiceError},
     service::{
-        serde_session_key, ClientFingerprint, ClientFingerprintError, RedisConnectionError, RedisConnectionPool,
-        SessionKey,
+        serde_session_key, ClientFingerprint, ClientFingerprintError, CookieAttributes, CookieCodec, CookieConfig,
+        CookieOverflowError, CookieOverflowStore, RedisConnectionError, RedisConnectionPool, SessionEpoch, SessionKey,
     },
+    utils::DurationStr,
 };
 use axum::{async_trait, extract::FromRequestParts, http::request::Parts, Extension, RequestPartsExt};
-use axum_extra::extract::{cookie::Key, SignedCookieJar};
+use axum_extra::extract::{
+    cookie::{Cookie, Key},
+    SignedCookieJar,
+};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
 use chrono::{DateTime, Utc};
+use opentelemetry::{
+    metrics::{Counter, Meter},
+    KeyValue,
+};
 use redis::AsyncCommands;
 use ring::digest;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use shine_macros::RedisJsonValue;
-use std::{ops, sync::Arc};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    ops,
+    sync::Arc,
+};
 use thiserror::Error as ThisError;
 use uuid::Uuid;
 
@@ -33,13 +47,35 @@ pub enum UserSessionError {
     RedisPoolError(#[source] RedisConnectionError),
     #[error("Redis error")]
     RedisError(#[from] redis::RedisError),
+    #[error(transparent)]
+    CookieOverflow(#[from] CookieOverflowError),
 }
 
-impl IntoProblem for UserSessionError {
-    fn into_problem(self, config: &ProblemConfig) -> Problem {
+impl ServiceError for UserSessionError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            UserSessionError::RedisPoolError(_) | UserSessionError::RedisError(_) => ErrorCategory::Dependency,
+            UserSessionError::CookieOverflow(err) => err.category(),
+            _ => ErrorCategory::User,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            UserSessionError::RedisPoolError(err) => err.is_retryable(),
+            UserSessionError::RedisError(err) => err.is_retryable(),
+            UserSessionError::CookieOverflow(err) => err.is_retryable(),
+            _ => false,
+        }
+    }
+
+    fn to_problem(&self, config: &ProblemConfig) -> Problem {
         match self {
             UserSessionError::RedisPoolError(err) => Problem::internal_error(config, "Redis connection error", err),
             UserSessionError::RedisError(err) => Problem::internal_error(config, "Redis error", err),
+            UserSessionError::CookieOverflow(err) if err.category() != ErrorCategory::User => {
+                Problem::internal_error(config, "Cookie overflow error", err)
+            }
             _ => Problem::unauthorized()
                 .with_detail(self.to_string())
                 .with_extension(config, format!("{:#?}", self)),
@@ -47,9 +83,15 @@ impl IntoProblem for UserSessionError {
     }
 }
 
+impl IntoProblem for UserSessionError {
+    fn into_problem(self, config: &ProblemConfig) -> Problem {
+        self.to_problem(config)
+    }
+}
+
 /// Current user accessible as an Extractor from the handlers and also the
 /// stored data in the session cookie
-#[derive(Clone, Debug, Hash, Serialize, Deserialize, RedisJsonValue)]
+#[derive(Clone, Debug, Serialize, Deserialize, RedisJsonValue)]
 pub struct CurrentUser {
     #[serde(rename = "u")]
     pub user_id: Uuid,
@@ -65,6 +107,58 @@ pub struct CurrentUser {
     pub fingerprint: String,
     #[serde(rename = "v")]
     pub version: i32,
+    /// Additional attributes populated by the identity service (ex. tenant id, locale). It is omitted from the
+    /// cookie when empty to keep the format of the existing cookies.
+    #[serde(rename = "c", default, skip_serializing_if = "HashMap::is_empty")]
+    pub claims: HashMap<String, JsonValue>,
+}
+
+impl Hash for CurrentUser {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.user_id.hash(state);
+        self.key.hash(state);
+        self.session_start.hash(state);
+        self.name.hash(state);
+        self.roles.hash(state);
+        self.fingerprint.hash(state);
+        self.version.hash(state);
+        // json values are not hashable, the claims are hashed in key order by their serialized form
+        let mut claims = self.claims.iter().collect::<Vec<_>>();
+        claims.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in claims {
+            name.hash(state);
+            serde_json::to_string(value).unwrap_or_default().hash(state);
+        }
+    }
+}
+
+impl CurrentUser {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    /// Hash of the session key identifying the session in the redis cache.
+    pub fn key_hash(&self) -> String {
+        hex::encode(digest::digest(&digest::SHA256, self.key.as_bytes()))
+    }
+
+    pub fn has_claim(&self, name: &str) -> bool {
+        self.claims.contains_key(name)
+    }
+
+    /// Get a claim converted into the given type, returns None if the claim is missing.
+    pub fn try_claim<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, serde_json::Error> {
+        self.claims.get(name).map(|value| T::deserialize(value)).transpose()
+    }
+
+    /// Get a claim converted into the given type, returns None if the claim is missing or it has a different type.
+    pub fn claim<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        self.try_claim(name).ok().flatten()
+    }
+
+    pub fn claim_str(&self, name: &str) -> Option<&str> {
+        self.claims.get(name).and_then(|value| value.as_str())
+    }
 }
 
 pub struct CheckedCurrentUser(CurrentUser);
@@ -117,6 +211,7 @@ where
             .refresh_user(&mut user)
             .await
             .map_err(|err| problem_config.configure(err))?;
+        AccessLogUser::record(&parts.extensions, user.user_id);
         Ok(CheckedCurrentUser(user))
     }
 }
@@ -171,10 +266,14 @@ where
             .map_err(|err| problem_config.configure(UserSessionError::from(err.problem)))?;
 
         let jar = SignedCookieJar::from_headers(&parts.headers, validator.cookie_secret.clone());
-        let user = jar
-            .get(&validator.cookie_name)
-            .and_then(|cookie| serde_json::from_str::<CurrentUser>(cookie.value()).ok())
-            .ok_or_else(|| problem_config.configure(UserSessionError::Unauthenticated))?;
+        let user = match jar.get(&validator.cookie_name) {
+            Some(cookie) => validator
+                .decode_cookie::<CurrentUser>(cookie.value())
+                .await
+                .map_err(|err| problem_config.configure(err))?,
+            None => None,
+        };
+        let user = user.ok_or_else(|| problem_config.configure(UserSessionError::Unauthenticated))?;
 
         // perform the least minimal validation
         if user.fingerprint != fingerprint.as_str() {
@@ -185,12 +284,76 @@ where
     }
 }
 
+/// Sentinel of a session in the redis cache. It should be in sync with the identity service.
+#[derive(Serialize, Deserialize, Debug, RedisJsonValue)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SessionSentinel {
+    pub created_at: DateTime<Utc>,
+    pub fingerprint: String,
+}
+
+/// Versioned data of a session in the redis cache. It should be in sync with the identity service.
+#[derive(Serialize, Deserialize, Debug, RedisJsonValue)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SessionData {
+    pub name: String,
+    pub is_email_confirmed: bool,
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub claims: HashMap<String, JsonValue>,
+}
+
+/// Return the redis keys of the sentinel and the data of a session.
+pub(crate) fn session_redis_keys(key_prefix: &str, user_id: &Uuid, key_hash: &str) -> (String, String) {
+    let prefix = format!("{}session:{}:{}", key_prefix, user_id.as_simple(), key_hash);
+    let sentinel_key = format!("{prefix}:openness");
+    let key = format!("{prefix}:data");
+    (sentinel_key, key)
+}
+
+/// Return the redis key of the flash messages of a session.
+pub(crate) fn session_flash_key(key_prefix: &str, user_id: &Uuid, key_hash: &str) -> String {
+    format!("{}session:{}:{}:flash", key_prefix, user_id.as_simple(), key_hash)
+}
+
+/// Return the redis key of the session index of a user, the set of the key hashes of the sessions. The identity
+/// service adds the new sessions to it, see `UserSessionManager::index_session`.
+pub(crate) fn session_index_key(key_prefix: &str, user_id: &Uuid) -> String {
+    format!("{}session:{}:index", key_prefix, user_id.as_simple())
+}
+
+/// Parse the user id and key hash from the key of a session sentinel.
+pub(crate) fn parse_session_sentinel_key(key_prefix: &str, key: &str) -> Option<(Uuid, String)> {
+    let key = key.strip_prefix(key_prefix)?.strip_prefix("session:")?;
+    let mut tokens = key.split(':');
+    let user_id = tokens.next().and_then(|id| Uuid::parse_str(id).ok())?;
+    let key_hash = tokens.next()?.to_string();
+    (tokens.next() == Some("openness")).then_some((user_id, key_hash))
+}
+
+/// Tolerance of the session data version of the cookie being ahead of the cache.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionVersionTolerance {
+    /// Maximum difference of the versions treated as a lag of the cache instead of a compromised session.
+    pub max_lag: i32,
+    /// Wait and read the versions again before rejecting the session as expired, ex: `"200ms"`. Without a
+    /// delay the session is rejected as expired immediately, requiring a re-authentication.
+    pub retry_delay: Option<DurationStr>,
+}
+
 /// Handle the user data query in the redis cache.
 pub struct UserSessionCacheReader {
     cookie_name: String,
+    cookie_attributes: CookieAttributes,
     cookie_secret: Key,
-    key_prefix: String,
-    redis: RedisConnectionPool,
+    cookie_codec: CookieCodec,
+    cookie_overflow: Option<Arc<CookieOverflowStore>>,
+    pub(crate) key_prefix: String,
+    epoch: Arc<SessionEpoch>,
+    version_tolerance: Option<SessionVersionTolerance>,
+    validation_count: Option<Counter<u64>>,
+    pub(crate) redis: RedisConnectionPool,
 }
 
 impl UserSessionCacheReader {
@@ -210,45 +373,146 @@ impl UserSessionCacheReader {
 
         Ok(Self {
             cookie_name: format!("sid{}", name_suffix),
+            cookie_attributes: CookieAttributes::default(),
             cookie_secret,
+            cookie_codec: CookieCodec::default(),
+            cookie_overflow: None,
             key_prefix: key_prefix.to_string(),
+            epoch: Arc::new(SessionEpoch::new(key_prefix)),
+            version_tolerance: None,
+            validation_count: None,
             redis,
         })
     }
 
+    /// Global session epoch, sessions created before it are rejected.
+    pub fn session_epoch(&self) -> &Arc<SessionEpoch> {
+        &self.epoch
+    }
+
+    /// Set the attributes of the session cookie. The cookie name is resolved with the configured `__Host-` or
+    /// `__Secure-` prefix.
+    #[must_use]
+    pub fn with_cookie_config(self, cookie_config: CookieConfig) -> Self {
+        let cookie_attributes = cookie_config.attributes(&self.cookie_name);
+        let cookie_name = cookie_attributes.prefixed_name(&self.cookie_name);
+        Self {
+            cookie_name,
+            cookie_attributes,
+            ..self
+        }
+    }
+
+    /// Create the session cookie with the configured attributes, it has to be added to a `SignedCookieJar` with
+    /// the cookie secret.
+    pub fn session_cookie(&self, value: String) -> Cookie<'static> {
+        self.cookie_attributes
+            .apply(Cookie::new(self.cookie_name.clone(), value))
+    }
+
+    /// Remove the session cookie, the domain and path of the removal match the configured attributes.
+    pub fn remove_session_cookie(&self, jar: SignedCookieJar) -> SignedCookieJar {
+        jar.remove(self.session_cookie(String::new()))
+    }
+
+    /// Set the encoding of the cookie payloads. The legacy json cookies are accepted with any codec, but the
+    /// session cookie is written by the identity service, thus the format has to be changed there first.
+    #[must_use]
+    pub fn with_cookie_codec(self, cookie_codec: CookieCodec) -> Self {
+        Self { cookie_codec, ..self }
+    }
+
+    /// Resolve the cookie payloads moved to redis by the identity service. The payloads are decoded with the
+    /// codec of the store, the inline payloads are accepted as before.
+    #[must_use]
+    pub fn with_cookie_overflow(self, cookie_overflow: CookieOverflowStore) -> Self {
+        Self {
+            cookie_overflow: Some(Arc::new(cookie_overflow)),
+            ..self
+        }
+    }
+
+    /// Decode a cookie payload, None if it is malformed, tampered or the stored payload has expired.
+    async fn decode_cookie<T: DeserializeOwned>(&self, value: &str) -> Result<Option<T>, UserSessionError> {
+        match &self.cookie_overflow {
+            Some(cookie_overflow) => match cookie_overflow.decode(value).await {
+                Ok(decoded) => Ok(Some(decoded)),
+                Err(err) if err.category() == ErrorCategory::User => {
+                    log::debug!("Rejected cookie payload: {err}");
+                    Ok(None)
+                }
+                Err(err) => Err(err.into()),
+            },
+            None => Ok(self.cookie_codec.decode(value).ok()),
+        }
+    }
+
+    /// Accept the cookies with a session data version ahead of the cache within the tolerance, by default any
+    /// difference is reported as a compromised session.
+    #[must_use]
+    pub fn with_version_tolerance(self, version_tolerance: SessionVersionTolerance) -> Self {
+        Self {
+            version_tolerance: Some(version_tolerance),
+            ..self
+        }
+    }
+
+    /// Count the session validations by the outcome in `session_validation_count`.
+    #[must_use]
+    pub fn with_meter(self, meter: &Meter) -> Self {
+        Self {
+            validation_count: Some(meter.u64_counter("session_validation_count").init()),
+            ..self
+        }
+    }
+
     pub fn into_layer(self) -> Extension<Arc<Self>> {
         Extension(Arc::new(self))
     }
 
+    async fn read_data<C>(client: &mut C, key: &str, version: i32) -> Result<Option<SessionData>, UserSessionError>
+    where
+        C: AsyncCommands + Send,
+    {
+        client
+            .hget(key, format!("{version}"))
+            .await
+            .map_err(UserSessionError::RedisError)
+    }
+
+    fn record_outcome(&self, outcome: &'static str) {
+        if let Some(counter) = &self.validation_count {
+            counter.add(1, &[KeyValue::new("outcome", outcome)]);
+        }
+    }
+
     /// Refresh the session data in the cache. It should be in sync with the identity service
     /// and introduce any breaking change with great care as that can break authentication in all the service.
     async fn refresh_user(&self, user: &mut CurrentUser) -> Result<(), UserSessionError> {
-        #[derive(Serialize, Deserialize, Debug, RedisJsonValue)]
-        #[serde(rename_all = "camelCase")]
-        struct SessionSentinel {
-            pub created_at: DateTime<Utc>,
-            pub fingerprint: String,
-        }
-
-        #[derive(Serialize, Deserialize, Debug, RedisJsonValue)]
-        #[serde(rename_all = "camelCase")]
-        struct SessionData {
-            pub name: String,
-            pub is_email_confirmed: bool,
-            pub roles: Vec<String>,
+        match self.validate_user(user).await {
+            Ok(outcome) => {
+                self.record_outcome(outcome);
+                Ok(())
+            }
+            Err((err, outcome)) => {
+                self.record_outcome(outcome);
+                Err(err)
+            }
         }
+    }
 
-        let (sentinel_key, key) = {
-            let key_hash = digest::digest(&digest::SHA256, user.key.as_bytes());
-            let key_hash = hex::encode(key_hash);
+    async fn validate_user(&self, user: &mut CurrentUser) -> Result<&'static str, (UserSessionError, &'static str)> {
+        let expired = |outcome| (UserSessionError::SessionExpired, outcome);
+        let compromised = || (UserSessionError::SessionCompromised, "compromised");
+        let failed = |err| (err, "error");
 
-            let prefix = format!("{}session:{}:{}", self.key_prefix, user.user_id.as_simple(), key_hash);
-            let sentinel_key = format!("{prefix}:openness");
-            let key = format!("{prefix}:data");
-            (sentinel_key, key)
-        };
+        let (sentinel_key, key) = session_redis_keys(&self.key_prefix, &user.user_id, &user.key_hash());
 
-        let mut client = self.redis.get().await.map_err(UserSessionError::RedisPoolError)?;
+        let mut client = self
+            .redis
+            .get()
+            .await
+            .map_err(|err| failed(UserSessionError::RedisPoolError(err)))?;
 
         // query sentinel and the available data versions
         let (sentinel, data_versions): (Option<SessionSentinel>, Vec<i32>) = redis::pipe()
@@ -256,41 +520,153 @@ impl UserSessionCacheReader {
             .hkeys(&key)
             .query_async(&mut *client)
             .await
-            .map_err(UserSessionError::RedisError)?;
+            .map_err(|err| failed(UserSessionError::RedisError(err)))?;
 
         // check if sentinel is present
         let sentinel = match sentinel {
             Some(sentinel) => sentinel,
-            _ => return Err(UserSessionError::SessionExpired),
+            _ => return Err(expired("expired")),
         };
 
+        // check if the session was created before the global session epoch
+        let epoch = self
+            .epoch
+            .get(&mut *client)
+            .await
+            .map_err(|err| failed(UserSessionError::RedisError(err)))?;
+        if epoch.is_some_and(|epoch| sentinel.created_at < epoch) {
+            return Err(expired("expired"));
+        }
+
         // find the latest data version
-        let version = match data_versions.into_iter().max() {
+        let mut version = match data_versions.into_iter().max() {
             Some(version) => version,
-            _ => return Err(UserSessionError::SessionExpired),
-        };
-
-        // find data. In a very unlikely case data could have been just deleted.
-        let data: SessionData = match client
-            .hget(&key, format!("{version}"))
-            .await
-            .map_err(UserSessionError::RedisError)?
-        {
-            Some(data) => data,
-            _ => return Err(UserSessionError::SessionExpired),
+            _ => return Err(expired("expired")),
         };
 
         // check the fingerprint and other validations
-        if user.fingerprint != sentinel.fingerprint
-            || user.version > version
-            || user.session_start != sentinel.created_at
-        {
-            return Err(UserSessionError::SessionCompromised);
+        if user.fingerprint != sentinel.fingerprint || user.session_start != sentinel.created_at {
+            return Err(compromised());
+        }
+
+        // The cookie is ahead of the cache. It is a replayed cookie or the replication of the cache lags behind
+        // the identity service, within the tolerance the latter is assumed.
+        let mut outcome = "valid";
+        if user.version > version {
+            let tolerance = self
+                .version_tolerance
+                .as_ref()
+                .filter(|tolerance| user.version - version <= tolerance.max_lag)
+                .ok_or_else(compromised)?;
+            let Some(retry_delay) = tolerance.retry_delay else {
+                return Err(expired("versionLag"));
+            };
+
+            // don't hold a pooled connection while waiting
+            drop(client);
+            tokio::time::sleep(retry_delay.into()).await;
+            client = self
+                .redis
+                .get()
+                .await
+                .map_err(|err| failed(UserSessionError::RedisPoolError(err)))?;
+            let data_versions: Vec<i32> = client
+                .hkeys(&key)
+                .await
+                .map_err(|err| failed(UserSessionError::RedisError(err)))?;
+            version = data_versions
+                .into_iter()
+                .max()
+                .filter(|version| *version >= user.version)
+                .ok_or_else(|| expired("versionLag"))?;
+            outcome = "versionLagRecovered";
         }
 
+        // find data. In a very unlikely case data could have been just deleted.
+        let data = Self::read_data(&mut *client, &key, version)
+            .await
+            .map_err(failed)?
+            .ok_or_else(|| expired("expired"))?;
+
         user.name = data.name;
         user.roles = data.roles;
+        user.claims = data.claims;
         user.version = version;
-        Ok(())
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    fn test_user() -> CurrentUser {
+        CurrentUser {
+            user_id: Uuid::new_v4(),
+            key: SessionKey::from_bytes([0; 16]),
+            session_start: Utc::now(),
+            name: "user".into(),
+            roles: vec![],
+            fingerprint: "fp".into(),
+            version: 1,
+            claims: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn session_index_is_not_a_sentinel() {
+        let user_id = Uuid::new_v4();
+        let (sentinel_key, _) = session_redis_keys("prefix:", &user_id, "hash");
+        assert_eq!(
+            parse_session_sentinel_key("prefix:", &sentinel_key),
+            Some((user_id, "hash".to_string()))
+        );
+        assert_eq!(
+            parse_session_sentinel_key("prefix:", &session_index_key("prefix:", &user_id)),
+            None
+        );
+    }
+
+    #[test]
+    fn claims_format() {
+        let mut user = test_user();
+        let json = serde_json::to_value(&user).unwrap();
+        assert!(json.get("c").is_none());
+        let user_without_claims: CurrentUser = serde_json::from_value(json).unwrap();
+        assert!(user_without_claims.claims.is_empty());
+
+        let tenant_id = Uuid::new_v4();
+        user.claims.insert("tenantId".into(), tenant_id.to_string().into());
+        user.claims.insert("locale".into(), "hu-HU".into());
+        let user: CurrentUser = serde_json::from_value(serde_json::to_value(&user).unwrap()).unwrap();
+        assert_eq!(user.claim::<Uuid>("tenantId"), Some(tenant_id));
+        assert_eq!(user.claim_str("locale"), Some("hu-HU"));
+        assert_eq!(user.claim::<i32>("locale"), None);
+        assert!(user.try_claim::<i32>("locale").is_err());
+        assert!(user.try_claim::<i32>("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn claims_hash() {
+        fn hash(user: &CurrentUser) -> u64 {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            user.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let mut user = test_user();
+        let plain = hash(&user);
+        user.claims.insert("tenantId".into(), "acme".into());
+        user.claims.insert("locale".into(), "hu-HU".into());
+        let with_claims = hash(&user);
+        assert_ne!(plain, with_claims);
+
+        let mut reordered = user.clone();
+        reordered.claims = user.claims.clone().into_iter().rev().collect();
+        assert_eq!(hash(&reordered), with_claims);
+
+        reordered.claims.insert("tenantId".into(), "other".into());
+        assert_ne!(hash(&reordered), with_claims);
     }
 }
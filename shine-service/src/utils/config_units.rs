@@ -0,0 +1,276 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr, time::Duration};
+use thiserror::Error as ThisError;
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
+
+#[derive(Debug, PartialEq, Eq, ThisError)]
+pub enum ConfigUnitError {
+    #[error("Empty value")]
+    Empty,
+    #[error("Missing unit in {0:?}")]
+    MissingUnit(String),
+    #[error("Unknown unit {1:?} in {0:?}")]
+    UnknownUnit(String, String),
+    #[error("Invalid number in {0:?}")]
+    InvalidNumber(String),
+    #[error("Value {0:?} is out of range")]
+    Overflow(String),
+}
+
+/// Split a value like `1h30m` into (number, unit) segments.
+fn segments(value: &str) -> Result<Vec<(u64, &str)>, ConfigUnitError> {
+    let mut result = Vec::new();
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return Err(ConfigUnitError::Empty);
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(ConfigUnitError::InvalidNumber(value.to_string()));
+        }
+        let number = rest[..digits]
+            .parse::<u64>()
+            .map_err(|_| ConfigUnitError::Overflow(value.to_string()))?;
+        rest = rest[digits..].trim_start();
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        result.push((number, rest[..unit_len].trim()));
+        rest = &rest[unit_len..];
+    }
+    Ok(result)
+}
+
+/// A duration in the config given with units, ex: `"500ms"`, `"30s"`, `"5m"`, `"1h30m"`, `"7d"`.
+/// A number without a unit is rejected to avoid the confusion of seconds and milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[cfg_attr(feature = "openapi", schema(value_type = String, example = "30s"))]
+pub struct DurationStr(Duration);
+
+impl DurationStr {
+    pub const fn new(duration: Duration) -> Self {
+        Self(duration)
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Self(Duration::from_millis(millis))
+    }
+
+    pub const fn as_duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for DurationStr {
+    fn from(value: Duration) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DurationStr> for Duration {
+    fn from(value: DurationStr) -> Self {
+        value.0
+    }
+}
+
+impl FromStr for DurationStr {
+    type Err = ConfigUnitError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut millis: u64 = 0;
+        for (number, unit) in segments(value)? {
+            let scale = match unit {
+                "" => return Err(ConfigUnitError::MissingUnit(value.to_string())),
+                "ms" => 1,
+                "s" => 1000,
+                "m" => 60 * 1000,
+                "h" => 60 * 60 * 1000,
+                "d" => 24 * 60 * 60 * 1000,
+                unit => return Err(ConfigUnitError::UnknownUnit(value.to_string(), unit.to_string())),
+            };
+            millis = number
+                .checked_mul(scale)
+                .and_then(|segment| millis.checked_add(segment))
+                .ok_or_else(|| ConfigUnitError::Overflow(value.to_string()))?;
+        }
+        Ok(Self(Duration::from_millis(millis)))
+    }
+}
+
+impl fmt::Display for DurationStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: &[(u128, &str)] = &[
+            (24 * 60 * 60 * 1000, "d"),
+            (60 * 60 * 1000, "h"),
+            (60 * 1000, "m"),
+            (1000, "s"),
+        ];
+        let millis = self.0.as_millis();
+        match UNITS.iter().find(|(scale, _)| millis > 0 && millis % scale == 0) {
+            Some((scale, unit)) => write!(f, "{}{unit}", millis / scale),
+            None => write!(f, "{millis}ms"),
+        }
+    }
+}
+
+impl Serialize for DurationStr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DurationStr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+/// A size in bytes in the config given with units, ex: `"512B"`, `"64KB"`, `"10MB"`, `"1GB"`.
+/// The units are binary (1KB = 1024B), the `KiB`, `MiB`, ... forms are also accepted. A plain number
+/// is taken as bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[cfg_attr(feature = "openapi", schema(value_type = String, example = "10MB"))]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    pub const fn new(bytes: u64) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn kb(kb: u64) -> Self {
+        Self(kb * 1024)
+    }
+
+    pub const fn mb(mb: u64) -> Self {
+        Self(mb * 1024 * 1024)
+    }
+
+    pub const fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    pub fn as_usize(&self) -> usize {
+        usize::try_from(self.0).unwrap_or(usize::MAX)
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = ConfigUnitError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut bytes: u64 = 0;
+        for (number, unit) in segments(value)? {
+            let scale: u64 = match unit.to_ascii_uppercase().as_str() {
+                "" | "B" => 1,
+                "K" | "KB" | "KIB" => 1 << 10,
+                "M" | "MB" | "MIB" => 1 << 20,
+                "G" | "GB" | "GIB" => 1 << 30,
+                "T" | "TB" | "TIB" => 1 << 40,
+                _ => return Err(ConfigUnitError::UnknownUnit(value.to_string(), unit.to_string())),
+            };
+            bytes = number
+                .checked_mul(scale)
+                .and_then(|segment| bytes.checked_add(segment))
+                .ok_or_else(|| ConfigUnitError::Overflow(value.to_string()))?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: &[(u64, &str)] = &[(1 << 40, "TB"), (1 << 30, "GB"), (1 << 20, "MB"), (1 << 10, "KB")];
+        match UNITS.iter().find(|(scale, _)| self.0 > 0 && self.0 % scale == 0) {
+            Some((scale, unit)) => write!(f, "{}{unit}", self.0 / scale),
+            None => write!(f, "{}B", self.0),
+        }
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(Self(bytes)),
+            Raw::Text(value) => value.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn parse_duration() {
+        assert_eq!("500ms".parse(), Ok(DurationStr::from_millis(500)));
+        assert_eq!("30s".parse(), Ok(DurationStr::from_secs(30)));
+        assert_eq!(" 1h 30m ".parse(), Ok(DurationStr::from_secs(90 * 60)));
+        assert_eq!("7d".parse(), Ok(DurationStr::from_secs(7 * 24 * 3600)));
+        assert_eq!(
+            "30".parse::<DurationStr>(),
+            Err(ConfigUnitError::MissingUnit("30".into()))
+        );
+        assert!(matches!(
+            "5 min".parse::<DurationStr>(),
+            Err(ConfigUnitError::UnknownUnit(..))
+        ));
+        assert_eq!("".parse::<DurationStr>(), Err(ConfigUnitError::Empty));
+        assert!(matches!(
+            "s".parse::<DurationStr>(),
+            Err(ConfigUnitError::InvalidNumber(_))
+        ));
+
+        assert_eq!(DurationStr::from_secs(90 * 60).to_string(), "90m");
+        assert_eq!(DurationStr::from_millis(1500).to_string(), "1500ms");
+        assert_eq!(serde_json::to_string(&DurationStr::from_secs(60)).unwrap(), r#""1m""#);
+        assert!(serde_json::from_str::<DurationStr>("60").is_err());
+    }
+
+    #[test]
+    fn parse_byte_size() {
+        assert_eq!("10MB".parse(), Ok(ByteSize::mb(10)));
+        assert_eq!("64kib".parse(), Ok(ByteSize::kb(64)));
+        assert_eq!("1GB 512MB".parse(), Ok(ByteSize::mb(1536)));
+        assert_eq!("123".parse(), Ok(ByteSize::new(123)));
+        assert!(matches!(
+            "10XB".parse::<ByteSize>(),
+            Err(ConfigUnitError::UnknownUnit(..))
+        ));
+        assert!(matches!(
+            "99999999TB".parse::<ByteSize>(),
+            Err(ConfigUnitError::Overflow(_))
+        ));
+
+        assert_eq!(ByteSize::mb(2).to_string(), "2MB");
+        assert_eq!(ByteSize::new(1000).to_string(), "1000B");
+        assert_eq!(serde_json::from_str::<ByteSize>(r#""2MB""#).unwrap(), ByteSize::mb(2));
+        assert_eq!(serde_json::from_str::<ByteSize>("2048").unwrap(), ByteSize::kb(2));
+    }
+}
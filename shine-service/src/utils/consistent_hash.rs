@@ -0,0 +1,119 @@
+use ring::digest;
+use std::collections::BTreeMap;
+
+pub const DEFAULT_VIRTUAL_NODES: usize = 128;
+
+/// Stable 64 bit hash, independent of the process and platform (unlike the std hasher).
+pub(crate) fn stable_hash(data: &[u8]) -> u64 {
+    let hash = digest::digest(&digest::SHA256, data);
+    u64::from_be_bytes(hash.as_ref()[..8].try_into().unwrap())
+}
+
+/// Map the keys (ex. user or tenant ids) to nodes (ex. replicas) such that a change of the nodes moves
+/// only a small portion of the keys. Each node is placed on the ring at multiple (virtual) positions for an
+/// even distribution.
+#[derive(Clone, Debug)]
+pub struct ConsistentHashRing {
+    virtual_nodes: usize,
+    ring: BTreeMap<u64, String>,
+}
+
+impl Default for ConsistentHashRing {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIRTUAL_NODES)
+    }
+}
+
+impl ConsistentHashRing {
+    pub fn new(virtual_nodes: usize) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            ring: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_nodes<I, S>(virtual_nodes: usize, nodes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut ring = Self::new(virtual_nodes);
+        for node in nodes {
+            ring.add_node(node.as_ref());
+        }
+        ring
+    }
+
+    fn positions<'a>(&self, node: &'a str) -> impl Iterator<Item = u64> + 'a {
+        (0..self.virtual_nodes).map(move |index| stable_hash(format!("{node}#{index}").as_bytes()))
+    }
+
+    pub fn add_node(&mut self, node: &str) {
+        for position in self.positions(node).collect::<Vec<_>>() {
+            self.ring.insert(position, node.to_string());
+        }
+    }
+
+    pub fn remove_node(&mut self, node: &str) {
+        self.ring.retain(|_, owner| owner != node);
+    }
+
+    /// The distinct nodes of the ring in alphabetical order.
+    pub fn nodes(&self) -> Vec<&str> {
+        let mut nodes: Vec<&str> = self.ring.values().map(String::as_str).collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+
+    /// Find the node owning the key, None if the ring is empty.
+    pub fn node_for<K: AsRef<[u8]>>(&self, key: K) -> Option<&str> {
+        let hash = stable_hash(key.as_ref());
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+
+    /// Check if the key is owned by the given node.
+    pub fn owns<K: AsRef<[u8]>>(&self, node: &str, key: K) -> bool {
+        self.node_for(key) == Some(node)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+    use std::collections::HashMap;
+
+    #[test]
+    fn stable_and_balanced_partitions() {
+        let ring = ConsistentHashRing::with_nodes(DEFAULT_VIRTUAL_NODES, ["replica-a", "replica-b", "replica-c"]);
+        assert_eq!(ring.nodes(), ["replica-a", "replica-b", "replica-c"]);
+
+        let keys: Vec<String> = (0..3000).map(|id| format!("user-{id}")).collect();
+        let mut counts = HashMap::new();
+        for key in &keys {
+            *counts.entry(ring.node_for(key).unwrap()).or_insert(0) += 1;
+        }
+        assert!(counts.values().all(|count| *count > 700), "{counts:?}");
+
+        // removing a node moves only its own keys
+        let mut reduced = ring.clone();
+        reduced.remove_node("replica-c");
+        for key in &keys {
+            let before = ring.node_for(key).unwrap();
+            if before != "replica-c" {
+                assert_eq!(reduced.node_for(key), Some(before));
+            }
+        }
+
+        assert_eq!(ConsistentHashRing::default().node_for("user-1"), None);
+    }
+}
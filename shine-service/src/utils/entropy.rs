@@ -0,0 +1,153 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error as ThisError;
+use uuid::{Builder, Uuid};
+
+#[derive(Debug, ThisError)]
+#[error("Failed to generate random bytes")]
+pub struct EntropyError;
+
+/// Source of the random values (keys, codes, ids). The services take it as a dependency to make the
+/// generated values reproducible in the tests.
+pub trait Entropy: Send + Sync {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), EntropyError>;
+
+    /// Current time used for the time ordered ids.
+    fn unix_millis(&self) -> u64;
+
+    fn uuid_v4(&self) -> Result<Uuid, EntropyError> {
+        let mut raw = [0_u8; 16];
+        self.fill(&mut raw)?;
+        Ok(Builder::from_random_bytes(raw).into_uuid())
+    }
+
+    /// Time ordered uuid, preferred for the database keys.
+    fn uuid_v7(&self) -> Result<Uuid, EntropyError> {
+        let mut raw = [0_u8; 10];
+        self.fill(&mut raw)?;
+        Ok(Builder::from_unix_timestamp_millis(self.unix_millis(), &raw).into_uuid())
+    }
+
+    /// Random token of `len` bytes, hex encoded.
+    fn hex_token(&self, len: usize) -> Result<String, EntropyError> {
+        let mut raw = vec![0_u8; len];
+        self.fill(&mut raw)?;
+        Ok(hex::encode(raw))
+    }
+}
+
+impl<T: Entropy + ?Sized> Entropy for Arc<T> {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), EntropyError> {
+        (**self).fill(dest)
+    }
+
+    fn unix_millis(&self) -> u64 {
+        (**self).unix_millis()
+    }
+}
+
+/// Cryptographically secure entropy from the OS and the system clock.
+pub struct SystemEntropy(SystemRandom);
+
+impl Default for SystemEntropy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemEntropy {
+    pub fn new() -> Self {
+        Self(SystemRandom::new())
+    }
+
+    pub fn shared() -> Arc<dyn Entropy> {
+        Arc::new(Self::new())
+    }
+}
+
+impl Entropy for SystemEntropy {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), EntropyError> {
+        self.0.fill(dest).map_err(|_| EntropyError)
+    }
+
+    fn unix_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default()
+    }
+}
+
+/// Deterministic entropy for the tests: the same seed generates the same sequence of values and the clock
+/// starts at a fixed time advancing by a millisecond on each query. It must never be used in production.
+pub struct SeededEntropy {
+    state: Mutex<(u64, u64)>,
+}
+
+impl SeededEntropy {
+    /// Start of the clock, 2024-01-01T00:00:00Z.
+    pub const START_MILLIS: u64 = 1_704_067_200_000;
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Mutex::new((seed, Self::START_MILLIS)),
+        }
+    }
+
+    pub fn shared(seed: u64) -> Arc<dyn Entropy> {
+        Arc::new(Self::new(seed))
+    }
+}
+
+/// SplitMix64 step, good enough statistically and trivial to reproduce.
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Entropy for SeededEntropy {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), EntropyError> {
+        let mut state = self.state.lock().unwrap();
+        for chunk in dest.chunks_mut(8) {
+            let value = split_mix(&mut state.0).to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn unix_millis(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.1 += 1;
+        state.1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let (a, b) = (SeededEntropy::new(42), SeededEntropy::new(42));
+        assert_eq!(a.hex_token(20).unwrap(), b.hex_token(20).unwrap());
+        assert_eq!(a.uuid_v4().unwrap(), b.uuid_v4().unwrap());
+
+        let first = a.uuid_v7().unwrap();
+        let second = a.uuid_v7().unwrap();
+        assert_eq!(first.get_version_num(), 7);
+        assert!(first < second);
+        assert_eq!(first, b.uuid_v7().unwrap());
+
+        assert_ne!(
+            SeededEntropy::new(1).hex_token(16).unwrap(),
+            SeededEntropy::new(2).hex_token(16).unwrap()
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::utils::Sensitive;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    HarshIdEncoder, IdEncoder, IdEncoderError, KeyedIdEncoder, OptimusIdEncoder, PrefixedIdEncoder, SqidsIdEncoder,
+    VersionedIdEncoder,
+};
+
+fn default_min_length() -> u8 {
+    6
+}
+
+/// A key of the versioned encoder.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionedIdKeyConfig {
+    pub version: u32,
+    #[serde(flatten)]
+    pub encoder: IdEncoderConfig,
+}
+
+/// Id encoder selection from the service config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum IdEncoderConfig {
+    #[serde(rename_all = "camelCase")]
+    Optimus { prime: u64, random: Sensitive<u64> },
+    #[serde(rename_all = "camelCase")]
+    Harsh { salt: Sensitive<String> },
+    #[serde(rename_all = "camelCase")]
+    Sqids {
+        alphabet: Option<Sensitive<String>>,
+        #[serde(default = "default_min_length")]
+        min_length: u8,
+    },
+    #[serde(rename_all = "camelCase")]
+    Keyed { secret: Sensitive<String> },
+    /// Encoder with key rotation, the key with the highest version is used for the new ids.
+    #[serde(rename_all = "camelCase")]
+    Versioned {
+        keys: Vec<VersionedIdKeyConfig>,
+        /// Encoder of the ids created before the versioning.
+        unversioned: Option<Box<IdEncoderConfig>>,
+    },
+}
+
+impl IdEncoderConfig {
+    pub fn create(&self) -> Result<Box<dyn IdEncoder>, IdEncoderError> {
+        let encoder: Box<dyn IdEncoder> = match self {
+            IdEncoderConfig::Optimus { prime, random } => {
+                if *prime >= i32::MAX as u64 || !primal_check::miller_rabin(*prime) {
+                    return Err(IdEncoderError::InvalidConfig(
+                        "Optimus prime is not a valid prime".into(),
+                    ));
+                }
+                if *random.expose() >= i32::MAX as u64 {
+                    return Err(IdEncoderError::InvalidConfig("Optimus random is out of range".into()));
+                }
+                Box::new(OptimusIdEncoder::new(*prime, *random.expose()))
+            }
+            IdEncoderConfig::Harsh { salt } => Box::new(HarshIdEncoder::new(salt.expose())?),
+            IdEncoderConfig::Sqids { alphabet, min_length } => {
+                let alphabet = alphabet
+                    .as_ref()
+                    .map(|alphabet| alphabet.expose().as_str())
+                    .unwrap_or(SqidsIdEncoder::DEFAULT_ALPHABET);
+                Box::new(SqidsIdEncoder::new(alphabet, *min_length)?)
+            }
+            IdEncoderConfig::Keyed { secret } => Box::new(KeyedIdEncoder::new(secret.expose())?),
+            IdEncoderConfig::Versioned { keys, unversioned } => {
+                let keys = keys
+                    .iter()
+                    .map(|key| Ok((key.version, key.encoder.create()?)))
+                    .collect::<Result<Vec<_>, IdEncoderError>>()?;
+                let encoder = VersionedIdEncoder::new(keys)?;
+                match unversioned {
+                    Some(unversioned) => Box::new(encoder.with_unversioned(unversioned.create()?)),
+                    None => Box::new(encoder),
+                }
+            }
+        };
+        Ok(encoder)
+    }
+
+    /// Create the encoder with a type prefix, ex: `usr_`.
+    pub fn create_prefixed(&self, prefix: &str) -> Result<Box<dyn IdEncoder>, IdEncoderError> {
+        Ok(Box::new(PrefixedIdEncoder::new(prefix, self.create()?)))
+    }
+}
@@ -6,3 +6,11 @@ mod harsh_id_encoder;
 pub use self::harsh_id_encoder::*;
 mod prefixed_id_encoder;
 pub use self::prefixed_id_encoder::*;
+mod sqids_id_encoder;
+pub use self::sqids_id_encoder::*;
+mod keyed_id_encoder;
+pub use self::keyed_id_encoder::*;
+mod versioned_id_encoder;
+pub use self::versioned_id_encoder::*;
+mod id_encoder_config;
+pub use self::id_encoder_config::*;
@@ -0,0 +1,45 @@
+use sqids::Sqids;
+use std::collections::HashSet;
+
+use super::{IdEncoder, IdEncoderError};
+
+/// Sqids (the successor of hashids) based encoder. The ids are obfuscated by shuffling the alphabet,
+/// thus a custom alphabet should be kept private.
+pub struct SqidsIdEncoder(Sqids);
+
+impl SqidsIdEncoder {
+    pub const DEFAULT_ALPHABET: &'static str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+    pub fn new(alphabet: &str, min_length: u8) -> Result<Self, IdEncoderError> {
+        let chars: Vec<char> = alphabet.chars().collect();
+        let unique: HashSet<char> = chars.iter().copied().collect();
+        if unique.len() != chars.len() {
+            return Err(IdEncoderError::InvalidConfig(
+                "Alphabet contains duplicated characters".into(),
+            ));
+        }
+
+        let sqids = Sqids::builder()
+            .alphabet(chars)
+            .min_length(min_length)
+            .build()
+            .map_err(|err| IdEncoderError::InvalidConfig(format!("{err}")))?;
+        Ok(Self(sqids))
+    }
+}
+
+impl IdEncoder for SqidsIdEncoder {
+    fn obfuscate(&self, id: u64) -> Result<String, IdEncoderError> {
+        self.0
+            .encode(&[id])
+            .map_err(|err| IdEncoderError::InvalidConfig(format!("{err}")))
+    }
+
+    fn deobfuscate(&self, id: &str) -> Result<u64, IdEncoderError> {
+        match self.0.decode(id).as_slice() {
+            // reject the non-canonical forms, each id has a single valid representation
+            [n] if self.0.encode(&[*n]).ok().as_deref() == Some(id) => Ok(*n),
+            _ => Err(IdEncoderError::InvalidObfuscatedId("Invalid id".into())),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::{Arc, RwLock};
+
+use super::{IdEncoder, IdEncoderError};
+
+/// Separator of the key version and the obfuscated id, none of the encoders use it in their alphabet.
+const VERSION_SEPARATOR: char = '.';
+
+#[derive(Clone)]
+struct VersionedKeys {
+    /// The encoders ordered by the version, the last one is the newest.
+    keys: Vec<(u32, Arc<dyn IdEncoder>)>,
+    /// Encoder of the ids created before the versioning was introduced.
+    unversioned: Option<Arc<dyn IdEncoder>>,
+}
+
+fn check_keys(keys: Vec<(u32, Box<dyn IdEncoder>)>) -> Result<Vec<(u32, Arc<dyn IdEncoder>)>, IdEncoderError> {
+    let mut keys: Vec<_> = keys
+        .into_iter()
+        .map(|(version, encoder)| (version, Arc::from(encoder)))
+        .collect();
+    if keys.is_empty() {
+        return Err(IdEncoderError::InvalidConfig("At least one key is required".into()));
+    }
+    keys.sort_by_key(|(version, _)| *version);
+    if keys.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+        return Err(IdEncoderError::InvalidConfig("Duplicate key version".into()));
+    }
+    Ok(keys)
+}
+
+/// Encoder with a key version prefix, ex: `3.Xk2p9`, to support key rotation. The ids are always
+/// obfuscated with the newest key, but all the configured (historical) keys are accepted, thus the ids
+/// already shared in urls and emails stay valid. The keys can be replaced at runtime.
+pub struct VersionedIdEncoder {
+    keys: RwLock<Arc<VersionedKeys>>,
+}
+
+impl VersionedIdEncoder {
+    pub fn new(keys: Vec<(u32, Box<dyn IdEncoder>)>) -> Result<Self, IdEncoderError> {
+        let keys = VersionedKeys {
+            keys: check_keys(keys)?,
+            unversioned: None,
+        };
+        Ok(Self {
+            keys: RwLock::new(Arc::new(keys)),
+        })
+    }
+
+    /// Accept the ids without a version prefix (created before the versioning) using the given encoder.
+    #[must_use]
+    pub fn with_unversioned(self, encoder: Box<dyn IdEncoder>) -> Self {
+        let mut keys = (*self.current()).clone();
+        keys.unversioned = Some(Arc::from(encoder));
+        Self {
+            keys: RwLock::new(Arc::new(keys)),
+        }
+    }
+
+    fn current(&self) -> Arc<VersionedKeys> {
+        self.keys.read().unwrap().clone()
+    }
+
+    /// Replace the keys at runtime, ex. after a new key was added to the config.
+    pub fn replace_keys(&self, keys: Vec<(u32, Box<dyn IdEncoder>)>) -> Result<(), IdEncoderError> {
+        let keys = check_keys(keys)?;
+        let mut current = self.keys.write().unwrap();
+        *current = Arc::new(VersionedKeys {
+            keys,
+            unversioned: current.unversioned.clone(),
+        });
+        Ok(())
+    }
+
+    /// The version of the key used to obfuscate the new ids.
+    pub fn current_version(&self) -> u32 {
+        self.current()
+            .keys
+            .last()
+            .map(|(version, _)| *version)
+            .unwrap_or_default()
+    }
+}
+
+impl IdEncoder for VersionedIdEncoder {
+    fn obfuscate(&self, id: u64) -> Result<String, IdEncoderError> {
+        let keys = self.current();
+        let (version, encoder) = keys.keys.last().expect("Missing key");
+        Ok(format!("{version}{VERSION_SEPARATOR}{}", encoder.obfuscate(id)?))
+    }
+
+    fn deobfuscate(&self, id: &str) -> Result<u64, IdEncoderError> {
+        let keys = self.current();
+        let Some((version, encoded)) = id.split_once(VERSION_SEPARATOR) else {
+            return match &keys.unversioned {
+                Some(encoder) => encoder.deobfuscate(id),
+                None => Err(IdEncoderError::InvalidObfuscatedId("Missing key version".into())),
+            };
+        };
+
+        let version = version
+            .parse::<u32>()
+            .map_err(|_| IdEncoderError::InvalidObfuscatedId("Invalid key version".into()))?;
+        match keys.keys.binary_search_by_key(&version, |(version, _)| *version) {
+            Ok(index) => keys.keys[index].1.deobfuscate(encoded),
+            Err(_) => Err(IdEncoderError::InvalidObfuscatedId(format!(
+                "Unknown key version {version}"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::{KeyedIdEncoder, OptimusIdEncoder};
+    use shine_test::test;
+
+    fn keyed(secret: &str) -> Box<dyn IdEncoder> {
+        Box::new(KeyedIdEncoder::new(secret).unwrap())
+    }
+
+    #[test]
+    fn rotate_keys() {
+        let legacy = OptimusIdEncoder::new(1580030173, 1163945558);
+        let legacy_id = legacy.obfuscate(42).unwrap();
+
+        let encoder = VersionedIdEncoder::new(vec![(1, keyed("c2VjcmV0LWZvci10aGUtaWQtZW5jb2Rlcg"))])
+            .unwrap()
+            .with_unversioned(Box::new(legacy));
+        let v1_id = encoder.obfuscate(42).unwrap();
+        assert!(v1_id.starts_with("1."));
+
+        encoder
+            .replace_keys(vec![
+                (2, keyed("YW5vdGhlci1zZWNyZXQtZm9yLWlkcw")),
+                (1, keyed("c2VjcmV0LWZvci10aGUtaWQtZW5jb2Rlcg")),
+            ])
+            .unwrap();
+        assert_eq!(encoder.current_version(), 2);
+        let v2_id = encoder.obfuscate(42).unwrap();
+        assert!(v2_id.starts_with("2."));
+
+        for id in [&legacy_id, &v1_id, &v2_id] {
+            assert_eq!(encoder.deobfuscate(id).unwrap(), 42);
+        }
+        assert!(encoder.deobfuscate(&v2_id.replacen("2.", "3.", 1)).is_err());
+        assert!(encoder
+            .replace_keys(vec![
+                (1, keyed("YW5vdGhlci1zZWNyZXQtZm9yLWlkcw")),
+                (1, keyed("c2VjcmV0LWZvci10aGUtaWQtZW5jb2Rlcg"))
+            ])
+            .is_err());
+    }
+}
@@ -4,5 +4,15 @@ mod id_encoders;
 pub use self::id_encoders::*;
 mod serde;
 pub use self::serde::*;
+mod consistent_hash;
+pub use self::consistent_hash::*;
+mod config_units;
+pub use self::config_units::*;
+mod sensitive;
+pub use self::sensitive::*;
+mod signed_token;
+pub use self::signed_token::*;
+mod entropy;
+pub use self::entropy::*;
 mod error;
 pub use self::error::*;
@@ -11,6 +11,19 @@ pub mod serde_string {
     }
 }
 
+/// Serialize a duration as whole seconds, ex. for the protocol fields defined in seconds.
+pub mod serde_duration_secs {
+    use crate::utils::DurationStr;
+    use serde::Serializer;
+
+    pub fn serialize<S>(value: &DurationStr, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u64(value.as_duration().as_secs())
+    }
+}
+
 pub mod serde_status_code {
     use axum::http::StatusCode;
     use serde::Serializer;
@@ -0,0 +1,188 @@
+use crate::utils::Sensitive;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64, Engine};
+use chrono::Utc;
+use ring::hmac;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error as ThisError;
+
+const MIN_SECRET_LEN: usize = 32;
+
+#[derive(Debug, ThisError)]
+pub enum SignedTokenError {
+    #[error("Invalid signing key: {0}")]
+    InvalidKey(String),
+    #[error("Malformed token")]
+    Malformed,
+    #[error("Unknown signing key: {0}")]
+    UnknownKey(String),
+    #[error("Invalid signature")]
+    InvalidSignature,
+    #[error("Token has expired")]
+    Expired,
+    #[error("Token was issued for a different purpose")]
+    PurposeMismatch,
+    #[error("Json error")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A signing key with an id to support the key rotation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SigningKeyConfig {
+    pub id: String,
+    /// Base64 (url safe, no padding) encoded secret of at least 32 bytes.
+    pub secret: Sensitive<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims<T> {
+    /// Expiration as unix timestamp in seconds.
+    exp: i64,
+    /// Purpose of the token, a token cannot be used for anything else.
+    pur: String,
+    dat: T,
+}
+
+/// Create and verify the HMAC-SHA256 signed, expiring tokens with a serde payload, ex. for download links
+/// or email verification. The token has the `<key id>.<payload>.<signature>` format, it is signed by the
+/// newest (last) key, but all the keys are accepted for the verification, thus the keys can be rotated by
+/// adding a new key and removing the old one once its tokens have expired.
+/// The payload is readable by anyone, it is not encrypted.
+pub struct SignedTokens {
+    keys: Vec<(String, hmac::Key)>,
+}
+
+impl SignedTokens {
+    pub fn new(keys: &[SigningKeyConfig]) -> Result<Self, SignedTokenError> {
+        if keys.is_empty() {
+            return Err(SignedTokenError::InvalidKey("At least one key is required".into()));
+        }
+
+        let mut result = Vec::with_capacity(keys.len());
+        for key in keys {
+            if key.id.is_empty() || key.id.contains('.') {
+                return Err(SignedTokenError::InvalidKey(format!("Invalid key id: {:?}", key.id)));
+            }
+            if result.iter().any(|(id, _)| *id == key.id) {
+                return Err(SignedTokenError::InvalidKey(format!("Duplicate key id: {}", key.id)));
+            }
+            let secret = B64
+                .decode(key.secret.expose())
+                .map_err(|err| SignedTokenError::InvalidKey(format!("{}: {err}", key.id)))?;
+            if secret.len() < MIN_SECRET_LEN {
+                return Err(SignedTokenError::InvalidKey(format!(
+                    "{}: secret must be at least {MIN_SECRET_LEN} bytes",
+                    key.id
+                )));
+            }
+            result.push((key.id.clone(), hmac::Key::new(hmac::HMAC_SHA256, &secret)));
+        }
+
+        Ok(Self { keys: result })
+    }
+
+    /// Create a token for the given purpose (ex. `email-verification`) valid for the `ttl`.
+    pub fn create<T: Serialize>(&self, purpose: &str, payload: &T, ttl: Duration) -> Result<String, SignedTokenError> {
+        let (key_id, key) = self.keys.last().expect("Missing signing key");
+        let claims = Claims {
+            exp: Utc::now().timestamp() + ttl.as_secs() as i64,
+            pur: purpose.to_string(),
+            dat: payload,
+        };
+        let message = format!("{key_id}.{}", B64.encode(serde_json::to_vec(&claims)?));
+        let signature = hmac::sign(key, message.as_bytes());
+        Ok(format!("{message}.{}", B64.encode(signature.as_ref())))
+    }
+
+    /// Verify the signature, the expiration and the purpose of the token and return its payload.
+    pub fn verify<T: DeserializeOwned>(&self, purpose: &str, token: &str) -> Result<T, SignedTokenError> {
+        let (message, signature) = token.rsplit_once('.').ok_or(SignedTokenError::Malformed)?;
+        let (key_id, claims) = message.split_once('.').ok_or(SignedTokenError::Malformed)?;
+
+        let (_, key) = self
+            .keys
+            .iter()
+            .find(|(id, _)| id == key_id)
+            .ok_or_else(|| SignedTokenError::UnknownKey(key_id.to_string()))?;
+        let signature = B64.decode(signature).map_err(|_| SignedTokenError::Malformed)?;
+        hmac::verify(key, message.as_bytes(), &signature).map_err(|_| SignedTokenError::InvalidSignature)?;
+
+        // the content is trusted only after the signature was verified
+        let claims = B64.decode(claims).map_err(|_| SignedTokenError::Malformed)?;
+        let claims: Claims<T> = serde_json::from_slice(&claims)?;
+        if claims.pur != purpose {
+            return Err(SignedTokenError::PurposeMismatch);
+        }
+        if claims.exp <= Utc::now().timestamp() {
+            return Err(SignedTokenError::Expired);
+        }
+        Ok(claims.dat)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shine_test::test;
+
+    const OLD_SECRET: &str = "b2xkLXNlY3JldC1mb3ItdGhlLXNpZ25lZC10b2tlbnMtdGVzdA";
+    const NEW_SECRET: &str = "bmV3LXNlY3JldC1mb3ItdGhlLXNpZ25lZC10b2tlbnMtdGVzdA";
+
+    fn key(id: &str, secret: &str) -> SigningKeyConfig {
+        SigningKeyConfig {
+            id: id.to_string(),
+            secret: Sensitive::new(secret.to_string()),
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Download {
+        file: String,
+    }
+
+    #[test]
+    fn create_and_verify() {
+        let payload = Download {
+            file: "report.pdf".into(),
+        };
+        let old = SignedTokens::new(&[key("k1", OLD_SECRET)]).unwrap();
+        let old_token = old.create("download", &payload, Duration::from_secs(60)).unwrap();
+
+        let rotated = SignedTokens::new(&[key("k1", OLD_SECRET), key("k2", NEW_SECRET)]).unwrap();
+        let new_token = rotated.create("download", &payload, Duration::from_secs(60)).unwrap();
+        assert!(new_token.starts_with("k2."));
+        assert_eq!(rotated.verify::<Download>("download", &old_token).unwrap(), payload);
+        assert_eq!(rotated.verify::<Download>("download", &new_token).unwrap(), payload);
+
+        assert!(matches!(
+            old.verify::<Download>("download", &new_token),
+            Err(SignedTokenError::UnknownKey(_))
+        ));
+        assert!(matches!(
+            rotated.verify::<Download>("email-verification", &new_token),
+            Err(SignedTokenError::PurposeMismatch)
+        ));
+
+        let (message, _) = new_token.rsplit_once('.').unwrap();
+        let forged = format!("{message}.{}", B64.encode([0_u8; 32]));
+        assert!(matches!(
+            rotated.verify::<Download>("download", &forged),
+            Err(SignedTokenError::InvalidSignature)
+        ));
+
+        let expired = rotated.create("download", &payload, Duration::ZERO).unwrap();
+        assert!(matches!(
+            rotated.verify::<Download>("download", &expired),
+            Err(SignedTokenError::Expired)
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_keys() {
+        assert!(SignedTokens::new(&[]).is_err());
+        assert!(SignedTokens::new(&[key("k1", "c2hvcnQ")]).is_err());
+        assert!(SignedTokens::new(&[key("k.1", OLD_SECRET)]).is_err());
+        assert!(SignedTokens::new(&[key("k1", OLD_SECRET), key("k1", NEW_SECRET)]).is_err());
+    }
+}
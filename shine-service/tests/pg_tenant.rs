@@ -0,0 +1,61 @@
+use shine_service::service::{create_postgres_pool_with_config, PGPoolConfig, PGTenantRouter};
+use shine_test::test;
+use std::env;
+
+#[test]
+async fn test_tenant_connections_in_turn() {
+    match env::var("SHINE_TEST_PG_CNS") {
+        Ok(cns) => {
+            // a single connection, thus all the tenants share the same session
+            let config = PGPoolConfig {
+                max_size: 1,
+                ..Default::default()
+            };
+            let pool = create_postgres_pool_with_config(&cns, &config, None).await.unwrap();
+            {
+                let client = pool.get().await.unwrap();
+                client.batch_execute(PGTenantRouter::SCHEMA).await.unwrap();
+                client
+                    .batch_execute(
+                        r#"
+                        INSERT INTO public.tenant_schemas (tenant_id, schema_name)
+                            VALUES ('test-a', 'test_tenant_a'), ('test-b', 'test_tenant_b')
+                            ON CONFLICT DO NOTHING;
+                        CREATE SCHEMA IF NOT EXISTS test_tenant_a;
+                        CREATE SCHEMA IF NOT EXISTS test_tenant_b;
+                        CREATE TABLE IF NOT EXISTS test_tenant_a.marker AS SELECT 'a' AS name;
+                        CREATE TABLE IF NOT EXISTS test_tenant_b.marker AS SELECT 'b' AS name;
+                        "#,
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            let router = PGTenantRouter::new(pool.clone());
+            for (tenant_id, expected) in [("test-a", "a"), ("test-b", "b"), ("test-a", "a")] {
+                let client = router.get(tenant_id).await.unwrap();
+                let name: String = client.query_one("SELECT name FROM marker", &[]).await.unwrap().get(0);
+                assert_eq!(name, expected);
+            }
+
+            // the released connection is returned to the pool with the default search_path
+            {
+                let client = pool.get().await.unwrap();
+                let search_path: String = client.query_one("SHOW search_path", &[]).await.unwrap().get(0);
+                assert!(!search_path.contains("test_tenant"), "{search_path}");
+                client
+                    .batch_execute(
+                        r#"
+                        DROP SCHEMA test_tenant_a CASCADE;
+                        DROP SCHEMA test_tenant_b CASCADE;
+                        DELETE FROM public.tenant_schemas WHERE tenant_id IN ('test-a', 'test-b');
+                        "#,
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+
+        _ => log::warn!("Skipping test_tenant_connections_in_turn"),
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use shine_service::{
+    pg_query,
+    service::{create_postgres_pool, PGJson},
+};
+use shine_test::test;
+use std::env;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Settings {
+    theme: String,
+    volume: u8,
+}
+
+pg_query!( ArrayQuery =>
+    in = ids: Vec<i32>, names: Vec<String>;
+    out = names: Vec<String>;
+    sql = r#"
+        SELECT $2::text[] || array_agg(id::text) as names FROM unnest($1::int4[]) as id
+    "#
+);
+
+pg_query!( UuidArrayQuery =>
+    in = ids: Vec<Uuid>;
+    out = ids: Vec<Uuid>;
+    sql = r#"
+        SELECT $1 as ids
+    "#
+);
+
+pg_query!( JsonQuery =>
+    in = settings: PGJson<Settings>;
+    out = settings: PGJson<Settings>;
+    sql = r#"
+        SELECT $1 as settings
+    "#
+);
+
+#[test]
+async fn test_pg_array_and_json_types() {
+    match env::var("SHINE_TEST_PG_CNS") {
+        Ok(cns) => {
+            let pool = create_postgres_pool(&cns).await.unwrap();
+            let client = pool.get().await.unwrap();
+
+            let array_query = ArrayQuery::new(&client).await.unwrap();
+            let names = array_query
+                .query_one(&client, &vec![1, 2], &vec!["zero".to_string()])
+                .await
+                .unwrap();
+            assert_eq!(names, vec!["zero", "1", "2"]);
+
+            let uuid_query = UuidArrayQuery::new(&client).await.unwrap();
+            let ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+            assert_eq!(uuid_query.query_one(&client, &ids).await.unwrap(), ids);
+
+            let json_query = JsonQuery::new(&client).await.unwrap();
+            let settings = Settings {
+                theme: "dark".to_string(),
+                volume: 7,
+            };
+            let loaded = json_query.query_one(&client, &PGJson(settings.clone())).await.unwrap();
+            assert_eq!(loaded.into_inner(), settings);
+        }
+
+        _ => log::warn!("Skipping test_pg_array_and_json_types"),
+    }
+}